@@ -203,6 +203,7 @@ async fn margin_post() {
         time_in_force: Some(TimeInForce::FOK),
         side_effect_type: SideEffectType::NoSideEffect,
         is_isolated: None,
+        grid_id: None,
     };
     let new_order = margin.new_order(margin_order).await;
     eprintln!("new_order = {new_order:?}");
@@ -1,8 +1,13 @@
+use std::sync::Arc;
 use rust_decimal::Decimal;
 use crate::client::*;
 use crate::errors::*;
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+use crate::futures::utils::order_guard::OrderGuard;
+use crate::futures::utils::order_tracking_request::OrderTrackingRequest;
+use crate::market::Market;
 use crate::rest_model::*;
-use crate::util::*;
+use crate::transport::HttpTransport;
 
 static API_V3_ACCOUNT: &str = "/api/v3/account";
 static API_V3_OPEN_ORDERS: &str = "/api/v3/openOrders";
@@ -19,6 +24,8 @@ static API_V3_ORDER_TEST: &str = "/api/v3/order/test";
 pub struct Account {
     pub client: Client,
     pub recv_window: u64,
+    /// Tracker and rules orders are checked against, orders are not checked by default
+    pub order_guard: Option<OrderGuard>,
 }
 
 /// Order Request
@@ -44,6 +51,9 @@ pub struct OrderRequest {
     pub new_order_resp_type: Option<OrderResponse>,
     /// Cannot be greater than 60000
     pub recv_window: Option<u64>,
+    /// Grid or strategy the order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing, default)]
+    pub grid_id: Option<String>,
 }
 
 impl OrderRequest {
@@ -112,6 +122,9 @@ pub struct CancelReplaceRequest {
     pub new_order_resp_type: Option<OrderResponse>,
     /// Cannot be greater than 60000
     pub recv_window: Option<u64>,
+    /// Grid or strategy the replacing order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing, default)]
+    pub grid_id: Option<String>,
 }
 
 impl CancelReplaceRequest {
@@ -153,6 +166,18 @@ pub struct OrdersQuery {
 }
 
 impl Account {
+    /// Checks orders against the spot rules of `order_guard`, e.g. `OrderGuard::global()`
+    pub fn with_order_guard(mut self, order_guard: OrderGuard) -> Self {
+        self.order_guard = Some(order_guard);
+        self
+    }
+
+    /// Sends requests through `transport`, see `Client::with_transport`
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.client = self.client.with_transport(transport);
+        self
+    }
+
     /// General account information
     /// # Examples
     /// ```rust,no_run
@@ -162,9 +187,7 @@ impl Account {
     /// assert!(account.is_ok(), "{:?}", account);
     /// ```
     pub async fn get_account(&self) -> Result<AccountInformation> {
-        // TODO: should parameters be Option<>?
-        let request = build_signed_request([("", "")], self.recv_window)?;
-        self.client.get_signed(API_V3_ACCOUNT, &request).await
+        self.client.get_signed_p(API_V3_ACCOUNT, None::<()>, self.recv_window).await
     }
 
     /// Account balance for a single asset
@@ -205,9 +228,10 @@ impl Account {
     where
         S: AsRef<str>,
     {
-        let parameters = [("symbol", symbol.as_ref())];
-        let request = build_signed_request(parameters, self.recv_window)?;
-        self.client.get_signed(API_V3_OPEN_ORDERS, &request).await
+        let query = PairQuery {
+            symbol: symbol.as_ref().to_string(),
+        };
+        self.client.get_signed_p(API_V3_OPEN_ORDERS, Some(query), self.recv_window).await
     }
 
     /// All orders for the account
@@ -228,8 +252,7 @@ impl Account {
     /// ```
     pub async fn get_all_orders(&self, query: OrdersQuery) -> Result<Vec<Order>> {
         let recv_window = query.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(query, recv_window)?;
-        self.client.get_signed(API_V3_ALL_ORDERS, &request).await
+        self.client.get_signed_p(API_V3_ALL_ORDERS, Some(query), recv_window).await
     }
 
     /// All currently open orders for the account
@@ -241,8 +264,7 @@ impl Account {
     /// assert!(orders.is_ok(), "{:?}", orders);
     /// ```
     pub async fn get_all_open_orders(&self) -> Result<Vec<Order>> {
-        let request = build_signed_request([("", "")], self.recv_window)?;
-        self.client.get_signed(API_V3_OPEN_ORDERS, &request).await
+        self.client.get_signed_p(API_V3_OPEN_ORDERS, None::<()>, self.recv_window).await
     }
 
     /// Cancels all currently open orders of specified symbol for the account
@@ -257,9 +279,10 @@ impl Account {
     where
         S: AsRef<str>,
     {
-        let params = [("symbol", symbol.as_ref())];
-        let request = build_signed_request(params, self.recv_window)?;
-        self.client.delete_signed(API_V3_OPEN_ORDERS, &request).await
+        let query = PairQuery {
+            symbol: symbol.as_ref().to_string(),
+        };
+        self.client.delete_signed_p(API_V3_OPEN_ORDERS, query, self.recv_window).await
     }
 
     /// Check an order's status
@@ -278,8 +301,7 @@ impl Account {
     /// ```
     pub async fn order_status(&self, osr: OrderStatusRequest) -> Result<Order> {
         let recv_window = osr.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(osr, recv_window)?;
-        self.client.get_signed(API_V3_ORDER, &request).await
+        self.client.get_signed_p(API_V3_ORDER, Some(osr), recv_window).await
     }
 
     /// Place a test status order
@@ -300,13 +322,27 @@ impl Account {
     /// ```
     pub async fn test_order_status(&self, osr: OrderStatusRequest) -> Result<TestResponse> {
         let recv_window = osr.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(osr, recv_window)?;
-        self.client.get_signed(API_V3_ORDER_TEST, &request).await
+        self.client.get_signed_p(API_V3_ORDER_TEST, Some(osr), recv_window).await
+    }
+
+    /// Fetches the last price the spot rules count orders without a price at, see
+    /// `OrderTrackingRequest::with_context_price`
+    async fn get_rule_context(&self, order: &OrderTrackingRequest) -> Result<ExpectedOrderRequestsRuleContext> {
+        let mut context = ExpectedOrderRequestsRuleContext::default();
+        if order.needs_market_price() {
+            let market = Market {
+                client: self.client.clone(),
+                recv_window: self.recv_window,
+            };
+            context.last_price = Some(market.get_price(&order.symbol).await?.price);
+        }
+        Ok(context)
     }
 
     /// Place an order
     /// Returns the Transaction if Ok
     /// This methods validates the order request before sending, making sure it complies with Binance rules
+    /// and with the spot rules of `order_guard` when set
     /// # Examples
     /// ```rust,no_run
     /// use rust_decimal_macros::dec;
@@ -326,9 +362,20 @@ impl Account {
     /// ```
     pub async fn place_order(&self, order: OrderRequest) -> Result<Transaction> {
         order.valid()?;
+        let tracking_request = OrderTrackingRequest::from(&order);
+        let checked = match &self.order_guard {
+            Some(order_guard) => {
+                let context = self.get_rule_context(&tracking_request).await?;
+                Some(order_guard.check_order_with_context(&tracking_request, &context)?)
+            }
+            None => None,
+        };
         let recv_window = order.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(order, recv_window)?;
-        self.client.post_signed(API_V3_ORDER, &request).await
+        let result = self.client.post_signed_p(API_V3_ORDER, order, recv_window).await;
+        if let (Some(order_guard), Some((tracking_entry, _))) = (&self.order_guard, &checked) {
+            order_guard.record_response(&tracking_request.get_tracking_key(), tracking_entry, &result);
+        }
+        result
     }
 
     /// Place a test order
@@ -355,8 +402,7 @@ impl Account {
     pub async fn place_test_order(&self, order: OrderRequest) -> Result<TestResponse> {
         order.valid()?;
         let recv_window = order.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(order, recv_window)?;
-        self.client.post_signed(API_V3_ORDER_TEST, &request).await
+        self.client.post_signed_p(API_V3_ORDER_TEST, order, recv_window).await
     }
 
     /// Place a cancellation order
@@ -376,15 +422,29 @@ impl Account {
     /// ```
     pub async fn cancel_order(&self, o: OrderCancellation) -> Result<OrderCanceled> {
         let recv_window = o.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(o, recv_window)?;
-        self.client.delete_signed(API_V3_ORDER, &request).await
+        self.client.delete_signed_p(API_V3_ORDER, o, recv_window).await
     }
 
+    /// Cancels an order and places another one, only the new order is checked against the rules
+    /// of `order_guard` when set
     pub async fn cancel_replace_order(&self, order: CancelReplaceRequest) -> Result<OrderCanceledReplaced> {
         order.valid()?;
+        let tracking_request = OrderTrackingRequest::from(&order);
+        let checked = match &self.order_guard {
+            Some(order_guard) => {
+                let context = self.get_rule_context(&tracking_request).await?;
+                Some(order_guard.check_order_with_context(&tracking_request, &context)?)
+            }
+            None => None,
+        };
         let recv_window = order.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(order, recv_window)?;
-        self.client.post_signed(API_V3_CANCEL_REPLACE, &request).await
+        let result: Result<OrderCanceledReplaced> =
+            self.client.post_signed_p(API_V3_CANCEL_REPLACE, order, recv_window).await;
+        // A failed cancellation may still have placed the new order, only responses are recorded
+        if let (Ok(_), Some(order_guard), Some((tracking_entry, _))) = (&result, &self.order_guard, &checked) {
+            order_guard.record_response(&tracking_request.get_tracking_key(), tracking_entry, &result);
+        }
+        result
     }
 
     /// Place a test cancel order
@@ -406,8 +466,7 @@ impl Account {
     /// ```
     pub async fn test_cancel_order(&self, o: OrderCancellation) -> Result<TestResponse> {
         let recv_window = o.recv_window.unwrap_or(self.recv_window);
-        let request = build_signed_request_p(o, recv_window)?;
-        self.client.delete_signed(API_V3_ORDER_TEST, &request).await
+        self.client.delete_signed_p(API_V3_ORDER_TEST, o, recv_window).await
     }
 
     /// Trade history
@@ -422,8 +481,106 @@ impl Account {
     where
         S: AsRef<str>,
     {
-        let parameters = [("symbol", symbol.as_ref())];
-        let request = build_signed_request(parameters, self.recv_window)?;
-        self.client.get_signed(API_V3_MYTRADES, &request).await
+        let query = PairQuery {
+            symbol: symbol.as_ref().to_string(),
+        };
+        self.client.get_signed_p(API_V3_MYTRADES, Some(query), self.recv_window).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Method;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::api::Binance;
+    use crate::config::Config;
+    use crate::futures::utils::expected_order_requests::rules_config::ExpectedOrderRequestsRulesConfig;
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::rate_limit::RateLimitMode;
+    use crate::transport::{HttpResponse, MemoryTransport};
+
+    const TRANSACTION: &str = r#"{"symbol":"BTCUSDT","orderId":1,"clientOrderId":"a","transactTime":1,"price":"100",
+        "origQty":"0.5","executedQty":"0","cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC","type":"LIMIT",
+        "side":"BUY","fills":[]}"#;
+
+    fn order() -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: OrderSide::Buy,
+            order_type: OrderType::Limit,
+            time_in_force: Some(TimeInForce::GTC),
+            quantity: Some(dec!(0.5)),
+            price: Some(dec!(100)),
+            ..OrderRequest::default()
+        }
+    }
+
+    fn account(transport: Arc<MemoryTransport>) -> Account {
+        let config = Config::default().set_rate_limit_mode(RateLimitMode::Disabled);
+        Account::new_with_config(Some("api-key".to_string()), Some("secret-key".to_string()), &config).with_transport(transport)
+    }
+
+    #[tokio::test]
+    async fn orders_are_checked_only_once_a_guard_is_set() {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond(Method::POST, API_V3_ORDER, HttpResponse::ok(TRANSACTION));
+
+        // Without guard orders are sent whatever the rules
+        let account = account(transport.clone());
+        assert!(account.order_guard.is_none());
+        assert!(account.place_order(order()).await.is_ok());
+        assert!(account.place_order(order()).await.is_ok());
+        assert_eq!(transport.requests_to(Method::POST, API_V3_ORDER).len(), 2);
+
+        // Allows one spot order of up to 1 BTCUSDT a day
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let config = ExpectedOrderRequestsRulesConfig::from_json(
+            r#"{"rules":[
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":1,"period":{"Hours":24},"side":"BUY"}},
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Weeks":52},"side":"BUY"}}
+            ]}"#,
+        )
+        .unwrap();
+        guard.rules().load(config.into_rule_map()).unwrap();
+        let account = account.with_order_guard(guard.clone());
+        assert!(account.place_order(order()).await.is_ok());
+        assert!(matches!(account.place_order(order()).await, Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(transport.requests_to(Method::POST, API_V3_ORDER).len(), 3);
+        assert_eq!(guard.tracker().get_all_tracking_items("spot.BTCUSDT").unwrap().len(), 1);
+
+        // A guard rejects the orders of symbols without rules
+        let other_symbol = OrderRequest { symbol: "ETHUSDT".to_string(), ..order() };
+        assert!(matches!(account.place_order(other_symbol).await, Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(transport.requests_to(Method::POST, API_V3_ORDER).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn orders_without_a_price_are_counted_at_the_last_price() {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond(Method::POST, API_V3_ORDER, HttpResponse::ok(TRANSACTION));
+        transport.respond(Method::GET, "/api/v3/ticker/price", HttpResponse::ok(r#"{"symbol":"BTCUSDT","price":"200"}"#));
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let config = ExpectedOrderRequestsRulesConfig::from_json(
+            r#"{"rules":[
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Hours":24},"side":"BUY"}},
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Weeks":52},"side":"BUY"}}
+            ]}"#,
+        )
+        .unwrap();
+        guard.rules().load(config.into_rule_map()).unwrap();
+        let account = account(transport.clone()).with_order_guard(guard.clone());
+
+        let market = OrderRequest { order_type: OrderType::Market, time_in_force: None, price: None, ..order() };
+        let quote_sized = OrderRequest { quantity: None, quote_order_qty: Some(dec!(100)), ..market.clone() };
+        let stop = OrderRequest { order_type: OrderType::StopLoss, stop_price: Some(dec!(90)), ..market.clone() };
+        for order in [market, quote_sized, stop] {
+            assert!(account.place_order(order).await.is_ok());
+        }
+        // Stop orders are counted at their stop price without fetching the last price
+        assert_eq!(transport.requests_to(Method::GET, "/api/v3/ticker/price").len(), 2);
+        let mut tracked: Vec<_> = guard.tracker().get_all_tracking_items("spot.BTCUSDT").unwrap().into_iter().map(|entry| (entry.item.size, entry.item.price)).collect();
+        tracked.sort();
+        assert_eq!(tracked, vec![(dec!(0.5), dec!(90)), (dec!(0.5), dec!(200)), (dec!(0.5), dec!(200))]);
     }
 }
@@ -1,22 +1,34 @@
-use std::time::Duration;
+use std::sync::Arc;
 
-use hex::encode as hex_encode;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, USER_AGENT};
-use reqwest::Response;
+use reqwest::Method;
 use reqwest::StatusCode;
-use ring::hmac;
 use serde::de;
 use serde::de::DeserializeOwned;
 
+use crate::clock::Clock;
+use crate::config::Config;
 use crate::errors::error_messages;
 use crate::errors::*;
-use crate::util::{build_request_p, build_signed_request_p};
+use crate::rest_model::RateLimit;
+use crate::rate_limit::{retry_after, RateLimitMode, RateLimiter};
+use crate::retry::RetryPolicy;
+use crate::signer::{RequestSigner, SignatureType};
+use crate::time_sync::{ServerTimeSource, TimeSync};
+use crate::traffic::{RecordingTransport, TrafficRecorder, TrafficReplay};
+use crate::transport::{HttpRequest, HttpResponse, HttpTransport, ReqwestTransport};
+use crate::util::{build_request_p, build_signed_request_p_at, get_timestamp};
 
 #[derive(Clone, Debug)]
 pub struct Client {
     api_key: String,
-    secret_key: String,
-    inner: reqwest::Client,
+    signature_type: SignatureType,
+    signer: std::result::Result<RequestSigner, String>,
+    time_sync: Option<TimeSync>,
+    clock: Option<Arc<dyn Clock>>,
+    rate_limiter: Option<RateLimiter>,
+    retry_policy: Option<RetryPolicy>,
+    transport: Arc<dyn HttpTransport>,
     host: String,
 }
 
@@ -25,24 +37,130 @@ impl Client {
     /// Credentials do not need to be specified when using public endpoints
     /// Host is mandatory
     pub fn new(api_key: Option<String>, secret_key: Option<String>, host: String, timeout: Option<u64>) -> Self {
-        let mut builder: reqwest::ClientBuilder = reqwest::ClientBuilder::new();
-        if let Some(timeout_secs) = timeout {
-            builder = builder.timeout(Duration::from_secs(timeout_secs))
+        Self::new_with_signature_type(api_key, secret_key, host, timeout, SignatureType::Hmac)
+    }
+
+    /// Returns a client for the host, with the timeout and signature type taken from `config`
+    /// Clients of the same host share the request weight of their rate limiter, and clients of the
+    /// same API key on that host its order count, unless rate limiting is disabled
+    /// Signed requests use the server time if `config` has a time sync interval
+    pub fn new_with_config(api_key: Option<String>, secret_key: Option<String>, host: String, config: &Config) -> Self {
+        let mut client = Self::new_with_signature_type(api_key, secret_key, host, config.timeout, config.signature_type);
+        if config.rate_limit_mode != RateLimitMode::Disabled {
+            let rate_limiter = RateLimiter::shared(&client.host, &client.api_key, config.rate_limit_mode);
+            client = client.with_rate_limiter(rate_limiter);
+        }
+        client.retry_policy = config.retry_policy.clone();
+        if let Some(refresh_interval) = config.time_sync_interval {
+            let time_sync = TimeSync::new(Self::server_time_source(client.clone(), config), refresh_interval);
+            client = client.with_time_sync(time_sync);
+        }
+        client
+    }
+
+    /// Server time endpoint of the API family of the client, which `client` is used to call
+    #[cfg_attr(not(feature = "futures_api"), allow(unused_variables))]
+    fn server_time_source(client: Client, config: &Config) -> ServerTimeSource {
+        #[cfg(feature = "futures_api")]
+        if client.host == config.futures_rest_api_endpoint {
+            return ServerTimeSource::Futures(crate::futures::general::FuturesGeneral { client });
         }
+        ServerTimeSource::Spot(crate::general::General { client })
+    }
+
+    /// Returns a client whose secret key is interpreted according to `signature_type`
+    /// A key that cannot be loaded is only reported when calling a signed endpoint
+    pub fn new_with_signature_type(
+        api_key: Option<String>,
+        secret_key: Option<String>,
+        host: String,
+        timeout: Option<u64>,
+        signature_type: SignatureType,
+    ) -> Self {
+        let signer = RequestSigner::new(signature_type, &secret_key.unwrap_or_default()).map_err(|e| e.to_string());
         Client {
             // Does it ever make sense for api_key and secret_key to be ""?
             api_key: api_key.unwrap_or_else(|| "".into()),
-            secret_key: secret_key.unwrap_or_else(|| "".into()),
-            inner: builder.build().unwrap(),
+            signature_type,
+            signer,
+            time_sync: None,
+            clock: None,
+            rate_limiter: None,
+            retry_policy: None,
+            transport: Arc::new(ReqwestTransport::new(timeout).unwrap()),
             host,
         }
     }
 
-    pub async fn get_signed<T: DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
-        let url = self.sign_request(endpoint, request, None);
-        let response = self.inner.get(&url).headers(self.build_headers(true)?).send().await?;
+    /// Replaces the signer used for signed endpoints
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signature_type = signer.signature_type();
+        self.signer = Ok(signer);
+        self
+    }
+
+    /// Sends requests through `transport` instead of reqwest, e.g. a `MemoryTransport` in tests
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
 
-        self.handler(response).await
+    /// Records every request and its response to `recorder`, added around the current transport
+    pub fn with_recorder(mut self, recorder: Arc<TrafficRecorder>) -> Self {
+        self.transport = Arc::new(RecordingTransport::new(self.transport, recorder));
+        self
+    }
+
+    /// Answers requests with the responses of a recording instead of sending them
+    pub fn with_replay(self, replay: Arc<TrafficReplay>) -> Self { self.with_transport(replay) }
+
+    /// Accounts every request against `rate_limiter` before sending it
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Applies limits advertised by `exchangeInfo` to the rate limiter, if any
+    pub fn set_rate_limits(&self, limits: &[RateLimit]) {
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            rate_limiter.set_limits(limits);
+        }
+    }
+
+    /// Retries GET requests that fail with a transient error according to `retry_policy`
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> { self.retry_policy.as_ref() }
+
+    /// Stamps signed requests with the server time tracked by `time_sync`
+    /// A request rejected because of its timestamp is retried once after re-syncing
+    pub fn with_time_sync(mut self, time_sync: TimeSync) -> Self {
+        self.time_sync = Some(time_sync);
+        self
+    }
+
+    /// Stamps signed requests with the time of `clock` instead of the global clock.
+    /// A time sync takes precedence over the clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Timestamp for a signed request: the server time if the client has a time sync, otherwise
+    /// the time of its clock or the global clock
+    async fn timestamp(&self) -> Result<u64> {
+        match (&self.time_sync, &self.clock) {
+            (Some(time_sync), _) => time_sync.timestamp().await,
+            (None, Some(clock)) => Ok(clock.now_millis()),
+            (None, None) => get_timestamp(),
+        }
+    }
+
+    pub async fn get_signed<T: DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
+        self.signed_request(Method::GET, endpoint, request, None).await
     }
 
     pub async fn get_signed_d<T: de::DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
@@ -55,22 +173,15 @@ impl Client {
         payload: Option<P>,
         recv_window: u64,
     ) -> Result<T> {
-        let req = build_signed_request_p(payload, recv_window)?;
-        self.get_signed(endpoint, &req).await
+        self.signed_request_p(Method::GET, endpoint, &payload, recv_window, None).await
     }
 
     pub async fn post_signed<T: DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
-        let url = self.sign_request(endpoint, request, None);
-        let response = self.inner.post(&url).headers(self.build_headers(true)?).send().await?;
-
-        self.handler(response).await
+        self.signed_request(Method::POST, endpoint, request, None).await
     }
 
     pub async fn post_signed_with_key<T: DeserializeOwned>(&self, endpoint: &str, request: &str, private_key: &str) -> Result<T> {
-        let url = self.sign_request(endpoint, request, Some(private_key.to_string()));
-        let response = self.inner.post(&url).headers(self.build_headers(true)?).send().await?;
-
-        self.handler(response).await
+        self.signed_request(Method::POST, endpoint, request, Some(private_key)).await
     }
 
     pub async fn post_signed_d<T: de::DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
@@ -83,8 +194,7 @@ impl Client {
         payload: P,
         recv_window: u64,
     ) -> Result<T> {
-        let request = build_signed_request_p(payload, recv_window)?;
-        self.post_signed(endpoint, &request).await
+        self.signed_request_p(Method::POST, endpoint, &payload, recv_window, None).await
     }
 
     pub async fn post_signed_p_with_key<T: de::DeserializeOwned, P: serde::Serialize>(
@@ -94,8 +204,8 @@ impl Client {
         recv_window: u64,
         private_key: &str,
     ) -> Result<T> {
-        let request = build_signed_request_p(payload, recv_window)?;
-        self.post_signed_with_key(endpoint, &request, private_key).await
+        self.signed_request_p(Method::POST, endpoint, &payload, recv_window, Some(private_key))
+            .await
     }
 
     pub async fn delete_signed_p<T: de::DeserializeOwned, P: serde::Serialize>(
@@ -104,20 +214,11 @@ impl Client {
         payload: P,
         recv_window: u64,
     ) -> Result<T> {
-        let request = build_signed_request_p(payload, recv_window)?;
-        self.delete_signed(endpoint, &request).await
+        self.signed_request_p(Method::DELETE, endpoint, &payload, recv_window, None).await
     }
 
     pub async fn delete_signed<T: DeserializeOwned>(&self, endpoint: &str, request: &str) -> Result<T> {
-        let url = self.sign_request(endpoint, request, None);
-        let response = self
-            .inner
-            .delete(&url)
-            .headers(self.build_headers(true)?)
-            .send()
-            .await?;
-
-        self.handler(response).await
+        self.signed_request(Method::DELETE, endpoint, request, None).await
     }
 
     pub async fn get<T: DeserializeOwned>(&self, endpoint: &str, request: Option<&str>) -> Result<T> {
@@ -125,9 +226,14 @@ impl Client {
             .map(|r| format!("{}{}?{}", self.host, endpoint, r))
             .unwrap_or_else(|| format!("{}{}", self.host, endpoint));
 
-        let response = self.inner.get(&url).send().await?;
-
-        self.handler(response).await
+        match self.retry_policy {
+            Some(ref retry_policy) => {
+                retry_policy
+                    .run(|_| self.execute(Method::GET, endpoint, &url, None))
+                    .await
+            }
+            None => self.execute(Method::GET, endpoint, &url, None).await,
+        }
     }
 
     pub async fn get_p<T: DeserializeOwned>(&self, endpoint: &str, request: Option<&str>) -> Result<T> {
@@ -153,9 +259,7 @@ impl Client {
             .unwrap_or_else(|| format!("{}{}", self.host, endpoint));
 
         // println!("client::post url: {url}");
-        let response = self.inner.post(url).headers(self.build_headers(false)?).send().await?;
-
-        self.handler(response).await
+        self.execute(Method::POST, endpoint, &url, Some(self.build_headers(false)?)).await
     }
 
     pub async fn put<T: DeserializeOwned>(&self, endpoint: &str, listen_key: &str, symbol: Option<&str>) -> Result<T> {
@@ -164,9 +268,7 @@ impl Client {
             .unwrap_or_else(|| format!("listenKey={listen_key}"));
         let headers = self.build_headers(false)?;
         let url = format!("{}{}?{}", self.host, endpoint, data);
-        let response = self.inner.put(&url).headers(headers).send().await?;
-
-        self.handler(response).await
+        self.execute(Method::PUT, endpoint, &url, Some(headers)).await
     }
 
     pub async fn delete<T: DeserializeOwned>(
@@ -179,27 +281,136 @@ impl Client {
             .map(|s| format!("listenKey={listen_key}&symbol={s}"))
             .unwrap_or_else(|| format!("listenKey={listen_key}"));
         let url = format!("{}{}?{}", self.host, endpoint, data);
-        let response = self
-            .inner
-            .delete(url)
-            .headers(self.build_headers(false)?)
-            .send()
-            .await?;
+        self.execute(Method::DELETE, endpoint, &url, Some(self.build_headers(false)?)).await
+    }
+
+    /// Sends a request already built with `build_signed_request`, as is
+    async fn signed_request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        request: &str,
+        secret_key: Option<&str>,
+    ) -> Result<T> {
+        match self.retry_policy {
+            Some(ref retry_policy) if method == Method::GET => {
+                retry_policy
+                    .run(|_| self.send_signed(Method::GET, endpoint, request, secret_key))
+                    .await
+            }
+            _ => self.send_signed(method, endpoint, request, secret_key).await,
+        }
+    }
+
+    /// Builds the request from `payload` and sends it. Every attempt is built with a new
+    /// timestamp, and a request rejected because of its timestamp is sent again once after
+    /// re-syncing the time sync.
+    async fn signed_request_p<T: DeserializeOwned, P: serde::Serialize>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        payload: &P,
+        recv_window: u64,
+        secret_key: Option<&str>,
+    ) -> Result<T> {
+        let result = match self.retry_policy {
+            Some(ref retry_policy) if method == Method::GET => {
+                retry_policy
+                    .run(|_| self.send_signed_p(Method::GET, endpoint, payload, recv_window, secret_key))
+                    .await
+            }
+            _ => {
+                self.send_signed_p(method.clone(), endpoint, payload, recv_window, secret_key)
+                    .await
+            }
+        };
+        match (&self.time_sync, result) {
+            (Some(time_sync), Err(Error::BinanceError { response }))
+                if response.error_code() == BinanceErrorCode::InvalidTimestamp =>
+            {
+                time_sync.sync().await?;
+                self.send_signed_p(method, endpoint, payload, recv_window, secret_key)
+                    .await
+            }
+            (_, result) => result,
+        }
+    }
+
+    async fn send_signed_p<T: DeserializeOwned, P: serde::Serialize>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        payload: &P,
+        recv_window: u64,
+        secret_key: Option<&str>,
+    ) -> Result<T> {
+        self.acquire(&method, endpoint, &build_request_p(payload)?).await?;
+        // Stamped once the rate limiter let it through, a delayed request would be outside of its recvWindow
+        let request = build_signed_request_p_at(payload, recv_window, self.timestamp().await?)?;
+        let url = self.sign_request(endpoint, &request, secret_key)?;
+        self.send(method, &url, Some(self.build_headers(true)?)).await
+    }
 
-        self.handler(response).await
+    async fn send_signed<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        request: &str,
+        secret_key: Option<&str>,
+    ) -> Result<T> {
+        self.acquire(&method, endpoint, request).await?;
+        let url = self.sign_request(endpoint, request, secret_key)?;
+        self.send(method, &url, Some(self.build_headers(true)?)).await
+    }
+
+    async fn execute<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        url: &str,
+        headers: Option<HeaderMap>,
+    ) -> Result<T> {
+        let query = url.split_once('?').map(|(_, query)| query).unwrap_or_default();
+        self.acquire(&method, endpoint, query).await?;
+        self.send(method, url, headers).await
+    }
+
+    /// Reserves the cost of a request with the rate limiter, if any
+    async fn acquire(&self, method: &Method, endpoint: &str, query: &str) -> Result<()> {
+        match self.rate_limiter {
+            Some(ref rate_limiter) => rate_limiter.acquire(method, endpoint, query).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, method: Method, url: &str, headers: Option<HeaderMap>) -> Result<T> {
+        let request = HttpRequest {
+            method,
+            url: url.to_string(),
+            headers: headers.unwrap_or_default(),
+            body: None,
+        };
+        let response = self.transport.send(request).await?;
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            rate_limiter.update_from_headers(&response.headers);
+        }
+
+        self.handler(response)
     }
 
     // Request must be signed
-    fn sign_request(&self, endpoint: &str, request: &str, secret_key: Option<String>) -> String {
-        let secret = match secret_key {
-            Some(secret_key) => secret_key,
-            None => self.secret_key.clone(),
+    fn sign_request(&self, endpoint: &str, request: &str, secret_key: Option<&str>) -> Result<String> {
+        let signature = match secret_key {
+            Some(secret_key) => RequestSigner::new(self.signature_type, secret_key)?.sign_url_encoded(request)?,
+            None => self
+                .signer
+                .as_ref()
+                .map_err(|e| Error::InvalidSigningKey(e.clone()))?
+                .sign_url_encoded(request)?,
         };
-        let signed_key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
-        let signature = hex_encode(hmac::sign(&signed_key, request.as_bytes()).as_ref());
         let url = format!("{}{}?{}&signature={}", self.host, endpoint, request, signature);
 
-        url
+        Ok(url)
     }
 
     fn build_headers(&self, content_type: bool) -> Result<HeaderMap> {
@@ -227,44 +438,180 @@ impl Client {
         Ok(header)
     }
 
-    async fn handler<T: de::DeserializeOwned>(&self, response: Response) -> Result<T> {
-        match response.status() {
-            StatusCode::OK => {
-                let text = match response.text().await {
-                    Ok(text) => text,
-                    Err(error) => return Err(Error::Msg(format!("Failed to read OK response text: {error:?}")))
-                };
-                serde_json::from_str::<T>(&text).map_err(|error| {
-                    Error::Msg(format!("Failed to JSON parse OK response: {error:?}. Raw response: {text}"))
-                })
-            },
+    fn handler<T: de::DeserializeOwned>(&self, response: HttpResponse) -> Result<T> {
+        match response.status {
+            StatusCode::OK => serde_json::from_str::<T>(&response.body).map_err(|error| {
+                Error::Msg(format!("Failed to JSON parse OK response: {error:?}. Raw response: {}", response.body))
+            }),
             StatusCode::INTERNAL_SERVER_ERROR => Err(Error::InternalServerError),
             StatusCode::SERVICE_UNAVAILABLE => Err(Error::ServiceUnavailable),
             StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::IM_A_TEAPOT => {
+                let retry_after = retry_after(&response.headers);
+                if let (Some(rate_limiter), Some(retry_after)) = (&self.rate_limiter, retry_after) {
+                    rate_limiter.block_for(retry_after);
+                }
+                if response.status == StatusCode::IM_A_TEAPOT {
+                    Err(Error::IpBanned { retry_after })
+                } else {
+                    Err(Error::TooManyRequests { retry_after })
+                }
+            }
             StatusCode::BAD_REQUEST => {
-                let text = match response.text().await {
-                    Ok(text) => text,
-                    Err(error) => return Err(Error::Msg(format!("Failed to read BAD REQUEST response text: {error:?}")))
-                };
-                let error: BinanceContentError = match serde_json::from_str::<BinanceContentError>(&text) {
+                let error: BinanceContentError = match serde_json::from_str::<BinanceContentError>(&response.body) {
                     Ok(error) => error,
-                    Err(parsing_error) => return Err(Error::Msg(format!("Failed to parse BAD REQUEST response: {parsing_error:?}. Raw response: {text}")))
+                    Err(parsing_error) => return Err(Error::Msg(format!("Failed to parse BAD REQUEST response: {parsing_error:?}. Raw response: {}", response.body)))
                 };
                 Err(handle_content_error(error))
             },
-            s => {
-                let debugged_response = format!("{response:?}");
-                let text = response.text().await.unwrap_or_else(|error| format!("Failed to read response text: {error:?}"));
-                Err(Error::Msg(format!("Received unexpected response status: {s:?}. Response obj: {debugged_response:?}. Text: {text}")))
-            },
+            s => Err(Error::Msg(format!("Received unexpected response status: {s:?}. Headers: {:?}. Text: {}", response.headers, response.body))),
         }
     }
 }
 
 fn handle_content_error(error: BinanceContentError) -> crate::errors::Error {
-    match (error.code, error.msg.as_ref()) {
-        (-1013, error_messages::INVALID_PRICE) => Error::InvalidPrice,
-        (-1125, msg) => Error::InvalidListenKey(msg.to_string()),
+    match (error.error_code(), error.msg.as_ref()) {
+        (BinanceErrorCode::InvalidMessage, error_messages::INVALID_PRICE) => Error::InvalidPrice,
+        (BinanceErrorCode::InvalidListenKey, msg) => Error::InvalidListenKey(msg.to_string()),
         _ => Error::BinanceError { response: error },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::general::General;
+    use crate::rate_limit::RateLimitMode;
+    use crate::transport::MemoryTransport;
+
+    use super::*;
+
+    #[test]
+    fn config_time_sync_is_attached_to_the_client() {
+        let config = Config::default().set_time_sync(Duration::from_secs(60));
+        let client = Client::new_with_config(None, None, config.rest_api_endpoint.clone(), &config);
+        assert!(client.time_sync.is_some());
+        let client = Client::new_with_config(None, None, config.rest_api_endpoint.clone(), &Config::default());
+        assert!(client.time_sync.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_timestamps_are_resent_with_the_resynced_server_time() {
+        let now = get_timestamp().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        let server_time = |offset: u64| HttpResponse::ok(format!(r#"{{"serverTime":{}}}"#, now + offset));
+        transport.respond_once(Method::GET, "/api/v3/time", server_time(3_600_000));
+        transport.respond(Method::GET, "/api/v3/time", server_time(7_200_000));
+        transport.respond_once(
+            Method::GET,
+            "/api/v3/account",
+            HttpResponse::new(
+                StatusCode::BAD_REQUEST,
+                r#"{"code":-1021,"msg":"Timestamp for this request is outside of the recvWindow."}"#,
+            ),
+        );
+        transport.respond(Method::GET, "/api/v3/account", HttpResponse::ok("{}"));
+        let client = |api_key: Option<String>| {
+            Client::new(api_key, Some("secret".to_string()), "https://api.binance.com".to_string(), None)
+                .with_transport(transport.clone())
+        };
+        let time_sync = TimeSync::new(
+            General { client: client(None) },
+            Duration::from_secs(3600),
+        );
+        let client = client(Some("key".to_string())).with_time_sync(time_sync);
+
+        let _: serde_json::Value = client
+            .get_signed_p("/api/v3/account", None::<()>, 5000)
+            .await
+            .unwrap();
+
+        assert_eq!(transport.requests_to(Method::GET, "/api/v3/time").len(), 2);
+        let sent = transport.requests_to(Method::GET, "/api/v3/account");
+        assert_eq!(sent.len(), 2);
+        let timestamps: Vec<u64> = sent
+            .iter()
+            .map(|request| request.get_query_param("timestamp").unwrap().parse().unwrap())
+            .collect();
+        // Each attempt is built with the offset measured before it, not the local clock
+        assert!(timestamps[0] >= now + 3_600_000 && timestamps[0] < now + 3_660_000);
+        assert!(timestamps[1] >= now + 7_200_000 && timestamps[1] < now + 7_260_000);
+        // The retry is signed again rather than reusing the first signature
+        assert_ne!(
+            sent[0].get_query_param("signature"),
+            sent[1].get_query_param("signature")
+        );
+    }
+
+    fn rate_limited_client(transport: &Arc<MemoryTransport>, mode: RateLimitMode) -> Client {
+        Client::new(
+            Some("key".to_string()),
+            Some("secret".to_string()),
+            "https://api.binance.com".to_string(),
+            None,
+        )
+        .with_transport(transport.clone())
+        .with_rate_limiter(RateLimiter::new(mode))
+    }
+
+    #[tokio::test]
+    async fn rate_limit_responses_map_to_typed_errors() {
+        let transport = Arc::new(MemoryTransport::new());
+        let too_many_requests = HttpResponse::new(StatusCode::TOO_MANY_REQUESTS, "")
+            .with_header("retry-after", "2")
+            .unwrap();
+        transport.respond_once(Method::GET, "/api/v3/account", too_many_requests);
+        let client = rate_limited_client(&transport, RateLimitMode::Reject);
+
+        let error = client
+            .get_signed_p::<serde_json::Value, _>("/api/v3/account", None::<()>, 5000)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::TooManyRequests { retry_after: Some(retry_after) } if retry_after == Duration::from_secs(2)));
+        // Until Retry-After has passed, requests are rejected without being sent
+        let error = client
+            .get_signed_p::<serde_json::Value, _>("/api/v3/account", None::<()>, 5000)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::RateLimitExceeded { .. }));
+        assert_eq!(transport.requests().len(), 1);
+
+        let transport = Arc::new(MemoryTransport::new());
+        let banned = HttpResponse::new(StatusCode::IM_A_TEAPOT, "")
+            .with_header("retry-after", "120")
+            .unwrap();
+        transport.respond(Method::GET, "/api/v3/depth", banned);
+        let client = rate_limited_client(&transport, RateLimitMode::Reject);
+        let error = client
+            .get::<serde_json::Value>("/api/v3/depth", Some("symbol=BTCUSDT"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::IpBanned { retry_after: Some(retry_after) } if retry_after == Duration::from_secs(120)));
+    }
+
+    #[tokio::test]
+    async fn delayed_requests_are_stamped_after_the_wait() {
+        let transport = Arc::new(MemoryTransport::new());
+        let too_many_requests = HttpResponse::new(StatusCode::TOO_MANY_REQUESTS, "")
+            .with_header("retry-after", "1")
+            .unwrap();
+        transport.respond_once(Method::GET, "/api/v3/account", too_many_requests);
+        transport.respond(Method::GET, "/api/v3/account", HttpResponse::ok("{}"));
+        let client = rate_limited_client(&transport, RateLimitMode::Delay);
+
+        let first = client
+            .get_signed_p::<serde_json::Value, _>("/api/v3/account", None::<()>, 5000)
+            .await;
+        assert!(matches!(first, Err(Error::TooManyRequests { .. })));
+        let blocked_at = get_timestamp().unwrap();
+        let _: serde_json::Value = client
+            .get_signed_p("/api/v3/account", None::<()>, 5000)
+            .await
+            .unwrap();
+
+        let sent = transport.requests_to(Method::GET, "/api/v3/account");
+        let timestamp: u64 = sent[1].get_query_param("timestamp").unwrap().parse().unwrap();
+        assert!(timestamp >= blocked_at + 900, "{timestamp} was stamped before waiting");
+    }
+}
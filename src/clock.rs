@@ -0,0 +1,107 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// Clock behind `util::get_timestamp` and the components not given a clock of their own
+static GLOBAL_CLOCK: Lazy<RwLock<Arc<dyn Clock>>> = Lazy::new(|| RwLock::new(Arc::new(SystemClock)));
+
+/// Source of the current time for request timestamps, order tracking and rule periods
+pub trait Clock: Send + Sync + Debug {
+    /// Nanoseconds since the epoch
+    fn now_nanos(&self) -> u64;
+
+    /// Milliseconds since the epoch
+    fn now_millis(&self) -> u64 { self.now_nanos() / NANOS_PER_MILLI }
+}
+
+/// The local system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_nanos(&self) -> u64 {
+        // A system clock before the epoch is reported as the epoch
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or_default()
+    }
+}
+
+/// Another clock shifted by an offset that can be changed at any time, e.g. to follow the
+/// exchange clock
+#[derive(Debug)]
+pub struct OffsetClock {
+    inner: Arc<dyn Clock>,
+    offset_nanos: AtomicI64,
+}
+
+impl OffsetClock {
+    pub fn new(inner: Arc<dyn Clock>, offset_nanos: i64) -> Self {
+        Self {
+            inner,
+            offset_nanos: AtomicI64::new(offset_nanos),
+        }
+    }
+
+    /// The system clock shifted by `offset_millis`
+    pub fn system(offset_millis: i64) -> Self { Self::new(Arc::new(SystemClock), offset_millis * NANOS_PER_MILLI as i64) }
+
+    pub fn get_offset_nanos(&self) -> i64 { self.offset_nanos.load(Ordering::Relaxed) }
+
+    pub fn set_offset_nanos(&self, offset_nanos: i64) { self.offset_nanos.store(offset_nanos, Ordering::Relaxed) }
+}
+
+impl Clock for OffsetClock {
+    fn now_nanos(&self) -> u64 { self.inner.now_nanos().saturating_add_signed(self.get_offset_nanos()) }
+}
+
+/// Clock that only moves when told to, for tests and simulations
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start_nanos: u64) -> Self {
+        Self {
+            nanos: AtomicU64::new(start_nanos),
+        }
+    }
+
+    pub fn set_nanos(&self, nanos: u64) { self.nanos.store(nanos, Ordering::Relaxed) }
+
+    pub fn advance(&self, duration: Duration) { self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed); }
+}
+
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u64 { self.nanos.load(Ordering::Relaxed) }
+}
+
+/// The clock of the process, the system clock unless replaced with `set_global_clock`
+pub fn global_clock() -> Arc<dyn Clock> { GLOBAL_CLOCK.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone() }
+
+/// Replaces the clock of the process, used by `util::get_timestamp` and by the clients, order
+/// trackers and guards not given a clock of their own
+pub fn set_global_clock(clock: Arc<dyn Clock>) { *GLOBAL_CLOCK.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = clock; }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offset_and_manual_clocks() {
+        let manual = Arc::new(ManualClock::new(10 * NANOS_PER_MILLI));
+        manual.advance(Duration::from_millis(5));
+        assert_eq!(manual.now_millis(), 15);
+
+        let offset = OffsetClock::new(manual.clone(), -20 * NANOS_PER_MILLI as i64);
+        assert_eq!(offset.now_nanos(), 0);
+        offset.set_offset_nanos(1);
+        manual.set_nanos(100);
+        assert_eq!(offset.now_nanos(), 101);
+        assert!(OffsetClock::system(1_000).now_millis() > SystemClock.now_millis());
+    }
+}
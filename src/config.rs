@@ -1,3 +1,9 @@
+use std::time::Duration;
+
+use crate::rate_limit::RateLimitMode;
+use crate::retry::RetryPolicy;
+use crate::signer::SignatureType;
+
 pub static DATA_REST_ENDPOINT: &str = "https://api.binance.com";
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,6 +19,18 @@ pub struct Config {
     pub binance_us_api: bool,
 
     pub timeout: Option<u64>,
+
+    #[serde(default)]
+    pub signature_type: SignatureType,
+
+    #[serde(default)]
+    pub rate_limit_mode: RateLimitMode,
+
+    #[serde(default)]
+    pub retry_policy: Option<RetryPolicy>,
+
+    #[serde(default)]
+    pub time_sync_interval: Option<Duration>,
 }
 
 impl Config {
@@ -150,6 +168,96 @@ impl Config {
         self.timeout = Some(timeout);
         self
     }
+
+    /// Sets how the secret key is interpreted when signing requests. Defaults to HMAC-SHA256,
+    /// with Ed25519 and RSA the secret key is the PEM encoded private key.
+    ///
+    /// # Arguments
+    ///
+    /// * `signature_type`: The type of the API key
+    ///
+    /// returns: Config
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binance::config::Config;
+    /// use binance::signer::SignatureType;
+    /// let config = Config::default();
+    /// config.set_signature_type(SignatureType::Ed25519);
+    /// ```
+    pub fn set_signature_type(mut self, signature_type: SignatureType) -> Self {
+        self.signature_type = signature_type;
+        self
+    }
+
+    /// Sets what happens to requests that would exceed the request weight or order rate limits.
+    /// Defaults to disabled, otherwise clients of the same host share the request weight and
+    /// clients of the same API key the order count.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate_limit_mode`: Delay or reject requests over the limit
+    ///
+    /// returns: Config
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binance::config::Config;
+    /// use binance::rate_limit::RateLimitMode;
+    /// let config = Config::default();
+    /// config.set_rate_limit_mode(RateLimitMode::Delay);
+    /// ```
+    pub fn set_rate_limit_mode(mut self, rate_limit_mode: RateLimitMode) -> Self {
+        self.rate_limit_mode = rate_limit_mode;
+        self
+    }
+
+    /// Sets the retry policy for transient failures. GET requests are retried, orders only when
+    /// they have a client order id. Defaults to no retries.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_policy`: Attempts and backoff between them
+    ///
+    /// returns: Config
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binance::config::Config;
+    /// use binance::retry::RetryPolicy;
+    /// let config = Config::default();
+    /// config.set_retry_policy(RetryPolicy::default());
+    /// ```
+    pub fn set_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Stamps signed requests with the server time instead of the local clock. The offset to the
+    /// server clock is measured on the first signed request and again once older than
+    /// `refresh_interval`. Defaults to the local clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `refresh_interval`: How long a measured offset is used for
+    ///
+    /// returns: Config
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use binance::config::Config;
+    /// let config = Config::default();
+    /// config.set_time_sync(Duration::from_secs(300));
+    /// ```
+    pub fn set_time_sync(mut self, refresh_interval: Duration) -> Self {
+        self.time_sync_interval = Some(refresh_interval);
+        self
+    }
 }
 
 impl Default for Config {
@@ -171,6 +279,12 @@ impl Default for Config {
             binance_us_api: false,
 
             timeout: None,
+
+            signature_type: SignatureType::Hmac,
+
+            rate_limit_mode: RateLimitMode::Disabled,
+            retry_policy: None,
+            time_sync_interval: None,
         }
     }
 }
@@ -0,0 +1,401 @@
+//! Error codes returned by Binance, see
+//! <https://developers.binance.com/docs/binance-spot-api-docs/errors> and
+//! <https://developers.binance.com/docs/derivatives/usds-margined-futures/error-code>
+//!
+//! A few codes have a different meaning on spot and futures, variants are named after the
+//! futures meaning and document the spot one.
+
+use std::fmt;
+
+macro_rules! binance_error_codes {
+    ($($(#[$meta:meta])* $name:ident = $code:literal,)+) => {
+        /// Typed Binance error code, codes unknown to this library are kept in `Other`
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(from = "i32", into = "i32")]
+        pub enum BinanceErrorCode {
+            $($(#[$meta])* $name,)+
+            Other(i32),
+        }
+
+        impl BinanceErrorCode {
+            pub fn from_code(code: i32) -> Self {
+                match code {
+                    $($code => Self::$name,)+
+                    other => Self::Other(other),
+                }
+            }
+
+            pub fn code(&self) -> i32 {
+                match self {
+                    $(Self::$name => $code,)+
+                    Self::Other(code) => *code,
+                }
+            }
+        }
+    };
+}
+
+binance_error_codes! {
+    // 10xx - General server or network issues
+    Unknown = -1000,
+    Disconnected = -1001,
+    Unauthorized = -1002,
+    TooManyRequests = -1003,
+    /// Spot: server is busy, futures: duplicate IP
+    ServerBusy = -1004,
+    NoSuchIp = -1005,
+    UnexpectedResponse = -1006,
+    /// The request timed out, its execution status is unknown
+    Timeout = -1007,
+    /// Server is overloaded or the request was throttled by system level protection
+    ServerOverloaded = -1008,
+    ErrorMessageReceived = -1010,
+    NonWhiteList = -1011,
+    /// Filter failures, e.g. invalid price or lot size
+    InvalidMessage = -1013,
+    UnknownOrderComposition = -1014,
+    TooManyOrders = -1015,
+    ServiceShuttingDown = -1016,
+    UnsupportedOperation = -1020,
+    /// Timestamp for this request is outside of the recvWindow
+    InvalidTimestamp = -1021,
+    InvalidSignature = -1022,
+    StartTimeGreaterThanEndTime = -1023,
+    TooManyConnections = -1034,
+    // 11xx - Request issues
+    NotFound = -1099,
+    IllegalChars = -1100,
+    TooManyParameters = -1101,
+    MandatoryParamEmptyOrMalformed = -1102,
+    UnknownParam = -1103,
+    UnreadParameters = -1104,
+    ParamEmpty = -1105,
+    ParamNotRequired = -1106,
+    /// Spot: parameter overflow, futures: invalid asset
+    BadAsset = -1108,
+    BadAccount = -1109,
+    BadInstrumentType = -1110,
+    BadPrecision = -1111,
+    NoDepth = -1112,
+    WithdrawNotNegative = -1113,
+    TimeInForceNotRequired = -1114,
+    InvalidTimeInForce = -1115,
+    InvalidOrderType = -1116,
+    InvalidSide = -1117,
+    EmptyNewClientOrderId = -1118,
+    EmptyOriginalClientOrderId = -1119,
+    BadInterval = -1120,
+    BadSymbol = -1121,
+    InvalidSymbolStatus = -1122,
+    InvalidListenKey = -1125,
+    AssetNotSupported = -1126,
+    MoreThanXxHours = -1127,
+    OptionalParamsBadCombo = -1128,
+    InvalidParameter = -1130,
+    BadRecvWindow = -1131,
+    BadStrategyType = -1134,
+    InvalidJson = -1135,
+    InvalidNewOrderResponseType = -1136,
+    InvalidTickerType = -1139,
+    InvalidCancelRestrictions = -1145,
+    DuplicateSymbols = -1151,
+    InvalidSbeHeader = -1152,
+    UnsupportedSchemaId = -1153,
+    SbeDisabled = -1155,
+    OcoOrderTypeRejected = -1158,
+    OcoIcebergQtyTimeInForce = -1160,
+    DeprecatedSchema = -1161,
+    BuyOcoLimitMustBeBelow = -1165,
+    SellOcoLimitMustBeAbove = -1166,
+    BothOcoOrdersCannotBeLimit = -1168,
+    InvalidTagNumber = -1169,
+    TagNotDefinedInMessage = -1170,
+    TagAppearsMoreThanOnce = -1171,
+    TagOutOfOrder = -1172,
+    GroupFieldsOutOfOrder = -1173,
+    InvalidComponent = -1174,
+    ResetSeqNumSupport = -1175,
+    AlreadyLoggedIn = -1176,
+    GarbledMessage = -1177,
+    BadSenderCompId = -1178,
+    BadSeqNum = -1179,
+    ExpectedLogon = -1180,
+    TooManyMessages = -1181,
+    ParamsBadCombo = -1182,
+    NotAllowedInDropCopySessions = -1183,
+    DropCopySessionNotAllowed = -1184,
+    DropCopySessionRequired = -1185,
+    NotAllowedInOrderEntrySessions = -1186,
+    NotAllowedInMarketDataSessions = -1187,
+    IncorrectNumInGroupCount = -1188,
+    DuplicateEntriesInAGroup = -1189,
+    InvalidRequestId = -1190,
+    TooManySubscriptions = -1191,
+    InvalidTimeUnit = -1194,
+    BuyOcoStopLossMustBeAbove = -1196,
+    SellOcoStopLossMustBeBelow = -1197,
+    BuyOcoTakeProfitMustBeBelow = -1198,
+    SellOcoTakeProfitMustBeAbove = -1199,
+    // 20xx - Processing issues
+    /// Spot orders rejected for insufficient balance also use this code, check the message
+    NewOrderRejected = -2010,
+    CancelRejected = -2011,
+    CancelAllFail = -2012,
+    NoSuchOrder = -2013,
+    BadApiKeyFormat = -2014,
+    RejectedApiKey = -2015,
+    NoTradingWindow = -2016,
+    BalanceNotSufficient = -2018,
+    MarginNotSufficient = -2019,
+    UnableToFill = -2020,
+    /// Spot: cancel-replace partially failed
+    OrderWouldImmediatelyTrigger = -2021,
+    /// Spot: cancel-replace failed
+    ReduceOnlyReject = -2022,
+    UserInLiquidation = -2023,
+    PositionNotSufficient = -2024,
+    MaxOpenOrderExceeded = -2025,
+    /// Spot: order archived
+    ReduceOnlyOrderTypeNotSupported = -2026,
+    MaxLeverageRatio = -2027,
+    MinLeverageRatio = -2028,
+    // 40xx - Futures filters and other issues
+    InvalidOrderStatus = -4000,
+    PriceLessThanZero = -4001,
+    PriceGreaterThanMaxPrice = -4002,
+    QtyLessThanZero = -4003,
+    QtyLessThanMinQty = -4004,
+    QtyGreaterThanMaxQty = -4005,
+    StopPriceLessThanZero = -4006,
+    StopPriceGreaterThanMaxPrice = -4007,
+    TickSizeLessThanZero = -4008,
+    MaxPriceLessThanMinPrice = -4009,
+    MaxQtyLessThanMinQty = -4010,
+    StepSizeLessThanZero = -4011,
+    MaxNumOrdersLessThanZero = -4012,
+    PriceLessThanMinPrice = -4013,
+    PriceNotIncreasedByTickSize = -4014,
+    InvalidClientOrderIdLen = -4015,
+    PriceHigherThanMultiplierUp = -4016,
+    MultiplierUpLessThanZero = -4017,
+    MultiplierDownLessThanZero = -4018,
+    CompositeScaleOverflow = -4019,
+    TargetStrategyInvalid = -4020,
+    InvalidDepthLimit = -4021,
+    WrongMarketStatus = -4022,
+    QtyNotIncreasedByStepSize = -4023,
+    PriceLowerThanMultiplierDown = -4024,
+    MultiplierDecimalLessThanZero = -4025,
+    CommissionInvalid = -4026,
+    InvalidAccountType = -4027,
+    InvalidLeverage = -4028,
+    InvalidTickSizePrecision = -4029,
+    InvalidStepSizePrecision = -4030,
+    InvalidWorkingType = -4031,
+    ExceedMaxCancelOrderSize = -4032,
+    InsuranceAccountNotFound = -4033,
+    InvalidBalanceType = -4044,
+    MaxStopOrderExceeded = -4045,
+    NoNeedToChangeMarginType = -4046,
+    ThereExistsOpenOrders = -4047,
+    ThereExistsQuantity = -4048,
+    AddIsolatedMarginReject = -4049,
+    CrossBalanceInsufficient = -4050,
+    IsolatedBalanceInsufficient = -4051,
+    NoNeedToChangeAutoAddMargin = -4052,
+    AutoAddCrossedMarginReject = -4053,
+    AddIsolatedMarginNoPositionReject = -4054,
+    AmountMustBePositive = -4055,
+    InvalidApiKeyType = -4056,
+    InvalidRsaPublicKey = -4057,
+    MaxPriceTooLarge = -4058,
+    NoNeedToChangePositionSide = -4059,
+    InvalidPositionSide = -4060,
+    PositionSideNotMatch = -4061,
+    ReduceOnlyConflict = -4062,
+    InvalidOptionsRequestType = -4063,
+    InvalidOptionsTimeFrame = -4064,
+    InvalidOptionsAmount = -4065,
+    InvalidOptionsEventType = -4066,
+    PositionSideChangeExistsOpenOrders = -4067,
+    PositionSideChangeExistsQuantity = -4068,
+    InvalidOptionsPremiumFee = -4069,
+    InvalidClientOptionsIdLen = -4070,
+    InvalidOptionsDirection = -4071,
+    OptionsPremiumNotUpdate = -4072,
+    OptionsPremiumInputLessThanZero = -4073,
+    OptionsAmountBiggerThanUpper = -4074,
+    OptionsPremiumOutputZero = -4075,
+    OptionsPremiumTooDiff = -4076,
+    OptionsPremiumReachLimit = -4077,
+    OptionsCommonError = -4078,
+    InvalidOptionsId = -4079,
+    OptionsUserNotFound = -4080,
+    OptionsNotFound = -4081,
+    InvalidBatchPlaceOrderSize = -4082,
+    PlaceBatchOrdersFail = -4083,
+    UpcomingMethod = -4084,
+    InvalidNotionalLimitCoef = -4085,
+    InvalidPriceSpreadThreshold = -4086,
+    ReduceOnlyOrderPermission = -4087,
+    NoPlaceOrderPermission = -4088,
+    InvalidContractType = -4104,
+    InvalidClientTranIdLen = -4114,
+    DuplicatedClientTranId = -4115,
+    ReduceOnlyMarginCheckFailed = -4118,
+    MarketOrderReject = -4131,
+    InvalidActivationPrice = -4135,
+    QuantityExistsWithClosePosition = -4137,
+    ReduceOnlyMustBeTrue = -4138,
+    OrderTypeCannotBeMarket = -4139,
+    InvalidOpeningPositionStatus = -4140,
+    SymbolAlreadyClosed = -4141,
+    StrategyInvalidTriggerPrice = -4142,
+    InvalidPair = -4144,
+    IsolatedLeverageRejectWithPosition = -4161,
+    MinNotional = -4164,
+    InvalidTimeInterval = -4165,
+    IsolatedRejectWithJointMargin = -4167,
+    JointMarginRejectWithIsolated = -4168,
+    JointMarginRejectWithMb = -4169,
+    JointMarginRejectWithOpenOrder = -4170,
+    NoNeedToChangeJointMargin = -4171,
+    JointMarginRejectWithNegativeBalance = -4172,
+    PriceHigherThanStopMultiplierUp = -4183,
+    PriceLowerThanStopMultiplierDown = -4184,
+    CoolingOffPeriod = -4192,
+    AdjustLeverageKycFailed = -4202,
+    AdjustLeverageOneMonthFailed = -4203,
+    AdjustLeverageXDaysFailed = -4205,
+    AdjustLeverageKycLimit = -4206,
+    AdjustLeverageAccountSymbolFailed = -4208,
+    AdjustLeverageSymbolFailed = -4209,
+    StopPriceHigherThanPriceMultiplierLimit = -4210,
+    StopPriceLowerThanPriceMultiplierLimit = -4211,
+    TradingQuantitativeRule = -4400,
+    LargePositionSymbolRule = -4401,
+    ComplianceBlackSymbolRestriction = -4402,
+    AdjustLeverageComplianceFailed = -4403,
+    // 50xx - Futures order execution issues
+    FokOrderReject = -5021,
+    /// The post only order would have been executed as taker
+    GtxOrderReject = -5022,
+    MoveOrderNotAllowedSymbolReason = -5024,
+    LimitOrderOnly = -5025,
+    ExceedMaximumModifyOrderLimit = -5026,
+    SameOrder = -5027,
+    MatchingEngineRecvWindowReject = -5028,
+    InvalidPriceMatch = -5037,
+    UnsupportedOrderTypePriceMatch = -5038,
+    InvalidSelfTradePreventionMode = -5039,
+    FutureGoodTillDate = -5040,
+    BboOrderReject = -5041,
+}
+
+impl BinanceErrorCode {
+    /// Transient server side failures, the same request may succeed later
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Unknown
+                | Self::Disconnected
+                | Self::UnexpectedResponse
+                | Self::Timeout
+                | Self::ServerOverloaded
+                | Self::MatchingEngineRecvWindowReject
+        )
+    }
+
+    /// Request or order rate limits were exceeded
+    pub fn is_rate_limit(&self) -> bool {
+        matches!(self, Self::TooManyRequests | Self::TooManyOrders | Self::TooManyConnections)
+    }
+
+    /// The account cannot afford the order. Spot rejections use [`BinanceErrorCode::NewOrderRejected`]
+    /// for this too, which can only be told apart by the message.
+    pub fn is_insufficient_balance(&self) -> bool {
+        matches!(
+            self,
+            Self::BalanceNotSufficient
+                | Self::MarginNotSufficient
+                | Self::CrossBalanceInsufficient
+                | Self::IsolatedBalanceInsufficient
+        )
+    }
+
+    /// A stop or take profit order was rejected because its trigger price is already reached
+    pub fn is_order_would_trigger_immediately(&self) -> bool { matches!(self, Self::OrderWouldImmediatelyTrigger) }
+
+    /// The request timestamp was outside of the receive window, the local clock is likely off
+    pub fn is_timestamp_error(&self) -> bool {
+        matches!(self, Self::InvalidTimestamp | Self::MatchingEngineRecvWindowReject)
+    }
+
+    /// The order was rejected by a symbol filter such as price, lot size or notional
+    pub fn is_filter_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidMessage
+                | Self::PriceLessThanZero
+                | Self::PriceGreaterThanMaxPrice
+                | Self::QtyLessThanZero
+                | Self::QtyLessThanMinQty
+                | Self::QtyGreaterThanMaxQty
+                | Self::StopPriceLessThanZero
+                | Self::StopPriceGreaterThanMaxPrice
+                | Self::PriceLessThanMinPrice
+                | Self::PriceNotIncreasedByTickSize
+                | Self::PriceHigherThanMultiplierUp
+                | Self::QtyNotIncreasedByStepSize
+                | Self::PriceLowerThanMultiplierDown
+                | Self::MinNotional
+        )
+    }
+}
+
+impl From<i32> for BinanceErrorCode {
+    fn from(code: i32) -> Self { Self::from_code(code) }
+}
+
+impl From<BinanceErrorCode> for i32 {
+    fn from(code: BinanceErrorCode) -> Self { code.code() }
+}
+
+impl fmt::Display for BinanceErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(code) => write!(f, "{code}"),
+            known => write!(f, "{known:?} ({})", known.code()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in -5100..=-1000 {
+            assert_eq!(BinanceErrorCode::from_code(code).code(), code);
+        }
+        assert_eq!(BinanceErrorCode::from_code(-2021), BinanceErrorCode::OrderWouldImmediatelyTrigger);
+        assert_eq!(BinanceErrorCode::from_code(-9999), BinanceErrorCode::Other(-9999));
+        assert_eq!(serde_json::to_string(&BinanceErrorCode::NoSuchOrder).unwrap(), "-2013");
+        assert_eq!(
+            serde_json::from_str::<BinanceErrorCode>("-4164").unwrap(),
+            BinanceErrorCode::MinNotional
+        );
+    }
+
+    #[test]
+    fn classification() {
+        assert!(BinanceErrorCode::Timeout.is_retryable());
+        assert!(!BinanceErrorCode::NewOrderRejected.is_retryable());
+        assert!(BinanceErrorCode::TooManyOrders.is_rate_limit());
+        assert!(BinanceErrorCode::MarginNotSufficient.is_insufficient_balance());
+        assert!(BinanceErrorCode::OrderWouldImmediatelyTrigger.is_order_would_trigger_immediately());
+        assert!(BinanceErrorCode::PriceNotIncreasedByTickSize.is_filter_failure());
+        assert!(!BinanceErrorCode::InvalidOrderStatus.is_filter_failure());
+    }
+}
@@ -1,7 +1,10 @@
 use serde_json::Value;
 use std::collections::HashMap;
+use std::time::Duration;
 use thiserror::Error;
 
+pub use crate::error_codes::BinanceErrorCode;
+
 #[derive(Debug, Deserialize, Error)]
 #[error("code: {code}, msg: {msg}")]
 pub struct BinanceContentError {
@@ -12,6 +15,18 @@ pub struct BinanceContentError {
     extra: HashMap<String, Value>,
 }
 
+impl BinanceContentError {
+    pub fn new(code: BinanceErrorCode, msg: impl Into<String>) -> Self {
+        Self {
+            code: code.code(),
+            msg: msg.into(),
+            extra: HashMap::new(),
+        }
+    }
+
+    pub fn error_code(&self) -> BinanceErrorCode { BinanceErrorCode::from_code(self.code) }
+}
+
 /// First errors are technical errors
 /// All unhandled binance content errors are BinanceError
 /// The rest are binance content errors that are properly handled
@@ -43,6 +58,8 @@ pub enum Error {
         #[from]
         response: BinanceContentError,
     },
+    #[error("invalid signing key: {0}")]
+    InvalidSigningKey(String),
     #[error("invalid listen key : {0}")]
     InvalidListenKey(String),
     #[error("unknown symbol {0}")]
@@ -59,6 +76,12 @@ pub enum Error {
     ServiceUnavailable,
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("too many requests, retry after {retry_after:?}")]
+    TooManyRequests { retry_after: Option<Duration> },
+    #[error("IP banned for exceeding rate limits, retry after {retry_after:?}")]
+    IpBanned { retry_after: Option<Duration> },
+    #[error("{limit} rate limit would be exceeded, retry after {retry_after:?}")]
+    RateLimitExceeded { limit: String, retry_after: Duration },
     #[error("{0}")]
     Msg(String),
     #[error("ExpectedOrdersRuleViolated: {0}")]
@@ -72,6 +95,16 @@ impl Error {
             _ => format!("{self:?}"),
         }
     }
+
+    /// The Binance error code behind this error, if it was returned by the exchange
+    pub fn binance_error_code(&self) -> Option<BinanceErrorCode> {
+        match self {
+            Self::BinanceError { response } => Some(response.error_code()),
+            Self::InvalidPrice => Some(BinanceErrorCode::InvalidMessage),
+            Self::InvalidListenKey(_) => Some(BinanceErrorCode::InvalidListenKey),
+            _ => None,
+        }
+    }
 }
 
 /// Custom error messages
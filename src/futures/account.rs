@@ -1,5 +1,5 @@
-use std::collections::{BTreeMap, HashMap};
-use super::rest_model::{AccountBalance, AccountInformation, AccountInformationV3, AccountTrade, CanceledOrderResponse, Order, Position, PositionSide, PositionV3, SupportedOrderType, Symbol, Transaction, WorkingType};
+use std::collections::HashMap;
+use super::rest_model::{AccountBalance, AccountInformation, AccountInformationV3, AccountTrade, CanceledOrderResponse, HistoryQuery, Order, Position, PositionSide, PositionV3, SupportedOrderType, Symbol, Transaction, WorkingType};
 use crate::account::{OrderCancellation, OrderCancellationWithU64};
 use crate::client::Client;
 use crate::errors::*;
@@ -8,15 +8,26 @@ use crate::rest_model::{PairAndWindowQuery, PairQuery};
 use crate::util::*;
 use serde::Serializer;
 use std::fmt;
-use anyhow::anyhow;
 use rust_decimal::Decimal;
-use crate::futures::utils::expected_order_requests::rules_map::validate_order_request;
-use crate::futures::utils::order_tracker::{add_order_tracking_item, remove_order_tracking_item};
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+use crate::futures::utils::order_guard::OrderGuard;
+use crate::futures::utils::order_tracking_request::{OrderMarket, OrderTrackingRequest};
+use crate::futures::utils::order_tracker_bootstrap::{history_entries, next_page_from_id, HISTORY_PAGE_LIMIT, HISTORY_WINDOW_MILLIS};
+use crate::futures::utils::order_journal::{OrderJournal, OrderJournalAction, OrderJournalRecord};
+use crate::futures::utils::order_tracking_item::OrderTrackingOutcome;
+use crate::transport::HttpTransport;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct FuturesAccount {
     pub client: Client,
     pub recv_window: u64,
+    /// Tracker and rules orders are checked against, `OrderGuard::global()` by default
+    pub order_guard: OrderGuard,
+    /// Where order placements and cancellations are recorded, if anywhere
+    pub order_journal: Option<Arc<dyn OrderJournal>>,
+    /// Whether requests are journaled before being sent and fail unsent when the journal fails
+    pub order_journal_required: bool,
 }
 
 /// Serialize bool as str
@@ -71,6 +82,56 @@ pub struct OrderRequest {
     #[serde(serialize_with = "serialize_opt_as_uppercase")]
     pub price_protect: Option<bool>,
     pub new_client_order_id: Option<String>,
+    /// Grid or strategy the order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing)]
+    pub grid_id: Option<String>,
+}
+
+impl From<&OrderRequest> for OrderTrackingRequest {
+    fn from(order: &OrderRequest) -> Self {
+        Self {
+            market: OrderMarket::Futures,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            stop_price: order.stop_price,
+            quote_order_qty: None,
+            grid_id: order.grid_id.clone(),
+            client_order_id: order.new_client_order_id.clone(),
+        }
+    }
+}
+
+/// Fields of an order sent as JSON that the order guard checks
+#[cfg(feature = "backtest")]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonOrderRequest {
+    symbol: String,
+    side: OrderSide,
+    quantity: Option<Decimal>,
+    price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    new_client_order_id: Option<String>,
+    grid_id: Option<String>,
+}
+
+#[cfg(feature = "backtest")]
+impl From<&JsonOrderRequest> for OrderTrackingRequest {
+    fn from(order: &JsonOrderRequest) -> Self {
+        Self {
+            market: OrderMarket::Futures,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            stop_price: order.stop_price,
+            quote_order_qty: None,
+            grid_id: order.grid_id.clone(),
+            client_order_id: order.new_client_order_id.clone(),
+        }
+    }
 }
 
 impl From<OrderRequestMandatoryClientId> for OrderRequest {
@@ -100,6 +161,7 @@ impl From<OrderRequestMandatoryClientId> for OrderRequest {
             working_type: order.working_type,
             price_protect: order.price_protect,
             new_client_order_id: Some(order.new_client_order_id),
+            grid_id: order.grid_id,
         }
     }
 }
@@ -127,6 +189,9 @@ pub struct OrderRequestMandatoryClientId {
     #[serde(serialize_with = "serialize_opt_as_uppercase")]
     pub price_protect: Option<bool>,
     pub new_client_order_id: String,
+    /// Grid or strategy the order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing, default)]
+    pub grid_id: Option<String>,
 }
 
 impl Default for OrderRequestMandatoryClientId {
@@ -148,6 +213,7 @@ impl Default for OrderRequestMandatoryClientId {
             working_type: None,
             price_protect: None,
             new_client_order_id: String::default(),
+            grid_id: None,
         }
     }
 }
@@ -190,6 +256,26 @@ impl OrderRequestMandatoryClientId {
     }
 }
 
+fn history_window_query(symbol: String, start_time: u64, end_time: u64, limit: u16) -> HistoryQuery {
+    HistoryQuery {
+        start_time: Some(start_time),
+        end_time: Some(end_time),
+        from_id: None,
+        limit,
+        symbol,
+        interval: None,
+        period: None,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OrdersFromIdQuery {
+    symbol: String,
+    order_id: u64,
+    limit: u16,
+}
+
 // #[derive(Serialize)]
 // #[serde(rename_all = "camelCase")]
 // struct ChangePositionModeRequest {
@@ -198,6 +284,52 @@ impl OrderRequestMandatoryClientId {
 // }
 
 impl FuturesAccount {
+    /// Checks orders against `order_guard` instead of the default guard
+    pub fn with_order_guard(mut self, order_guard: OrderGuard) -> Self {
+        self.order_guard = order_guard;
+        self
+    }
+
+    /// Records order placements and cancellations to `order_journal`, nothing is recorded by default.
+    /// Failing to journal is logged and does not fail the request.
+    pub fn with_order_journal(mut self, order_journal: Arc<dyn OrderJournal>) -> Self {
+        self.order_journal = Some(order_journal);
+        self.order_journal_required = false;
+        self
+    }
+
+    /// Records order placements and cancellations to `order_journal`, each one first as
+    /// `OrderJournalOutcome::Sending` before it is sent. A request that could not be journaled
+    /// that way fails without being sent.
+    pub fn with_required_order_journal(mut self, order_journal: Arc<dyn OrderJournal>) -> Self {
+        self.order_journal = Some(order_journal);
+        self.order_journal_required = true;
+        self
+    }
+
+    /// Sends requests through `transport`, see `Client::with_transport`
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.client = self.client.with_transport(transport);
+        self
+    }
+
+    fn journal(&self, record: OrderJournalRecord) {
+        let Some(ref order_journal) = self.order_journal else {
+            return;
+        };
+        if let Err(error) = order_journal.append(&record) {
+            eprintln!("failed to journal order record {record:?}: {error:?}")
+        }
+    }
+
+    /// Journals `record` as being sent when the journal is required
+    fn journal_sending(&self, record: &OrderJournalRecord) -> Result<()> {
+        match self.order_journal {
+            Some(ref order_journal) if self.order_journal_required => order_journal.append(&record.sending()),
+            _ => Ok(()),
+        }
+    }
+
     /// Get an order
     pub async fn get_order(&self, order: Option<GetOrderRequest>) -> Result<Order> {
         self.client
@@ -205,78 +337,224 @@ impl FuturesAccount {
             .await
     }
 
+    /// Fetches the mark price and position the rules of the order symbol are checked against, if
+    /// any, and the mark price orders without a price are counted at
+    async fn get_rule_context(&self, order: &OrderTrackingRequest) -> Result<ExpectedOrderRequestsRuleContext> {
+        let symbol = order.symbol.as_str();
+        let mut context = ExpectedOrderRequestsRuleContext::default();
+        if self.order_guard.rules().needs_position(symbol) {
+            let positions = self.position_information_v3(symbol).await?;
+            // Hedge mode returns one position per side, with short amounts negative
+            context.position_amount = Some(positions.iter().map(|position| position.position_amount).sum());
+            context.mark_price = positions.first().map(|position| position.mark_price);
+        }
+        if (self.order_guard.rules().needs_mark_price(symbol) || order.needs_market_price()) && context.mark_price.is_none() {
+            let mark_price: super::rest_model::MarkPrice = self
+                .client
+                .get_d("/fapi/v1/premiumIndex", Some(PairQuery { symbol: symbol.to_string() }))
+                .await?;
+            context.mark_price = Some(mark_price.mark_price);
+        }
+        Ok(context)
+    }
+
     /// Place an order
-    #[cfg(not(feature = "backtest"))]
     pub async fn place_order(&self, order: OrderRequest) -> Result<Transaction> {
-        let top_n_entry = add_order_tracking_item(&order)?;
-        let validated_rules = match validate_order_request(&order.symbol, &top_n_entry) {
-            Ok(rules) => rules,
+        self.place_checked_order(order, None).await
+    }
+
+    pub async fn place_order_with_key(&self, order: OrderRequest, private_key: &str) -> Result<Transaction> {
+        self.place_checked_order(order, Some(private_key)).await
+    }
+
+    /// Checks the order against the rules of `order_guard`, sends it, records the response on the
+    /// tracked order and journals the outcome
+    async fn place_checked_order(&self, order: OrderRequest, private_key: Option<&str>) -> Result<Transaction> {
+        let mut record = OrderJournalRecord::new(OrderJournalAction::Place, &order.symbol, order.new_client_order_id.clone(), &order);
+        let tracking_request = OrderTrackingRequest::from(&order);
+        let checked = match self.get_rule_context(&tracking_request).await {
+            Ok(context) => self.order_guard.check_order_with_context(&tracking_request, &context),
+            Err(error) => Err(error),
+        };
+        record.rules_version = Some(self.order_guard.rules().version());
+        let (tracking_entry, validated_rules) = match checked {
+            Ok(checked) => checked,
             Err(error) => {
-                remove_order_tracking_item(&order.symbol, &top_n_entry);
+                let size = order.quantity.unwrap_or_default();
+                record.evaluated_rules = self.order_guard.rules().get_matching_rules(&order.symbol, &order.side, size);
+                self.journal(record.rejected(&error));
                 return Err(error);
             }
         };
-        if validated_rules.is_empty() {
-            return Err(anyhow!("Expected some validated rule but got none").into());
+        record.tracking_entry = Some(tracking_entry.clone());
+        record.evaluated_rules = validated_rules.to_vec();
+        let symbol = order.symbol.clone();
+        if let Err(error) = self.journal_sending(&record) {
+            // The order is never sent, so it does not count against the rules
+            if let Err(error) = self.order_guard.tracker().record_outcome(&symbol, &tracking_entry.item.id, &OrderTrackingOutcome::rejected()) {
+                eprintln!("failed to record the unsent order {tracking_entry:?} in {symbol} tracker: {error:?}")
+            }
+            self.journal(record.rejected(&error));
+            return Err(error);
         }
-        match self.client
-            .post_signed_p::<Transaction, OrderRequest>("/fapi/v1/order", order, self.recv_window)
-            .await {
+        let result = match self.post_order(order, private_key).await {
             Ok(mut transaction) => {
-                transaction.validated_rules = validated_rules;
+                transaction.validated_rules = validated_rules.to_vec();
                 Ok(transaction)
             },
             Err(error) => Err(error)
-        }
+        };
+        self.order_guard.record_response(&symbol, &tracking_entry, &result);
+        self.journal(record.completed(&result));
+        result
     }
 
-    #[cfg(not(feature = "backtest"))]
-    pub async fn place_order_with_key(&self, order: OrderRequest, private_key: &str) -> Result<Transaction> {
-        let top_n_entry = add_order_tracking_item(&order)?;
-        let validated_rules = match validate_order_request(&order.symbol, &top_n_entry) {
-            Ok(rules) => rules,
-            Err(error) => {
-                remove_order_tracking_item(&order.symbol, &top_n_entry);
+    /// Sends an order, retrying transient failures when the order has a client order id and the
+    /// client has a retry policy. Before each retry the order is looked up by its client order id
+    /// in case the failed attempt reached the exchange.
+    async fn post_order(&self, order: OrderRequest, private_key: Option<&str>) -> Result<Transaction> {
+        let mut attempt = 1;
+        loop {
+            let result = match private_key {
+                Some(private_key) => {
+                    self.client
+                        .post_signed_p_with_key("/fapi/v1/order", order.clone(), self.recv_window, private_key)
+                        .await
+                }
+                None => {
+                    self.client
+                        .post_signed_p("/fapi/v1/order", order.clone(), self.recv_window)
+                        .await
+                }
+            };
+            let error = match result {
+                Ok(transaction) => return Ok(transaction),
+                Err(error) => error,
+            };
+            let (Some(retry_policy), Some(client_order_id)) =
+                (self.client.retry_policy(), order.new_client_order_id.as_ref())
+            else {
+                return Err(error);
+            };
+            if !retry_policy.should_retry(&error, attempt) {
                 return Err(error);
             }
-        };
-        if validated_rules.is_empty() {
-            return Err(anyhow!("Expected some validated rule but got none").into());
-        }
-        match self.client
-            .post_signed_p_with_key::<Transaction, OrderRequest>("/fapi/v1/order", order, self.recv_window, private_key)
-            .await {
-            Ok(mut transaction) => {
-                transaction.validated_rules = validated_rules;
-                Ok(transaction)
-            },
-            Err(error) => Err(error)
+            tokio::time::sleep(retry_policy.backoff(attempt)).await;
+            let lookup = GetOrderRequest {
+                symbol: order.symbol.clone(),
+                order_id: None,
+                orig_client_order_id: Some(client_order_id.clone()),
+            };
+            match self.get_order(Some(lookup)).await {
+                Ok(placed_order) => return Ok(placed_order.into()),
+                Err(Error::BinanceError { response }) if response.error_code() == BinanceErrorCode::NoSuchOrder => {}
+                // Without knowing whether the order exists, sending it again could duplicate it
+                Err(_) => return Err(error),
+            }
+            attempt += 1;
         }
     }
 
-
-    /// Place an order
+    /// Place an order given as JSON, e.g. to an external simulator answering with the order.
+    /// Checked against the rules of `order_guard` first
     #[cfg(feature = "backtest")]
-    pub async fn place_order(&self, order: serde_json::Value) -> Result<Order> {
-        self.client
-            .post_signed_p("/fapi/v1/order", order, self.recv_window)
-            .await
+    pub async fn place_json_order(&self, order: serde_json::Value) -> Result<Order> {
+        let tracking_request = OrderTrackingRequest::from(&serde_json::from_value::<JsonOrderRequest>(order.clone())?);
+        let (tracking_entry, _) = self.order_guard.check_order(&tracking_request)?;
+        let result = self.client.post_signed_p("/fapi/v1/order", order, self.recv_window).await;
+        self.order_guard.record_response(&tracking_request.symbol, &tracking_entry, &result);
+        result
     }
 
     /// Get currently open orders
     pub async fn get_open_orders(&self, symbol: Option<impl Into<String>>) -> Result<Vec<Order>> {
-        let dummy_hashmap: HashMap<String, String> = HashMap::new();
-        let payload = match symbol {
-            Some(symbol) => build_signed_request_p(PairQuery { symbol: symbol.into() }, self.recv_window)?,
-            None => build_signed_request_p(dummy_hashmap, self.recv_window)?,
-        };
-        self.client.get_signed("/fapi/v1/openOrders", &payload).await
+        let query = symbol.map(|symbol| PairQuery { symbol: symbol.into() });
+        self.client.get_signed_p("/fapi/v1/openOrders", query, self.recv_window).await
     }
 
     /// Get all orders
     pub async fn get_all_orders(&self, symbol: impl Into<String>) -> Result<Vec<Order>> {
-        let payload = build_signed_request_p(PairQuery { symbol: symbol.into() }, self.recv_window)?;
-        self.client.get_signed("/fapi/v1/allOrders", &payload).await
+        let query = PairQuery { symbol: symbol.into() };
+        self.client.get_signed_p("/fapi/v1/allOrders", Some(query), self.recv_window).await
+    }
+
+    /// Get the orders of `symbol` placed between `start_time` and `end_time`, in milliseconds.
+    /// The range cannot exceed 7 days
+    pub async fn get_all_orders_in_window(&self, symbol: impl Into<String>, start_time: u64, end_time: u64, limit: u16) -> Result<Vec<Order>> {
+        let query = history_window_query(symbol.into(), start_time, end_time, limit);
+        self.client.get_signed_p("/fapi/v1/allOrders", Some(query), self.recv_window).await
+    }
+
+    /// Get the trades of `symbol` between `start_time` and `end_time`, in milliseconds.
+    /// The range cannot exceed 7 days
+    pub async fn get_account_trades_in_window(&self, symbol: impl Into<String>, start_time: u64, end_time: u64, limit: u16) -> Result<Vec<AccountTrade>> {
+        let query = history_window_query(symbol.into(), start_time, end_time, limit);
+        self.client.get_signed_p("/fapi/v1/userTrades", Some(query), self.recv_window).await
+    }
+
+    /// Get the orders of `symbol` with an order id of at least `order_id`
+    pub async fn get_all_orders_from_id(&self, symbol: impl Into<String>, order_id: u64, limit: u16) -> Result<Vec<Order>> {
+        let query = OrdersFromIdQuery { symbol: symbol.into(), order_id, limit };
+        self.client.get_signed_p("/fapi/v1/allOrders", Some(query), self.recv_window).await
+    }
+
+    /// Get the trades of `symbol` with a trade id of at least `from_id`
+    pub async fn get_account_trades_from_id(&self, symbol: impl Into<String>, from_id: u64, limit: u16) -> Result<Vec<AccountTrade>> {
+        let query = HistoryQuery {
+            start_time: None,
+            end_time: None,
+            from_id: Some(from_id),
+            limit,
+            symbol: symbol.into(),
+            interval: None,
+            period: None,
+        };
+        self.client.get_signed_p("/fapi/v1/userTrades", Some(query), self.recv_window).await
+    }
+
+    /// Rebuilds the tracked orders of `symbol` from the orders and trades of the account since
+    /// `start_time`, in milliseconds, and merges them with the orders tracked locally, see
+    /// `OrderTracker::merge_history`. Returns the number of merged entries.
+    ///
+    /// Binance drops canceled orders without fills after a few days, older ones are only rebuilt
+    /// from their trades.
+    pub async fn bootstrap_order_tracker(&self, symbol: &str, start_time: u64) -> Result<usize> {
+        let end_time = get_timestamp()?;
+        // The first records are found by time, 7 days at a time, the next ones by id as records
+        // sharing a millisecond can span pages
+        let mut orders = vec![];
+        let mut window_start = start_time;
+        let mut page = vec![];
+        while page.is_empty() && window_start <= end_time {
+            let window_end = (window_start + HISTORY_WINDOW_MILLIS).min(end_time);
+            page = self.get_all_orders_in_window(symbol, window_start, window_end, HISTORY_PAGE_LIMIT).await?;
+            window_start = window_end + 1;
+        }
+        // The first page is followed up even if short, later windows can hold more orders
+        let mut page_len = HISTORY_PAGE_LIMIT as usize;
+        while let Some(order_id) = next_page_from_id(page_len, page.iter().filter_map(|order| order.order_id.parse().ok()).max()) {
+            orders.append(&mut page);
+            page = self.get_all_orders_from_id(symbol, order_id, HISTORY_PAGE_LIMIT).await?;
+            page_len = page.len();
+        }
+        orders.append(&mut page);
+
+        let mut trades = vec![];
+        let mut window_start = start_time;
+        let mut page = vec![];
+        while page.is_empty() && window_start <= end_time {
+            let window_end = (window_start + HISTORY_WINDOW_MILLIS).min(end_time);
+            page = self.get_account_trades_in_window(symbol, window_start, window_end, HISTORY_PAGE_LIMIT).await?;
+            window_start = window_end + 1;
+        }
+        let mut page_len = HISTORY_PAGE_LIMIT as usize;
+        while let Some(from_id) = next_page_from_id(page_len, page.iter().map(|trade| trade.id).max()) {
+            trades.append(&mut page);
+            page = self.get_account_trades_from_id(symbol, from_id, HISTORY_PAGE_LIMIT).await?;
+            page_len = page.len();
+        }
+        trades.append(&mut page);
+        self.order_guard.tracker().merge_history(symbol, history_entries(&orders, &trades))
     }
 
     /// Place a test order    
@@ -292,12 +570,10 @@ impl FuturesAccount {
         if let Some(order_id) = order_id {
             data.insert("orderId".to_string(), order_id.to_string());
         }
-        let payload = build_signed_request_p(data, self.recv_window)?;
-        self.client.get_signed("/fapi/v1/userTrades", &payload).await
+        self.client.get_signed_p("/fapi/v1/userTrades", Some(data), self.recv_window).await
     }
 
     /// Place a limit buy order
-    #[cfg(not(feature = "backtest"))]
     pub async fn limit_buy(
         &self,
         symbol: impl Into<String>,
@@ -321,12 +597,12 @@ impl FuturesAccount {
             working_type: None,
             price_protect: None,
             new_client_order_id: None,
+            grid_id: None,
         };
         self.place_order(order).await
     }
 
     /// Place a limit sell order
-    #[cfg(not(feature = "backtest"))]
     pub async fn limit_sell(
         &self,
         symbol: impl Into<String>,
@@ -350,12 +626,12 @@ impl FuturesAccount {
             working_type: None,
             price_protect: None,
             new_client_order_id: None,
+            grid_id: None,
         };
         self.place_order(order).await
     }
 
     /// Place a Market buy order
-    #[cfg(not(feature = "backtest"))]
     pub async fn market_buy<S, F>(&self, symbol: S, qty: F) -> Result<Transaction>
     where
         S: Into<String>,
@@ -377,12 +653,12 @@ impl FuturesAccount {
             working_type: None,
             price_protect: None,
             new_client_order_id: None,
+            grid_id: None,
         };
         self.place_order(order).await
     }
 
     /// Place a Market sell order
-    #[cfg(not(feature = "backtest"))]
     pub async fn market_sell<S, F>(&self, symbol: S, qty: F) -> Result<Transaction>
     where
         S: Into<String>,
@@ -404,12 +680,24 @@ impl FuturesAccount {
             working_type: None,
             price_protect: None,
             new_client_order_id: None,
+            grid_id: None,
         };
         self.place_order(order).await
     }
 
     /// Place a cancellation order
     pub async fn cancel_order(&self, o: OrderCancellation) -> Result<CanceledOrderResponse> {
+        let record = OrderJournalRecord::new(OrderJournalAction::Cancel, &o.symbol, o.orig_client_order_id.clone(), &o);
+        if let Err(error) = self.journal_sending(&record) {
+            self.journal(record.rejected(&error));
+            return Err(error);
+        }
+        let result = self.send_cancel_order(o).await;
+        self.journal(record.completed(&result));
+        result
+    }
+
+    async fn send_cancel_order(&self, o: OrderCancellation) -> Result<CanceledOrderResponse> {
         let recv_window = o.recv_window.unwrap_or(self.recv_window);
         if let Some(order_id) = o.order_id {
             let as_u64 = OrderCancellationWithU64 {
@@ -475,23 +763,17 @@ impl FuturesAccount {
 
     /// Return general [`AccountInformation`]
     pub async fn account_information(&self) -> Result<AccountInformation> {
-        // needs to be changed to smth better later
-        let payload = build_signed_request(BTreeMap::<String, String>::new(), self.recv_window)?;
-        self.client.get_signed_d("/fapi/v2/account", &payload).await
+        self.client.get_signed_p("/fapi/v2/account", None::<()>, self.recv_window).await
     }
 
     // but its positions are missing entry_price which we need
     pub async fn account_information_v3(&self) -> Result<AccountInformationV3> {
-        // needs to be changed to smth better later
-        let payload = build_signed_request(BTreeMap::<String, String>::new(), self.recv_window)?;
-        self.client.get_signed_d("/fapi/v3/account", &payload).await
+        self.client.get_signed_p("/fapi/v3/account", None::<()>, self.recv_window).await
     }
 
     /// Return account's [`AccountBalance`]
     pub async fn account_balance(&self) -> Result<Vec<AccountBalance>> {
-        let parameters = BTreeMap::<String, String>::new();
-        let request = build_signed_request(parameters, self.recv_window)?;
-        self.client.get_signed_d("/fapi/v2/balance", request.as_str()).await
+        self.client.get_signed_p("/fapi/v2/balance", None::<()>, self.recv_window).await
     }
 
     // commented out to be safe since I don't expect to use it
@@ -526,7 +808,8 @@ impl FuturesAccount {
     where
         S: Into<String>,
     {
-        self.client
+        let _: serde_json::Value = self
+            .client
             .delete_signed_p(
                 "/fapi/v1/allOpenOrders",
                 PairQuery { symbol: symbol.into() },
@@ -536,3 +819,264 @@ impl FuturesAccount {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use reqwest::{Method, StatusCode};
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::api::Binance;
+    use crate::config::Config;
+    use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+    use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+    use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+    use crate::futures::utils::order_journal::{InMemoryOrderJournal, OrderJournalOutcome, OrderJournalQuery};
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::rate_limit::RateLimitMode;
+    use crate::transport::{HttpResponse, MemoryTransport};
+
+    const TRANSACTION: &str = r#"{"clientOrderId":"a","cumQty":"0","cumQuote":"0","executedQty":"0","orderId":1,
+        "avgPrice":"0","origQty":"0.5","price":"100","reduceOnly":false,"side":"BUY","positionSide":"LONG",
+        "status":"NEW","stopPrice":"0","closePosition":false,"symbol":"BTCUSDT","timeInForce":"GTC","type":"LIMIT",
+        "origType":"LIMIT","updateTime":1,"workingType":"CONTRACT_PRICE","priceProtect":false}"#;
+
+    fn order() -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: OrderSide::Buy,
+            position_side: None,
+            order_type: SupportedOrderType::Limit,
+            time_in_force: Some(TimeInForce::GTC),
+            quantity: Some(dec!(0.5)),
+            reduce_only: None,
+            price: Some(dec!(100)),
+            stop_price: None,
+            close_position: None,
+            activation_price: None,
+            callback_rate: None,
+            working_type: None,
+            price_protect: None,
+            new_client_order_id: None,
+            grid_id: None,
+        }
+    }
+
+    /// Allows one accepted order of up to 1 BTCUSDT a day
+    fn guard() -> OrderGuard {
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let rule = |max_count: u64, period: RulePeriod| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                market: OrderMarket::Futures,
+                count_by: ExpectedOrderRequestsRuleCountBasis::Accepted,
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count,
+                period,
+                side: OrderSide::Buy,
+                limits: vec![],
+            })
+        };
+        let rules = HashSet::from([rule(1, RulePeriod::Hours(24)), rule(10, RulePeriod::Weeks(52))]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        guard
+    }
+
+    /// Rejects the first order for insufficient margin and accepts the next ones
+    fn transport() -> Arc<MemoryTransport> {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond_once(Method::POST, "/fapi/v1/order", HttpResponse::new(StatusCode::BAD_REQUEST, r#"{"code":-2019,"msg":"Margin is insufficient."}"#));
+        transport.respond(Method::POST, "/fapi/v1/order", HttpResponse::ok(TRANSACTION));
+        transport
+    }
+
+    fn account() -> FuturesAccount {
+        let config = Config::default().set_rate_limit_mode(RateLimitMode::Disabled);
+        FuturesAccount::new_with_config(Some("api-key".to_string()), Some("secret-key".to_string()), &config)
+    }
+
+    #[tokio::test]
+    async fn orders_are_checked_and_sent_through_the_transport() {
+        let transport = transport();
+        let account = account()
+            .with_order_guard(guard())
+            .with_order_journal(Arc::new(InMemoryOrderJournal::new()))
+            .with_transport(transport.clone());
+
+        // The rejected order does not count against the rule, the accepted one does
+        let error = account.place_order(order()).await.unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::MarginNotSufficient));
+        assert_eq!(account.place_order(order()).await.unwrap().order_id, "1");
+        assert!(matches!(account.place_order(order()).await, Err(Error::ExpectedOrdersRuleViolated(_))));
+
+        let sent = transport.requests_to(Method::POST, "/fapi/v1/order");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].get_query_param("quantity").as_deref(), Some("0.5"));
+        assert!(sent[0].get_query_param("signature").is_some());
+        assert_eq!(sent[0].headers.get("x-mbx-apikey").unwrap(), "api-key");
+    }
+
+    #[tokio::test]
+    async fn order_attempts_are_journaled_once_a_journal_is_set() {
+        assert!(account().order_journal.is_none());
+        let journal = Arc::new(InMemoryOrderJournal::new());
+        let account = account()
+            .with_order_guard(guard())
+            .with_order_journal(journal.clone())
+            .with_transport(transport());
+        let order = |client_order_id: &str| OrderRequest {
+            new_client_order_id: Some(client_order_id.to_string()),
+            ..order()
+        };
+
+        assert!(account.place_order(order("a")).await.is_err());
+        assert!(account.place_order(order("b")).await.is_ok());
+        assert!(account.place_order(order("c")).await.is_err());
+
+        let records = journal.query(&OrderJournalQuery::new().with_symbol("BTCUSDT")).unwrap();
+        let outcomes: Vec<OrderJournalOutcome> = records.iter().map(|record| record.outcome).collect();
+        assert_eq!(outcomes, vec![OrderJournalOutcome::Failed, OrderJournalOutcome::Accepted, OrderJournalOutcome::Rejected]);
+        assert_eq!(records[0].error_code, Some(BinanceErrorCode::MarginNotSufficient));
+        assert_eq!(records[1].client_order_id.as_deref(), Some("b"));
+        assert!(records[1].tracking_entry.is_some());
+        assert_eq!(records[1].evaluated_rules.len(), 2);
+        assert!(records[1].response.is_some());
+        // The rejected order was never tracked, the rules it broke are recorded
+        assert!(records[2].tracking_entry.is_none());
+        assert_eq!(records[2].evaluated_rules.len(), 2);
+        assert!(records.iter().all(|record| record.rules_version == Some(account.order_guard.rules().version())));
+    }
+
+    #[derive(Debug)]
+    struct FailingJournal;
+
+    impl OrderJournal for FailingJournal {
+        fn append(&self, _record: &OrderJournalRecord) -> Result<()> { Err(Error::Msg("disk full".to_string())) }
+
+        fn query(&self, _query: &OrderJournalQuery) -> Result<Vec<OrderJournalRecord>> { Ok(vec![]) }
+    }
+
+    #[tokio::test]
+    async fn required_journal_records_orders_before_sending_them() {
+        let transport = transport();
+        let journal = Arc::new(InMemoryOrderJournal::new());
+        let journaled = account()
+            .with_order_guard(guard())
+            .with_required_order_journal(journal.clone())
+            .with_transport(transport.clone());
+        assert!(journaled.place_order(order()).await.is_err());
+        assert!(journaled.place_order(order()).await.is_ok());
+        let records = journal.query(&OrderJournalQuery::new()).unwrap();
+        let outcomes: Vec<OrderJournalOutcome> = records.iter().map(|record| record.outcome).collect();
+        assert_eq!(outcomes, vec![OrderJournalOutcome::Sending, OrderJournalOutcome::Failed, OrderJournalOutcome::Sending, OrderJournalOutcome::Accepted]);
+        assert_eq!(records[2].id, records[3].id);
+
+        let unjournaled = account()
+            .with_order_guard(guard())
+            .with_required_order_journal(Arc::new(FailingJournal))
+            .with_transport(transport.clone());
+        assert!(matches!(unjournaled.place_order(order()).await, Err(Error::Msg(_))));
+        let cancellation = OrderCancellation {
+            symbol: "BTCUSDT".to_string(),
+            order_id: Some("1".to_string()),
+            ..OrderCancellation::default()
+        };
+        assert!(matches!(unjournaled.cancel_order(cancellation).await, Err(Error::Msg(_))));
+        assert_eq!(transport.requests_to(Method::POST, "/fapi/v1/order").len(), 2);
+        assert!(transport.requests_to(Method::DELETE, "/fapi/v1/order").is_empty());
+    }
+
+    #[tokio::test]
+    async fn bootstrap_pages_past_orders_sharing_a_millisecond() {
+        let time = get_timestamp().unwrap() - 1_000;
+        let orders = |order_ids: std::ops::RangeInclusive<u64>| {
+            let orders: Vec<String> = order_ids
+                .map(|order_id| {
+                    TRANSACTION
+                        .replace(r#""clientOrderId":"a""#, &format!(r#""clientOrderId":"{order_id}""#))
+                        .replace(r#""orderId":1"#, &format!(r#""orderId":{order_id},"time":{time}"#))
+                })
+                .collect();
+            HttpResponse::ok(format!("[{}]", orders.join(",")))
+        };
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond_once(Method::GET, "/fapi/v1/allOrders", orders(1..=1000));
+        transport.respond_once(Method::GET, "/fapi/v1/allOrders", orders(1001..=1001));
+        transport.respond(Method::GET, "/fapi/v1/userTrades", HttpResponse::ok("[]"));
+        let account = account()
+            .with_order_guard(OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new())))
+            .with_transport(transport.clone());
+
+        assert_eq!(account.bootstrap_order_tracker("BTCUSDT", time).await.unwrap(), 1001);
+        let sent = transport.requests_to(Method::GET, "/fapi/v1/allOrders");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].get_query_param("startTime"), Some(time.to_string()));
+        assert_eq!(sent[1].get_query_param("orderId").as_deref(), Some("1001"));
+        assert_eq!(sent[1].get_query_param("startTime"), None);
+    }
+}
+
+#[cfg(all(test, feature = "backtest"))]
+mod backtest_test {
+    use std::collections::HashSet;
+    use reqwest::Method;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::api::Binance;
+    use crate::clock::{Clock, ManualClock};
+    use crate::config::Config;
+    use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+    use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+    use crate::futures::utils::order_tracker::OrderTracker;
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::rate_limit::RateLimitMode;
+    use crate::transport::{HttpResponse, MemoryTransport};
+
+    const ORDER: &str = r#"{"clientOrderId":"a","cumQuote":"0","executedQty":"0","orderId":1,"avgPrice":"0",
+        "origQty":"0.5","price":"100","reduceOnly":false,"side":"BUY","positionSide":"LONG","status":"NEW",
+        "closePosition":false,"symbol":"BTCUSDT","timeInForce":"GTC","type":"LIMIT","origType":"LIMIT",
+        "updateTime":1,"workingType":"CONTRACT_PRICE","priceProtect":false}"#;
+
+    #[tokio::test]
+    async fn json_orders_are_checked_at_the_injected_time() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000_000_000_000));
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()))
+            .with_tracker(OrderTracker::new(Arc::new(InMemoryOrderTrackerStore::new())).with_clock(clock.clone()));
+        let rule = |max_count: u64, period: RulePeriod| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count,
+                period,
+                side: OrderSide::Buy,
+                ..ExpectedOrderRequestsRulePayload::default()
+            })
+        };
+        let rules = HashSet::from([rule(1, RulePeriod::Hours(24)), rule(10, RulePeriod::Weeks(52))]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond(Method::POST, "/fapi/v1/order", HttpResponse::ok(ORDER));
+        let config = Config::default().set_rate_limit_mode(RateLimitMode::Disabled);
+        let mut account = FuturesAccount::new_with_config(Some("api-key".to_string()), Some("secret-key".to_string()), &config)
+            .with_order_guard(guard.clone())
+            .with_transport(transport.clone());
+        account.client = account.client.with_clock(clock.clone());
+        let order = serde_json::json!({"symbol": "BTCUSDT", "side": "BUY", "type": "LIMIT", "quantity": "0.5", "price": "100"});
+
+        assert_eq!(account.place_json_order(order.clone()).await.unwrap().order_id, "1");
+        assert!(matches!(account.place_json_order(order.clone()).await, Err(Error::ExpectedOrdersRuleViolated(_))));
+        // Once the first order left the day by the clock of the tracker, compacted orders are
+        // counted until the end of their hour
+        clock.advance(std::time::Duration::from_secs(25 * 60 * 60));
+        assert!(account.place_json_order(order).await.is_ok());
+        let sent = transport.requests_to(Method::POST, "/fapi/v1/order");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].get_query_param("timestamp"), Some(clock.now_millis().to_string()));
+        let tracked = guard.tracker().get_all_tracking_items("BTCUSDT").unwrap();
+        assert_eq!(tracked[0].item.outcome.order_id.as_deref(), Some("1"));
+    }
+}
@@ -1,3 +1,5 @@
+use serde_json::Value;
+
 use crate::client::*;
 use crate::errors::*;
 use crate::futures::rest_model::*;
@@ -11,7 +13,7 @@ pub struct FuturesGeneral {
 impl FuturesGeneral {
     /// Test connectivity
     pub async fn ping(&self) -> Result<String> {
-        self.client.get("/fapi/v1/ping", None).await?;
+        let _: Value = self.client.get("/fapi/v1/ping", None).await?;
         Ok("pong".into())
     }
 
@@ -21,7 +23,9 @@ impl FuturesGeneral {
     /// Obtain exchange information
     /// - Current exchange trading rules and symbol information
     pub async fn exchange_info(&self) -> Result<ExchangeInformation> {
-        self.client.get_p("/fapi/v1/exchangeInfo", None).await
+        let info: ExchangeInformation = self.client.get_p("/fapi/v1/exchangeInfo", None).await?;
+        self.client.set_rate_limits(&info.rate_limits);
+        Ok(info)
     }
 
     /// Get Symbol information
@@ -26,6 +26,24 @@ impl FuturesMarket {
             .await
     }
 
+    /// Order book with a custom depth limit
+    /// Supported limits are: 5, 10, 20, 50, 100, 500, 1000
+    pub async fn get_custom_depth<S>(&self, symbol: S, limit: u16) -> Result<OrderBook>
+    where
+        S: Into<String>,
+    {
+        let query = HistoryQuery {
+            start_time: None,
+            end_time: None,
+            from_id: None,
+            limit,
+            symbol: symbol.into(),
+            interval: None,
+            period: None,
+        };
+        self.client.get_d("/fapi/v1/depth", Some(query)).await
+    }
+
     /// Get trades for a pair
     pub async fn get_trades<S>(&self, symbol: S) -> Result<Trades>
     where
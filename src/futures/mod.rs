@@ -1,9 +1,22 @@
+#[cfg(feature = "futures_api")]
 pub mod account;
+#[cfg(feature = "futures_api")]
 pub mod general;
+#[cfg(feature = "futures_api")]
 pub mod market;
+#[cfg(feature = "futures_api")]
 pub mod rest_model;
+#[cfg(feature = "futures_api")]
 pub mod userstream;
+#[cfg(feature = "futures_api")]
 pub mod websockets;
+#[cfg(feature = "futures_api")]
 pub mod ws_model;
+#[cfg(feature = "futures_api")]
 pub mod serde;
+#[cfg(feature = "futures_api")]
+pub mod simulator;
+#[cfg(feature = "futures_api")]
+pub mod trading;
+// The order guard also checks spot and margin orders, so it is built without futures_api
 pub mod utils;
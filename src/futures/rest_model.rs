@@ -158,6 +158,7 @@ pub enum SupportedOrderType { // order types that our bot supports
     StopMarket,
     TakeProfit,
     TakeProfitMarket,
+    TrailingStopMarket,
 }
 
 impl Default for SupportedOrderType {
@@ -175,6 +176,7 @@ impl SupportedOrderType {
             SupportedOrderType::StopMarket => OrderType::StopMarket,
             SupportedOrderType::TakeProfit => OrderType::TakeProfit,
             SupportedOrderType::TakeProfitMarket => OrderType::TakeProfitMarket,
+            SupportedOrderType::TrailingStopMarket => OrderType::TrailingStopMarket,
         }
     }
     
@@ -186,7 +188,7 @@ impl SupportedOrderType {
             OrderType::StopMarket => Some(SupportedOrderType::StopMarket),
             OrderType::TakeProfit => Some(SupportedOrderType::TakeProfit),
             OrderType::TakeProfitMarket => Some(SupportedOrderType::TakeProfitMarket),
-            _ => None,
+            OrderType::TrailingStopMarket => Some(SupportedOrderType::TrailingStopMarket),
         }
     }
 
@@ -196,6 +198,7 @@ impl SupportedOrderType {
             Self::StopMarket => true,
             Self::TakeProfit => true,
             Self::TakeProfitMarket => true,
+            Self::TrailingStopMarket => true,
             _ => false,
         }
     }
@@ -351,6 +354,16 @@ pub struct OrderBook {
     pub asks: Vec<Asks>,
 }
 
+impl From<OrderBook> for crate::rest_model::OrderBook {
+    fn from(order_book: OrderBook) -> Self {
+        crate::rest_model::OrderBook {
+            last_update_id: order_book.last_update_id,
+            bids: order_book.bids,
+            asks: order_book.asks,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PriceStats {
@@ -795,6 +808,36 @@ pub struct Transaction {
     pub validated_rules: Vec<ExpectedOrderRequestsRule>
 }
 
+impl From<Order> for Transaction {
+    fn from(order: Order) -> Self {
+        Self {
+            client_order_id: order.client_order_id,
+            cum_qty: order.executed_qty,
+            cum_quote: order.cum_quote,
+            executed_qty: order.executed_qty,
+            order_id: order.order_id,
+            avg_price: order.avg_price,
+            orig_qty: order.orig_qty,
+            reduce_only: order.reduce_only,
+            side: order.side,
+            position_side: order.position_side,
+            status: order.status,
+            stop_price: order.stop_price,
+            close_position: order.close_position,
+            symbol: order.symbol,
+            time_in_force: order.time_in_force,
+            type_name: order.order_type.to_order_type(),
+            orig_type: order.orig_type.to_order_type(),
+            activate_price: Some(order.activate_price),
+            price_rate: Some(order.price_rate),
+            update_time: order.update_time,
+            working_type: order.working_type,
+            price_protect: order.price_protect,
+            validated_rules: vec![],
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CanceledOrder {
@@ -826,6 +869,44 @@ pub struct CanceledOrder {
     price_protect: bool,
 }
 
+/// The cancellation Binance answers with for `order`
+impl From<Order> for CanceledOrder {
+    fn from(order: Order) -> Self {
+        Self {
+            client_order_id: order.client_order_id,
+            cum_qty: order.executed_qty,
+            cum_quote: order.cum_quote,
+            executed_qty: order.executed_qty,
+            order_id: order.order_id,
+            orig_qty: order.orig_qty,
+            orig_type: wire_name(&order.orig_type),
+            price: order.price,
+            reduce_only: order.reduce_only,
+            side: wire_name(&order.side),
+            position_side: wire_name(&order.position_side),
+            status: wire_name(&order.status),
+            stop_price: order.stop_price,
+            close_position: order.close_position,
+            symbol: order.symbol,
+            time_in_force: wire_name(&order.time_in_force),
+            type_name: wire_name(&order.order_type),
+            activate_price: Some(order.activate_price),
+            price_rate: Some(order.price_rate),
+            update_time: order.update_time,
+            working_type: wire_name(&order.working_type),
+            price_protect: order.price_protect,
+        }
+    }
+}
+
+/// How Binance names an enum value, e.g. `BUY`
+fn wire_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Position {
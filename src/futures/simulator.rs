@@ -0,0 +1,963 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use anyhow::anyhow;
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use crate::account::OrderCancellation;
+use crate::clock::ManualClock;
+use crate::errors::{BinanceContentError, BinanceErrorCode, Error, Result};
+use crate::futures::account::{GetOrderRequest, OrderRequest};
+use crate::futures::rest_model::{AccountBalance, AggTrade, CanceledOrderResponse, Filters, KlineSummary, MarginType, Order, OrderSide, OrderStatus, PositionSide, PositionV3, SupportedOrderType, Symbol, TimeInForce, Trade, Transaction};
+use crate::futures::trading::{AccountQueries, OrderEntry};
+use crate::futures::ws_model::{Account, AccountUpdate, Balance, OrderTradeUpdate, Position, PriceMatch, ReasonType, SelfTradePreventionMode, WebsocketEvent, WebsocketOrder};
+use crate::rest_model::ExecutionType;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+/// Binance fees of the lowest VIP level
+const DEFAULT_MAKER_FEE: Decimal = dec!(0.0002);
+const DEFAULT_TAKER_FEE: Decimal = dec!(0.0005);
+
+/// Filters and fee rates a symbol is traded with on the simulated exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedSymbol {
+    pub symbol: String,
+    pub tick_size: Decimal,
+    pub step_size: Decimal,
+    pub min_qty: Decimal,
+    pub min_notional: Decimal,
+    /// Rate of the notional charged on maker fills, e.g. 0.0002 for 0.02%
+    pub maker_fee: Decimal,
+    /// Rate of the notional charged on taker fills
+    pub taker_fee: Decimal,
+}
+
+impl SimulatedSymbol {
+    pub fn new(symbol: impl Into<String>, tick_size: Decimal, step_size: Decimal) -> Self {
+        Self {
+            symbol: symbol.into(),
+            tick_size,
+            step_size,
+            min_qty: step_size,
+            min_notional: Decimal::ZERO,
+            maker_fee: DEFAULT_MAKER_FEE,
+            taker_fee: DEFAULT_TAKER_FEE,
+        }
+    }
+
+    pub fn with_min_qty(mut self, min_qty: Decimal) -> Self {
+        self.min_qty = min_qty;
+        self
+    }
+
+    pub fn with_min_notional(mut self, min_notional: Decimal) -> Self {
+        self.min_notional = min_notional;
+        self
+    }
+
+    pub fn with_fees(mut self, maker_fee: Decimal, taker_fee: Decimal) -> Self {
+        self.maker_fee = maker_fee;
+        self.taker_fee = taker_fee;
+        self
+    }
+}
+
+/// The filters of a symbol from the exchange information, with the default fees
+impl From<&Symbol> for SimulatedSymbol {
+    fn from(symbol: &Symbol) -> Self {
+        let mut simulated = SimulatedSymbol::new(symbol.symbol.clone(), symbol.tick_size, symbol.step_size);
+        for filter in symbol.filters.iter() {
+            match filter {
+                Filters::PriceFilter { tick_size, .. } => simulated.tick_size = *tick_size,
+                Filters::LotSize { min_qty, step_size, .. } => {
+                    simulated.step_size = *step_size;
+                    simulated.min_qty = *min_qty;
+                }
+                Filters::MinNotional { notional } => simulated.min_notional = *notional,
+                _ => {}
+            }
+        }
+        simulated
+    }
+}
+
+/// A position of the simulated account, the account is in hedge mode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulatedPosition {
+    /// Size of the position, positive for short positions too
+    pub amount: Decimal,
+    pub entry_price: Decimal,
+    /// Profit realized by the fills that reduced the position, before fees
+    pub realized_profit: Decimal,
+}
+
+impl SimulatedPosition {
+    pub fn get_unrealized_profit(&self, position_side: &PositionSide, price: Decimal) -> Decimal {
+        match position_side {
+            PositionSide::Long => (price - self.entry_price) * self.amount,
+            PositionSide::Short => (self.entry_price - price) * self.amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimulatedOrder {
+    order: Order,
+    /// Best price since a trailing stop order was activated, `None` until then
+    trailing_extreme: Option<Decimal>,
+}
+
+/// In-process futures exchange that fills orders against replayed trades or klines
+///
+/// Orders are the real `OrderRequest`s, checked against the tick size, step size, minimum
+/// quantity and minimum notional of their symbol and rejected with the Binance errors. Resting
+/// limit orders fill as maker at their price when a trade reaches it, up to the traded quantity.
+/// Market orders and triggered stop, take profit and trailing stop orders fill as taker at the
+/// trade price that triggered them. Klines are replayed as open, low, high and close trades, or
+/// open, high, low and close for bearish klines, with unlimited quantity.
+///
+/// The account is in hedge mode with a single cross margined asset: fills reduce or grow the
+/// position of their position side and the realized profit and fees go to the wallet balance.
+/// Stop prices are compared to the trade price whatever the working type, margin and
+/// liquidations are not simulated.
+///
+/// Fills and order changes are queued as the `OrderTradeUpdate` and `AccountUpdate` events of the
+/// user data stream, see `drain_events`.
+#[derive(Debug)]
+pub struct SimulatedExchange {
+    symbols: HashMap<String, SimulatedSymbol>,
+    asset: String,
+    wallet_balance: Decimal,
+    positions: HashMap<(String, PositionSide), SimulatedPosition>,
+    orders: BTreeMap<u64, SimulatedOrder>,
+    last_prices: HashMap<String, Decimal>,
+    next_order_id: u64,
+    next_trade_id: u64,
+    now_millis: u64,
+    clock: Option<Arc<ManualClock>>,
+    events: Vec<WebsocketEvent>,
+}
+
+impl SimulatedExchange {
+    /// Empty account holding `wallet_balance` of `asset`, e.g. USDT
+    pub fn new(asset: impl Into<String>, wallet_balance: Decimal) -> Self {
+        Self {
+            symbols: HashMap::new(),
+            asset: asset.into(),
+            wallet_balance,
+            positions: HashMap::new(),
+            orders: BTreeMap::new(),
+            last_prices: HashMap::new(),
+            next_order_id: 1,
+            next_trade_id: 1,
+            now_millis: 0,
+            clock: None,
+            events: vec![],
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: SimulatedSymbol) -> Self {
+        self.symbols.insert(symbol.symbol.clone(), symbol);
+        self
+    }
+
+    /// Moves `clock` along the replayed market data, e.g. the clock of an order tracker
+    pub fn with_clock(mut self, clock: Arc<ManualClock>) -> Self {
+        clock.set_nanos(self.now_millis * NANOS_PER_MILLI);
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Time of the last replayed trade, in milliseconds
+    pub fn now_millis(&self) -> u64 {
+        self.now_millis
+    }
+
+    pub fn get_wallet_balance(&self) -> Decimal {
+        self.wallet_balance
+    }
+
+    pub fn get_last_price(&self, symbol: &str) -> Option<Decimal> {
+        self.last_prices.get(symbol).copied()
+    }
+
+    pub fn get_position(&self, symbol: &str, position_side: &PositionSide) -> Option<&SimulatedPosition> {
+        self.positions.get(&(symbol.to_string(), position_side.clone()))
+    }
+
+    /// Open orders of `symbol`, of every symbol if `None`, oldest first
+    pub fn get_open_orders(&self, symbol: Option<&str>) -> Vec<Order> {
+        self.orders
+            .values()
+            .filter(|simulated| simulated.order.status.is_open() && symbol.is_none_or(|symbol| simulated.order.symbol == symbol))
+            .map(|simulated| simulated.order.clone())
+            .collect()
+    }
+
+    /// Every order of `symbol`, oldest first
+    pub fn get_all_orders(&self, symbol: &str) -> Vec<Order> {
+        self.orders
+            .values()
+            .filter(|simulated| simulated.order.symbol == symbol)
+            .map(|simulated| simulated.order.clone())
+            .collect()
+    }
+
+    /// An order of any status by its order id or its client order id
+    pub fn get_order(&self, query: &GetOrderRequest) -> Result<Order> {
+        self.orders
+            .values()
+            .find(|simulated| {
+                simulated.order.symbol == query.symbol
+                    && (query.order_id.as_ref() == Some(&simulated.order.order_id)
+                        || query.orig_client_order_id.as_ref() == Some(&simulated.order.client_order_id))
+            })
+            .map(|simulated| simulated.order.clone())
+            .ok_or_else(|| binance_error(BinanceErrorCode::NoSuchOrder, "Order does not exist."))
+    }
+
+    /// The long and short positions of `symbol`, as reported by the position risk endpoint
+    pub fn get_positions(&self, symbol: &str) -> Vec<PositionV3> {
+        let mark_price = self.get_last_price(symbol).unwrap_or_default();
+        [PositionSide::Long, PositionSide::Short]
+            .into_iter()
+            .map(|position_side| {
+                let position = self.get_position(symbol, &position_side).cloned().unwrap_or_default();
+                let position_amount = match position_side {
+                    PositionSide::Long => position.amount,
+                    PositionSide::Short => -position.amount,
+                };
+                PositionV3 {
+                    symbol: symbol.to_string(),
+                    unrealized_profit: position.get_unrealized_profit(&position_side, mark_price),
+                    position_side,
+                    position_amount,
+                    entry_price: position.entry_price,
+                    break_even_price: position.entry_price,
+                    mark_price,
+                    liquidation_price: Decimal::ZERO,
+                    isolated_margin: Decimal::ZERO,
+                    notional: position_amount * mark_price,
+                    margin_asset: self.asset.clone(),
+                    isolated_wallet: Decimal::ZERO,
+                    initial_margin: Decimal::ZERO,
+                    maint_margin: Decimal::ZERO,
+                    position_initial_margin: Decimal::ZERO,
+                    open_order_initial_margin: Decimal::ZERO,
+                    adl: 0,
+                    bid_notional: Decimal::ZERO,
+                    ask_notional: Decimal::ZERO,
+                    update_time: self.now_millis,
+                }
+            })
+            .collect()
+    }
+
+    /// The wallet balance, with the unrealized profit of every position at the last trade prices
+    pub fn get_balance(&self) -> AccountBalance {
+        let unrealized_profit: Decimal = self
+            .positions
+            .iter()
+            .map(|((symbol, position_side), position)| {
+                let price = self.get_last_price(symbol).unwrap_or(position.entry_price);
+                position.get_unrealized_profit(position_side, price)
+            })
+            .sum();
+        AccountBalance {
+            account_alias: "simulated".to_string(),
+            asset: self.asset.clone(),
+            balance: self.wallet_balance,
+            cross_wallet_balance: self.wallet_balance,
+            cross_unrealized_pnl: unrealized_profit,
+            available_balance: self.wallet_balance + unrealized_profit,
+            max_withdraw_amount: self.wallet_balance,
+            margin_available: true,
+            update_time: self.now_millis,
+        }
+    }
+
+    /// User data stream events queued since the last call, oldest first
+    pub fn drain_events(&mut self) -> Vec<WebsocketEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Accepts the order, fills market orders and marketable limit orders right away at the last
+    /// trade price and keeps the rest open until the replayed trades reach them
+    pub fn place_order(&mut self, order: OrderRequest) -> Result<Transaction> {
+        let symbol = self
+            .symbols
+            .get(&order.symbol)
+            .cloned()
+            .ok_or_else(|| binance_error(BinanceErrorCode::BadSymbol, "Invalid symbol."))?;
+        let position_side = order
+            .position_side
+            .clone()
+            .ok_or_else(|| binance_error(BinanceErrorCode::PositionSideNotMatch, "Order's position side does not match user's setting."))?;
+        let close_position = order.close_position.unwrap_or(false);
+        let quantity = match (order.quantity, close_position) {
+            (_, true) => Decimal::ZERO,
+            (Some(quantity), false) => quantity,
+            (None, false) => return Err(mandatory_parameter_error("quantity")),
+        };
+        if !close_position {
+            if quantity <= Decimal::ZERO {
+                return Err(binance_error(BinanceErrorCode::QtyLessThanZero, "Quantity less than or equal to zero."));
+            }
+            if !is_multiple_of(quantity, symbol.step_size) {
+                return Err(binance_error(BinanceErrorCode::BadPrecision, "Precision is over the maximum defined for this asset."));
+            }
+            if quantity < symbol.min_qty {
+                return Err(binance_error(BinanceErrorCode::QtyLessThanMinQty, "Quantity less than min quantity."));
+            }
+        }
+        let price = match order.order_type {
+            SupportedOrderType::Limit | SupportedOrderType::Stop | SupportedOrderType::TakeProfit => {
+                Some(order.price.ok_or_else(|| mandatory_parameter_error("price"))?)
+            }
+            _ => None,
+        };
+        let stop_price = match order.order_type {
+            SupportedOrderType::Stop | SupportedOrderType::StopMarket | SupportedOrderType::TakeProfit | SupportedOrderType::TakeProfitMarket => {
+                Some(order.stop_price.ok_or_else(|| mandatory_parameter_error("stopPrice"))?)
+            }
+            _ => None,
+        };
+        let callback_rate = match order.order_type {
+            SupportedOrderType::TrailingStopMarket => Some(order.callback_rate.ok_or_else(|| mandatory_parameter_error("callbackRate"))?),
+            _ => None,
+        };
+        for price in [price, stop_price, order.activation_price.filter(|_| callback_rate.is_some())].into_iter().flatten() {
+            if price <= Decimal::ZERO || !is_multiple_of(price, symbol.tick_size) {
+                return Err(binance_error(BinanceErrorCode::PriceNotIncreasedByTickSize, "Price not increased by tick size."));
+            }
+        }
+        let last_price = self.get_last_price(&symbol.symbol);
+        if let Some(reference_price) = price.or(stop_price).or(last_price) {
+            if !close_position && quantity * reference_price < symbol.min_notional {
+                return Err(binance_error(
+                    BinanceErrorCode::MinNotional,
+                    format!("Order's notional must be no smaller than {} (unless you choose reduce only).", symbol.min_notional),
+                ));
+            }
+        }
+        if let (Some(stop_price), Some(last_price)) = (stop_price, last_price) {
+            if is_triggered(&order.order_type, &order.side, stop_price, last_price) {
+                return Err(binance_error(BinanceErrorCode::OrderWouldImmediatelyTrigger, "Order would immediately trigger."));
+            }
+        }
+        if order.order_type == SupportedOrderType::Market && last_price.is_none() {
+            return Err(anyhow!("No trade of {} was replayed yet, the market order cannot be filled", symbol.symbol).into());
+        }
+
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let simulated_order = Order {
+            client_order_id: order.new_client_order_id.clone().unwrap_or_else(|| format!("simulated-{order_id}")),
+            cum_quote: Decimal::ZERO,
+            executed_qty: Decimal::ZERO,
+            order_id: order_id.to_string(),
+            avg_price: Decimal::ZERO,
+            orig_qty: quantity,
+            price: price.unwrap_or_default(),
+            side: order.side.clone(),
+            reduce_only: order.reduce_only.unwrap_or(false),
+            position_side,
+            status: OrderStatus::New,
+            stop_price: stop_price.unwrap_or_default(),
+            close_position,
+            symbol: symbol.symbol.clone(),
+            time_in_force: order.time_in_force.clone().unwrap_or(TimeInForce::GTC),
+            order_type: order.order_type.clone(),
+            orig_type: order.order_type.clone(),
+            activate_price: order.activation_price.unwrap_or_default(),
+            price_rate: callback_rate.unwrap_or_default(),
+            update_time: self.now_millis,
+            working_type: order.working_type.clone().unwrap_or_default(),
+            price_protect: order.price_protect.unwrap_or(false),
+            good_till_date: None,
+            time: Some(self.now_millis),
+        };
+        let mut simulated = SimulatedOrder {
+            order: simulated_order,
+            trailing_extreme: None,
+        };
+        self.push_order_update(&simulated.order, ExecutionType::New, None);
+        if let Some(last_price) = last_price {
+            self.place_at(&mut simulated, last_price);
+        }
+        let transaction = Transaction::from(simulated.order.clone());
+        self.orders.insert(order_id, simulated);
+        Ok(transaction)
+    }
+
+    /// Cancels an open order by its order id or its client order id
+    pub fn cancel_order(&mut self, cancellation: OrderCancellation) -> Result<Order> {
+        let order_id = self
+            .orders
+            .iter()
+            .find(|(_, simulated)| {
+                simulated.order.symbol == cancellation.symbol
+                    && (cancellation.order_id.as_ref() == Some(&simulated.order.order_id)
+                        || cancellation.orig_client_order_id.as_ref() == Some(&simulated.order.client_order_id))
+            })
+            .map(|(order_id, _)| *order_id)
+            .ok_or_else(|| binance_error(BinanceErrorCode::NoSuchOrder, "Unknown order sent."))?;
+        let mut simulated = self.orders.remove(&order_id).unwrap();
+        let result = match simulated.order.status.is_open() {
+            true => {
+                self.close_order(&mut simulated, OrderStatus::Canceled);
+                Ok(simulated.order.clone())
+            }
+            false => Err(binance_error(BinanceErrorCode::NoSuchOrder, "Unknown order sent.")),
+        };
+        self.orders.insert(order_id, simulated);
+        result
+    }
+
+    /// Cancels every open order of `symbol`
+    pub fn cancel_all_open_orders(&mut self, symbol: &str) {
+        let order_ids: Vec<u64> = self.get_open_order_ids(symbol);
+        for order_id in order_ids {
+            let mut simulated = self.orders.remove(&order_id).unwrap();
+            self.close_order(&mut simulated, OrderStatus::Canceled);
+            self.orders.insert(order_id, simulated);
+        }
+    }
+
+    pub fn on_agg_trade(&mut self, symbol: &str, trade: &AggTrade) {
+        self.on_trade_price(symbol, trade.time, trade.price, Some(trade.qty));
+    }
+
+    pub fn on_trade(&mut self, symbol: &str, trade: &Trade) {
+        self.on_trade_price(symbol, trade.time, trade.price, Some(trade.qty));
+    }
+
+    /// Replays the kline as four trades of unlimited quantity, see `SimulatedExchange`
+    pub fn on_kline(&mut self, symbol: &str, kline: &KlineSummary) {
+        let open_time = kline.open_time.max(0) as u64;
+        let close_time = (kline.close_time.max(0) as u64).max(open_time);
+        let (first_extreme, second_extreme) = match kline.close >= kline.open {
+            true => (kline.low, kline.high),
+            false => (kline.high, kline.low),
+        };
+        let step = (close_time - open_time) / 3;
+        self.on_trade_price(symbol, open_time, kline.open, None);
+        self.on_trade_price(symbol, open_time + step, first_extreme, None);
+        self.on_trade_price(symbol, open_time + 2 * step, second_extreme, None);
+        self.on_trade_price(symbol, close_time, kline.close, None);
+    }
+
+    /// Moves the exchange to `time`, in milliseconds, and matches the open orders of `symbol`
+    /// against a trade at `price`. A `quantity` of `None` fills resting orders in full.
+    pub fn on_trade_price(&mut self, symbol: &str, time: u64, price: Decimal, quantity: Option<Decimal>) {
+        self.now_millis = self.now_millis.max(time);
+        if let Some(clock) = &self.clock {
+            clock.set_nanos(self.now_millis * NANOS_PER_MILLI);
+        }
+        self.last_prices.insert(symbol.to_string(), price);
+        let mut liquidity = quantity;
+        for order_id in self.get_open_order_ids(symbol) {
+            let mut simulated = self.orders.remove(&order_id).unwrap();
+            let triggered = self.trigger(&mut simulated, price);
+            match simulated.order.order_type {
+                SupportedOrderType::Market => {
+                    self.fill(&mut simulated, price, None, false);
+                }
+                // Orders triggered by this trade take liquidity, resting ones provide it
+                SupportedOrderType::Limit if crosses(&simulated.order.side, simulated.order.price, price) => match triggered {
+                    true => {
+                        self.fill(&mut simulated, price, None, false);
+                    }
+                    false => {
+                        let limit_price = simulated.order.price;
+                        let filled = self.fill(&mut simulated, limit_price, liquidity, true);
+                        liquidity = liquidity.map(|liquidity| liquidity - filled);
+                    }
+                },
+                _ => {}
+            }
+            self.orders.insert(order_id, simulated);
+        }
+    }
+
+    fn get_open_order_ids(&self, symbol: &str) -> Vec<u64> {
+        self.orders
+            .iter()
+            .filter(|(_, simulated)| simulated.order.symbol == symbol && simulated.order.status.is_open())
+            .map(|(order_id, _)| *order_id)
+            .collect()
+    }
+
+    /// Fills a new order as far as `last_price` allows, orders that cannot stay open are expired
+    fn place_at(&mut self, simulated: &mut SimulatedOrder, last_price: Decimal) {
+        match simulated.order.order_type {
+            SupportedOrderType::Market => {
+                self.fill(simulated, last_price, None, false);
+            }
+            SupportedOrderType::Limit => {
+                let marketable = crosses(&simulated.order.side, simulated.order.price, last_price);
+                if marketable && simulated.order.time_in_force == TimeInForce::GTX {
+                    // Post only orders would take liquidity
+                    self.close_order(simulated, OrderStatus::Expired);
+                    return;
+                }
+                if marketable {
+                    self.fill(simulated, last_price, None, false);
+                }
+            }
+            SupportedOrderType::TrailingStopMarket if simulated.order.activate_price.is_zero() => {
+                simulated.trailing_extreme = Some(last_price);
+            }
+            _ => {}
+        }
+        let time_in_force = &simulated.order.time_in_force;
+        if simulated.order.status.is_open() && (*time_in_force == TimeInForce::IOC || *time_in_force == TimeInForce::FOK) {
+            self.close_order(simulated, OrderStatus::Expired);
+        }
+    }
+
+    /// Turns stop, take profit and trailing stop orders reached by `price` into market or limit
+    /// orders, as Binance reports them once triggered. Returns whether the order was triggered.
+    fn trigger(&mut self, simulated: &mut SimulatedOrder, price: Decimal) -> bool {
+        let order = &mut simulated.order;
+        let triggered = match order.order_type {
+            SupportedOrderType::Stop | SupportedOrderType::StopMarket | SupportedOrderType::TakeProfit | SupportedOrderType::TakeProfitMarket => {
+                is_triggered(&order.order_type, &order.side, order.stop_price, price)
+            }
+            SupportedOrderType::TrailingStopMarket => {
+                let activated = simulated.trailing_extreme.is_some()
+                    || order.activate_price.is_zero()
+                    || match order.side {
+                        OrderSide::Buy => price <= order.activate_price,
+                        OrderSide::Sell => price >= order.activate_price,
+                    };
+                if !activated {
+                    return false;
+                }
+                let callback = order.price_rate / dec!(100);
+                match order.side {
+                    OrderSide::Buy => {
+                        let lowest = simulated.trailing_extreme.map_or(price, |extreme| extreme.min(price));
+                        simulated.trailing_extreme = Some(lowest);
+                        price >= lowest * (Decimal::ONE + callback)
+                    }
+                    OrderSide::Sell => {
+                        let highest = simulated.trailing_extreme.map_or(price, |extreme| extreme.max(price));
+                        simulated.trailing_extreme = Some(highest);
+                        price <= highest * (Decimal::ONE - callback)
+                    }
+                }
+            }
+            SupportedOrderType::Limit | SupportedOrderType::Market => false,
+        };
+        if triggered {
+            order.order_type = match order.order_type {
+                SupportedOrderType::Stop | SupportedOrderType::TakeProfit => SupportedOrderType::Limit,
+                _ => SupportedOrderType::Market,
+            };
+            order.update_time = self.now_millis;
+        }
+        triggered
+    }
+
+    /// Fills the order at `price`, up to `max_quantity` if given, and updates the position and the
+    /// wallet balance. Fills that would reduce a position beyond its size are capped and the rest of
+    /// the order is expired. Returns the filled quantity.
+    fn fill(&mut self, simulated: &mut SimulatedOrder, price: Decimal, max_quantity: Option<Decimal>, is_maker: bool) -> Decimal {
+        let symbol = self.symbols[&simulated.order.symbol].clone();
+        let order = &mut simulated.order;
+        let position_key = (order.symbol.clone(), order.position_side.clone());
+        let position = self.positions.entry(position_key).or_default();
+        let reduces = matches!(
+            (&order.position_side, &order.side),
+            (PositionSide::Long, OrderSide::Sell) | (PositionSide::Short, OrderSide::Buy)
+        );
+        let remaining = match order.close_position {
+            true => position.amount,
+            false => order.orig_qty - order.executed_qty,
+        };
+        let mut quantity = max_quantity.map_or(remaining, |max_quantity| remaining.min(max_quantity));
+        if reduces {
+            quantity = quantity.min(position.amount);
+        }
+        if quantity <= Decimal::ZERO {
+            if reduces && position.amount.is_zero() {
+                self.close_order(simulated, OrderStatus::Expired);
+            }
+            return Decimal::ZERO;
+        }
+
+        let notional = price * quantity;
+        let commission = notional * if is_maker { symbol.maker_fee } else { symbol.taker_fee };
+        let realized_profit = match reduces {
+            true => {
+                let realized_profit = match order.position_side {
+                    PositionSide::Long => (price - position.entry_price) * quantity,
+                    PositionSide::Short => (position.entry_price - price) * quantity,
+                };
+                position.amount -= quantity;
+                if position.amount.is_zero() {
+                    position.entry_price = Decimal::ZERO;
+                }
+                realized_profit
+            }
+            false => {
+                position.entry_price = (position.entry_price * position.amount + notional) / (position.amount + quantity);
+                position.amount += quantity;
+                Decimal::ZERO
+            }
+        };
+        position.realized_profit += realized_profit;
+        let position_closed = reduces && position.amount.is_zero();
+        self.wallet_balance += realized_profit - commission;
+
+        if order.close_position {
+            order.orig_qty = order.executed_qty + quantity;
+        }
+        order.executed_qty += quantity;
+        order.cum_quote += notional;
+        order.avg_price = order.cum_quote / order.executed_qty;
+        order.status = match order.executed_qty >= order.orig_qty {
+            true => OrderStatus::Filled,
+            false => OrderStatus::PartiallyFilled,
+        };
+        order.update_time = self.now_millis;
+        let trade = SimulatedFill {
+            trade_id: self.next_trade_id,
+            quantity,
+            price,
+            commission,
+            is_maker,
+            realized_profit,
+        };
+        self.next_trade_id += 1;
+        self.push_order_update(&simulated.order, ExecutionType::Trade, Some(&trade));
+        self.push_account_update(&simulated.order.symbol, &simulated.order.position_side);
+        if position_closed && simulated.order.status.is_open() {
+            self.close_order(simulated, OrderStatus::Expired);
+        }
+        quantity
+    }
+
+    fn close_order(&mut self, simulated: &mut SimulatedOrder, status: OrderStatus) {
+        let execution_type = match status {
+            OrderStatus::Canceled => ExecutionType::Canceled,
+            _ => ExecutionType::Expired,
+        };
+        simulated.order.status = status;
+        simulated.order.update_time = self.now_millis;
+        self.push_order_update(&simulated.order, execution_type, None);
+    }
+
+    fn push_order_update(&mut self, order: &Order, execution_type: ExecutionType, fill: Option<&SimulatedFill>) {
+        let update = OrderTradeUpdate {
+            event_time: self.now_millis,
+            transaction_time: self.now_millis,
+            order: WebsocketOrder {
+                symbol: order.symbol.clone(),
+                client_order_id: order.client_order_id.clone(),
+                side: order.side.clone(),
+                order_type: order.order_type.clone(),
+                time_in_force: order.time_in_force.clone(),
+                quantity: order.orig_qty,
+                price: order.price,
+                average_price: order.avg_price,
+                stop_price: order.stop_price,
+                execution_type,
+                order_status: order.status.clone(),
+                order_id: order.order_id.clone(),
+                order_last_filled_quantity: fill.map_or(Decimal::ZERO, |fill| fill.quantity),
+                order_filled_accumulated_quantity: order.executed_qty,
+                last_filled_price: fill.map_or(Decimal::ZERO, |fill| fill.price),
+                commission: fill.map(|fill| fill.commission),
+                commission_asset: fill.map(|_| self.asset.clone()),
+                order_trade_time: self.now_millis,
+                trade_id: fill.map_or(0, |fill| fill.trade_id),
+                bid_notional: Decimal::ZERO,
+                ask_notional: Decimal::ZERO,
+                is_maker: fill.is_some_and(|fill| fill.is_maker),
+                is_reduce: order.reduce_only,
+                working_type: order.working_type.clone(),
+                original_order_type: order.orig_type.clone(),
+                position_side: order.position_side.clone(),
+                close_position: order.close_position,
+                activation_price: Some(order.activate_price).filter(|_| order.orig_type == SupportedOrderType::TrailingStopMarket),
+                callback_rate: Some(order.price_rate).filter(|_| order.orig_type == SupportedOrderType::TrailingStopMarket),
+                price_protect: order.price_protect,
+                realized_profit: fill.map_or(Decimal::ZERO, |fill| fill.realized_profit),
+                stp_mode: SelfTradePreventionMode::None,
+                price_match: PriceMatch::None,
+                good_till_date: 0,
+            },
+        };
+        self.events.push(WebsocketEvent::OrderTradeUpdate(Box::new(update)));
+    }
+
+    fn push_account_update(&mut self, symbol: &str, position_side: &PositionSide) {
+        let position = self.get_position(symbol, position_side).cloned().unwrap_or_default();
+        let last_price = self.get_last_price(symbol).unwrap_or(position.entry_price);
+        let position_amount = match position_side {
+            PositionSide::Long => position.amount,
+            PositionSide::Short => -position.amount,
+        };
+        let update = AccountUpdate {
+            event_time: self.now_millis,
+            transaction_time: self.now_millis,
+            account: Account {
+                reason_type: ReasonType::Order,
+                balances: vec![Balance {
+                    asset: self.asset.clone(),
+                    wallet_balance: self.wallet_balance,
+                    cross_wallet_balance: self.wallet_balance,
+                    balance_change: Decimal::ZERO,
+                }],
+                positions: vec![Position {
+                    symbol: symbol.to_string(),
+                    position_amount,
+                    entry_price: position.entry_price,
+                    breakeven_price: position.entry_price,
+                    accumulated_realized: position.realized_profit,
+                    unrealized_profit: position.get_unrealized_profit(position_side, last_price),
+                    margin_type: MarginType::Cross,
+                    isolated_wallet: Decimal::ZERO,
+                    position_side: position_side.clone(),
+                }],
+            },
+        };
+        self.events.push(WebsocketEvent::AccountUpdate(Box::new(update)));
+    }
+}
+
+fn lock(exchange: &Mutex<SimulatedExchange>) -> MutexGuard<'_, SimulatedExchange> {
+    exchange.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The exchange is shared with the loop replaying the market data behind a lock
+impl OrderEntry for Mutex<SimulatedExchange> {
+    fn place_order(&self, order: OrderRequest) -> BoxFuture<'_, Result<Transaction>> { future::ready(lock(self).place_order(order)).boxed() }
+
+    // `CanceledOrderResponse` is `Order` itself under `backtest`
+    #[allow(clippy::useless_conversion)]
+    fn cancel_order(&self, cancellation: OrderCancellation) -> BoxFuture<'_, Result<CanceledOrderResponse>> {
+        future::ready(lock(self).cancel_order(cancellation).map(CanceledOrderResponse::from)).boxed()
+    }
+
+    fn cancel_all_open_orders(&self, symbol: String) -> BoxFuture<'_, Result<()>> {
+        lock(self).cancel_all_open_orders(&symbol);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+impl AccountQueries for Mutex<SimulatedExchange> {
+    fn get_order(&self, query: GetOrderRequest) -> BoxFuture<'_, Result<Order>> { future::ready(lock(self).get_order(&query)).boxed() }
+
+    fn get_open_orders(&self, symbol: Option<String>) -> BoxFuture<'_, Result<Vec<Order>>> {
+        future::ready(Ok(lock(self).get_open_orders(symbol.as_deref()))).boxed()
+    }
+
+    fn get_positions(&self, symbol: String) -> BoxFuture<'_, Result<Vec<PositionV3>>> { future::ready(Ok(lock(self).get_positions(&symbol))).boxed() }
+
+    fn get_balances(&self) -> BoxFuture<'_, Result<Vec<AccountBalance>>> { future::ready(Ok(vec![lock(self).get_balance()])).boxed() }
+}
+
+struct SimulatedFill {
+    trade_id: u64,
+    quantity: Decimal,
+    price: Decimal,
+    commission: Decimal,
+    is_maker: bool,
+    realized_profit: Decimal,
+}
+
+fn binance_error(code: BinanceErrorCode, msg: impl Into<String>) -> Error {
+    Error::BinanceError {
+        response: BinanceContentError::new(code, msg),
+    }
+}
+
+fn mandatory_parameter_error(parameter: &str) -> Error {
+    binance_error(
+        BinanceErrorCode::MandatoryParamEmptyOrMalformed,
+        format!("Mandatory parameter '{parameter}' was not sent, was empty/null, or malformed."),
+    )
+}
+
+fn is_multiple_of(value: Decimal, step: Decimal) -> bool {
+    step.is_zero() || (value % step).is_zero()
+}
+
+/// Whether a limit order at `limit_price` can be filled by a trade at `price`
+fn crosses(side: &OrderSide, limit_price: Decimal, price: Decimal) -> bool {
+    match side {
+        OrderSide::Buy => price <= limit_price,
+        OrderSide::Sell => price >= limit_price,
+    }
+}
+
+/// Whether a trade at `price` reaches the stop price of a stop or take profit order
+fn is_triggered(order_type: &SupportedOrderType, side: &OrderSide, stop_price: Decimal, price: Decimal) -> bool {
+    match (order_type, side) {
+        (SupportedOrderType::Stop | SupportedOrderType::StopMarket, OrderSide::Buy) => price >= stop_price,
+        (SupportedOrderType::Stop | SupportedOrderType::StopMarket, OrderSide::Sell) => price <= stop_price,
+        (SupportedOrderType::TakeProfit | SupportedOrderType::TakeProfitMarket, OrderSide::Buy) => price <= stop_price,
+        (SupportedOrderType::TakeProfit | SupportedOrderType::TakeProfitMarket, OrderSide::Sell) => price >= stop_price,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::Binance;
+    use crate::futures::account::FuturesAccount;
+
+    fn exchange() -> SimulatedExchange {
+        SimulatedExchange::new("USDT", dec!(1000)).with_symbol(SimulatedSymbol::new("BTCUSDT", dec!(0.1), dec!(0.001)))
+    }
+
+    fn order(side: OrderSide, order_type: SupportedOrderType, quantity: Option<Decimal>, price: Option<Decimal>) -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side,
+            position_side: Some(PositionSide::Long),
+            order_type,
+            time_in_force: None,
+            quantity,
+            reduce_only: None,
+            price,
+            stop_price: None,
+            close_position: None,
+            activation_price: None,
+            callback_rate: None,
+            working_type: None,
+            price_protect: None,
+            new_client_order_id: None,
+            grid_id: None,
+        }
+    }
+
+    fn error_code(result: Result<Transaction>) -> Option<BinanceErrorCode> {
+        result.err().and_then(|error| error.binance_error_code())
+    }
+
+    /// Only knows about the trading traits, not what is behind them
+    async fn buy_the_dip(entry: &dyn OrderEntry, queries: &dyn AccountQueries, price: Decimal) -> Result<Vec<Order>> {
+        entry.place_order(order(OrderSide::Buy, SupportedOrderType::Limit, Some(dec!(1)), Some(price))).await?;
+        queries.get_open_orders(Some("BTCUSDT".to_string())).await
+    }
+
+    #[test]
+    fn strategies_trade_through_the_traits() {
+        let exchange = Mutex::new(exchange());
+        lock(&exchange).on_trade_price("BTCUSDT", 1_000, dec!(100), None);
+        let open_orders = futures::executor::block_on(buy_the_dip(&exchange, &exchange, dec!(90))).unwrap();
+        assert_eq!(open_orders.len(), 1);
+
+        lock(&exchange).on_trade_price("BTCUSDT", 2_000, dec!(90), None);
+        let positions = futures::executor::block_on(exchange.get_positions("BTCUSDT".to_string())).unwrap();
+        assert_eq!(positions[0].position_amount, dec!(1));
+        let balances = futures::executor::block_on(exchange.get_balances()).unwrap();
+        assert_eq!(balances[0].balance, dec!(1000) - dec!(90) * DEFAULT_MAKER_FEE);
+    }
+
+    #[test]
+    fn live_and_simulated_venues_share_the_traits() {
+        let live: FuturesAccount = Binance::new(None, None);
+        let exchange = Mutex::new(exchange());
+        let venues: [&dyn OrderEntry; 2] = [&live, &exchange];
+        lock(&exchange).on_trade_price("BTCUSDT", 1_000, dec!(100), None);
+        let placed = futures::executor::block_on(venues[1].place_order(order(OrderSide::Buy, SupportedOrderType::Limit, Some(dec!(1)), Some(dec!(90))))).unwrap();
+
+        let cancellation = OrderCancellation {
+            symbol: "BTCUSDT".to_string(),
+            order_id: Some(placed.order_id.clone()),
+            orig_client_order_id: None,
+            new_client_order_id: None,
+            recv_window: None,
+        };
+        let canceled: CanceledOrderResponse = futures::executor::block_on(venues[1].cancel_order(cancellation)).unwrap();
+        assert_eq!((canceled.order_id, canceled.orig_qty), (placed.order_id, dec!(1)));
+        assert!(lock(&exchange).get_open_orders(Some("BTCUSDT")).is_empty());
+    }
+
+    #[test]
+    fn limit_orders_rest_and_fill_as_maker() {
+        let mut exchange = exchange();
+        exchange.on_trade_price("BTCUSDT", 1_000, dec!(100), Some(dec!(1)));
+        let limit = |price: Decimal, quantity: Decimal| order(OrderSide::Buy, SupportedOrderType::Limit, Some(quantity), Some(price));
+        assert_eq!(error_code(exchange.place_order(limit(dec!(99.05), dec!(1)))), Some(BinanceErrorCode::PriceNotIncreasedByTickSize));
+        assert_eq!(error_code(exchange.place_order(limit(dec!(99), dec!(0.0005)))), Some(BinanceErrorCode::BadPrecision));
+        let one_way = OrderRequest { position_side: None, ..limit(dec!(99), dec!(1)) };
+        assert_eq!(error_code(exchange.place_order(one_way)), Some(BinanceErrorCode::PositionSideNotMatch));
+
+        let placed = exchange.place_order(limit(dec!(99), dec!(1))).unwrap();
+        assert_eq!(placed.status, OrderStatus::New);
+        exchange.on_trade_price("BTCUSDT", 2_000, dec!(99), Some(dec!(0.4)));
+        assert_eq!(exchange.get_open_orders(Some("BTCUSDT"))[0].status, OrderStatus::PartiallyFilled);
+        exchange.on_trade_price("BTCUSDT", 3_000, dec!(98.9), Some(dec!(5)));
+        assert!(exchange.get_open_orders(None).is_empty());
+
+        let position = exchange.get_position("BTCUSDT", &PositionSide::Long).unwrap();
+        assert_eq!((position.amount, position.entry_price), (dec!(1), dec!(99)));
+        assert_eq!(exchange.get_wallet_balance(), dec!(1000) - dec!(99) * DEFAULT_MAKER_FEE);
+        let events = exchange.drain_events();
+        assert_eq!(events.len(), 5);
+        let WebsocketEvent::OrderTradeUpdate(update) = &events[3] else { panic!("expected an order update, got {:?}", events[3]) };
+        assert_eq!(update.order.order_status, OrderStatus::Filled);
+        assert_eq!((update.order.order_last_filled_quantity, update.order.last_filled_price), (dec!(0.6), dec!(99)));
+        assert!(update.order.is_maker);
+        assert!(matches!(&events[4], WebsocketEvent::AccountUpdate(update) if update.account.positions[0].position_amount == dec!(1)));
+    }
+
+    #[test]
+    fn stop_and_trailing_orders_close_positions() {
+        let mut exchange = exchange();
+        exchange.on_trade_price("BTCUSDT", 1_000, dec!(100), None);
+        exchange.place_order(order(OrderSide::Buy, SupportedOrderType::Market, Some(dec!(1)), None)).unwrap();
+        let stop = |stop_price: Decimal| OrderRequest {
+            stop_price: Some(stop_price),
+            close_position: Some(true),
+            ..order(OrderSide::Sell, SupportedOrderType::StopMarket, None, None)
+        };
+        assert_eq!(error_code(exchange.place_order(stop(dec!(101)))), Some(BinanceErrorCode::OrderWouldImmediatelyTrigger));
+        let stop_loss = exchange.place_order(stop(dec!(95))).unwrap();
+        let trailing = OrderRequest {
+            callback_rate: Some(dec!(1)),
+            ..order(OrderSide::Sell, SupportedOrderType::TrailingStopMarket, Some(dec!(1)), None)
+        };
+        exchange.place_order(trailing).unwrap();
+
+        // The trailing stop follows the high of the kline and triggers on the way down to its close
+        let kline = KlineSummary {
+            open_time: 60_000,
+            open: dec!(100),
+            high: dec!(110),
+            low: dec!(99.5),
+            close: dec!(105),
+            volume: dec!(10),
+            close_time: 119_999,
+            quote_asset_volume: dec!(1000),
+            number_of_trades: 10,
+            taker_buy_base_asset_volume: dec!(5),
+            taker_buy_quote_asset_volume: dec!(500),
+        };
+        exchange.on_kline("BTCUSDT", &kline);
+        let position = exchange.get_position("BTCUSDT", &PositionSide::Long).unwrap();
+        assert_eq!((position.amount, position.realized_profit), (dec!(0), dec!(5)));
+        assert_eq!(exchange.get_wallet_balance(), dec!(1000) + dec!(5) - dec!(205) * DEFAULT_TAKER_FEE);
+        let filled = exchange.get_all_orders("BTCUSDT").into_iter().find(|order| order.orig_type == SupportedOrderType::TrailingStopMarket).unwrap();
+        assert_eq!((filled.status, filled.order_type, filled.avg_price), (OrderStatus::Filled, SupportedOrderType::Market, dec!(105)));
+
+        let cancellation = OrderCancellation {
+            symbol: "BTCUSDT".to_string(),
+            order_id: Some(stop_loss.order_id),
+            orig_client_order_id: None,
+            new_client_order_id: None,
+            recv_window: None,
+        };
+        assert_eq!(exchange.cancel_order(cancellation.clone()).unwrap().status, OrderStatus::Canceled);
+        assert!(exchange.cancel_order(cancellation).is_err());
+        assert_eq!(exchange.now_millis(), 119_999);
+    }
+}
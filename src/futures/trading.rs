@@ -0,0 +1,188 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+use anyhow::anyhow;
+use futures::future::{self, BoxFuture, Either};
+use futures::FutureExt;
+use serde_json::Value;
+use crate::account::OrderCancellation;
+use crate::config::Config;
+use crate::errors::Result;
+use crate::futures::account::{FuturesAccount, GetOrderRequest, OrderRequest};
+use crate::futures::market::FuturesMarket;
+use crate::futures::rest_model::{AccountBalance, AggTrades, CanceledOrderResponse, GetKlinesParams, KlineSummaries, MarkPrice, Order, OrderBook, PositionV3, Transaction};
+use crate::futures::userstream::UserStream;
+use crate::futures::websockets::{ReconnectPolicy, WebSockets};
+use crate::futures::ws_model::WebsocketEvent;
+use crate::ws_model::CombinedStreamEvent;
+
+/// Listen keys expire after 60 minutes without a keep alive
+const LISTEN_KEY_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Receives the events of an `EventSubscription`, an error ends the subscription
+pub type EventHandler<'a, E> = Box<dyn FnMut(E) -> Result<()> + Send + 'a>;
+
+/// Sends and cancels orders, implemented by `FuturesAccount` and by the simulated exchange so that
+/// strategies can trade on either
+pub trait OrderEntry: Send + Sync {
+    fn place_order(&self, order: OrderRequest) -> BoxFuture<'_, Result<Transaction>>;
+
+    fn cancel_order(&self, cancellation: OrderCancellation) -> BoxFuture<'_, Result<CanceledOrderResponse>>;
+
+    fn cancel_all_open_orders(&self, symbol: String) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Orders, positions and balances of the account
+pub trait AccountQueries: Send + Sync {
+    fn get_order(&self, query: GetOrderRequest) -> BoxFuture<'_, Result<Order>>;
+
+    /// Open orders of `symbol`, of every symbol if `None`
+    fn get_open_orders(&self, symbol: Option<String>) -> BoxFuture<'_, Result<Vec<Order>>>;
+
+    fn get_positions(&self, symbol: String) -> BoxFuture<'_, Result<Vec<PositionV3>>>;
+
+    fn get_balances(&self) -> BoxFuture<'_, Result<Vec<AccountBalance>>>;
+}
+
+/// Market data queries
+pub trait MarketData: Send + Sync {
+    fn get_depth(&self, symbol: String) -> BoxFuture<'_, Result<OrderBook>>;
+
+    fn get_klines(&self, params: GetKlinesParams) -> BoxFuture<'_, Result<KlineSummaries>>;
+
+    /// Aggregated trades of `symbol` between `start_time` and `end_time`, in milliseconds
+    fn get_agg_trades(&self, symbol: String, start_time: Option<u64>, end_time: Option<u64>, limit: u16) -> BoxFuture<'_, Result<AggTrades>>;
+
+    fn get_mark_price(&self, symbol: String) -> BoxFuture<'_, Result<MarkPrice>>;
+}
+
+/// Subscriptions to the user data stream and to market streams. The returned futures deliver
+/// events to the handler until `running` is false, the handler fails or the connection drops.
+pub trait EventSubscription: Send + Sync {
+    fn subscribe_user_events<'a>(&'a self, handler: EventHandler<'a, WebsocketEvent>, running: &'a AtomicBool) -> BoxFuture<'a, Result<()>>;
+
+    /// Events of market `streams`, e.g. `websockets::agg_trade_stream("btcusdt")`, as JSON
+    fn subscribe_market_events<'a>(
+        &'a self,
+        streams: Vec<String>,
+        handler: EventHandler<'a, CombinedStreamEvent<Value>>,
+        running: &'a AtomicBool,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+impl OrderEntry for FuturesAccount {
+    fn place_order(&self, order: OrderRequest) -> BoxFuture<'_, Result<Transaction>> { FuturesAccount::place_order(self, order).boxed() }
+
+    fn cancel_order(&self, cancellation: OrderCancellation) -> BoxFuture<'_, Result<CanceledOrderResponse>> {
+        FuturesAccount::cancel_order(self, cancellation).boxed()
+    }
+
+    fn cancel_all_open_orders(&self, symbol: String) -> BoxFuture<'_, Result<()>> {
+        FuturesAccount::cancel_all_open_orders(self, symbol).boxed()
+    }
+}
+
+impl AccountQueries for FuturesAccount {
+    fn get_order(&self, query: GetOrderRequest) -> BoxFuture<'_, Result<Order>> { FuturesAccount::get_order(self, Some(query)).boxed() }
+
+    fn get_open_orders(&self, symbol: Option<String>) -> BoxFuture<'_, Result<Vec<Order>>> {
+        FuturesAccount::get_open_orders(self, symbol).boxed()
+    }
+
+    fn get_positions(&self, symbol: String) -> BoxFuture<'_, Result<Vec<PositionV3>>> { self.position_information_v3(symbol).boxed() }
+
+    fn get_balances(&self) -> BoxFuture<'_, Result<Vec<AccountBalance>>> { self.account_balance().boxed() }
+}
+
+impl MarketData for FuturesMarket {
+    fn get_depth(&self, symbol: String) -> BoxFuture<'_, Result<OrderBook>> { FuturesMarket::get_depth(self, symbol).boxed() }
+
+    fn get_klines(&self, params: GetKlinesParams) -> BoxFuture<'_, Result<KlineSummaries>> { FuturesMarket::get_klines(self, params).boxed() }
+
+    fn get_agg_trades(&self, symbol: String, start_time: Option<u64>, end_time: Option<u64>, limit: u16) -> BoxFuture<'_, Result<AggTrades>> {
+        FuturesMarket::get_agg_trades(self, symbol, None, start_time, end_time, limit).boxed()
+    }
+
+    fn get_mark_price(&self, symbol: String) -> BoxFuture<'_, Result<MarkPrice>> {
+        async move {
+            let mark_prices = self.get_mark_prices(Some(symbol.clone())).await?;
+            mark_prices.into_iter().next().ok_or_else(|| anyhow!("No mark price was returned for {symbol}").into())
+        }
+        .boxed()
+    }
+}
+
+/// Live user data and market streams of Binance futures
+#[derive(Clone)]
+pub struct FuturesStreams {
+    pub user_stream: UserStream,
+    pub config: Config,
+    /// How dropped connections are re-established, the event loop fails on the first drop if `None`
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
+impl FuturesStreams {
+    pub fn new(user_stream: UserStream, config: Config) -> Self {
+        Self {
+            user_stream,
+            config,
+            reconnect: None,
+        }
+    }
+
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Keeps the listen key alive until it fails
+    async fn keep_alive(&self, listen_key: &str) -> Result<()> {
+        loop {
+            tokio::time::sleep(LISTEN_KEY_KEEP_ALIVE_INTERVAL).await;
+            self.user_stream.keep_alive(listen_key).await?;
+        }
+    }
+
+    fn web_sockets<'a, WE: serde::de::DeserializeOwned + 'a>(&self, handler: EventHandler<'a, WE>) -> WebSockets<'a, WE> {
+        let web_sockets = WebSockets::new_with_options(handler, self.config.clone());
+        match &self.reconnect {
+            Some(policy) => web_sockets.with_reconnect(policy.clone()),
+            None => web_sockets,
+        }
+    }
+}
+
+impl EventSubscription for FuturesStreams {
+    /// Starts a listen key, keeps it alive while the events are delivered and closes it once done
+    fn subscribe_user_events<'a>(&'a self, handler: EventHandler<'a, WebsocketEvent>, running: &'a AtomicBool) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let listen_key = self.user_stream.start().await?.listen_key;
+            let mut web_sockets = self.web_sockets(handler);
+            web_sockets.connect(&listen_key).await?;
+            let result = match future::select(web_sockets.event_loop(running).boxed(), self.keep_alive(&listen_key).boxed()).await {
+                Either::Left((result, _)) => result,
+                Either::Right((result, _)) => result,
+            };
+            // The listen key expires on its own if it cannot be closed
+            let _ = web_sockets.disconnect().await;
+            let _ = self.user_stream.close(&listen_key).await;
+            result
+        }
+        .boxed()
+    }
+
+    fn subscribe_market_events<'a>(
+        &'a self,
+        streams: Vec<String>,
+        handler: EventHandler<'a, CombinedStreamEvent<Value>>,
+        running: &'a AtomicBool,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let mut web_sockets = self.web_sockets(handler);
+            web_sockets.connect_multiple(streams).await?;
+            let result = web_sockets.event_loop(running).await;
+            let _ = web_sockets.disconnect().await;
+            result
+        }
+        .boxed()
+    }
+}
@@ -1,6 +1,10 @@
 pub mod rules_map;
+pub mod rules_config;
 pub mod rule_period;
 pub mod rule_type;
 pub mod rule_size_params;
+pub mod rule_count_basis;
+pub mod rule_limit;
 pub mod rule_payload;
+pub mod rule_capacity;
 pub mod rule;
\ No newline at end of file
@@ -1,6 +1,8 @@
 use strum_macros::{Display, EnumString};
 use crate::errors;
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
 use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+use crate::futures::utils::order_tracker::OrderTracker;
 use crate::futures::utils::order_tracking_item::OrderTrackingItem;
 use std::time::Duration;
 use crate::futures::utils::top_n::TopNEntry;
@@ -12,10 +14,10 @@ pub enum ExpectedOrderRequestsRule {
 }
 
 impl ExpectedOrderRequestsRule {
-    pub fn validate(&self, order_request: &TopNEntry<OrderTrackingItem>) -> errors::Result<()> {
+    pub fn validate(&self, order_request: &TopNEntry<OrderTrackingItem>, tracker: &OrderTracker, context: &ExpectedOrderRequestsRuleContext) -> errors::Result<()> {
         match self {
-            ExpectedOrderRequestsRule::Global(global_rule) => global_rule.validate(order_request),
-            ExpectedOrderRequestsRule::PerGrid(per_grid_rule) => per_grid_rule.validate(order_request),
+            ExpectedOrderRequestsRule::Global(global_rule) => global_rule.validate(order_request, tracker, context, false),
+            ExpectedOrderRequestsRule::PerGrid(per_grid_rule) => per_grid_rule.validate(order_request, tracker, context, true),
         }
     }
     
@@ -26,6 +28,17 @@ impl ExpectedOrderRequestsRule {
         }
     }
     
+    /// Whether the rule only counts the orders of the grid of the validated order
+    pub fn is_grid_scoped(&self) -> bool {
+        matches!(self, ExpectedOrderRequestsRule::PerGrid(_))
+    }
+    
+    pub fn get_payload(&self) -> &ExpectedOrderRequestsRulePayload {
+        match self {
+            ExpectedOrderRequestsRule::Global(payload) | ExpectedOrderRequestsRule::PerGrid(payload) => payload,
+        }
+    }
+    
     pub fn get_duration(&self) -> errors::Result<Duration> {
         match self {
             ExpectedOrderRequestsRule::Global(global_rule) => global_rule.period.get_validated_duration(),
@@ -0,0 +1,96 @@
+use crate::errors::Result;
+use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::order_tracker::OrderTracker;
+
+/// How many more orders a rule allows at a point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedOrderRequestsRuleCapacity {
+    pub rule: ExpectedOrderRequestsRule,
+    /// Tracked orders counted by the rule within its period
+    pub used: u64,
+    pub max_count: u64,
+    /// Orders the rule still allows, `max_count - used`
+    pub headroom: u64,
+    /// Timestamp in nanoseconds when the oldest counted order leaves the rule period,
+    /// `None` when no order is counted
+    pub next_slot_free_at: Option<u64>,
+}
+
+impl ExpectedOrderRequestsRuleCapacity {
+    /// Capacity of `rule` at `now_nanos` for orders of `grid_id`, the grid only matters for `PerGrid` rules
+    pub fn new(rule: &ExpectedOrderRequestsRule, tracker: &OrderTracker, grid_id: Option<&String>, now_nanos: u64) -> Result<Self> {
+        let payload = rule.get_payload();
+        let min_timestamp = payload.get_min_timestamp(now_nanos)?;
+        let (counted, compacted) = tracker.count_orders(&payload.get_tracking_key(), payload, min_timestamp, grid_id, rule.is_grid_scoped()).unwrap_or_default();
+        let used = counted.count + compacted.count;
+        // Compacted orders are only known to leave the period once their whole bucket did
+        let next_slot_free_at = counted
+            .oldest
+            .into_iter()
+            .chain(compacted.oldest_end)
+            .min()
+            .map(|oldest| oldest + (now_nanos - min_timestamp));
+        Ok(Self {
+            rule: rule.clone(),
+            used,
+            max_count: payload.max_count,
+            headroom: payload.max_count.saturating_sub(used),
+            next_slot_free_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::futures::utils::order_tracking_request::OrderTrackingRequest;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+    use crate::futures::utils::expected_order_requests::rules_map::ExpectedOrderRequestsRules;
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::futures::utils::test_support::rule;
+    use crate::rest_model::OrderSide;
+
+    #[test]
+    fn capacity_of_matching_rules() {
+        let rules = ExpectedOrderRequestsRules::new();
+        let day = rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(24), 3);
+        let year = rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Weeks(52), 10);
+        rules.set_global_rules_for_symbol("BTCUSDT".to_string(), HashSet::from([day.clone(), year])).unwrap();
+        let tracker = OrderTracker::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let order = OrderTrackingRequest {
+            symbol: "BTCUSDT".to_string(),
+            quantity: Some(dec!(0.5)),
+            price: Some(dec!(100)),
+            ..OrderTrackingRequest::default()
+        };
+        let first = tracker.add_order_tracking_item(&order).unwrap();
+        let second = tracker.add_order_tracking_item(&order).unwrap();
+
+        let mut capacities = rules.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(0.5), None, &tracker, second.timestamp).unwrap();
+        capacities.sort_by_key(|capacity| capacity.max_count);
+        let day_nanos = Duration::from_secs(24 * 60 * 60).as_nanos() as u64;
+        assert_eq!(capacities[0], ExpectedOrderRequestsRuleCapacity {
+            rule: day,
+            used: 2,
+            max_count: 3,
+            headroom: 1,
+            next_slot_free_at: Some(first.timestamp + day_nanos),
+        });
+        assert_eq!((capacities[1].used, capacities[1].headroom), (2, 8));
+
+        // Once the first order left the day window only the second one is counted by the day rule
+        let capacities = rules.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(0.5), None, &tracker, first.timestamp + day_nanos + 1).unwrap();
+        let day_capacity = capacities.iter().find(|capacity| capacity.max_count == 3).unwrap();
+        assert_eq!((day_capacity.used, day_capacity.next_slot_free_at), (1, Some(second.timestamp + day_nanos)));
+
+        // Orders no rule matches have no capacity
+        assert!(rules.get_capacity("BTCUSDT", &OrderSide::Sell, dec!(0.5), None, &tracker, second.timestamp).unwrap().is_empty());
+        assert!(rules.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(2), None, &tracker, second.timestamp).unwrap().is_empty());
+        assert!(rules.get_capacity("ETHUSDT", &OrderSide::Buy, dec!(0.5), None, &tracker, second.timestamp).is_err());
+    }
+}
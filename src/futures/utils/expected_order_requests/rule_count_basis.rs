@@ -0,0 +1,44 @@
+use strum_macros::{Display, EnumString};
+use rust_decimal::Decimal;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+
+/// Which tracked orders a rule counts, and their notional
+#[derive(Deserialize, Serialize, Display, EnumString, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash, Default)]
+pub enum ExpectedOrderRequestsRuleCountBasis {
+    /// Every order sent, at its submitted size, whatever the exchange made of it
+    #[default]
+    Submitted,
+    /// Orders the exchange did not reject, at their submitted size. Orders the exchange did not
+    /// answer yet are counted.
+    Accepted,
+    /// Orders with fills, at their filled size
+    Filled,
+}
+
+impl ExpectedOrderRequestsRuleCountBasis {
+    pub const ALL: [ExpectedOrderRequestsRuleCountBasis; 3] = [
+        ExpectedOrderRequestsRuleCountBasis::Submitted,
+        ExpectedOrderRequestsRuleCountBasis::Accepted,
+        ExpectedOrderRequestsRuleCountBasis::Filled,
+    ];
+
+    pub fn is_submitted(&self) -> bool {
+        *self == ExpectedOrderRequestsRuleCountBasis::Submitted
+    }
+
+    pub fn counts(&self, item: &OrderTrackingItem) -> bool {
+        match self {
+            ExpectedOrderRequestsRuleCountBasis::Submitted => true,
+            ExpectedOrderRequestsRuleCountBasis::Accepted => !item.outcome.is_rejected(),
+            ExpectedOrderRequestsRuleCountBasis::Filled => !item.outcome.filled_size.is_zero(),
+        }
+    }
+
+    /// Price x size of the order on this basis
+    pub fn get_notional(&self, item: &OrderTrackingItem) -> Decimal {
+        match self {
+            ExpectedOrderRequestsRuleCountBasis::Submitted | ExpectedOrderRequestsRuleCountBasis::Accepted => item.price * item.size,
+            ExpectedOrderRequestsRuleCountBasis::Filled => item.outcome.filled_notional,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use strum_macros::Display;
+use rust_decimal::Decimal;
+use crate::errors::{Error, Result};
+use crate::futures::utils::order_tracker_retention::CompactedOrders;
+use crate::futures::utils::order_tracking_index::CountedOrders;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+
+/// Limits checked on top of the order count of a rule
+#[derive(Deserialize, Serialize, Display, PartialEq, Eq, Debug, Clone, Hash)]
+pub enum ExpectedOrderRequestsRuleLimit {
+    /// Maximum cumulative notional (price x size) of the matching orders within the rule period,
+    /// including the submitted order
+    Notional(Decimal),
+    /// Maximum deviation of the order price from the mark price, as a fraction of the mark price
+    /// e.g. 0.05 allows prices within 5% of the mark price
+    MarkPriceDeviation(Decimal),
+    /// Maximum position on the rule side once the order and the other open orders on that side
+    /// are filled, in base asset: the long position for buy rules and the short position for
+    /// sell rules
+    NetPosition(Decimal),
+}
+
+/// Market state some limits are checked against, fetched before the order is validated
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedOrderRequestsRuleContext {
+    pub mark_price: Option<Decimal>,
+    /// Last traded price of the symbol
+    pub last_price: Option<Decimal>,
+    /// Net position of the symbol, positive when long and negative when short
+    pub position_amount: Option<Decimal>,
+}
+
+impl ExpectedOrderRequestsRuleLimit {
+    pub fn needs_mark_price(&self) -> bool {
+        matches!(self, ExpectedOrderRequestsRuleLimit::MarkPriceDeviation(_))
+    }
+
+    pub fn needs_position(&self) -> bool {
+        matches!(self, ExpectedOrderRequestsRuleLimit::NetPosition(_))
+    }
+
+    /// `counted` are the tracked orders matching the rule within its period, including
+    /// the submitted one, `compacted` the older orders the rule counts and `open_size` the size
+    /// the other open tracked orders on the rule side may still fill
+    pub fn validate(
+        &self,
+        order_request: &TopNEntry<OrderTrackingItem>,
+        counted: &CountedOrders,
+        compacted: &CompactedOrders,
+        side: &OrderSide,
+        open_size: Decimal,
+        context: &ExpectedOrderRequestsRuleContext,
+    ) -> Result<()> {
+        match self {
+            ExpectedOrderRequestsRuleLimit::Notional(max_notional) => {
+                let notional = counted.notional + compacted.notional;
+                if notional > *max_notional {
+                    return Err(Error::ExpectedOrdersRuleViolated(format!("The submitted order brings the notional in the rule period to {notional}, above the max of {max_notional}")));
+                }
+            }
+            ExpectedOrderRequestsRuleLimit::MarkPriceDeviation(max_deviation) => {
+                let mark_price = match context.mark_price {
+                    Some(mark_price) if !mark_price.is_zero() => mark_price,
+                    _ => return Err(Error::ExpectedOrdersRuleViolated("A mark price is required to validate the price deviation".to_string())),
+                };
+                let deviation = (order_request.item.price - mark_price).abs() / mark_price;
+                if deviation > *max_deviation {
+                    return Err(Error::ExpectedOrdersRuleViolated(format!("The submitted order price {} deviates by {deviation} from the mark price {mark_price}, above the max of {max_deviation}", order_request.item.price)));
+                }
+            }
+            ExpectedOrderRequestsRuleLimit::NetPosition(max_position) => {
+                let position_amount = match context.position_amount {
+                    Some(position_amount) => position_amount,
+                    None => return Err(Error::ExpectedOrdersRuleViolated("The position is required to validate the net position".to_string())),
+                };
+                let signed_size = match order_request.item.side {
+                    OrderSide::Buy => order_request.item.size,
+                    OrderSide::Sell => -order_request.item.size,
+                };
+                let position_after = position_amount + signed_size;
+                // Resting and concurrent orders on the rule side may fill before the submitted one
+                let exposure = match side {
+                    OrderSide::Buy => position_after,
+                    OrderSide::Sell => -position_after,
+                } + open_size;
+                if exposure > *max_position {
+                    return Err(Error::ExpectedOrdersRuleViolated(format!("The submitted order and the open orders bring the {side:?} position to {exposure}, above the max of {max_position}")));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::futures::utils::test_support::entry;
+
+    #[test]
+    fn notional_limit() {
+        let second = entry(2, OrderSide::Buy, dec!(1), dec!(150));
+        let both = CountedOrders {
+            count: 2,
+            notional: dec!(350),
+            oldest: Some(1),
+        };
+        let only_second = CountedOrders {
+            count: 1,
+            notional: dec!(150),
+            oldest: Some(2),
+        };
+        let context = ExpectedOrderRequestsRuleContext::default();
+        let limit = ExpectedOrderRequestsRuleLimit::Notional(dec!(350));
+        assert!(limit.validate(&second, &both, &CompactedOrders::default(), &OrderSide::Buy, Decimal::ZERO, &context).is_ok());
+        let limit = ExpectedOrderRequestsRuleLimit::Notional(dec!(349));
+        assert!(limit.validate(&second, &both, &CompactedOrders::default(), &OrderSide::Buy, Decimal::ZERO, &context).is_err());
+        let compacted = CompactedOrders {
+            count: 1,
+            notional: dec!(200),
+            oldest_end: Some(0),
+        };
+        assert!(limit.validate(&second, &only_second, &compacted, &OrderSide::Buy, Decimal::ZERO, &context).is_err());
+    }
+
+    #[test]
+    fn mark_price_deviation_limit() {
+        let order = entry(1, OrderSide::Sell, dec!(1), dec!(104));
+        let limit = ExpectedOrderRequestsRuleLimit::MarkPriceDeviation(dec!(0.05));
+        let without_mark_price = ExpectedOrderRequestsRuleContext::default();
+        assert!(limit.validate(&order, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Sell, Decimal::ZERO, &without_mark_price).is_err());
+        let context = ExpectedOrderRequestsRuleContext {
+            mark_price: Some(dec!(100)),
+            last_price: None,
+            position_amount: None,
+        };
+        assert!(limit.validate(&order, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Sell, Decimal::ZERO, &context).is_ok());
+        let order = entry(1, OrderSide::Sell, dec!(1), dec!(94));
+        assert!(limit.validate(&order, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Sell, Decimal::ZERO, &context).is_err());
+    }
+
+    #[test]
+    fn net_position_limit() {
+        let limit = ExpectedOrderRequestsRuleLimit::NetPosition(dec!(3));
+        let context = ExpectedOrderRequestsRuleContext {
+            mark_price: None,
+            last_price: None,
+            position_amount: Some(dec!(-2)),
+        };
+        // Short 2, selling 1 more makes a short of 3
+        let sell = entry(1, OrderSide::Sell, dec!(1), dec!(100));
+        assert!(limit.validate(&sell, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Sell, Decimal::ZERO, &context).is_ok());
+        let sell = entry(1, OrderSide::Sell, dec!(1.5), dec!(100));
+        assert!(limit.validate(&sell, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Sell, Decimal::ZERO, &context).is_err());
+        // Buying reduces the short and stays well below a long of 3
+        let buy = entry(2, OrderSide::Buy, dec!(4), dec!(100));
+        assert!(limit.validate(&buy, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Buy, Decimal::ZERO, &context).is_ok());
+        let buy = entry(2, OrderSide::Buy, dec!(5.5), dec!(100));
+        assert!(limit.validate(&buy, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Buy, Decimal::ZERO, &context).is_err());
+        // Open orders on the rule side may fill first
+        let buy = entry(2, OrderSide::Buy, dec!(4), dec!(100));
+        assert!(limit.validate(&buy, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Buy, dec!(1), &context).is_ok());
+        assert!(limit.validate(&buy, &CountedOrders::default(), &CompactedOrders::default(), &OrderSide::Buy, dec!(1.5), &context).is_err());
+    }
+
+    #[test]
+    fn limits_are_optional_in_serialized_rules() {
+        use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+
+        let json = r#"{"Global":{"symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Hours":24},"side":"BUY"}}"#;
+        let rule: ExpectedOrderRequestsRule = serde_json::from_str(json).unwrap();
+        assert!(rule.get_payload().limits.is_empty());
+        assert_eq!(serde_json::to_string(&rule).unwrap(), json);
+
+        let mut payload = rule.get_payload().clone();
+        payload.limits = vec![
+            ExpectedOrderRequestsRuleLimit::Notional(dec!(1000)),
+            ExpectedOrderRequestsRuleLimit::MarkPriceDeviation(dec!(0.05)),
+            ExpectedOrderRequestsRuleLimit::NetPosition(dec!(3)),
+        ];
+        let rule = ExpectedOrderRequestsRule::PerGrid(payload);
+        let json = serde_json::to_string(&rule).unwrap();
+        assert!(json.contains(r#""limits":[{"Notional":"1000"},{"MarkPriceDeviation":"0.05"},{"NetPosition":"3"}]"#), "{json}");
+        assert_eq!(serde_json::from_str::<ExpectedOrderRequestsRule>(&json).unwrap(), rule);
+    }
+}
@@ -1,23 +1,39 @@
+use std::borrow::Cow;
+use rust_decimal::Decimal;
 use crate::errors;
 use crate::errors::Error;
+use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+use crate::futures::utils::expected_order_requests::rule_limit::{ExpectedOrderRequestsRuleContext, ExpectedOrderRequestsRuleLimit};
 use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
 use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
-use crate::futures::utils::order_tracker::get_gte_timestamp;
+use crate::futures::utils::order_tracker::OrderTracker;
 use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::order_tracking_request::OrderMarket;
 use crate::futures::utils::top_n::TopNEntry;
 use crate::rest_model::OrderSide;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
 pub struct ExpectedOrderRequestsRulePayload {
+    /// Market of the orders counted by this rule, futures unless set
+    #[serde(default, skip_serializing_if = "OrderMarket::is_futures")]
+    pub market: OrderMarket,
     pub symbol: String,
     pub size_params: ExpectedOrderRequestsRuleSizeParams,
     pub max_count: u64,
     pub period: RulePeriod,
     pub side: OrderSide,
+    /// Which tracked orders count towards `max_count` and the limits, all the submitted ones unless set
+    #[serde(default, skip_serializing_if = "ExpectedOrderRequestsRuleCountBasis::is_submitted")]
+    pub count_by: ExpectedOrderRequestsRuleCountBasis,
+    /// Extra limits checked on the orders counted by this rule
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub limits: Vec<ExpectedOrderRequestsRuleLimit>,
 }
 
 impl ExpectedOrderRequestsRulePayload {
-    pub fn validate(&self, order_request: &TopNEntry<OrderTrackingItem>) -> errors::Result<()> {
+    /// Counts the tracked orders matching this rule within its period.
+    /// When `grid_scoped`, only the orders of the submitted order's grid are counted.
+    pub fn validate(&self, order_request: &TopNEntry<OrderTrackingItem>, tracker: &OrderTracker, context: &ExpectedOrderRequestsRuleContext, grid_scoped: bool) -> errors::Result<()> {
         if order_request.item.size.is_zero() {
             return Err(Error::ExpectedOrdersRuleViolated("Order size is zero".to_string()));
         }
@@ -26,47 +42,68 @@ impl ExpectedOrderRequestsRulePayload {
             return Err(Error::ExpectedOrdersRuleViolated("Max count is zero".to_string()));
         }
         
-        let min_timestamp = match self.period.get_min_nanos_timestamp(order_request.timestamp) {
-            Ok(timestamp) => timestamp,
-            Err(error) => return Err(Error::ExpectedOrdersRuleViolated(format!("Failed to get min timestamp for order request: {}", error.get_msg()))),
-        };
-        let orders_in_period = match get_gte_timestamp(&self.symbol, min_timestamp) {
-            Some(orders) => orders,
-            None => return Err(Error::ExpectedOrdersRuleViolated(format!("Order tracker is not tracking symbol {}", self.symbol))),
-        };
-        let mut counter = 0;
-        let mut found_submitted_order_in_tracker = false;
-        for tracking_wrapper in orders_in_period.iter() {
-            if self.matches_order(tracking_wrapper) {
-                continue;
-            }
-            
-            if tracking_wrapper.item.id == *order_request.item.id {
-                found_submitted_order_in_tracker = true;
-            }
-            
-            counter += 1;
-        }
-        
-        if !found_submitted_order_in_tracker {
+        let min_timestamp = self.get_min_timestamp(order_request.timestamp)?;
+        let tracking_key = self.get_tracking_key();
+        if !tracker.contains(&tracking_key, order_request) {
             return Err(Error::ExpectedOrdersRuleViolated("Expected the submitted order to be in the tracker but it was not found by id".to_string()));
         }
-        if counter > self.max_count {
+        let (mut counted, compacted) = match tracker.count_orders(&tracking_key, self, min_timestamp, order_request.item.grid_id.as_ref(), grid_scoped) {
+            Some(counts) => counts,
+            None => return Err(Error::ExpectedOrdersRuleViolated(format!("Order tracker is not tracking {tracking_key}"))),
+        };
+        if !self.count_by.counts(&order_request.item) {
+            // The submitted order is counted as if it was filled in full
+            counted.add_entry(order_request, order_request.item.price * order_request.item.size);
+        }
+        if counted.count + compacted.count > self.max_count {
             return Err(Error::ExpectedOrdersRuleViolated("The submitted order violates the max count rule".to_string()));
         }
         
+        let open_size = if self.limits.iter().any(ExpectedOrderRequestsRuleLimit::needs_position) {
+            tracker.get_open_size(&tracking_key, &self.side, order_request)
+        } else {
+            Decimal::ZERO
+        };
+        for limit in self.limits.iter() {
+            limit.validate(order_request, &counted, &compacted, &self.side, open_size, context)?;
+        }
+        
         Ok(())
     }
     
+    /// Key the rules and tracked orders of this rule's market and symbol are stored under,
+    /// see `OrderMarket::get_tracking_key`
+    pub fn get_tracking_key(&self) -> Cow<'_, str> {
+        self.market.get_tracking_key(&self.symbol)
+    }
+
+    /// Start of the rule period ending at `now_nanos`
+    pub fn get_min_timestamp(&self, now_nanos: u64) -> errors::Result<u64> {
+        match self.period.get_min_nanos_timestamp(now_nanos) {
+            Ok(timestamp) => Ok(timestamp),
+            Err(error) => Err(Error::ExpectedOrdersRuleViolated(format!("Failed to get min timestamp for order request: {}", error.get_msg()))),
+        }
+    }
+
+    /// Whether this rule counts `order` when validating an order of `grid_id`, on its count basis.
+    /// When `grid_scoped`, only the orders of `grid_id` are counted.
+    pub fn counts_order(&self, order: &TopNEntry<OrderTrackingItem>, grid_id: Option<&String>, grid_scoped: bool) -> bool {
+        self.matches_order(order) && self.count_by.counts(&order.item) && (!grid_scoped || order.item.grid_id.as_ref() == grid_id)
+    }
+    
     pub fn matches_order(&self, order: &TopNEntry<OrderTrackingItem>) -> bool {
-        if order.item.side != self.side {
+        self.matches(&order.item.side, order.item.size)
+    }
+
+    pub fn matches(&self, side: &OrderSide, size: Decimal) -> bool {
+        if *side != self.side {
             return false;
         }
         
         match self.size_params {
-            ExpectedOrderRequestsRuleSizeParams::Min(min_size) => order.item.size >= min_size,
-            ExpectedOrderRequestsRuleSizeParams::Max(max_size) => order.item.size <= max_size,
-            ExpectedOrderRequestsRuleSizeParams::MinMax { min, max } => order.item.size >= min && order.item.size <= max,
+            ExpectedOrderRequestsRuleSizeParams::Min(min_size) => size >= min_size,
+            ExpectedOrderRequestsRuleSizeParams::Max(max_size) => size <= max_size,
+            ExpectedOrderRequestsRuleSizeParams::MinMax { min, max } => size >= min && size <= max,
         }
     }
 }
\ No newline at end of file
@@ -43,6 +43,7 @@ impl RulePeriod {
     pub fn get_min_nanos_timestamp(&self, now_nanos: u64) -> Result<u64> {
         let duration = self.get_validated_duration()?;
         let selected_nanos = duration.as_nanos() as u64;
-        Ok(now_nanos - selected_nanos)
+        // Simulated clocks can start close to the epoch
+        Ok(now_nanos.saturating_sub(selected_nanos))
     }
 }
\ No newline at end of file
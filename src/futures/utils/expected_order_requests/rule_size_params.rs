@@ -18,4 +18,26 @@ impl Default for ExpectedOrderRequestsRuleSizeParams {
             max: Decimal::MAX,
         }
     }
-}
\ No newline at end of file
+}
+impl ExpectedOrderRequestsRuleSizeParams {
+    /// Smallest matching size, inclusive
+    pub fn get_min(&self) -> Option<Decimal> {
+        match self {
+            ExpectedOrderRequestsRuleSizeParams::Min(min) | ExpectedOrderRequestsRuleSizeParams::MinMax { min, .. } => Some(*min),
+            ExpectedOrderRequestsRuleSizeParams::Max(_) => None,
+        }
+    }
+
+    /// Largest matching size, inclusive
+    pub fn get_max(&self) -> Option<Decimal> {
+        match self {
+            ExpectedOrderRequestsRuleSizeParams::Max(max) | ExpectedOrderRequestsRuleSizeParams::MinMax { max, .. } => Some(*max),
+            ExpectedOrderRequestsRuleSizeParams::Min(_) => None,
+        }
+    }
+
+    /// Whether some size between `min_size` and `max_size` matches
+    pub fn intersects(&self, min_size: Decimal, max_size: Decimal) -> bool {
+        self.get_min().is_none_or(|min| max_size >= min) && self.get_max().is_none_or(|max| min_size <= max)
+    }
+}
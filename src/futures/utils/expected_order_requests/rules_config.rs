@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use anyhow::anyhow;
+use crate::errors::Result;
+use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::expected_order_requests::rules_map::{ExpectedOrderRequestsRuleMap, ExpectedOrderRequestsRules};
+
+/// Expected order requests rules of all symbols, as stored in a JSON or TOML file
+///
+/// Rules are grouped by the market and symbol of their payload, see `OrderMarket::get_tracking_key`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedOrderRequestsRulesConfig {
+    pub rules: Vec<ExpectedOrderRequestsRule>,
+}
+
+impl ExpectedOrderRequestsRulesConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        match serde_json::from_str(json) {
+            Ok(config) => Ok(config),
+            Err(error) => Err(anyhow!("Failed to parse expected order requests rules json: {error}").into()),
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        match toml::from_str(toml) {
+            Ok(config) => Ok(config),
+            Err(error) => Err(anyhow!("Failed to parse expected order requests rules toml: {error}").into()),
+        }
+    }
+
+    /// Parses `.toml` files as TOML, with the `toml` feature, and any other file as JSON
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) => return Err(anyhow!("Failed to read expected order requests rules file {path:?}: {error:?}").into()),
+        };
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&content),
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => Err(anyhow!("The toml feature is required to load expected order requests rules file {path:?}").into()),
+            _ => Self::from_json(&content),
+        }
+    }
+
+    pub fn into_rule_map(self) -> ExpectedOrderRequestsRuleMap {
+        let mut rule_map = ExpectedOrderRequestsRuleMap::new();
+        for rule in self.rules {
+            rule_map.entry(rule.get_payload().get_tracking_key().into_owned()).or_default().insert(rule);
+        }
+        rule_map
+    }
+}
+
+impl ExpectedOrderRequestsRules {
+    /// Replaces the rules of all symbols with the rules of a configuration file, see `load`.
+    /// An invalid file leaves the installed rules untouched.
+    pub fn load_file(&self, path: &Path) -> Result<u64> {
+        self.load(ExpectedOrderRequestsRulesConfig::from_file(path)?.into_rule_map())
+    }
+}
+
+/// Reloads the rules whenever their configuration file changes, until dropped
+///
+/// Invalid files are reported and skipped, the last valid rules stay installed.
+#[derive(Debug)]
+pub struct ExpectedOrderRequestsRulesWatcher {
+    path: PathBuf,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+fn get_modified(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl ExpectedOrderRequestsRulesWatcher {
+    /// Loads the file into `rules` and checks it for changes every `interval`.
+    /// Fails if the file cannot be loaded in the first place.
+    pub fn new(rules: Arc<ExpectedOrderRequestsRules>, path: impl Into<PathBuf>, interval: Duration) -> Result<Self> {
+        let path = path.into();
+        let mut last_modified = get_modified(&path);
+        rules.load_file(&path)?;
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let watched_path = path.clone();
+        let handle = thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+            let modified = get_modified(&watched_path);
+            if modified.is_none() || modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+            if let Err(error) = rules.load_file(&watched_path) {
+                eprintln!("rejected expected order requests rules file {watched_path:?}, keeping version {}: {error:?}", rules.version())
+            }
+        });
+        Ok(Self {
+            path,
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ExpectedOrderRequestsRulesWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Error;
+
+    fn rule_json(rule_type: &str, symbol: &str, period: &str) -> String {
+        format!(r#"{{"{rule_type}":{{"symbol":"{symbol}","size_params":{{"Max":"1"}},"max_count":10,"period":{period},"side":"BUY"}}}}"#)
+    }
+
+    fn config_json(rules: &[String]) -> String {
+        format!(r#"{{"rules":[{}]}}"#, rules.join(","))
+    }
+
+    #[test]
+    fn load_validates_and_swaps_all_symbols() {
+        let rules = ExpectedOrderRequestsRules::new();
+        let valid = config_json(&[
+            rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#),
+            rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#),
+            rule_json("PerGrid", "BTCUSDT", r#"{"Hours":1}"#),
+            rule_json("Global", "ETHUSDT", r#"{"Days":1}"#),
+            rule_json("Global", "ETHUSDT", r#"{"Weeks":60}"#),
+        ]);
+        let config = ExpectedOrderRequestsRulesConfig::from_json(&valid).unwrap();
+        assert_eq!(rules.load(config.into_rule_map()).unwrap(), 1);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 3);
+        assert_eq!(rules.get_rules("ETHUSDT").unwrap().len(), 2);
+
+        // ETHUSDT misses its 52 weeks rule, nothing is installed
+        let invalid = config_json(&[
+            rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#),
+            rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#),
+            rule_json("Global", "ETHUSDT", r#"{"Days":1}"#),
+        ]);
+        let config = ExpectedOrderRequestsRulesConfig::from_json(&invalid).unwrap();
+        assert!(matches!(rules.load(config.into_rule_map()), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert!(ExpectedOrderRequestsRulesConfig::from_json("{").is_err());
+        assert_eq!(rules.version(), 1);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 3);
+
+        let btc_only = config_json(&[
+            rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#),
+            rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#),
+        ]);
+        let config = ExpectedOrderRequestsRulesConfig::from_json(&btc_only).unwrap();
+        assert_eq!(rules.load(config.into_rule_map()).unwrap(), 2);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 2);
+        assert!(rules.get_rules("ETHUSDT").is_none());
+
+        assert_eq!(rules.rollback().unwrap(), 3);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 3);
+        assert_eq!(rules.get_rules("ETHUSDT").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn set_rules_validates_the_resulting_set() {
+        let rule_set = |rules: &[String]| ExpectedOrderRequestsRulesConfig::from_json(&config_json(rules)).unwrap().into_rule_map().remove("BTCUSDT").unwrap();
+        let rules = ExpectedOrderRequestsRules::new();
+
+        // Global rules missing the 52 weeks rule are not installed
+        let day_only = rule_set(&[rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#)]);
+        assert!(matches!(rules.set_global_rules_for_symbol("BTCUSDT".to_string(), day_only), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(rules.version(), 0);
+        assert!(rules.get_rules("BTCUSDT").is_none());
+
+        let globals = rule_set(&[rule_json("Global", "BTCUSDT", r#"{"Hours":72}"#), rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#)]);
+        assert!(matches!(rules.set_global_rules_for_symbol("BTCUSDT".to_string(), globals), Err(Error::ExpectedOrdersRuleViolated(_))));
+        let globals = rule_set(&[rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#), rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#)]);
+        rules.set_global_rules_for_symbol("BTCUSDT".to_string(), globals).unwrap();
+        assert_eq!(rules.version(), 1);
+
+        let per_grid = rule_set(&[rule_json("PerGrid", "BTCUSDT", r#"{"Hours":1}"#)]);
+        rules.set_rules_for_symbol("BTCUSDT".to_string(), per_grid).unwrap();
+        assert_eq!(rules.version(), 2);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 3);
+
+        // Replacing the global rules validates them together with the other rules
+        let year_only = rule_set(&[rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#)]);
+        rules.set_global_rules_for_symbol("BTCUSDT".to_string(), year_only).unwrap();
+        assert_eq!(rules.version(), 3);
+        let three_days = rule_set(&[rule_json("PerGrid", "BTCUSDT", r#"{"Hours":72}"#)]);
+        assert!(matches!(rules.set_rules_for_symbol("BTCUSDT".to_string(), three_days), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(rules.version(), 3);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 2);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn parses_toml() {
+        let toml = r#"
+            [[rules]]
+            [rules.Global]
+            symbol = "BTCUSDT"
+            size_params = { Max = "1" }
+            max_count = 10
+            period = { Hours = 24 }
+            side = "BUY"
+        "#;
+        let config = ExpectedOrderRequestsRulesConfig::from_toml(toml).unwrap();
+        let json = config_json(&[rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#)]);
+        assert_eq!(config, ExpectedOrderRequestsRulesConfig::from_json(&json).unwrap());
+    }
+
+    #[test]
+    fn watcher_reloads_changed_file() {
+        let dir = std::env::temp_dir().join(format!("rules_config_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rules.json");
+        let day = rule_json("Global", "BTCUSDT", r#"{"Hours":24}"#);
+        let year = rule_json("Global", "BTCUSDT", r#"{"Weeks":52}"#);
+        std::fs::write(&path, config_json(&[day.clone(), year.clone()])).unwrap();
+
+        let rules = Arc::new(ExpectedOrderRequestsRules::new());
+        let watcher = ExpectedOrderRequestsRulesWatcher::new(rules.clone(), &path, Duration::from_millis(10)).unwrap();
+        assert_eq!(rules.version(), 1);
+
+        std::fs::write(&path, config_json(std::slice::from_ref(&day))).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(rules.version(), 1);
+
+        std::fs::write(&path, config_json(&[day, year, rule_json("PerGrid", "BTCUSDT", r#"{"Hours":1}"#)])).unwrap();
+        let mut waited = 0;
+        while rules.version() == 1 && waited < 100 {
+            thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+        assert_eq!(rules.version(), 2);
+        assert_eq!(rules.get_rules("BTCUSDT").unwrap().len(), 3);
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,78 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 use anyhow::anyhow;
-use dashmap::DashMap;
-use once_cell::sync::Lazy;
 use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::expected_order_requests::rule_capacity::ExpectedOrderRequestsRuleCapacity;
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
 use crate::errors::{Error, Result};
+use crate::futures::utils::order_guard::OrderGuard;
+use crate::futures::utils::order_tracker::OrderTracker;
 use crate::futures::utils::order_tracking_item::OrderTrackingItem;
 use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+use rust_decimal::Decimal;
 
 type OrderRequestSymbol = String;
-type ExpectedOrderRequestsRuleMap = DashMap<OrderRequestSymbol, HashSet<ExpectedOrderRequestsRule>>;
+pub type ExpectedOrderRequestsRuleMap = HashMap<OrderRequestSymbol, HashSet<ExpectedOrderRequestsRule>>;
 
-static EXPECTED_ORDER_REQUESTS_RULES: Lazy<ExpectedOrderRequestsRuleMap> = Lazy::new(ExpectedOrderRequestsRuleMap::new);
+/// Most rules a symbol can have, the rules an order was validated against are kept as a bit set
+pub const MAX_RULES_PER_SYMBOL: usize = 64;
+
+#[derive(Debug, Default, Clone)]
+struct VersionedRules {
+    version: u64,
+    rules: Arc<ExpectedOrderRequestsRuleMap>,
+    /// The rules of each symbol in a fixed order, so that orders are validated without copying them
+    ordered: Arc<HashMap<OrderRequestSymbol, Arc<[ExpectedOrderRequestsRule]>>>,
+}
+
+impl VersionedRules {
+    fn new(version: u64, rules: Arc<ExpectedOrderRequestsRuleMap>) -> Self {
+        let ordered = rules.iter().map(|(symbol, rules)| (symbol.clone(), rules.iter().cloned().collect())).collect();
+        Self {
+            version,
+            rules,
+            ordered: Arc::new(ordered),
+        }
+    }
+}
+
+/// Rules an order was validated against, sharing the rules installed when it was checked
+#[derive(Debug, Clone, Default)]
+pub struct ValidatedRules {
+    rules: Arc<[ExpectedOrderRequestsRule]>,
+    /// Bit `i` is set when `rules[i]` was validated
+    validated: u64,
+}
+
+impl ValidatedRules {
+    pub fn iter(&self) -> impl Iterator<Item = &ExpectedOrderRequestsRule> {
+        self.rules.iter().enumerate().filter(|(index, _)| self.validated & (1 << index) != 0).map(|(_, rule)| rule)
+    }
+
+    pub fn len(&self) -> usize { self.validated.count_ones() as usize }
+
+    pub fn is_empty(&self) -> bool { self.validated == 0 }
+
+    pub fn to_vec(&self) -> Vec<ExpectedOrderRequestsRule> { self.iter().cloned().collect() }
+}
+
+/// Expected order requests rules per symbol
+///
+/// Spot and margin rules are stored under the tracking key of their market and symbol, e.g.
+/// `spot.BTCUSDT`, see `OrderMarket::get_tracking_key`. Futures rules under the bare symbol.
+///
+/// Global rules are the hard limits of a symbol and can only be set with
+/// `set_global_rules_for_symbol`, `set_rules_for_symbol` replaces the other rules around them.
+/// `load` replaces the rules of all symbols at once, e.g. from a configuration file.
+///
+/// Every change installs a new version of the rules, the previous one is kept for `rollback`.
+#[derive(Debug, Default)]
+pub struct ExpectedOrderRequestsRules {
+    current: RwLock<VersionedRules>,
+    previous: RwLock<Option<VersionedRules>>,
+}
 
 fn validate_rule_set_durations(symbol: &OrderRequestSymbol, rules: &HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
     let mut durations = vec![];
@@ -48,6 +110,9 @@ fn validate_rule_set_durations(symbol: &OrderRequestSymbol, rules: &HashSet<Expe
 }
 
 fn validate_rule_set(symbol: &OrderRequestSymbol, rules: &HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
+    if rules.len() > MAX_RULES_PER_SYMBOL {
+        return Err(Error::ExpectedOrdersRuleViolated(format!("{} rules set for symbol {symbol}, at most {MAX_RULES_PER_SYMBOL} are supported", rules.len())));
+    }
     let found_global_rules = rules.iter().any(|rule| matches!(rule, ExpectedOrderRequestsRule::Global(_)));
     if !found_global_rules {
         return Err(Error::ExpectedOrdersRuleViolated(format!("No global expected order requests rules found for symbol {symbol}")));
@@ -57,66 +122,209 @@ fn validate_rule_set(symbol: &OrderRequestSymbol, rules: &HashSet<ExpectedOrderR
     Ok(())
 }
 
-pub fn validate_order_request(symbol: &OrderRequestSymbol, tracking_item_wrapper: &TopNEntry<OrderTrackingItem>) -> Result<Vec<ExpectedOrderRequestsRule>> {
-    let rules = match EXPECTED_ORDER_REQUESTS_RULES.get(symbol) {
-        Some(rules) => rules.clone(),
-        None => return Err(Error::ExpectedOrdersRuleViolated(format!("No expected order requests rules found for symbol {symbol}")))
-    };
-    validate_rule_set(symbol, &rules)?;
-    
-    let matching_rules = rules.iter().filter(|rule| rule.matches_order(tracking_item_wrapper)).collect::<Vec<&ExpectedOrderRequestsRule>>();
-    if matching_rules.is_empty() {
-        return Err(Error::ExpectedOrdersRuleViolated(format!("No matching expected order requests rules found for order request {tracking_item_wrapper:?}")));
+impl ExpectedOrderRequestsRules {
+    pub fn new() -> Self {
+        Self::default()
     }
-    
-    let mut validated_rules = vec![];
-    for rule in matching_rules.into_iter() {
-        if let Err(error) = rule.validate(tracking_item_wrapper) {
-            let error_msg = error.get_msg();
-            return Err(Error::ExpectedOrdersRuleViolated(format!("{symbol} order request violates rule. Error: {error_msg}. Order: {tracking_item_wrapper:?}, Rule: {rule:?}")));
-        } else {
-            validated_rules.push(rule.clone());
+
+    pub fn get_rules(&self, symbol: &str) -> Option<HashSet<ExpectedOrderRequestsRule>> {
+        self.snapshot().get(symbol).cloned()
+    }
+
+    /// The rules of all symbols, unaffected by later changes
+    pub fn snapshot(&self) -> Arc<ExpectedOrderRequestsRuleMap> {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).rules.clone()
+    }
+
+    /// The rules of `symbol` in the order orders are validated against them
+    fn ordered_rules(&self, symbol: &str) -> Option<Arc<[ExpectedOrderRequestsRule]>> {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).ordered.get(symbol).cloned()
+    }
+
+    /// Version of the installed rules, 0 until rules are first set and bumped on every change
+    pub fn version(&self) -> u64 {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).version
+    }
+
+    /// Replaces the rules of all symbols at once, symbols missing from `rules` lose their rules.
+    /// Every rule set is validated first and nothing is installed if one is invalid.
+    /// Returns the new version.
+    pub fn load(&self, rules: ExpectedOrderRequestsRuleMap) -> Result<u64> {
+        for (symbol, symbol_rules) in rules.iter() {
+            if let Some(rule) = symbol_rules.iter().find(|rule| rule.get_payload().get_tracking_key() != symbol.as_str()) {
+                return Err(Error::ExpectedOrdersRuleViolated(format!("Rule of {} listed under {symbol}. Rule: {rule:?}", rule.get_payload().get_tracking_key())));
+            }
         }
+        self.update(|current| {
+            *current = rules;
+            Ok(())
+        })
     }
-    Ok(validated_rules)
-}
 
-pub fn set_rules_for_symbol(symbol: OrderRequestSymbol, rules: HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
-    if rules.is_empty() {
-        return Err(anyhow!("No rules provided for symbol {symbol}").into());
+    /// Reinstalls the rules replaced by the last change, returns the new version
+    pub fn rollback(&self) -> Result<u64> {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut previous = self.previous.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let restored = match previous.take() {
+            Some(restored) => restored,
+            None => return Err(anyhow!("No previous expected order requests rules to roll back to").into()),
+        };
+        let version = current.version + 1;
+        *previous = Some(std::mem::replace(&mut *current, VersionedRules { version, ..restored }));
+        Ok(version)
     }
-    
-    let mut existing_rules = EXPECTED_ORDER_REQUESTS_RULES.entry(symbol.clone()).or_default();
-    if existing_rules.is_empty() {
-        return Err(anyhow!("No rules found for symbol {symbol}. Should be hardcoded before calling this function").into());
+
+    /// Applies `change` to a copy of the rules and installs the copy if it succeeds and every rule
+    /// set of the copy is valid, so that orders are checked against valid rule sets only
+    fn update(&self, change: impl FnOnce(&mut ExpectedOrderRequestsRuleMap) -> Result<()>) -> Result<u64> {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut rules = (*current.rules).clone();
+        change(&mut rules)?;
+        for (symbol, symbol_rules) in rules.iter() {
+            validate_rule_set(symbol, symbol_rules)?;
+        }
+        let version = current.version + 1;
+        let replaced = std::mem::replace(&mut *current, VersionedRules::new(version, Arc::new(rules)));
+        *self.previous.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(replaced);
+        Ok(version)
     }
-    
-    let mut global_rules = vec![];
-    for rule in existing_rules.iter() {
-        if let ExpectedOrderRequestsRule::Global(_) = rule {
-            global_rules.push(rule.clone());
+
+    /// Replaces the global rules of `symbol`, keeping its other rules.
+    /// Nothing is installed if the resulting rule set is invalid.
+    pub fn set_global_rules_for_symbol(&self, symbol: OrderRequestSymbol, rules: HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
+        if rules.is_empty() {
+            return Err(anyhow!("No global rules provided for symbol {symbol}").into());
+        }
+        if rules.iter().any(|rule| !matches!(rule, ExpectedOrderRequestsRule::Global(_))) {
+            return Err(anyhow!("Only global rules should be submitted into this function").into());
         }
+
+        self.update(|current| {
+            let existing_rules = current.entry(symbol).or_default();
+            existing_rules.retain(|rule| !matches!(rule, ExpectedOrderRequestsRule::Global(_)));
+            existing_rules.extend(rules);
+            Ok(())
+        })?;
+        Ok(())
     }
-    
-    if global_rules.is_empty() {
-        return Err(anyhow!("No global rules found for symbol {symbol}. Should be hardcoded and set into some static before calling this function").into());
+
+    /// Whether some rule of `symbol` is checked against the mark price
+    pub fn needs_mark_price(&self, symbol: &str) -> bool {
+        self.snapshot().get(symbol).is_some_and(|rules| rules.iter().any(|rule| rule.get_payload().limits.iter().any(|limit| limit.needs_mark_price())))
     }
-    
-    let mut new_set: HashSet<ExpectedOrderRequestsRule> = HashSet::new();
-    for rule in global_rules.iter() {
-        new_set.insert(rule.clone());
+
+    /// Whether some rule of `symbol` is checked against the current position
+    pub fn needs_position(&self, symbol: &str) -> bool {
+        self.snapshot().get(symbol).is_some_and(|rules| rules.iter().any(|rule| rule.get_payload().limits.iter().any(|limit| limit.needs_position())))
     }
-    
-    for rule in rules.iter() {
-        if let ExpectedOrderRequestsRule::Global(_) = rule {
-            return Err(anyhow!("Global rules should not be submitted into this function, they should be hardcoded").into())
+
+    /// Validates the order against the rules of `symbol` it matches, which all have to allow it
+    pub fn validate_order_request(&self, symbol: &OrderRequestSymbol, tracking_item_wrapper: &TopNEntry<OrderTrackingItem>, tracker: &OrderTracker, context: &ExpectedOrderRequestsRuleContext) -> Result<ValidatedRules> {
+        let rules = match self.ordered_rules(symbol) {
+            Some(rules) => rules,
+            None => return Err(Error::ExpectedOrdersRuleViolated(format!("No expected order requests rules found for symbol {symbol}")))
+        };
+
+        let mut validated = 0;
+        for (index, rule) in rules.iter().enumerate().filter(|(_, rule)| rule.matches_order(tracking_item_wrapper)) {
+            if let Err(error) = rule.validate(tracking_item_wrapper, tracker, context) {
+                let error_msg = error.get_msg();
+                return Err(Error::ExpectedOrdersRuleViolated(format!("{symbol} order request violates rule. Error: {error_msg}. Order: {tracking_item_wrapper:?}, Rule: {rule:?}")));
+            }
+            validated |= 1 << index;
+        }
+        if validated == 0 {
+            return Err(Error::ExpectedOrdersRuleViolated(format!("No matching expected order requests rules found for order request {tracking_item_wrapper:?}")));
+        }
+        Ok(ValidatedRules { rules, validated })
+    }
+
+    /// Rules of `symbol` an order of `side` and `size` is checked against
+    pub fn get_matching_rules(&self, symbol: &str, side: &OrderSide, size: Decimal) -> Vec<ExpectedOrderRequestsRule> {
+        match self.snapshot().get(symbol) {
+            Some(rules) => rules.iter().filter(|rule| rule.get_payload().matches(side, size)).cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Capacity of each rule of `symbol` matching an order of `side` and `size` at `now_nanos`.
+    /// Does not track nor validate anything.
+    pub fn get_capacity(&self, symbol: &str, side: &OrderSide, size: Decimal, grid_id: Option<&String>, tracker: &OrderTracker, now_nanos: u64) -> Result<Vec<ExpectedOrderRequestsRuleCapacity>> {
+        let rules = match self.ordered_rules(symbol) {
+            Some(rules) => rules,
+            None => return Err(Error::ExpectedOrdersRuleViolated(format!("No expected order requests rules found for symbol {symbol}")))
+        };
+        tracker.load_symbol(symbol)?;
+
+        let mut capacities = vec![];
+        for rule in rules.iter().filter(|rule| rule.get_payload().matches(side, size)) {
+            capacities.push(ExpectedOrderRequestsRuleCapacity::new(rule, tracker, grid_id, now_nanos)?);
+        }
+        Ok(capacities)
+    }
+
+    pub fn set_rules_for_symbol(&self, symbol: OrderRequestSymbol, rules: HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
+        if rules.is_empty() {
+            return Err(anyhow!("No rules provided for symbol {symbol}").into());
         }
-        new_set.insert(rule.clone());
-    };
     
-    if new_set.is_empty() {
-        return Err(anyhow!("Logic bug, new set of rules is empty for symbol {symbol}").into());
+        self.update(|current| {
+            let existing_rules = current.entry(symbol.clone()).or_default();
+            if existing_rules.is_empty() {
+                return Err(anyhow!("No rules found for symbol {symbol}. Global rules should be set with set_global_rules_for_symbol before calling this function").into());
+            }
+    
+            let mut global_rules = vec![];
+            for rule in existing_rules.iter() {
+                if let ExpectedOrderRequestsRule::Global(_) = rule {
+                    global_rules.push(rule.clone());
+                }
+            }
+    
+            if global_rules.is_empty() {
+                return Err(anyhow!("No global rules found for symbol {symbol}. Should be set with set_global_rules_for_symbol before calling this function").into());
+            }
+    
+            let mut new_set: HashSet<ExpectedOrderRequestsRule> = HashSet::new();
+            for rule in global_rules.iter() {
+                new_set.insert(rule.clone());
+            }
+    
+            for rule in rules.iter() {
+                if let ExpectedOrderRequestsRule::Global(_) = rule {
+                    return Err(anyhow!("Global rules should not be submitted into this function, they should be set with set_global_rules_for_symbol or load").into())
+                }
+                new_set.insert(rule.clone());
+            };
+    
+            if new_set.is_empty() {
+                return Err(anyhow!("Logic bug, new set of rules is empty for symbol {symbol}").into());
+            }
+            *existing_rules = new_set;
+            Ok(())
+        })?;
+        Ok(())
     }
-    *existing_rules = new_set;
-    Ok(())
-}
\ No newline at end of file
+}
+
+pub fn validate_order_request(symbol: &OrderRequestSymbol, tracking_item_wrapper: &TopNEntry<OrderTrackingItem>) -> Result<ValidatedRules> {
+    let guard = OrderGuard::global();
+    guard.rules().validate_order_request(symbol, tracking_item_wrapper, guard.tracker(), &ExpectedOrderRequestsRuleContext::default())
+}
+
+pub fn get_capacity(symbol: &str, side: &OrderSide, size: Decimal, grid_id: Option<&String>) -> Result<Vec<ExpectedOrderRequestsRuleCapacity>> {
+    OrderGuard::global().get_capacity(symbol, side, size, grid_id)
+}
+
+/// Replaces the rules of the default `OrderGuard` with the rules of a configuration file
+pub fn load_rules_file(path: &Path) -> Result<u64> {
+    OrderGuard::global().rules().load_file(path)
+}
+
+pub fn set_global_rules_for_symbol(symbol: OrderRequestSymbol, rules: HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
+    OrderGuard::global().rules().set_global_rules_for_symbol(symbol, rules)
+}
+
+pub fn set_rules_for_symbol(symbol: OrderRequestSymbol, rules: HashSet<ExpectedOrderRequestsRule>) -> Result<()> {
+    OrderGuard::global().rules().set_rules_for_symbol(symbol, rules)
+}
@@ -1,4 +1,12 @@
 pub mod order_tracking_item;
+pub mod order_tracking_request;
 pub mod top_n;
+pub mod order_guard;
 pub mod order_tracker;
-pub mod expected_order_requests;
\ No newline at end of file
+pub mod order_tracker_store;
+pub mod order_tracker_bootstrap;
+pub mod order_tracker_retention;
+pub mod order_tracking_index;
+pub mod order_journal;
+pub mod expected_order_requests;#[cfg(test)]
+pub(crate) mod test_support;
@@ -0,0 +1,539 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
+use crate::errors::{Error, Result};
+use crate::futures::utils::expected_order_requests::rule_capacity::ExpectedOrderRequestsRuleCapacity;
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+use crate::futures::utils::expected_order_requests::rules_config::ExpectedOrderRequestsRulesWatcher;
+use crate::futures::utils::expected_order_requests::rules_map::{ExpectedOrderRequestsRules, ValidatedRules};
+use crate::futures::utils::order_tracker::OrderTracker;
+use crate::futures::utils::order_tracker_retention::RetentionPolicy;
+use crate::futures::utils::order_tracker_store::{JsonFileOrderTrackerStore, OrderTrackerStore};
+use crate::futures::utils::order_tracking_item::{OrderTrackingItem, OrderTrackingOutcome};
+use crate::futures::utils::order_tracking_request::OrderTrackingRequest;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+#[cfg(feature = "futures_api")]
+use crate::futures::ws_model::OrderTradeUpdate;
+
+/// Persists to JSON files in the current working directory, see `JsonFileOrderTrackerStore`
+static DEFAULT_ORDER_GUARD: Lazy<OrderGuard> = Lazy::new(|| OrderGuard::new(Arc::new(JsonFileOrderTrackerStore::default())));
+
+/// Checks orders against the expected order requests rules before they are sent
+///
+/// Owns an order tracker and a rule set, so that accounts with separate guards do not share
+/// counters or rules. Clones share the same tracker and rules. `OrderGuard::default()` tracks
+/// orders in memory only, use `OrderGuard::new` to persist them.
+/// The free functions of `order_tracker` and `rules_map` operate on `OrderGuard::global()`, which
+/// `FuturesAccount`, `Account` and `Margin` use unless given another guard.
+#[derive(Clone, Debug, Default)]
+pub struct OrderGuard {
+    tracker: Arc<OrderTracker>,
+    rules: Arc<ExpectedOrderRequestsRules>,
+    /// Version of the rules the retention policy of each symbol was computed from
+    retention_versions: Arc<DashMap<String, u64>>,
+    bootstrap_required: bool,
+}
+
+impl OrderGuard {
+    pub fn new(store: Arc<dyn OrderTrackerStore>) -> Self {
+        Self {
+            tracker: Arc::new(OrderTracker::new(store)),
+            rules: Arc::new(ExpectedOrderRequestsRules::new()),
+            retention_versions: Arc::new(DashMap::new()),
+            bootstrap_required: false,
+        }
+    }
+
+    pub fn with_tracker(mut self, tracker: OrderTracker) -> Self {
+        self.tracker = Arc::new(tracker);
+        self.retention_versions = Arc::new(DashMap::new());
+        self
+    }
+
+    /// Rejects the orders of symbols whose tracker was not bootstrapped from the exchange history,
+    /// see `FuturesAccount::bootstrap_order_tracker`
+    pub fn with_bootstrap_required(mut self, bootstrap_required: bool) -> Self {
+        self.bootstrap_required = bootstrap_required;
+        self
+    }
+
+    /// The process wide guard behind the free functions
+    pub fn global() -> &'static OrderGuard {
+        &DEFAULT_ORDER_GUARD
+    }
+
+    pub fn tracker(&self) -> &OrderTracker {
+        &self.tracker
+    }
+
+    pub fn rules(&self) -> &ExpectedOrderRequestsRules {
+        &self.rules
+    }
+
+    /// Keeps the rules in sync with a configuration file, see `ExpectedOrderRequestsRulesWatcher`
+    pub fn watch_rules_file(&self, path: impl Into<PathBuf>, interval: Duration) -> Result<ExpectedOrderRequestsRulesWatcher> {
+        ExpectedOrderRequestsRulesWatcher::new(self.rules.clone(), path, interval)
+    }
+
+    /// Tracks the order and validates it against the rules of its market and symbol.
+    /// The order is no longer tracked if it violates a rule.
+    pub fn check_order(&self, order: &OrderTrackingRequest) -> Result<(TopNEntry<OrderTrackingItem>, ValidatedRules)> {
+        self.check_order_with_context(order, &ExpectedOrderRequestsRuleContext::default())
+    }
+
+    /// Same as `check_order`, with the market state needed by mark price and position limits
+    pub fn check_order_with_context(&self, order: &OrderTrackingRequest, context: &ExpectedOrderRequestsRuleContext) -> Result<(TopNEntry<OrderTrackingItem>, ValidatedRules)> {
+        let order = &order.with_context_price(context);
+        let tracking_key = order.get_tracking_key();
+        if self.bootstrap_required && !self.tracker.is_bootstrapped(&tracking_key) {
+            return Err(Error::ExpectedOrdersRuleViolated(format!("The order tracker of {tracking_key} was not bootstrapped from the exchange history yet")));
+        }
+        self.apply_retention_policy(&tracking_key);
+        let top_n_entry = self.tracker.add_order_tracking_item(order)?;
+        let tracking_key = tracking_key.into_owned();
+        let validated_rules = match self.rules.validate_order_request(&tracking_key, &top_n_entry, &self.tracker, context) {
+            Ok(rules) => rules,
+            Err(error) => {
+                self.tracker.remove_order_tracking_item(&tracking_key, &top_n_entry);
+                return Err(error);
+            }
+        };
+        if validated_rules.is_empty() {
+            return Err(anyhow!("Expected some validated rule but got none").into());
+        }
+        Ok((top_n_entry, validated_rules))
+    }
+
+    /// Checks orders sent together, such as the legs of an OCO order, one after the other so that
+    /// each leg is counted by the rules of the next ones.
+    /// Either all of them are tracked or, if one violates a rule, none of them.
+    pub fn check_orders(&self, orders: &[OrderTrackingRequest]) -> Result<Vec<(TopNEntry<OrderTrackingItem>, ValidatedRules)>> {
+        let mut checked: Vec<(TopNEntry<OrderTrackingItem>, ValidatedRules)> = vec![];
+        for order in orders.iter() {
+            match self.check_order(order) {
+                Ok(checked_order) => checked.push(checked_order),
+                Err(error) => {
+                    for (order, (entry, _)) in orders.iter().zip(checked.iter()) {
+                        self.tracker.remove_order_tracking_item(&order.get_tracking_key().into_owned(), entry);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(checked)
+    }
+
+    /// Records what the exchange answered to an order checked by this guard under `tracking_key`:
+    /// the outcome of the response, or a rejection for Binance errors. Other errors leave the order
+    /// submitted as it may still have reached the exchange. Failures are logged.
+    pub fn record_response<T>(&self, tracking_key: &str, entry: &TopNEntry<OrderTrackingItem>, response: &Result<T>)
+    where
+        for<'a> &'a T: Into<OrderTrackingOutcome>,
+    {
+        let outcome = match response {
+            Ok(response) => response.into(),
+            Err(Error::BinanceError { .. }) => OrderTrackingOutcome::rejected(),
+            Err(_) => return,
+        };
+        if let Err(error) = self.tracker.record_outcome(tracking_key, &entry.item.id, &outcome) {
+            eprintln!("failed to record the outcome of {entry:?} in {tracking_key} tracker: {error:?}")
+        }
+    }
+
+    /// Records the status and fills of a futures order reported by the user data stream
+    #[cfg(feature = "futures_api")]
+    pub fn record_order_trade_update(&self, update: &OrderTradeUpdate) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+        self.tracker.record_exchange_outcome(&update.order.symbol, Some(&update.order.client_order_id), &update.into())
+    }
+
+    /// How many more orders of `side` and `size` each matching rule of `symbol` allows right now,
+    /// see `ExpectedOrderRequestsRules::get_capacity`.
+    /// Spot and margin symbols are given by their tracking key, see `OrderMarket::get_tracking_key`.
+    pub fn get_capacity(&self, symbol: &str, side: &OrderSide, size: Decimal, grid_id: Option<&String>) -> Result<Vec<ExpectedOrderRequestsRuleCapacity>> {
+        self.apply_retention_policy(symbol);
+        self.rules.get_capacity(symbol, side, size, grid_id, &self.tracker, self.tracker.now_nanos())
+    }
+
+    /// Keeps the retention and the index of the tracked orders of `symbol` in line with its rules.
+    /// The policy is only computed again once the rules changed.
+    fn apply_retention_policy(&self, symbol: &str) {
+        let version = self.rules.version();
+        if self.retention_versions.get(symbol).is_some_and(|applied| *applied == version) {
+            return;
+        }
+        if let Some(rules) = self.rules.snapshot().get(symbol) {
+            self.tracker.set_retention_policy(symbol, RetentionPolicy::from_rules(rules.iter()));
+        }
+        self.retention_versions.insert(symbol.to_string(), version);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use super::*;
+    use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+    use crate::futures::utils::expected_order_requests::rules_map::MAX_RULES_PER_SYMBOL;
+    use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+    use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleLimit;
+    use crate::futures::utils::order_tracking_request::OrderMarket;
+    use crate::futures::utils::expected_order_requests::rules_config::ExpectedOrderRequestsRulesConfig;
+    use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+    use crate::clock::{Clock, ManualClock};
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::futures::utils::order_tracking_item::OrderTrackingStatus;
+    use crate::errors::BinanceContentError;
+
+    fn guard_with_rules(max_count: u64) -> OrderGuard {
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let rule = |period: RulePeriod| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                market: OrderMarket::Futures,
+                count_by: ExpectedOrderRequestsRuleCountBasis::Submitted,
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count,
+                period,
+                side: OrderSide::Buy,
+                limits: vec![],
+            })
+        };
+        let rules = HashSet::from([rule(RulePeriod::Hours(24)), rule(RulePeriod::Weeks(52))]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        guard
+    }
+
+    fn order() -> OrderTrackingRequest {
+        OrderTrackingRequest {
+            market: OrderMarket::Futures,
+            symbol: "BTCUSDT".to_string(),
+            side: OrderSide::Buy,
+            quantity: Some(dec!(0.5)),
+            price: Some(dec!(100)),
+            ..OrderTrackingRequest::default()
+        }
+    }
+
+    #[test]
+    fn guards_do_not_share_state() {
+        let first = guard_with_rules(10);
+        let second = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let entry = first.tracker().add_order_tracking_item(&order()).unwrap();
+        assert_eq!(first.tracker().get_all_tracking_items("BTCUSDT"), Some(vec![entry]));
+        assert_eq!(first.rules().get_rules("BTCUSDT").unwrap().len(), 2);
+        assert!(second.tracker().get_all_tracking_items("BTCUSDT").is_none());
+        assert!(second.rules().get_rules("BTCUSDT").is_none());
+
+        // No rules were set on the second guard, the rejected order is not tracked
+        assert!(matches!(second.check_order(&order()), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(second.tracker().get_all_tracking_items("BTCUSDT"), Some(vec![]));
+        // Clones share the same state
+        assert_eq!(first.clone().tracker().get_all_tracking_items("BTCUSDT").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn validated_rules_share_the_installed_rules() {
+        let guard = guard_with_rules(10);
+        let (_, validated) = guard.check_order(&order()).unwrap();
+        assert_eq!(validated.len(), 2);
+        let installed = guard.rules().get_rules("BTCUSDT").unwrap();
+        assert!(validated.iter().all(|rule| installed.contains(rule)));
+
+        // Too many rules to keep track of the validated ones
+        let rules: HashSet<ExpectedOrderRequestsRule> = (1..=MAX_RULES_PER_SYMBOL as u64)
+            .map(|max_count| ExpectedOrderRequestsRule::PerGrid(ExpectedOrderRequestsRulePayload { max_count, ..installed.iter().next().unwrap().get_payload().clone() }))
+            .collect();
+        assert!(matches!(guard.rules().set_rules_for_symbol("BTCUSDT".to_string(), rules), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(guard.rules().get_rules("BTCUSDT").unwrap(), installed);
+        // Once validated, the rules are unaffected by later changes
+        guard.rules().load(HashMap::new()).unwrap();
+        assert_eq!(validated.to_vec().len(), 2);
+    }
+
+    #[test]
+    fn default_guards_do_not_share_state() {
+        let first = OrderGuard::default();
+        let second = OrderGuard::default();
+        first.tracker().add_order_tracking_item(&order()).unwrap();
+        assert_eq!(first.tracker().get_all_tracking_items("BTCUSDT").unwrap().len(), 1);
+        // Nothing was persisted where the second guard loads from
+        second.tracker().load_symbol("BTCUSDT").unwrap();
+        assert_eq!(second.tracker().get_all_tracking_items("BTCUSDT"), Some(vec![]));
+    }
+
+    #[test]
+    fn retention_follows_rule_changes() {
+        let guard = guard_with_rules(10);
+        guard.check_order(&order()).unwrap();
+        let policy = guard.tracker().get_retention_policy("BTCUSDT").unwrap();
+
+        let rule = |period: RulePeriod| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                period,
+                ..guard.rules().get_rules("BTCUSDT").unwrap().into_iter().next().unwrap().get_payload().clone()
+            })
+        };
+        let rules = HashSet::from([rule(RulePeriod::Hours(24)), rule(RulePeriod::Weeks(104))]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        guard.check_order(&order()).unwrap();
+        assert!(guard.tracker().get_retention_policy("BTCUSDT").unwrap().max_age > policy.max_age);
+    }
+
+    #[test]
+    fn bootstrap_required_until_history_merged() {
+        let guard = guard_with_rules(10).with_bootstrap_required(true);
+        assert!(matches!(guard.check_order(&order()), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert!(guard.tracker().get_all_tracking_items("BTCUSDT").is_none());
+        assert_eq!(guard.tracker().merge_history("BTCUSDT", vec![]).unwrap(), 0);
+        assert!(guard.check_order(&order()).is_ok());
+    }
+
+    #[test]
+    fn per_grid_rules_count_only_their_grid() {
+        let guard = guard_with_rules(3);
+        let per_grid = ExpectedOrderRequestsRule::PerGrid(ExpectedOrderRequestsRulePayload {
+            market: OrderMarket::Futures,
+            count_by: ExpectedOrderRequestsRuleCountBasis::Submitted,
+            symbol: "BTCUSDT".to_string(),
+            size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+            max_count: 2,
+            period: RulePeriod::Hours(1),
+            side: OrderSide::Buy,
+            limits: vec![],
+        });
+        guard.rules().set_rules_for_symbol("BTCUSDT".to_string(), HashSet::from([per_grid])).unwrap();
+        let grid_order = |grid_id: &str| OrderTrackingRequest { grid_id: Some(grid_id.to_string()), ..order() };
+
+        assert!(guard.check_order(&grid_order("a")).is_ok());
+        assert!(guard.check_order(&grid_order("a")).is_ok());
+        assert!(matches!(guard.check_order(&grid_order("a")), Err(Error::ExpectedOrdersRuleViolated(_))));
+        // The per grid rule does not count the orders of grid "a", the global rule does
+        assert!(guard.check_order(&grid_order("b")).is_ok());
+        assert!(matches!(guard.check_order(&grid_order("b")), Err(Error::ExpectedOrdersRuleViolated(_))));
+        let tracked = guard.tracker().get_all_tracking_items("BTCUSDT").unwrap();
+        assert_eq!(tracked.iter().filter(|entry| entry.item.grid_id.as_deref() == Some("a")).count(), 2);
+        assert_eq!(tracked.len(), 3);
+    }
+
+    #[test]
+    fn evicted_orders_are_still_counted() {
+        let store = Arc::new(InMemoryOrderTrackerStore::new());
+        let guard = guard_with_rules(3).with_tracker(OrderTracker::new(store.clone()).with_capacity(2));
+        for _ in 0..3 {
+            assert!(guard.check_order(&order()).is_ok());
+        }
+        // The rejected order evicted a third one before being removed
+        assert!(matches!(guard.check_order(&order()), Err(Error::ExpectedOrdersRuleViolated(_))));
+        assert_eq!(guard.tracker().get_all_tracking_items("BTCUSDT").unwrap().len(), 1);
+        let buckets = store.load_buckets("BTCUSDT").unwrap();
+        assert_eq!(buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 2);
+        let capacity = guard.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(0.5), None).unwrap();
+        assert!(capacity.iter().all(|capacity| capacity.used == 3 && capacity.headroom == 0));
+    }
+
+    #[test]
+    fn replays_a_year_of_orders() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000_000_000_000));
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()))
+            .with_tracker(OrderTracker::new(Arc::new(InMemoryOrderTrackerStore::new())).with_clock(clock.clone()));
+        let rule = |period: RulePeriod, max_count: u64| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                market: OrderMarket::Futures,
+                count_by: ExpectedOrderRequestsRuleCountBasis::Submitted,
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count,
+                period,
+                side: OrderSide::Buy,
+                limits: vec![],
+            })
+        };
+        let rules = HashSet::from([rule(RulePeriod::Hours(24), 10), rule(RulePeriod::Weeks(52), 2_500)]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+
+        // Sliding windows over the accepted orders, both inclusive of their start
+        let day = Duration::from_secs(24 * 60 * 60).as_nanos() as u64;
+        let mut accepted: Vec<u64> = vec![];
+        let mut expected_accepted = 0;
+        for _ in 0..300 * 12 {
+            clock.advance(Duration::from_secs(2 * 60 * 60));
+            let now = clock.now_nanos();
+            let in_day = accepted.iter().filter(|timestamp| **timestamp >= now - day).count();
+            let expected = in_day < 10 && accepted.len() < 2_500;
+            assert_eq!(guard.check_order(&order()).is_ok(), expected, "after {} accepted orders", accepted.len());
+            if expected {
+                accepted.push(now);
+                expected_accepted += 1;
+            }
+        }
+        assert_eq!(expected_accepted, 2_500);
+        let capacity = guard.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(0.5), None).unwrap();
+        assert!(capacity.iter().any(|capacity| capacity.used == 2_500 && capacity.headroom == 0));
+    }
+
+    #[test]
+    fn markets_do_not_share_rules_nor_counters() {
+        let guard = guard_with_rules(2);
+        let config = ExpectedOrderRequestsRulesConfig::from_json(
+            r#"{"rules":[
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":3,"period":{"Hours":24},"side":"BUY"}},
+                {"Global":{"market":"SPOT","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":3,"period":{"Weeks":52},"side":"BUY"}},
+                {"Global":{"symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":2,"period":{"Hours":24},"side":"BUY"}},
+                {"Global":{"symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":2,"period":{"Weeks":52},"side":"BUY"}}
+            ]}"#,
+        )
+        .unwrap();
+        guard.rules().load(config.into_rule_map()).unwrap();
+        assert_eq!(guard.rules().get_rules("spot.BTCUSDT").unwrap().len(), 2);
+        assert!(guard.rules().get_rules("margin.BTCUSDT").is_none());
+
+        let spot_order = || OrderTrackingRequest { market: OrderMarket::Spot, ..order() };
+        assert!(guard.check_order(&order()).is_ok());
+        assert!(guard.check_order(&order()).is_ok());
+        assert!(guard.check_order(&order()).is_err());
+        // Futures orders are not counted by the spot rules, both legs of an OCO order are
+        let oco = [spot_order(), OrderTrackingRequest { price: Some(dec!(90)), ..spot_order() }];
+        assert_eq!(guard.check_orders(&oco).unwrap().len(), 2);
+        assert_eq!(guard.tracker().get_all_tracking_items("spot.BTCUSDT").unwrap().len(), 2);
+        assert_eq!(guard.tracker().get_all_tracking_items("BTCUSDT").unwrap().len(), 2);
+
+        // The first leg fits but the second does not, neither is tracked
+        assert!(guard.check_orders(&oco).is_err());
+        assert_eq!(guard.tracker().get_all_tracking_items("spot.BTCUSDT").unwrap().len(), 2);
+        assert!(guard.check_order(&spot_order()).is_ok());
+
+        // Margin orders have no rules of their own
+        let margin_order = OrderTrackingRequest { market: OrderMarket::Margin, ..order() };
+        assert!(matches!(guard.check_order(&margin_order), Err(Error::ExpectedOrdersRuleViolated(_))));
+    }
+
+    #[test]
+    fn rules_count_by_exchange_outcomes() {
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let rule = |count_by: ExpectedOrderRequestsRuleCountBasis, period: RulePeriod, max_count: u64| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                market: OrderMarket::Futures,
+                count_by,
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count,
+                period,
+                side: OrderSide::Buy,
+                limits: vec![],
+            })
+        };
+        let rules = HashSet::from([
+            rule(ExpectedOrderRequestsRuleCountBasis::Accepted, RulePeriod::Hours(24), 2),
+            rule(ExpectedOrderRequestsRuleCountBasis::Filled, RulePeriod::Weeks(52), 1),
+        ]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        let client_order = |client_order_id: &str| OrderTrackingRequest { client_order_id: Some(client_order_id.to_string()), ..order() };
+
+        let (rejected, _) = guard.check_order(&client_order("a")).unwrap();
+        assert!(guard.check_order(&client_order("b")).is_ok());
+        assert!(guard.check_order(&client_order("c")).is_err());
+        let response: BinanceContentError = serde_json::from_str(r#"{"code":-2019,"msg":"Margin is insufficient."}"#).unwrap();
+        guard.record_response::<crate::rest_model::Transaction>("BTCUSDT", &rejected, &Err(response.into()));
+        // The rejected order no longer counts, unanswered orders still do
+        assert!(guard.check_order(&client_order("c")).is_ok());
+        assert!(guard.check_order(&client_order("d")).is_err());
+
+        // Each order is counted by the filled rule until another one fills
+        let filled = OrderTrackingOutcome {
+            status: OrderTrackingStatus::Filled,
+            order_id: Some("1".to_string()),
+            filled_size: dec!(0.5),
+            filled_notional: dec!(50),
+        };
+        let entry = guard.tracker().record_exchange_outcome("BTCUSDT", Some("b"), &filled).unwrap().unwrap();
+        assert_eq!(entry.item.outcome, filled);
+        assert!(guard.tracker().record_exchange_outcome("BTCUSDT", Some("unknown"), &filled).unwrap().is_some());
+        assert!(guard.tracker().record_exchange_outcome("BTCUSDT", Some("unknown"), &OrderTrackingOutcome::accepted()).unwrap().is_none());
+        let capacity = guard.get_capacity("BTCUSDT", &OrderSide::Buy, dec!(0.5), None).unwrap();
+        assert!(capacity.iter().all(|capacity| capacity.headroom == 0));
+    }
+
+    #[test]
+    fn net_position_counts_the_open_orders() {
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let rule = |period: RulePeriod, limits: Vec<ExpectedOrderRequestsRuleLimit>| {
+            ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+                market: OrderMarket::Futures,
+                count_by: ExpectedOrderRequestsRuleCountBasis::Submitted,
+                symbol: "BTCUSDT".to_string(),
+                size_params: ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)),
+                max_count: 1_000,
+                period,
+                side: OrderSide::Buy,
+                limits,
+            })
+        };
+        let rules = HashSet::from([rule(RulePeriod::Hours(24), vec![ExpectedOrderRequestsRuleLimit::NetPosition(dec!(5))]), rule(RulePeriod::Weeks(52), vec![])]);
+        guard.rules().set_global_rules_for_symbol("BTCUSDT".to_string(), rules).unwrap();
+        let flat = ExpectedOrderRequestsRuleContext {
+            mark_price: None,
+            last_price: None,
+            position_amount: Some(dec!(0)),
+        };
+        let buy = OrderTrackingRequest { quantity: Some(dec!(1)), ..order() };
+
+        let accepted: Vec<_> = (0..100).filter_map(|_| guard.check_order_with_context(&buy, &flat).ok()).map(|(entry, _)| entry).collect();
+        assert_eq!(accepted.len(), 5);
+
+        // A fill moves size from the open orders to the position
+        let filled = OrderTrackingOutcome {
+            status: OrderTrackingStatus::Filled,
+            order_id: Some("1".to_string()),
+            filled_size: dec!(1),
+            filled_notional: dec!(100),
+        };
+        guard.tracker().record_outcome("BTCUSDT", &accepted[0].item.id, &filled).unwrap();
+        let long_one = ExpectedOrderRequestsRuleContext {
+            mark_price: None,
+            last_price: None,
+            position_amount: Some(dec!(1)),
+        };
+        assert!(guard.check_order_with_context(&buy, &long_one).is_err());
+        // A canceled order no longer counts
+        let canceled = OrderTrackingOutcome {
+            status: OrderTrackingStatus::Canceled,
+            order_id: Some("2".to_string()),
+            ..OrderTrackingOutcome::default()
+        };
+        guard.tracker().record_outcome("BTCUSDT", &accepted[1].item.id, &canceled).unwrap();
+        assert!(guard.check_order_with_context(&buy, &long_one).is_ok());
+        assert!(guard.check_order_with_context(&buy, &long_one).is_err());
+    }
+
+    #[test]
+    fn outcomes_are_looked_up_among_tracked_orders_only() {
+        let tracker = OrderTracker::new(Arc::new(InMemoryOrderTrackerStore::new())).with_capacity(2);
+        let client_order = |client_order_id: &str| OrderTrackingRequest { client_order_id: Some(client_order_id.to_string()), ..order() };
+        let accepted = |order_id: &str| OrderTrackingOutcome { order_id: Some(order_id.to_string()), ..OrderTrackingOutcome::accepted() };
+        let first = tracker.add_order_tracking_item(&client_order("a")).unwrap();
+        assert!(tracker.record_outcome("BTCUSDT", &first.item.id, &accepted("1")).unwrap().is_some());
+        let second = tracker.add_order_tracking_item(&client_order("b")).unwrap();
+        assert_eq!(tracker.record_exchange_outcome("BTCUSDT", None, &accepted("1")).unwrap().unwrap().item.id, first.item.id);
+
+        // Removed as it was returned before its outcome, the entry is no longer found by any id
+        assert!(tracker.record_exchange_outcome("BTCUSDT", Some("b"), &accepted("2")).unwrap().is_some());
+        tracker.remove_order_tracking_item(&"BTCUSDT".to_string(), &second);
+        assert!(tracker.record_exchange_outcome("BTCUSDT", None, &accepted("2")).unwrap().is_none());
+        assert!(tracker.record_exchange_outcome("BTCUSDT", Some("b"), &accepted("2")).unwrap().is_none());
+        // Evicted by the capacity
+        tracker.add_order_tracking_item(&client_order("c")).unwrap();
+        tracker.add_order_tracking_item(&client_order("d")).unwrap();
+        assert!(tracker.record_outcome("BTCUSDT", &first.item.id, &accepted("1")).unwrap().is_none());
+        assert!(tracker.record_exchange_outcome("BTCUSDT", Some("a"), &accepted("1")).unwrap().is_none());
+        assert!(tracker.record_exchange_outcome("BTCUSDT", Some("c"), &accepted("3")).unwrap().is_some());
+    }
+}
@@ -0,0 +1,391 @@
+use std::fmt::Debug;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use anyhow::anyhow;
+use serde::Serialize;
+use uuid::Uuid;
+use crate::errors::{BinanceErrorCode, Error, Result};
+use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::json_lines::{read_json_lines, truncate_partial_last_line};
+use crate::util::get_timestamp;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderJournalAction {
+    Place,
+    Cancel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderJournalOutcome {
+    /// Refused before being sent, by the expected order requests rules or while gathering what
+    /// they are checked against
+    Rejected,
+    /// About to be sent, recorded first when the journal is required. The outcome follows in a
+    /// later record with the same id.
+    Sending,
+    /// Sent and acknowledged by the exchange
+    Accepted,
+    /// Sent but the exchange returned an error or could not be reached
+    Failed,
+}
+
+/// One order attempt, as written to an `OrderJournal`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderJournalRecord {
+    pub id: String,
+    pub action: OrderJournalAction,
+    pub symbol: String,
+    pub client_order_id: Option<String>,
+    /// The order or cancellation as sent to the exchange
+    pub request: serde_json::Value,
+    /// Entry the order was tracked as, `None` when it was rejected or for cancellations
+    #[serde(default)]
+    pub tracking_entry: Option<TopNEntry<OrderTrackingItem>>,
+    #[serde(default)]
+    pub rules_version: Option<u64>,
+    /// Rules the order was checked against: the validated rules of accepted orders and the
+    /// matching rules of rejected ones
+    #[serde(default)]
+    pub evaluated_rules: Vec<ExpectedOrderRequestsRule>,
+    pub outcome: OrderJournalOutcome,
+    #[serde(default)]
+    pub response: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<BinanceErrorCode>,
+    /// Milliseconds since the epoch
+    pub requested_at: u64,
+    /// Milliseconds since the epoch
+    pub completed_at: u64,
+}
+
+impl OrderJournalRecord {
+    pub fn new<R: Serialize>(action: OrderJournalAction, symbol: &str, client_order_id: Option<String>, request: &R) -> Self {
+        let requested_at = get_timestamp().unwrap_or_default();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            action,
+            symbol: symbol.to_string(),
+            client_order_id,
+            request: serde_json::to_value(request).unwrap_or_default(),
+            tracking_entry: None,
+            rules_version: None,
+            evaluated_rules: vec![],
+            outcome: OrderJournalOutcome::Rejected,
+            response: None,
+            error: None,
+            error_code: None,
+            requested_at,
+            completed_at: requested_at,
+        }
+    }
+
+    /// Copy of the record to journal right before the request is sent
+    pub fn sending(&self) -> Self {
+        Self {
+            outcome: OrderJournalOutcome::Sending,
+            ..self.clone()
+        }
+    }
+
+    /// Records an order refused before being sent
+    pub fn rejected(mut self, error: &Error) -> Self {
+        self.outcome = OrderJournalOutcome::Rejected;
+        self.set_error(error);
+        self
+    }
+
+    /// Records the exchange response to the request
+    pub fn completed<T: Serialize>(mut self, result: &Result<T>) -> Self {
+        match result {
+            Ok(response) => {
+                self.outcome = OrderJournalOutcome::Accepted;
+                self.response = serde_json::to_value(response).ok();
+                self.completed_at = get_timestamp().unwrap_or_default();
+            }
+            Err(error) => {
+                self.outcome = OrderJournalOutcome::Failed;
+                self.set_error(error);
+            }
+        }
+        self
+    }
+
+    fn set_error(&mut self, error: &Error) {
+        self.error = Some(error.to_string());
+        self.error_code = error.binance_error_code();
+        self.completed_at = get_timestamp().unwrap_or_default();
+    }
+}
+
+/// Filters journal records, every field set must match
+#[derive(Debug, Clone, Default)]
+pub struct OrderJournalQuery {
+    pub symbol: Option<String>,
+    /// Inclusive lower bound of `requested_at`, in milliseconds
+    pub from: Option<u64>,
+    /// Inclusive upper bound of `requested_at`, in milliseconds
+    pub to: Option<u64>,
+    pub client_order_id: Option<String>,
+}
+
+impl OrderJournalQuery {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn with_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = Some(symbol.into());
+        self
+    }
+
+    pub fn with_time_range(mut self, from: Option<u64>, to: Option<u64>) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
+    pub fn with_client_order_id(mut self, client_order_id: impl Into<String>) -> Self {
+        self.client_order_id = Some(client_order_id.into());
+        self
+    }
+
+    pub fn matches(&self, record: &OrderJournalRecord) -> bool {
+        self.symbol.as_ref().is_none_or(|symbol| *symbol == record.symbol)
+            && self.from.is_none_or(|from| record.requested_at >= from)
+            && self.to.is_none_or(|to| record.requested_at <= to)
+            && self.client_order_id.as_ref().is_none_or(|client_order_id| record.client_order_id.as_ref() == Some(client_order_id))
+    }
+}
+
+/// Append-only record of the order attempts of an account
+pub trait OrderJournal: Send + Sync + Debug {
+    fn append(&self, record: &OrderJournalRecord) -> Result<()>;
+
+    /// Records matching `query`, in the order they were appended
+    fn query(&self, query: &OrderJournalQuery) -> Result<Vec<OrderJournalRecord>>;
+}
+
+enum JournalCommand {
+    /// Answered with the outcome of the write when the append waits for it
+    Append(String, Option<mpsc::Sender<std::io::Result<()>>>),
+    /// Answered once the lines sent before have been written
+    Flush(mpsc::Sender<()>),
+}
+
+/// Appends every record as a JSON line to a file
+///
+/// Lines are written and synced to disk by a background thread so that orders never wait on the
+/// file system, unless `with_durable_appends` is set. A write failure fails the next `append` or
+/// `query`, `query` waits for the records appended before it to be written.
+#[derive(Debug)]
+pub struct JsonLinesOrderJournal {
+    path: PathBuf,
+    writer: mpsc::Sender<JournalCommand>,
+    /// First write failure of the background thread not reported yet
+    failure: Arc<Mutex<Option<String>>>,
+    durable_appends: bool,
+}
+
+impl JsonLinesOrderJournal {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let (writer, commands) = mpsc::channel();
+        let failure = Arc::new(Mutex::new(None));
+        let writer_path = path.clone();
+        let writer_failure = failure.clone();
+        thread::spawn(move || write_lines(&writer_path, commands, &writer_failure));
+        Self {
+            path,
+            writer,
+            failure,
+            durable_appends: false,
+        }
+    }
+
+    /// `append` returns once the record is written and synced to disk, with the write error if any
+    pub fn with_durable_appends(mut self) -> Self {
+        self.durable_appends = true;
+        self
+    }
+
+    pub fn file_path(&self) -> &PathBuf { &self.path }
+
+    /// Fails with the last write failure of the background thread, if not reported yet
+    fn check_failure(&self) -> Result<()> {
+        match self.failure.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            Some(failure) => Err(anyhow!("Failed to write a record to {}: {failure}", self.path.display()).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+/// Runs until the journal is dropped
+fn write_lines(path: &Path, commands: mpsc::Receiver<JournalCommand>, failure: &Mutex<Option<String>>) {
+    let mut repaired = false;
+    for command in commands {
+        match command {
+            JournalCommand::Append(line, written) => {
+                // A crash may have left a partial line the first record would extend
+                if !repaired {
+                    repaired = truncate_partial_last_line(path).is_ok();
+                }
+                let result = append_line(path, &line);
+                match written {
+                    Some(written) => {
+                        let _ = written.send(result);
+                    }
+                    None => {
+                        if let Err(error) = result {
+                            eprintln!("failed to append to {}: {error:?}", path.display());
+                            *failure.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(format!("{error:?}"));
+                        }
+                    }
+                }
+            }
+            JournalCommand::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+impl OrderJournal for JsonLinesOrderJournal {
+    fn append(&self, record: &OrderJournalRecord) -> Result<()> {
+        self.check_failure()?;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let (written, write_result) = if self.durable_appends {
+            let (written, write_result) = mpsc::channel();
+            (Some(written), Some(write_result))
+        } else {
+            (None, None)
+        };
+        if self.writer.send(JournalCommand::Append(line, written)).is_err() {
+            return Err(anyhow!("The writer of {} has stopped", self.path.display()).into());
+        }
+        match write_result.map(|write_result| write_result.recv()) {
+            None | Some(Ok(Ok(()))) => Ok(()),
+            Some(Ok(Err(error))) => Err(anyhow!("Failed to append to {}: {error:?}", self.path.display()).into()),
+            Some(Err(_)) => Err(anyhow!("The writer of {} has stopped", self.path.display()).into()),
+        }
+    }
+
+    fn query(&self, query: &OrderJournalQuery) -> Result<Vec<OrderJournalRecord>> {
+        let (done, flushed) = mpsc::channel();
+        if self.writer.send(JournalCommand::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+        self.check_failure()?;
+        let records: Vec<OrderJournalRecord> = read_json_lines(&self.path)?;
+        Ok(records.into_iter().filter(|record| query.matches(record)).collect())
+    }
+}
+
+/// Keeps the records in memory only, for tests and processes that do not need persistence
+#[derive(Debug, Default)]
+pub struct InMemoryOrderJournal {
+    records: Mutex<Vec<OrderJournalRecord>>,
+}
+
+impl InMemoryOrderJournal {
+    pub fn new() -> Self { Self::default() }
+}
+
+impl OrderJournal for InMemoryOrderJournal {
+    fn append(&self, record: &OrderJournalRecord) -> Result<()> {
+        self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record.clone());
+        Ok(())
+    }
+
+    fn query(&self, query: &OrderJournalQuery) -> Result<Vec<OrderJournalRecord>> {
+        let records = self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(records.iter().filter(|record| query.matches(record)).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::account::OrderCancellation;
+
+    fn record(symbol: &str, client_order_id: &str, requested_at: u64) -> OrderJournalRecord {
+        let cancellation = OrderCancellation {
+            symbol: symbol.to_string(),
+            orig_client_order_id: Some(client_order_id.to_string()),
+            ..OrderCancellation::default()
+        };
+        let mut record = OrderJournalRecord::new(OrderJournalAction::Cancel, symbol, Some(client_order_id.to_string()), &cancellation);
+        record.requested_at = requested_at;
+        record
+    }
+
+    #[test]
+    fn json_lines_journal_queries() {
+        let path = std::env::temp_dir().join(format!("order_journal_{}.jsonl", Uuid::new_v4()));
+        let journal = JsonLinesOrderJournal::new(&path);
+        assert!(journal.query(&OrderJournalQuery::new()).unwrap().is_empty());
+
+        let error = Error::ExpectedOrdersRuleViolated("max count".to_string());
+        let rejected = record("BTCUSDT", "a", 1_000).rejected(&error);
+        let accepted = record("BTCUSDT", "b", 2_000).completed(&Ok(serde_json::json!({"orderId": 1})));
+        let other_symbol = record("ETHUSDT", "c", 3_000).completed::<()>(&Err(Error::Msg("timeout".to_string())));
+        for record in [&rejected, &accepted, &other_symbol] {
+            journal.append(record).unwrap();
+        }
+
+        assert_eq!(journal.query(&OrderJournalQuery::new()).unwrap().len(), 3);
+        assert_eq!(journal.query(&OrderJournalQuery::new().with_symbol("BTCUSDT")).unwrap(), vec![rejected.clone(), accepted.clone()]);
+        assert_eq!(journal.query(&OrderJournalQuery::new().with_time_range(Some(1_500), Some(3_000))).unwrap(), vec![accepted.clone(), other_symbol.clone()]);
+        assert_eq!(journal.query(&OrderJournalQuery::new().with_client_order_id("a")).unwrap(), vec![rejected.clone()]);
+        assert_eq!(rejected.outcome, OrderJournalOutcome::Rejected);
+        assert_eq!(accepted.outcome, OrderJournalOutcome::Accepted);
+        assert_eq!(other_symbol.outcome, OrderJournalOutcome::Failed);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn json_lines_journal_reports_write_failures() {
+        let path = std::env::temp_dir().join(format!("order_journal_{}", Uuid::new_v4())).join("journal.jsonl");
+        let journal = JsonLinesOrderJournal::new(&path);
+        // Queued, the failure is reported by the next call
+        journal.append(&record("BTCUSDT", "a", 1_000)).unwrap();
+        assert!(journal.query(&OrderJournalQuery::new()).is_err());
+        assert!(journal.query(&OrderJournalQuery::new()).unwrap().is_empty());
+
+        let journal = JsonLinesOrderJournal::new(&path).with_durable_appends();
+        assert!(journal.append(&record("BTCUSDT", "a", 1_000)).is_err());
+    }
+
+    #[test]
+    fn json_lines_journal_repairs_a_partial_last_line() {
+        let path = std::env::temp_dir().join(format!("order_journal_{}.jsonl", Uuid::new_v4()));
+        let first = record("BTCUSDT", "a", 1_000);
+        let mut contents = serde_json::to_string(&first).unwrap();
+        contents.push_str("\n{\"id\":\"trunc");
+        fs::write(&path, contents).unwrap();
+
+        let journal = JsonLinesOrderJournal::new(&path).with_durable_appends();
+        let second = record("BTCUSDT", "b", 2_000);
+        journal.append(&second).unwrap();
+        assert_eq!(journal.query(&OrderJournalQuery::new()).unwrap(), vec![first, second]);
+
+        // A corrupt record before the last one fails the query rather than going missing
+        let contents = fs::read_to_string(&path).unwrap().replacen("\"id\"", "\"id", 1);
+        fs::write(&path, contents).unwrap();
+        assert!(journal.query(&OrderJournalQuery::new()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,18 +1,27 @@
-use std::collections::BTreeSet;
-use std::fs;
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
 use anyhow::anyhow;
-use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use rust_decimal::Decimal;
+use crate::clock::{global_clock, Clock};
+use crate::futures::utils::order_guard::OrderGuard;
+use crate::futures::utils::order_tracker_bootstrap::TrackedOrderIndex;
+use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+use crate::futures::utils::order_tracker_retention::{CompactedOrders, OrderSizeClasses, OrderTrackingBucket, OrderTrackingBucketKey, RetentionPolicy};
+use crate::futures::utils::order_tracker_store::{InMemoryOrderTrackerStore, OrderTrackerStore};
+use crate::futures::utils::order_tracking_index::{CountedOrders, OrderTrackingIndex};
+use crate::futures::utils::order_tracking_item::{OrderTrackingItem, OrderTrackingOutcome};
+use crate::futures::utils::order_tracking_request::OrderTrackingRequest;
 use crate::futures::utils::top_n::{TopN, TopNEntry};
-use once_cell::sync::Lazy;
-use dashmap::DashMap;
+use crate::rest_model::OrderSide;
+use dashmap::{DashMap, DashSet};
+use dashmap::mapref::one::RefMut;
 use uuid::Uuid;
 use crate::errors::Result;
-use crate::futures::account::OrderRequest;
 
 const TOP_N_ORDER_TRACKING_CAPACITY: usize = 3000;
 type OrderSymbol = String;
+type OrderTrackingBuckets = BTreeMap<OrderTrackingBucketKey, OrderTrackingBucket>;
 
 impl Default for TopN<OrderTrackingItem> {
     fn default() -> Self {
@@ -20,115 +29,492 @@ impl Default for TopN<OrderTrackingItem> {
     }
 }
 
-static SYMBOL_ORDER_TRACKING: Lazy<DashMap<OrderSymbol, TopN<OrderTrackingItem>>> =
-    Lazy::new(DashMap::new);
+/// Tracked entries by entry id, and their entry ids by client order id and by exchange order id
+#[derive(Default)]
+struct TrackedOrderIds {
+    entries: HashMap<String, TopNEntry<OrderTrackingItem>>,
+    client_order_ids: HashMap<String, String>,
+    order_ids: HashMap<String, String>,
+}
 
+impl TrackedOrderIds {
+    fn insert(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        let id = &entry.item.id;
+        if let Some(client_order_id) = &entry.item.client_order_id {
+            self.client_order_ids.insert(client_order_id.clone(), id.clone());
+        }
+        if let Some(order_id) = &entry.item.outcome.order_id {
+            self.order_ids.insert(order_id.clone(), id.clone());
+        }
+        self.entries.insert(id.clone(), entry.clone());
+    }
 
-fn get_file_path(order_symbol: &OrderSymbol) -> PathBuf {
-    PathBuf::from(format!("order_tracker_{}.json", order_symbol))
+    fn remove(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        let id = &entry.item.id;
+        self.entries.remove(id);
+        // Another entry may have been sent with the same client order id since
+        if let Some(client_order_id) = &entry.item.client_order_id {
+            if self.client_order_ids.get(client_order_id) == Some(id) {
+                self.client_order_ids.remove(client_order_id);
+            }
+        }
+        if let Some(order_id) = &entry.item.outcome.order_id {
+            if self.order_ids.get(order_id) == Some(id) {
+                self.order_ids.remove(order_id);
+            }
+        }
+    }
 }
 
-pub fn remove_order_tracking_item(order_symbol: &OrderSymbol, item: &TopNEntry<OrderTrackingItem>) {
-    if let Some(mut top_n) = SYMBOL_ORDER_TRACKING.get_mut(order_symbol) {
-        if !top_n.remove(item) {
-            eprintln!("item {item:?} not found in {order_symbol} tracker so cannot remove it")
-        }
-    } else {
-        eprintln!("symbol {order_symbol} not found in tracker map so cannot remove item: {item:?}")
-    }
-}
-
-pub fn add_order_tracking_item(order_request: &OrderRequest) -> Result<TopNEntry<OrderTrackingItem>> {
-    let symbol = order_request.symbol.clone();
-    let file_path = get_file_path(&symbol);
-
-    let timestamp_nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(duration) => duration.as_nanos() as u64,
-        Err(error) => return Err(anyhow!("Failed to get current time for order tracking item: {error:?}").into())
-    };
-    let tracking_item = OrderTrackingItem {
-        size: match order_request.quantity {
-            Some(quantity) => quantity,
-            None => return Err(anyhow!("Quantity is required to add order tracking item").into())
-        },
-        price: match order_request.price {
-            Some(price) => price,
-            None => return Err(anyhow!("Price is required to add order tracking item").into())
-        },
-        side: order_request.side.clone(),
-        id: format!("{}-{timestamp_nanos}", Uuid::new_v4()),
-    };
-    let new_item_entry = TopNEntry {
-        timestamp: timestamp_nanos,
-        item: tracking_item.clone(),
-    };
-
-    // --- LOCK ACQUIRED HERE ---
-    // .or_default() gets the existing entry or inserts a
-    // *new, empty* TopN, and returns a write-lock (RefMut).
-    let mut top_n_ref = SYMBOL_ORDER_TRACKING
-        .entry(symbol.clone())
-        .or_default();
-
-    //
-    // This is your "or_else" logic:
-    //
-    // If the TopN is empty, it *might* be brand new.
-    // Try to load it from disk.
-    if top_n_ref.is_empty() && file_path.exists() {
-        // Try to load
-        match load_top_n_from_file(&file_path, TOP_N_ORDER_TRACKING_CAPACITY) {
-            Ok(loaded_top_n) => {
-                // Success! Replace the empty TopN with the loaded one.
-                // We are still under the lock, so this is safe.
-                *top_n_ref = loaded_top_n;
+/// Tracked orders of a symbol, their counters and their ids, kept in sync under the same lock
+struct TrackedOrders {
+    orders: TopN<OrderTrackingItem>,
+    index: OrderTrackingIndex,
+    ids: TrackedOrderIds,
+    /// Size the open orders may still fill per side, see `OrderTrackingItem::get_open_size`
+    open_sizes: HashMap<OrderSide, Decimal>,
+}
+
+impl TrackedOrders {
+    fn new(capacity: usize, size_classes: OrderSizeClasses) -> Self {
+        Self {
+            orders: TopN::new(capacity, None),
+            index: OrderTrackingIndex::new(size_classes),
+            ids: TrackedOrderIds::default(),
+            open_sizes: HashMap::new(),
+        }
+    }
+
+    fn add_open_size(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        let open_size = entry.item.get_open_size();
+        if !open_size.is_zero() {
+            *self.open_sizes.entry(entry.item.side.clone()).or_default() += open_size;
+        }
+    }
+
+    fn remove_open_size(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        let open_size = entry.item.get_open_size();
+        if let Some(total) = self.open_sizes.get_mut(&entry.item.side) {
+            *total -= open_size;
+        }
+    }
+
+    /// Returns the oldest entry if it was evicted to stay within the capacity
+    fn insert(&mut self, entry: TopNEntry<OrderTrackingItem>) -> Option<TopNEntry<OrderTrackingItem>> {
+        if self.orders.contains(&entry) {
+            return None;
+        }
+        self.index.insert(&entry);
+        self.ids.insert(&entry);
+        self.add_open_size(&entry);
+        let evicted = self.orders.insert_evicting(entry);
+        if let Some(evicted) = &evicted {
+            self.index.remove(evicted);
+            self.ids.remove(evicted);
+            self.remove_open_size(evicted);
+        }
+        evicted
+    }
+
+    fn remove(&mut self, entry: &TopNEntry<OrderTrackingItem>) -> bool {
+        // The stored entry may have an outcome the given one does not
+        let Some(stored) = self.orders.get(entry).cloned() else {
+            return false;
+        };
+        self.orders.remove(&stored);
+        self.index.remove(&stored);
+        self.ids.remove(&stored);
+        self.remove_open_size(&stored);
+        true
+    }
+
+    /// Replaces an entry by the same order with another outcome
+    fn replace(&mut self, previous: &TopNEntry<OrderTrackingItem>, entry: TopNEntry<OrderTrackingItem>) {
+        // The stored entry may have an outcome the given one does not
+        let Some(stored) = self.orders.get(previous).cloned() else {
+            return;
+        };
+        self.index.update(&stored, &entry);
+        self.ids.remove(&stored);
+        self.ids.insert(&entry);
+        self.remove_open_size(&stored);
+        self.add_open_size(&entry);
+        self.orders.remove(&stored);
+        self.orders.insert(entry);
+    }
+
+    fn drain_lt_timestamp(&mut self, timestamp: u64) -> Vec<TopNEntry<OrderTrackingItem>> {
+        let drained = self.orders.drain_lt_timestamp(timestamp);
+        for entry in drained.iter() {
+            self.index.remove(entry);
+            self.ids.remove(entry);
+            self.remove_open_size(entry);
+        }
+        drained
+    }
+
+    fn get_by_id(&self, entry_id: &str) -> Option<&TopNEntry<OrderTrackingItem>> {
+        self.ids.entries.get(entry_id).and_then(|entry| self.orders.get(entry))
+    }
+
+    fn get_by_client_order_id(&self, client_order_id: &str) -> Option<&TopNEntry<OrderTrackingItem>> {
+        self.ids.client_order_ids.get(client_order_id).and_then(|entry_id| self.get_by_id(entry_id))
+    }
+
+    fn get_by_order_id(&self, order_id: &str) -> Option<&TopNEntry<OrderTrackingItem>> {
+        self.ids.order_ids.get(order_id).and_then(|entry_id| self.get_by_id(entry_id))
+    }
+
+    fn rebuild_index(&mut self, size_classes: OrderSizeClasses) {
+        self.index = OrderTrackingIndex::new(size_classes);
+        for entry in self.orders.iter_gte_timestamp(0) {
+            self.index.insert(entry);
+        }
+    }
+}
+
+/// Latest orders submitted per symbol, persisted to an `OrderTrackerStore`
+///
+/// Symbols with a `RetentionPolicy` keep their orders as they are for its raw window only,
+/// older orders and orders evicted by the capacity are compacted into buckets.
+/// The orders are indexed by the size classes of the policy, so that rules count them
+/// without scanning, see `count_orders`.
+pub struct OrderTracker {
+    symbol_order_tracking: DashMap<OrderSymbol, TrackedOrders>,
+    symbol_buckets: DashMap<OrderSymbol, OrderTrackingBuckets>,
+    retention: DashMap<OrderSymbol, RetentionPolicy>,
+    store: RwLock<Arc<dyn OrderTrackerStore>>,
+    capacity: usize,
+    /// Clock orders are timestamped with, the global clock if `None`
+    clock: Option<Arc<dyn Clock>>,
+    /// Symbols whose exchange history was merged, see `merge_history`
+    bootstrapped: DashSet<OrderSymbol>,
+}
+
+impl Default for OrderTracker {
+    /// Keeps the tracked orders in memory only, so that default trackers never share state
+    fn default() -> Self {
+        Self::new(Arc::new(InMemoryOrderTrackerStore::new()))
+    }
+}
+
+impl fmt::Debug for OrderTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderTracker")
+            .field("symbols", &self.symbol_order_tracking.len())
+            .field("store", &self.get_store())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl OrderTracker {
+    pub fn new(store: Arc<dyn OrderTrackerStore>) -> Self {
+        Self {
+            symbol_order_tracking: DashMap::new(),
+            symbol_buckets: DashMap::new(),
+            retention: DashMap::new(),
+            store: RwLock::new(store),
+            capacity: TOP_N_ORDER_TRACKING_CAPACITY,
+            clock: None,
+            bootstrapped: DashSet::new(),
+        }
+    }
+
+    /// Number of orders kept per symbol, 3000 by default
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Timestamps orders and measures rule periods with `clock` instead of the global clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Current time in nanoseconds according to the clock of the tracker
+    pub fn now_nanos(&self) -> u64 {
+        match self.clock {
+            Some(ref clock) => clock.now_nanos(),
+            None => global_clock().now_nanos(),
+        }
+    }
+
+    fn get_store(&self) -> Arc<dyn OrderTrackerStore> {
+        self.store.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Replaces the store the tracked orders are persisted to.
+    /// Tracked orders are reloaded from the new store on the next order.
+    pub fn set_store(&self, store: Arc<dyn OrderTrackerStore>) {
+        *self.store.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = store;
+        self.symbol_order_tracking.clear();
+        self.symbol_buckets.clear();
+        self.bootstrapped.clear();
+    }
+
+    /// Sets how long the orders of `symbol` are tracked, applied from the next order on
+    pub fn set_retention_policy(&self, symbol: &str, policy: RetentionPolicy) {
+        if self.retention.get(symbol).is_some_and(|current| *current == policy) {
+            return;
+        }
+        let size_classes = policy.size_classes.clone();
+        self.retention.insert(symbol.to_string(), policy);
+        if let Some(mut tracked) = self.symbol_order_tracking.get_mut(symbol) {
+            if *tracked.index.get_size_classes() != size_classes {
+                tracked.rebuild_index(size_classes);
+            }
+        }
+    }
+
+    pub fn get_retention_policy(&self, symbol: &str) -> Option<RetentionPolicy> {
+        self.retention.get(symbol).map(|policy| policy.clone())
+    }
+
+    pub fn remove_order_tracking_item(&self, order_symbol: &OrderSymbol, item: &TopNEntry<OrderTrackingItem>) {
+        if let Some(mut tracked) = self.symbol_order_tracking.get_mut(order_symbol) {
+            if !tracked.remove(item) {
+                eprintln!("item {item:?} not found in {order_symbol} tracker so cannot remove it")
+            } else if let Err(error) = self.get_store().remove(order_symbol, item, &tracked.orders) {
+                eprintln!("failed to persist removal of {item:?} from {order_symbol} tracker: {error:?}")
             }
-            Err(error) => return Err(anyhow!("Failed to load order tracker file from {} when adding item {symbol} {new_item_entry:?}: {error:?}", file_path.display()).into())
+        } else {
+            eprintln!("symbol {order_symbol} not found in tracker map so cannot remove item: {item:?}")
         }
     }
 
-    // Now, whether it was loaded or was pre-existing,
-    // insert the new item (in-memory only).
-    top_n_ref.insert(new_item_entry.clone());
+    /// Tracks the order under the tracking key of its market and symbol, see `OrderMarket::get_tracking_key`
+    pub fn add_order_tracking_item(&self, order_request: &OrderTrackingRequest) -> Result<TopNEntry<OrderTrackingItem>> {
+        let symbol = order_request.get_tracking_key().into_owned();
+        let store = self.get_store();
+
+        let timestamp_nanos = self.now_nanos();
+        let tracking_item = OrderTrackingItem {
+            size: match order_request.quantity {
+                Some(quantity) => quantity,
+                None => return Err(anyhow!("Quantity is required to add order tracking item").into())
+            },
+            price: match order_request.price {
+                Some(price) => price,
+                None => return Err(anyhow!("Price is required to add order tracking item").into())
+            },
+            side: order_request.side.clone(),
+            id: format!("{}-{timestamp_nanos}", Uuid::new_v4()),
+            grid_id: order_request.grid_id.clone(),
+            client_order_id: order_request.client_order_id.clone(),
+            outcome: OrderTrackingOutcome::default(),
+        };
+        let new_item_entry = TopNEntry {
+            timestamp: timestamp_nanos,
+            item: tracking_item.clone(),
+        };
+
+        let mut top_n_ref = match self.get_or_load(&symbol, &store) {
+            Ok(top_n_ref) => top_n_ref,
+            Err(error) => return Err(anyhow!("Failed to load order tracker store when adding item {symbol} {new_item_entry:?}: {error:?}").into())
+        };
+
+        // Now, whether it was loaded or was pre-existing,
+        // insert the new item (in-memory only).
+        let evicted = top_n_ref.insert(new_item_entry.clone());
+
+        if let Err(error) = store.insert(&symbol, &new_item_entry, &top_n_ref.orders) {
+            return Err(anyhow!("Failed to persist TopN set when adding item {symbol} {new_item_entry:?}: {error:?}").into());
+        }
+        if let Some(policy) = self.get_retention_policy(&symbol) {
+            let mut removed = top_n_ref.drain_lt_timestamp(timestamp_nanos.saturating_sub(policy.raw_window.as_nanos() as u64));
+            removed.extend(evicted);
+            let mut buckets = self.symbol_buckets.entry(symbol.clone()).or_default();
+            if policy.compact(&mut buckets, &removed, timestamp_nanos) || !removed.is_empty() {
+                let buckets_vec = buckets.values().cloned().collect::<Vec<_>>();
+                if let Err(error) = store.compact(&symbol, &removed, &top_n_ref.orders, &buckets_vec) {
+                    eprintln!("failed to persist compaction of {symbol} tracker: {error:?}")
+                }
+            }
+        }
+        Ok(new_item_entry)
+    }
+
+    /// Records what the exchange reported on the tracked entry `entry_id` of `symbol`, e.g. the
+    /// response to the order. Returns the updated entry, `None` if it is no longer tracked.
+    pub fn record_outcome(&self, symbol: &str, entry_id: &str, outcome: &OrderTrackingOutcome) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+        self.update_outcome(symbol, |tracked| tracked.get_by_id(entry_id), outcome)
+    }
+
+    /// Records what the exchange reported on an order of `symbol` known by its exchange order id,
+    /// `outcome.order_id`, or by its client order id, e.g. from a user data stream event.
+    /// Returns the updated entry, `None` if no tracked entry is that order.
+    pub fn record_exchange_outcome(&self, symbol: &str, client_order_id: Option<&str>, outcome: &OrderTrackingOutcome) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+        self.update_outcome(
+            symbol,
+            |tracked| {
+                let by_order_id = outcome.order_id.as_deref().and_then(|order_id| tracked.get_by_order_id(order_id));
+                by_order_id.or_else(|| client_order_id.and_then(|client_order_id| tracked.get_by_client_order_id(client_order_id)))
+            },
+            outcome,
+        )
+    }
+
+    fn update_outcome(&self, symbol: &str, find: impl Fn(&TrackedOrders) -> Option<&TopNEntry<OrderTrackingItem>>, outcome: &OrderTrackingOutcome) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+        let store = self.get_store();
+        let mut top_n_ref = self.get_or_load(symbol, &store)?;
+        let Some(previous) = find(&top_n_ref).cloned() else {
+            return Ok(None);
+        };
+        let mut entry = previous.clone();
+        if !entry.item.outcome.merge(outcome) {
+            return Ok(Some(entry));
+        }
+        top_n_ref.replace(&previous, entry.clone());
+        if let Err(error) = store.update(symbol, &previous, &entry, &top_n_ref.orders) {
+            return Err(anyhow!("Failed to persist the outcome of {entry:?} in {symbol} tracker: {error:?}").into());
+        }
+        Ok(Some(entry))
+    }
+
+    /// Merges entries rebuilt from the exchange history, e.g. by `FuturesAccount::bootstrap_order_tracker`,
+    /// into the tracked orders of `symbol` and marks it as bootstrapped.
+    /// Entries of orders already tracked are skipped, see `is_same_order`. Returns the number of merged entries.
+    pub fn merge_history(&self, symbol: &str, entries: Vec<TopNEntry<OrderTrackingItem>>) -> Result<usize> {
+        let store = self.get_store();
+        let mut top_n_ref = self.get_or_load(symbol, &store)?;
+        let mut tracked = TrackedOrderIndex::new(&top_n_ref.orders.get_all());
+        let mut merged = vec![];
+        for entry in entries.into_iter() {
+            if tracked.contains(&entry) {
+                continue;
+            }
+            tracked.insert(&entry);
+            top_n_ref.insert(entry.clone());
+            merged.push(entry);
+        }
+        if !merged.is_empty() {
+            if let Err(error) = store.insert_many(symbol, &merged, &top_n_ref.orders) {
+                return Err(anyhow!("Failed to persist TopN set when merging the history of {symbol}: {error:?}").into());
+            }
+        }
+        self.bootstrapped.insert(symbol.to_string());
+        Ok(merged.len())
+    }
+
+    pub fn is_bootstrapped(&self, symbol: &str) -> bool {
+        self.bootstrapped.contains(symbol)
+    }
 
-    if let Err(error) = save_top_n_to_file(&top_n_ref, &file_path) {
-        return Err(anyhow!("Failed to write TopN set to file when adding item {symbol} {new_item_entry:?}: {error:?}").into());
+    /// Loads the orders of `symbol` from the store unless they are tracked already
+    pub fn load_symbol(&self, symbol: &str) -> Result<()> {
+        self.get_or_load(symbol, &self.get_store()).map(|_| ())
     }
-    Ok(new_item_entry)
+
+    fn get_or_load(&self, symbol: &str, store: &Arc<dyn OrderTrackerStore>) -> Result<RefMut<'_, OrderSymbol, TrackedOrders>> {
+        // --- LOCK ACQUIRED HERE ---
+        // .or_insert_with() gets the existing entry or inserts a
+        // *new, empty* TopN, and returns a write-lock (RefMut).
+        let mut top_n_ref = self
+            .symbol_order_tracking
+            .entry(symbol.to_string())
+            .or_insert_with(|| TrackedOrders::new(self.capacity, self.get_retention_policy(symbol).map(|policy| policy.size_classes).unwrap_or_default()));
+
+        // If the TopN is empty, it *might* be brand new.
+        // Try to load it from the store.
+        if top_n_ref.orders.is_empty() {
+            // We are still under the lock, so this is safe.
+            // Inserting one by one enforces the capacity on sets persisted with a larger one.
+            for entry in store.load(symbol)? {
+                top_n_ref.insert(entry);
+            }
+            if !self.symbol_buckets.contains_key(symbol) {
+                let buckets = store.load_buckets(symbol)?;
+                self.symbol_buckets.insert(symbol.to_string(), buckets.into_iter().map(|bucket| (bucket.key(), bucket)).collect());
+            }
+        }
+        Ok(top_n_ref)
+    }
+
+    /// Buckets of the compacted orders of `symbol` ending after `min_timestamp`
+    pub fn get_buckets(&self, symbol: &str, min_timestamp: u64) -> Vec<OrderTrackingBucket> {
+        match self.symbol_buckets.get(symbol) {
+            Some(buckets) => buckets.values().filter(|bucket| bucket.end() > min_timestamp).cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Tracked and compacted orders of `symbol` counted by `payload` since `min_timestamp`, `None`
+    /// if the symbol is not tracked. When `grid_scoped`, only the orders of `grid_id` are counted.
+    /// Orders are counted from the index unless the size bounds of the rule are unknown to the
+    /// retention policy of the symbol.
+    pub fn count_orders(&self, symbol: &str, payload: &ExpectedOrderRequestsRulePayload, min_timestamp: u64, grid_id: Option<&String>, grid_scoped: bool) -> Option<(CountedOrders, CompactedOrders)> {
+        let tracked = self.symbol_order_tracking.get(symbol)?;
+        let counted = match tracked.index.count(payload, min_timestamp, grid_id, grid_scoped) {
+            Some(counted) => counted,
+            None => CountedOrders::scan(tracked.orders.iter_gte_timestamp(min_timestamp), payload, grid_id, grid_scoped),
+        };
+        let compacted = match self.symbol_buckets.get(symbol) {
+            Some(buckets) => CompactedOrders::new(buckets.values(), payload, min_timestamp, grid_id, grid_scoped),
+            None => CompactedOrders::default(),
+        };
+        Some((counted, compacted))
+    }
+
+    /// Size the open tracked orders of `symbol` on `side` may still fill, but `excluded`
+    pub fn get_open_size(&self, symbol: &str, side: &OrderSide, excluded: &TopNEntry<OrderTrackingItem>) -> Decimal {
+        let Some(tracked) = self.symbol_order_tracking.get(symbol) else {
+            return Decimal::ZERO;
+        };
+        let open_size = tracked.open_sizes.get(side).copied().unwrap_or_default();
+        match tracked.orders.get(excluded) {
+            Some(stored) if stored.item.side == *side => open_size - stored.item.get_open_size(),
+            _ => open_size,
+        }
+    }
+
+    pub fn contains(&self, symbol: &str, entry: &TopNEntry<OrderTrackingItem>) -> bool {
+        self.symbol_order_tracking.get(symbol).is_some_and(|tracked| tracked.orders.contains(entry))
+    }
+
+    pub fn get_all_tracking_items(&self, symbol: &str) -> Option<Vec<TopNEntry<OrderTrackingItem>>> {
+        self.symbol_order_tracking
+            .get(symbol)
+            .map(|tracked| tracked.orders.get_all()) // .get() returns a Ref<...>
+    }
+
+    pub fn get_gte_timestamp(&self, symbol: &OrderSymbol, timestamp: u64) -> Option<Vec<TopNEntry<OrderTrackingItem>>> {
+        self.symbol_order_tracking
+            .get(symbol)
+            .map(|tracked| tracked.orders.get_gte_timestamp(timestamp))
+    }
+}
+
+/// Replaces the store of the default `OrderGuard`, see `OrderTracker::set_store`
+pub fn set_order_tracker_store(store: Arc<dyn OrderTrackerStore>) {
+    OrderGuard::global().tracker().set_store(store)
+}
+
+pub fn remove_order_tracking_item(order_symbol: &OrderSymbol, item: &TopNEntry<OrderTrackingItem>) {
+    OrderGuard::global().tracker().remove_order_tracking_item(order_symbol, item)
+}
+
+pub fn add_order_tracking_item(order_request: &OrderTrackingRequest) -> Result<TopNEntry<OrderTrackingItem>> {
+    OrderGuard::global().tracker().add_order_tracking_item(order_request)
+}
+
+pub fn record_outcome(symbol: &str, entry_id: &str, outcome: &OrderTrackingOutcome) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+    OrderGuard::global().tracker().record_outcome(symbol, entry_id, outcome)
+}
+
+pub fn record_exchange_outcome(symbol: &str, client_order_id: Option<&str>, outcome: &OrderTrackingOutcome) -> Result<Option<TopNEntry<OrderTrackingItem>>> {
+    OrderGuard::global().tracker().record_exchange_outcome(symbol, client_order_id, outcome)
 }
 
 pub fn get_all_tracking_items(symbol: &str) -> Option<Vec<TopNEntry<OrderTrackingItem>>> {
-    SYMBOL_ORDER_TRACKING
-        .get(symbol)
-        .map(|top_n| top_n.get_all()) // .get() returns a Ref<...>
+    OrderGuard::global().tracker().get_all_tracking_items(symbol)
 }
 
 pub fn get_gte_timestamp(symbol: &OrderSymbol, timestamp: u64) -> Option<Vec<TopNEntry<OrderTrackingItem>>> {
-    SYMBOL_ORDER_TRACKING
-        .get(symbol)
-        .map(|top_n| top_n.get_gte_timestamp(timestamp))
-}
-
-fn save_top_n_to_file(top_n: &TopN<OrderTrackingItem>, save_file_path: &PathBuf) -> Result<()>
-{
-    let as_json = top_n.get_set_json()?;
-    if let Err(error) = fs::write(save_file_path, as_json) {
-        return Err(anyhow!("Failed to save top n to file: {error:?}").into())
-    }
-    Ok(())
-}
-
-// New function to load the set from a file
-fn load_top_n_from_file(save_file_path: &PathBuf, capacity: usize) -> Result<TopN<OrderTrackingItem>>
-{
-    let data = match fs::read_to_string(save_file_path) {
-        Ok(data) => data,
-        Err(error) => return Err(anyhow!("Failed to read top n from file: {error:?}").into())
-    };
-    let set: BTreeSet<TopNEntry<OrderTrackingItem>> = match serde_json::from_str(&data) {
-        Ok(set) => set,
-        Err(error) => return Err(anyhow!("Failed to deserialize top n from json: {error:?}").into())
-    };
-
-    Ok(TopN::new(capacity, Some(set)))
-}
\ No newline at end of file
+    OrderGuard::global().tracker().get_gte_timestamp(symbol, timestamp)
+}
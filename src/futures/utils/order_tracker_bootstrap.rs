@@ -0,0 +1,315 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(feature = "futures_api")]
+use std::collections::BTreeMap;
+use rust_decimal::Decimal;
+#[cfg(feature = "futures_api")]
+use crate::futures::rest_model::{AccountTrade, Order};
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+#[cfg(feature = "futures_api")]
+use crate::futures::utils::order_tracking_item::{OrderTrackingOutcome, OrderTrackingStatus};
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+
+/// Longest time range the order and trade history endpoints accept, in milliseconds
+pub const HISTORY_WINDOW_MILLIS: u64 = 7 * 24 * 60 * 60 * 1000 - 1;
+/// Most orders or trades the history endpoints return at once
+pub const HISTORY_PAGE_LIMIT: u16 = 1000;
+/// How far apart a locally tracked order without client order id and its exchange counterpart
+/// can be timestamped, in nanoseconds
+const LOCAL_MATCH_WINDOW_NANOS: u64 = 60 * 1_000_000_000;
+#[cfg(feature = "futures_api")]
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+#[cfg(feature = "futures_api")]
+fn exchange_entry_id(order_id: &str) -> String { format!("order-{order_id}") }
+
+#[cfg(feature = "futures_api")]
+/// The order as it would have been tracked when it was sent, `None` for orders without a size
+/// such as close position orders
+pub fn entry_from_order(order: &Order) -> Option<TopNEntry<OrderTrackingItem>> {
+    if order.orig_qty.is_zero() {
+        return None;
+    }
+    Some(TopNEntry {
+        timestamp: order.time.unwrap_or(order.update_time) * NANOS_PER_MILLI,
+        item: OrderTrackingItem {
+            size: order.orig_qty,
+            // Market orders have no price
+            price: if order.price.is_zero() { order.avg_price } else { order.price },
+            side: order.side.clone(),
+            id: exchange_entry_id(&order.order_id),
+            grid_id: None,
+            client_order_id: Some(order.client_order_id.clone()),
+            outcome: OrderTrackingOutcome {
+                status: (&order.status).into(),
+                order_id: Some(order.order_id.clone()),
+                filled_size: order.executed_qty,
+                filled_notional: order.cum_quote,
+            },
+        },
+    })
+}
+
+#[cfg(feature = "futures_api")]
+/// One entry per order the trades filled, at the time of its first fill.
+/// Trades listed more than once are counted once.
+pub fn entries_from_trades(trades: &[AccountTrade]) -> Vec<TopNEntry<OrderTrackingItem>> {
+    let unique_trades: BTreeMap<u64, &AccountTrade> = trades.iter().map(|trade| (trade.id, trade)).collect();
+    let mut trades_by_order: BTreeMap<&str, Vec<&AccountTrade>> = BTreeMap::new();
+    for trade in unique_trades.into_values() {
+        trades_by_order.entry(trade.order_id.as_str()).or_default().push(trade);
+    }
+    let mut entries = vec![];
+    for (order_id, order_trades) in trades_by_order.into_iter() {
+        let size: Decimal = order_trades.iter().map(|trade| trade.qty).sum();
+        if size.is_zero() {
+            continue;
+        }
+        let quote: Decimal = order_trades.iter().map(|trade| trade.quote_qty).sum();
+        let first_trade = order_trades.iter().min_by_key(|trade| trade.time).unwrap();
+        entries.push(TopNEntry {
+            timestamp: first_trade.time * NANOS_PER_MILLI,
+            item: OrderTrackingItem {
+                size,
+                price: quote / size,
+                side: first_trade.side.clone(),
+                id: exchange_entry_id(order_id),
+                grid_id: None,
+                client_order_id: None,
+                outcome: OrderTrackingOutcome {
+                    // Whether the order filled in full is unknown without the order itself
+                    status: OrderTrackingStatus::Filled,
+                    order_id: Some(order_id.to_string()),
+                    filled_size: size,
+                    filled_notional: quote,
+                },
+            },
+        });
+    }
+    entries
+}
+
+#[cfg(feature = "futures_api")]
+/// Entries of the orders, then of the trades of orders missing from `orders`, once per order
+pub fn history_entries(orders: &[Order], trades: &[AccountTrade]) -> Vec<TopNEntry<OrderTrackingItem>> {
+    let mut entries = BTreeMap::new();
+    for entry in orders.iter().filter_map(entry_from_order) {
+        entries.insert(entry.item.id.clone(), entry);
+    }
+    for entry in entries_from_trades(trades) {
+        entries.entry(entry.item.id.clone()).or_insert(entry);
+    }
+    entries.into_values().collect()
+}
+
+/// Whether a tracked entry and an entry rebuilt from the exchange history are the same order
+///
+/// Entries are matched by id, then by client order id. Tracked entries without client order id
+/// match exchange entries of the same side, size and price sent around the same time.
+pub fn is_same_order(tracked: &TopNEntry<OrderTrackingItem>, history: &TopNEntry<OrderTrackingItem>) -> bool {
+    if tracked.item.id == history.item.id {
+        return true;
+    }
+    if let (Some(tracked_client_id), Some(history_client_id)) = (&tracked.item.client_order_id, &history.item.client_order_id) {
+        return tracked_client_id == history_client_id;
+    }
+    tracked.item.side == history.item.side
+        && tracked.item.size == history.item.size
+        && tracked.item.price == history.item.price
+        && tracked.timestamp.abs_diff(history.timestamp) <= LOCAL_MATCH_WINDOW_NANOS
+}
+
+/// Side, size and price of an order
+type OrderShape = (OrderSide, Decimal, Decimal);
+
+fn order_shape(entry: &TopNEntry<OrderTrackingItem>) -> OrderShape {
+    (entry.item.side.clone(), entry.item.size, entry.item.price)
+}
+
+/// Tracked entries of a symbol indexed for `is_same_order`, so that matching an entry of the
+/// exchange history does not scan every tracked entry
+#[derive(Default)]
+pub struct TrackedOrderIndex {
+    ids: HashSet<String>,
+    client_ids: HashSet<String>,
+    /// Timestamps of the tracked entries by side, size and price
+    shapes: HashMap<OrderShape, BTreeSet<u64>>,
+    /// Same as `shapes`, for the tracked entries without client order id only
+    shapes_without_client_id: HashMap<OrderShape, BTreeSet<u64>>,
+}
+
+impl TrackedOrderIndex {
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a TopNEntry<OrderTrackingItem>>) -> Self {
+        let mut index = Self::default();
+        for entry in entries {
+            index.insert(entry);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        self.ids.insert(entry.item.id.clone());
+        self.shapes.entry(order_shape(entry)).or_default().insert(entry.timestamp);
+        match &entry.item.client_order_id {
+            Some(client_order_id) => {
+                self.client_ids.insert(client_order_id.clone());
+            }
+            None => {
+                self.shapes_without_client_id.entry(order_shape(entry)).or_default().insert(entry.timestamp);
+            }
+        }
+    }
+
+    /// Whether `is_same_order` holds for `history` and any indexed entry
+    pub fn contains(&self, history: &TopNEntry<OrderTrackingItem>) -> bool {
+        if self.ids.contains(&history.item.id) {
+            return true;
+        }
+        let shapes = match &history.item.client_order_id {
+            Some(client_order_id) if self.client_ids.contains(client_order_id) => return true,
+            // Tracked entries with another client order id are other orders
+            Some(_) => &self.shapes_without_client_id,
+            None => &self.shapes,
+        };
+        let window = history.timestamp.saturating_sub(LOCAL_MATCH_WINDOW_NANOS)..=history.timestamp.saturating_add(LOCAL_MATCH_WINDOW_NANOS);
+        shapes.get(&order_shape(history)).is_some_and(|timestamps| timestamps.range(window).next().is_some())
+    }
+}
+
+#[cfg(feature = "futures_api")]
+/// Id to request the next page of a history from, `None` once the history is exhausted.
+/// Pages are requested by order or trade id as records sharing a timestamp can span pages.
+pub(crate) fn next_page_from_id(page_len: usize, last_id: Option<u64>) -> Option<u64> {
+    if page_len < HISTORY_PAGE_LIMIT as usize {
+        return None;
+    }
+    last_id.map(|last_id| last_id + 1)
+}
+
+#[cfg(all(test, feature = "futures_api"))]
+mod test {
+    use std::sync::Arc;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::futures::utils::order_tracker::OrderTracker;
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::rest_model::OrderSide;
+
+    fn order(order_id: u64, client_order_id: &str, time: u64, price: &str) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "clientOrderId": client_order_id,
+            "cumQuote": "0",
+            "executedQty": "0",
+            "orderId": order_id,
+            "avgPrice": "100",
+            "origQty": "1",
+            "price": price,
+            "side": "BUY",
+            "reduceOnly": false,
+            "positionSide": "LONG",
+            "status": "CANCELED",
+            "closePosition": false,
+            "symbol": "BTCUSDT",
+            "timeInForce": "GTC",
+            "type": "LIMIT",
+            "origType": "LIMIT",
+            "updateTime": time + 10,
+            "workingType": "CONTRACT_PRICE",
+            "priceProtect": false,
+            "time": time,
+        }))
+        .unwrap()
+    }
+
+    fn trade(id: u64, order_id: u64, time: u64, qty: &str, quote_qty: &str) -> AccountTrade {
+        serde_json::from_value(serde_json::json!({
+            "symbol": "BTCUSDT",
+            "id": id,
+            "orderId": order_id,
+            "side": "SELL",
+            "price": "100",
+            "qty": qty,
+            "realizedPnl": "0",
+            "quoteQty": quote_qty,
+            "commission": "0",
+            "commissionAsset": "USDT",
+            "time": time,
+            "positionSide": "LONG",
+            "maker": false,
+            "buyer": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rebuilds_history_without_duplicates() {
+        let orders = vec![order(1, "local", 1_000, "100"), order(2, "remote", 2_000, "0"), order(2, "remote", 2_000, "0")];
+        let trades = vec![trade(10, 2, 2_001, "1", "100"), trade(11, 3, 500, "1", "90"), trade(12, 3, 400, "1", "110"), trade(12, 3, 400, "1", "110")];
+        let entries = history_entries(&orders, &trades);
+        assert_eq!(entries.len(), 3);
+        let filled = entries.iter().find(|entry| entry.item.id == "order-3").unwrap();
+        assert_eq!((filled.timestamp, filled.item.size, filled.item.price), (400 * NANOS_PER_MILLI, dec!(2), dec!(100)));
+        assert_eq!(filled.item.side, OrderSide::Sell);
+        // Market orders are tracked at their average price
+        assert_eq!(entries.iter().find(|entry| entry.item.id == "order-2").unwrap().item.price, dec!(100));
+
+        let tracker = OrderTracker::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let local = TopNEntry {
+            timestamp: 1_000 * NANOS_PER_MILLI - 5,
+            item: OrderTrackingItem {
+                size: dec!(1),
+                price: dec!(100),
+                side: OrderSide::Buy,
+                id: "local-id".to_string(),
+                grid_id: Some("grid".to_string()),
+                client_order_id: Some("local".to_string()),
+                outcome: OrderTrackingOutcome::default(),
+            },
+        };
+        assert_eq!(tracker.merge_history("BTCUSDT", vec![local.clone()]).unwrap(), 1);
+        assert_eq!(tracker.merge_history("BTCUSDT", entries.clone()).unwrap(), 2);
+        assert_eq!(tracker.merge_history("BTCUSDT", entries).unwrap(), 0);
+        let tracked = tracker.get_all_tracking_items("BTCUSDT").unwrap();
+        assert_eq!(tracked.len(), 3);
+        assert!(tracked.contains(&local));
+        assert!(tracker.is_bootstrapped("BTCUSDT"));
+    }
+
+    #[test]
+    fn pages_until_short_page() {
+        assert_eq!(next_page_from_id(10, Some(5)), None);
+        assert_eq!(next_page_from_id(1000, Some(5)), Some(6));
+        assert_eq!(next_page_from_id(1000, None), None);
+    }
+
+    #[test]
+    fn index_matches_like_is_same_order() {
+        let entry = |id: &str, client_order_id: Option<&str>, timestamp: u64, price| TopNEntry {
+            timestamp,
+            item: OrderTrackingItem {
+                size: dec!(1),
+                price,
+                side: OrderSide::Buy,
+                id: id.to_string(),
+                grid_id: None,
+                client_order_id: client_order_id.map(str::to_string),
+                outcome: OrderTrackingOutcome::default(),
+            },
+        };
+        let tracked = [entry("a", Some("client-a"), 0, dec!(100)), entry("b", None, LOCAL_MATCH_WINDOW_NANOS * 10, dec!(100))];
+        let index = TrackedOrderIndex::new(&tracked);
+        let history = [
+            entry("a", None, 0, dec!(1)),
+            entry("x", Some("client-a"), 0, dec!(1)),
+            entry("x", Some("client-x"), 1, dec!(100)),
+            entry("x", Some("client-x"), LOCAL_MATCH_WINDOW_NANOS * 11, dec!(100)),
+            entry("x", Some("client-x"), LOCAL_MATCH_WINDOW_NANOS * 11 + 1, dec!(100)),
+            entry("x", None, 1, dec!(100)),
+            entry("x", None, 1, dec!(101)),
+        ];
+        for history_entry in history.iter() {
+            let expected = tracked.iter().any(|tracked_entry| is_same_order(tracked_entry, history_entry));
+            assert_eq!(index.contains(history_entry), expected, "{history_entry:?}");
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use rust_decimal::Decimal;
+use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest window raw entries are kept for
+const MAX_RAW_WINDOW: Duration = Duration::from_secs(48 * 60 * 60);
+const MIN_HOURLY_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BucketGranularity {
+    Hour,
+    Day,
+}
+
+impl BucketGranularity {
+    pub fn get_nanos(&self) -> u64 {
+        match self {
+            BucketGranularity::Hour => HOUR.as_nanos() as u64,
+            BucketGranularity::Day => DAY.as_nanos() as u64,
+        }
+    }
+}
+
+pub type OrderTrackingBucketKey = (u64, BucketGranularity, OrderSide, Option<String>, u32);
+
+/// Aggregate of the tracked orders of one side, grid and size class sent within an hour or a day
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderTrackingBucket {
+    /// Start of the bucket in nanoseconds, a multiple of the granularity
+    pub start: u64,
+    pub granularity: BucketGranularity,
+    pub side: OrderSide,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid_id: Option<String>,
+    /// Size class of the orders, see `OrderSizeClasses`
+    pub size_class: u32,
+    pub count: u64,
+    pub min_size: Decimal,
+    pub max_size: Decimal,
+    /// Sum of price x size of the orders
+    pub notional: Decimal,
+    /// Orders of the bucket the exchange rejected, and their notional
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rejected_count: u64,
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub rejected_notional: Decimal,
+    /// Orders of the bucket with fills, and the notional of their fills
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub filled_count: u64,
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub filled_notional: Decimal,
+}
+
+fn is_zero(count: &u64) -> bool { *count == 0 }
+
+impl OrderTrackingBucket {
+    fn new(entry: &TopNEntry<OrderTrackingItem>, granularity: BucketGranularity, size_class: u32) -> Self {
+        Self {
+            start: entry.timestamp - entry.timestamp % granularity.get_nanos(),
+            granularity,
+            side: entry.item.side.clone(),
+            grid_id: entry.item.grid_id.clone(),
+            size_class,
+            count: 1,
+            min_size: entry.item.size,
+            max_size: entry.item.size,
+            notional: entry.item.price * entry.item.size,
+            rejected_count: entry.item.outcome.is_rejected() as u64,
+            rejected_notional: if entry.item.outcome.is_rejected() { entry.item.price * entry.item.size } else { Decimal::ZERO },
+            filled_count: !entry.item.outcome.filled_size.is_zero() as u64,
+            filled_notional: entry.item.outcome.filled_notional,
+        }
+    }
+
+    pub fn key(&self) -> OrderTrackingBucketKey { (self.start, self.granularity, self.side.clone(), self.grid_id.clone(), self.size_class) }
+
+    /// End of the bucket in nanoseconds, exclusive
+    pub fn end(&self) -> u64 { self.start + self.granularity.get_nanos() }
+
+    fn merge(&mut self, other: &OrderTrackingBucket) {
+        self.count += other.count;
+        self.min_size = self.min_size.min(other.min_size);
+        self.max_size = self.max_size.max(other.max_size);
+        self.notional += other.notional;
+        self.rejected_count += other.rejected_count;
+        self.rejected_notional += other.rejected_notional;
+        self.filled_count += other.filled_count;
+        self.filled_notional += other.filled_notional;
+    }
+
+    /// Orders of the bucket counted on `basis`, and their notional
+    pub fn get_counted(&self, basis: ExpectedOrderRequestsRuleCountBasis) -> (u64, Decimal) {
+        match basis {
+            ExpectedOrderRequestsRuleCountBasis::Submitted => (self.count, self.notional),
+            ExpectedOrderRequestsRuleCountBasis::Accepted => (self.count - self.rejected_count, self.notional - self.rejected_notional),
+            ExpectedOrderRequestsRuleCountBasis::Filled => (self.filled_count, self.filled_notional),
+        }
+    }
+
+    /// Whether the orders of the bucket are counted by `payload` within a period starting at
+    /// `min_timestamp`. Buckets partly within the period or partly matching the size of the rule
+    /// are counted whole, so the count is never below the actual one.
+    pub fn is_counted_by(&self, payload: &ExpectedOrderRequestsRulePayload, min_timestamp: u64) -> bool {
+        self.end() > min_timestamp && self.side == payload.side && payload.size_params.intersects(self.min_size, self.max_size)
+    }
+}
+
+/// Orders compacted into buckets that a rule counts, on top of the tracked entries
+///
+/// Buckets keep the outcome of their orders as it was when they were compacted, later reports
+/// on those orders are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactedOrders {
+    pub count: u64,
+    pub notional: Decimal,
+    /// End of the oldest counted bucket in nanoseconds
+    pub oldest_end: Option<u64>,
+}
+
+impl CompactedOrders {
+    /// When `grid_scoped`, only the buckets of `grid_id` are counted
+    pub fn new<'a>(buckets: impl IntoIterator<Item = &'a OrderTrackingBucket>, payload: &ExpectedOrderRequestsRulePayload, min_timestamp: u64, grid_id: Option<&String>, grid_scoped: bool) -> Self {
+        let mut compacted = Self::default();
+        for bucket in buckets.into_iter() {
+            if !bucket.is_counted_by(payload, min_timestamp) || (grid_scoped && bucket.grid_id.as_ref() != grid_id) {
+                continue;
+            }
+            let (count, notional) = bucket.get_counted(payload.count_by);
+            if count == 0 {
+                continue;
+            }
+            compacted.count += count;
+            compacted.notional += notional;
+            compacted.oldest_end = Some(compacted.oldest_end.map_or(bucket.end(), |oldest_end| oldest_end.min(bucket.end())));
+        }
+        compacted
+    }
+}
+
+/// Splits order sizes at the size bounds of the rules, so that every rule matches whole classes
+///
+/// Orders of the same class are on the same side of every bound, classes grow with the size.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderSizeClasses {
+    /// Sorted min bounds, inclusive
+    pub min_boundaries: Vec<Decimal>,
+    /// Sorted max bounds, inclusive
+    pub max_boundaries: Vec<Decimal>,
+}
+
+impl OrderSizeClasses {
+    pub fn from_rules<'a>(rules: impl IntoIterator<Item = &'a ExpectedOrderRequestsRule>) -> Self {
+        let mut min_boundaries = vec![];
+        let mut max_boundaries = vec![];
+        for rule in rules.into_iter() {
+            min_boundaries.extend(rule.get_payload().size_params.get_min());
+            max_boundaries.extend(rule.get_payload().size_params.get_max());
+        }
+        min_boundaries.sort();
+        min_boundaries.dedup();
+        max_boundaries.sort();
+        max_boundaries.dedup();
+        Self { min_boundaries, max_boundaries }
+    }
+
+    pub fn get_size_class(&self, size: Decimal) -> u32 {
+        let above_min = self.min_boundaries.partition_point(|min| size >= *min);
+        let above_max = self.max_boundaries.partition_point(|max| size > *max);
+        (above_min + above_max) as u32
+    }
+
+    /// Classes of the sizes matching `size_params`, `None` unless its bounds are boundaries of
+    /// these classes
+    pub fn get_class_range(&self, size_params: &ExpectedOrderRequestsRuleSizeParams) -> Option<RangeInclusive<u32>> {
+        let min_class = match size_params.get_min() {
+            Some(min) if self.min_boundaries.binary_search(&min).is_err() => return None,
+            Some(min) => self.get_size_class(min),
+            None => 0,
+        };
+        let max_class = match size_params.get_max() {
+            Some(max) if self.max_boundaries.binary_search(&max).is_err() => return None,
+            Some(max) => self.get_size_class(max),
+            None => (self.min_boundaries.len() + self.max_boundaries.len()) as u32,
+        };
+        Some(min_class..=max_class)
+    }
+}
+
+/// How long the orders of a symbol are tracked and in which detail
+///
+/// Entries are kept as they are for `raw_window`, then compacted into hourly buckets, merged into
+/// daily buckets after `hourly_window`. Buckets are dropped once they end `max_age` ago.
+/// Orders are bucketed by size class so that rules count buckets exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub raw_window: Duration,
+    pub hourly_window: Duration,
+    pub max_age: Duration,
+    pub size_classes: OrderSizeClasses,
+}
+
+impl RetentionPolicy {
+    /// Keeps raw entries for the longest rule period up to 48 hours and buckets for the longest rule period
+    pub fn from_rules<'a>(rules: impl IntoIterator<Item = &'a ExpectedOrderRequestsRule> + Clone) -> Self {
+        let mut raw_window = None;
+        let mut max_age = Duration::ZERO;
+        for rule in rules.clone().into_iter() {
+            let Ok(duration) = rule.get_duration() else {
+                continue;
+            };
+            max_age = max_age.max(duration);
+            if duration <= MAX_RAW_WINDOW {
+                raw_window = Some(raw_window.unwrap_or(duration).max(duration));
+            }
+        }
+        let raw_window = raw_window.unwrap_or(MAX_RAW_WINDOW);
+        Self {
+            raw_window,
+            hourly_window: raw_window.max(MIN_HOURLY_WINDOW),
+            max_age: max_age.max(raw_window),
+            size_classes: OrderSizeClasses::from_rules(rules),
+        }
+    }
+
+    pub fn get_size_class(&self, size: Decimal) -> u32 {
+        self.size_classes.get_size_class(size)
+    }
+
+    /// Folds `entries` into `buckets` and rolls up or drops the buckets that aged, as of `now`
+    /// in nanoseconds. Returns whether the buckets changed.
+    pub fn compact(&self, buckets: &mut BTreeMap<OrderTrackingBucketKey, OrderTrackingBucket>, entries: &[TopNEntry<OrderTrackingItem>], now: u64) -> bool {
+        let hourly_cutoff = now.saturating_sub(self.hourly_window.as_nanos() as u64);
+        let max_age_cutoff = now.saturating_sub(self.max_age.as_nanos() as u64);
+        let mut changed = false;
+
+        let aged_keys = buckets
+            .iter()
+            .filter(|(_, bucket)| bucket.end() <= max_age_cutoff || (bucket.granularity == BucketGranularity::Hour && bucket.end() <= hourly_cutoff))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in aged_keys.into_iter() {
+            let Some(mut bucket) = buckets.remove(&key) else {
+                continue;
+            };
+            changed = true;
+            if bucket.end() <= max_age_cutoff {
+                continue;
+            }
+            bucket.granularity = BucketGranularity::Day;
+            bucket.start -= bucket.start % bucket.granularity.get_nanos();
+            add_bucket(buckets, bucket);
+        }
+
+        for entry in entries.iter() {
+            let granularity = if entry.timestamp < hourly_cutoff { BucketGranularity::Day } else { BucketGranularity::Hour };
+            let bucket = OrderTrackingBucket::new(entry, granularity, self.get_size_class(entry.item.size));
+            if bucket.end() <= max_age_cutoff {
+                continue;
+            }
+            add_bucket(buckets, bucket);
+            changed = true;
+        }
+        changed
+    }
+}
+
+fn add_bucket(buckets: &mut BTreeMap<OrderTrackingBucketKey, OrderTrackingBucket>, bucket: OrderTrackingBucket) {
+    match buckets.get_mut(&bucket.key()) {
+        Some(existing) => existing.merge(&bucket),
+        None => {
+            buckets.insert(bucket.key(), bucket);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::test_support::{entry, rule};
+
+    #[test]
+    fn policy_follows_rules() {
+        let rules = [
+            rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(24), 10),
+            rule(ExpectedOrderRequestsRuleSizeParams::Min(dec!(2)), RulePeriod::Weeks(52), 10),
+        ];
+        let policy = RetentionPolicy::from_rules(rules.iter());
+        assert_eq!(policy.raw_window, DAY);
+        assert_eq!(policy.hourly_window, MIN_HOURLY_WINDOW);
+        assert_eq!(policy.max_age, DAY * 7 * 52);
+        // Sizes on the same side of every rule bound share a class
+        assert_eq!(policy.get_size_class(dec!(0.5)), policy.get_size_class(dec!(1)));
+        assert_ne!(policy.get_size_class(dec!(1)), policy.get_size_class(dec!(1.5)));
+        assert_eq!(policy.get_size_class(dec!(1.5)), policy.get_size_class(dec!(1.9)));
+        assert_ne!(policy.get_size_class(dec!(1.9)), policy.get_size_class(dec!(2)));
+        assert_eq!(policy.size_classes.get_class_range(&rules[0].get_payload().size_params), Some(0..=policy.get_size_class(dec!(1))));
+        assert_eq!(policy.size_classes.get_class_range(&rules[1].get_payload().size_params), Some(policy.get_size_class(dec!(2))..=2));
+        assert_eq!(policy.size_classes.get_class_range(&ExpectedOrderRequestsRuleSizeParams::Max(dec!(3))), None);
+    }
+
+    #[test]
+    fn compacts_rolls_up_and_drops_buckets() {
+        let rules = [rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(24), 10), rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Weeks(2), 10)];
+        let policy = RetentionPolicy::from_rules(rules.iter());
+        let day = DAY.as_nanos() as u64;
+        let hour = HOUR.as_nanos() as u64;
+        let now = 100 * day;
+        let mut buckets = BTreeMap::new();
+        let entries = [
+            entry(now - 2 * day, OrderSide::Buy, dec!(0.5), dec!(100)),
+            entry(now - 2 * day + 1, OrderSide::Buy, dec!(1), dec!(100)),
+            entry(now - 2 * day + hour, OrderSide::Buy, dec!(0.5), dec!(100)),
+            entry(now - 10 * day, OrderSide::Buy, dec!(0.5), dec!(100)),
+        ];
+        assert!(policy.compact(&mut buckets, &entries, now));
+        let granularities = buckets.values().map(|bucket| (bucket.granularity, bucket.count)).collect::<Vec<_>>();
+        assert_eq!(granularities, vec![(BucketGranularity::Day, 1), (BucketGranularity::Hour, 2), (BucketGranularity::Hour, 1)]);
+
+        let payload = rules[1].get_payload();
+        let compacted = CompactedOrders::new(buckets.values(), payload, now - 14 * day, None, false);
+        assert_eq!((compacted.count, compacted.notional), (4, dec!(250)));
+
+        // A week later the hourly buckets are merged into a daily one, the oldest bucket expired
+        assert!(policy.compact(&mut buckets, &[], now + 7 * day));
+        let granularities = buckets.values().map(|bucket| (bucket.granularity, bucket.count)).collect::<Vec<_>>();
+        assert_eq!(granularities, vec![(BucketGranularity::Day, 3)]);
+        assert!(!policy.compact(&mut buckets, &[], now + 7 * day));
+        assert!(policy.compact(&mut buckets, &[], now + 13 * day));
+        assert!(buckets.is_empty());
+    }
+}
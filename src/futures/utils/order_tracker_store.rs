@@ -0,0 +1,412 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::anyhow;
+use uuid::Uuid;
+use crate::errors::Result;
+use crate::json_lines::{read_json_lines, truncate_partial_last_line};
+use crate::futures::utils::order_tracker_retention::OrderTrackingBucket;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::top_n::{TopN, TopNEntry};
+
+pub type OrderTrackingEntry = TopNEntry<OrderTrackingItem>;
+
+/// Persists the tracked orders of each symbol so that they survive restarts
+///
+/// `insert`, `remove` and `update` are called while the tracker holds the lock of the symbol, with the
+/// tracked set after the change, so backends may either persist the change or the whole set.
+pub trait OrderTrackerStore: Send + Sync + Debug {
+    /// Returns the persisted entries of `symbol`, empty if nothing was persisted yet
+    fn load(&self, symbol: &str) -> Result<BTreeSet<OrderTrackingEntry>>;
+
+    fn insert(&self, symbol: &str, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()>;
+
+    fn remove(&self, symbol: &str, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()>;
+
+    /// Persists the new outcome of a tracked entry, `tracked` already holds `entry` instead of `previous`
+    fn update(&self, symbol: &str, previous: &OrderTrackingEntry, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.remove(symbol, previous, tracked)?;
+        self.insert(symbol, entry, tracked)
+    }
+
+    /// Persists several inserted entries at once, `tracked` already includes all of them
+    fn insert_many(&self, symbol: &str, entries: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        for entry in entries.iter() {
+            self.insert(symbol, entry, tracked)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the persisted buckets of `symbol`, empty if nothing was persisted yet
+    fn load_buckets(&self, _symbol: &str) -> Result<Vec<OrderTrackingBucket>> {
+        Ok(vec![])
+    }
+
+    /// Persists a compaction of the tracked orders: the `removed` entries are no longer tracked and
+    /// were folded into `buckets`, all the buckets of `symbol` after the change.
+    /// Stores that do not override it only persist the removals, losing the buckets on restart.
+    fn compact(&self, symbol: &str, removed: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>, _buckets: &[OrderTrackingBucket]) -> Result<()> {
+        for entry in removed.iter() {
+            self.remove(symbol, entry, tracked)?;
+        }
+        Ok(())
+    }
+}
+
+fn buckets_file_path(directory: &Path, symbol: &str) -> PathBuf { directory.join(format!("order_tracker_{symbol}_buckets.json")) }
+
+fn load_buckets_file(file_path: &Path) -> Result<Vec<OrderTrackingBucket>> {
+    if !file_path.exists() {
+        return Ok(vec![]);
+    }
+    let data = match fs::read_to_string(file_path) {
+        Ok(data) => data,
+        Err(error) => return Err(anyhow!("Failed to read {}: {error:?}", file_path.display()).into()),
+    };
+    match serde_json::from_str(&data) {
+        Ok(buckets) => Ok(buckets),
+        Err(error) => Err(anyhow!("Failed to parse {}: {error:?}", file_path.display()).into()),
+    }
+}
+
+/// Buckets are written before the removal of the compacted entries, so a crash in between counts
+/// the entries twice rather than not at all
+fn save_buckets_file(file_path: &Path, buckets: &[OrderTrackingBucket]) -> Result<()> {
+    write_atomically(file_path, serde_json::to_string(buckets)?.as_bytes())
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so readers
+/// in other processes see either the old or the new contents, never a partial write
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.{}.{}.tmp", std::process::id(), Uuid::new_v4()));
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    };
+    if let Err(error) = write() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(anyhow!("Failed to write {}: {error:?}", path.display()).into());
+    }
+    Ok(())
+}
+
+/// Stores the whole tracked set of each symbol as `order_tracker_{symbol}.json` in a directory,
+/// rewritten atomically on every change
+#[derive(Debug, Clone)]
+pub struct JsonFileOrderTrackerStore {
+    directory: PathBuf,
+}
+
+impl JsonFileOrderTrackerStore {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn file_path(&self, symbol: &str) -> PathBuf { self.directory.join(format!("order_tracker_{symbol}.json")) }
+
+    fn save(&self, symbol: &str, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        write_atomically(&self.file_path(symbol), tracked.get_set_json()?.as_bytes())
+    }
+}
+
+impl Default for JsonFileOrderTrackerStore {
+    /// Files in the current working directory
+    fn default() -> Self { Self::new(".") }
+}
+
+impl OrderTrackerStore for JsonFileOrderTrackerStore {
+    fn load(&self, symbol: &str) -> Result<BTreeSet<OrderTrackingEntry>> {
+        let file_path = self.file_path(symbol);
+        if !file_path.exists() {
+            return Ok(BTreeSet::new());
+        }
+        let data = match fs::read_to_string(&file_path) {
+            Ok(data) => data,
+            Err(error) => return Err(anyhow!("Failed to read {}: {error:?}", file_path.display()).into()),
+        };
+        match serde_json::from_str(&data) {
+            Ok(set) => Ok(set),
+            Err(error) => Err(anyhow!("Failed to deserialize {}: {error:?}", file_path.display()).into()),
+        }
+    }
+
+    fn insert(&self, symbol: &str, _entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.save(symbol, tracked)
+    }
+
+    fn remove(&self, symbol: &str, _entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.save(symbol, tracked)
+    }
+
+    fn update(&self, symbol: &str, _previous: &OrderTrackingEntry, _entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.save(symbol, tracked)
+    }
+
+    fn insert_many(&self, symbol: &str, _entries: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.save(symbol, tracked)
+    }
+
+    fn load_buckets(&self, symbol: &str) -> Result<Vec<OrderTrackingBucket>> {
+        load_buckets_file(&buckets_file_path(&self.directory, symbol))
+    }
+
+    fn compact(&self, symbol: &str, _removed: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>, buckets: &[OrderTrackingBucket]) -> Result<()> {
+        save_buckets_file(&buckets_file_path(&self.directory, symbol), buckets)?;
+        self.save(symbol, tracked)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", content = "e", rename_all = "lowercase")]
+enum LogRecord {
+    Add(OrderTrackingEntry),
+    Remove(OrderTrackingEntry),
+}
+
+/// Appends every change of a symbol as a JSON line to `order_tracker_{symbol}.log` in a directory
+///
+/// Appending keeps inserts cheap regardless of the number of tracked orders. Once the log holds
+/// `compact_after` records it is atomically replaced by the tracked set.
+/// Compaction rewrites the log from the set of this process, so a log should not be written by
+/// several processes at once.
+/// A partial last line left by a crash is dropped when the log is first read, a corrupt record
+/// anywhere else fails the load instead of silently losing tracked orders.
+#[derive(Debug)]
+pub struct AppendLogOrderTrackerStore {
+    directory: PathBuf,
+    compact_after: usize,
+    /// Number of records in the log of each symbol, known once it was loaded or written
+    record_counts: Mutex<HashMap<String, usize>>,
+}
+
+impl AppendLogOrderTrackerStore {
+    pub fn new<P: Into<PathBuf>>(directory: P, compact_after: usize) -> Self {
+        Self {
+            directory: directory.into(),
+            compact_after,
+            record_counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn file_path(&self, symbol: &str) -> PathBuf { self.directory.join(format!("order_tracker_{symbol}.log")) }
+
+    fn append(&self, symbol: &str, record: &LogRecord, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        let file_path = self.file_path(symbol);
+        let mut record_counts = self.record_counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = match record_counts.get(symbol) {
+            Some(count) => *count,
+            None => self.read_records(&file_path)?.len(),
+        };
+        if count >= self.compact_after {
+            let compacted = tracked.get_all();
+            let mut contents = String::new();
+            for entry in compacted.iter() {
+                contents.push_str(&serde_json::to_string(&LogRecord::Add(entry.clone()))?);
+                contents.push('\n');
+            }
+            write_atomically(&file_path, contents.as_bytes())?;
+            record_counts.insert(symbol.to_string(), compacted.len());
+            // The tracked set already includes the change being recorded
+            return Ok(());
+        }
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file_path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(error) = written {
+            // The line may be partially written, the log is repaired before the next append
+            record_counts.remove(symbol);
+            return Err(anyhow!("Failed to append to {}: {error:?}", file_path.display()).into());
+        }
+        record_counts.insert(symbol.to_string(), count + 1);
+        Ok(())
+    }
+
+    /// Reads the log, dropping a partial last line left by a crash first so that the next append
+    /// does not extend it
+    fn read_records(&self, file_path: &Path) -> Result<Vec<LogRecord>> {
+        truncate_partial_last_line(file_path)?;
+        read_json_lines(file_path)
+    }
+}
+
+impl OrderTrackerStore for AppendLogOrderTrackerStore {
+    fn load(&self, symbol: &str) -> Result<BTreeSet<OrderTrackingEntry>> {
+        let records = self.read_records(&self.file_path(symbol))?;
+        let mut set = BTreeSet::new();
+        for record in records.iter() {
+            match record {
+                LogRecord::Add(entry) => set.insert(entry.clone()),
+                LogRecord::Remove(entry) => set.remove(entry),
+            };
+        }
+        self.record_counts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(symbol.to_string(), records.len());
+        Ok(set)
+    }
+
+    fn insert(&self, symbol: &str, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.append(symbol, &LogRecord::Add(entry.clone()), tracked)
+    }
+
+    fn remove(&self, symbol: &str, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.append(symbol, &LogRecord::Remove(entry.clone()), tracked)
+    }
+
+    fn load_buckets(&self, symbol: &str) -> Result<Vec<OrderTrackingBucket>> {
+        load_buckets_file(&buckets_file_path(&self.directory, symbol))
+    }
+
+    fn compact(&self, symbol: &str, removed: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>, buckets: &[OrderTrackingBucket]) -> Result<()> {
+        save_buckets_file(&buckets_file_path(&self.directory, symbol), buckets)?;
+        for entry in removed.iter() {
+            self.remove(symbol, entry, tracked)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the tracked sets in memory only, for tests and processes that do not need persistence
+#[derive(Debug, Default)]
+pub struct InMemoryOrderTrackerStore {
+    sets: Mutex<HashMap<String, BTreeSet<OrderTrackingEntry>>>,
+    buckets: Mutex<HashMap<String, Vec<OrderTrackingBucket>>>,
+}
+
+impl InMemoryOrderTrackerStore {
+    pub fn new() -> Self { Self::default() }
+}
+
+impl OrderTrackerStore for InMemoryOrderTrackerStore {
+    fn load(&self, symbol: &str) -> Result<BTreeSet<OrderTrackingEntry>> {
+        let sets = self.sets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(sets.get(symbol).cloned().unwrap_or_default())
+    }
+
+    fn insert(&self, symbol: &str, _entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        let mut sets = self.sets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        sets.insert(symbol.to_string(), tracked.get_all().into_iter().collect());
+        Ok(())
+    }
+
+    fn remove(&self, symbol: &str, entry: &OrderTrackingEntry, tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        self.insert(symbol, entry, tracked)
+    }
+
+    fn insert_many(&self, symbol: &str, entries: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>) -> Result<()> {
+        match entries.first() {
+            Some(entry) => self.insert(symbol, entry, tracked),
+            None => Ok(()),
+        }
+    }
+
+    fn load_buckets(&self, symbol: &str) -> Result<Vec<OrderTrackingBucket>> {
+        let buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(buckets.get(symbol).cloned().unwrap_or_default())
+    }
+
+    fn compact(&self, symbol: &str, removed: &[OrderTrackingEntry], tracked: &TopN<OrderTrackingItem>, buckets: &[OrderTrackingBucket]) -> Result<()> {
+        self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(symbol.to_string(), buckets.to_vec());
+        self.insert_many(symbol, removed, tracked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::futures::utils::test_support::entry;
+    use crate::rest_model::OrderSide;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("order_tracker_store_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Inserts 5 entries into a tracker of capacity 3 and removes one, then reloads
+    fn round_trip(store: &dyn OrderTrackerStore) -> Vec<u64> {
+        let mut tracked = TopN::new(3, None);
+        for timestamp in 1..=5 {
+            tracked.insert(entry(timestamp, OrderSide::Buy, dec!(1), dec!(100)));
+            store.insert("BTCUSDT", &entry(timestamp, OrderSide::Buy, dec!(1), dec!(100)), &tracked).unwrap();
+        }
+        tracked.remove(&entry(4, OrderSide::Buy, dec!(1), dec!(100)));
+        store.remove("BTCUSDT", &entry(4, OrderSide::Buy, dec!(1), dec!(100)), &tracked).unwrap();
+        assert!(store.load("ETHUSDT").unwrap().is_empty());
+        store.load("BTCUSDT").unwrap().iter().map(|entry| entry.timestamp).collect()
+    }
+
+    #[test]
+    fn json_file_store() {
+        let dir = temp_dir();
+        let store = JsonFileOrderTrackerStore::new(&dir);
+        assert_eq!(round_trip(&store), vec![3, 5]);
+        // Only the final file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn append_log_store_compacts() {
+        let dir = temp_dir();
+        let store = AppendLogOrderTrackerStore::new(&dir, 4);
+        // Entries evicted by the capacity are dropped on compaction
+        assert_eq!(round_trip(&store), vec![3, 5]);
+        let lines = fs::read_to_string(store.file_path("BTCUSDT")).unwrap().lines().count();
+        // 5 adds compacted into 3 when the 5th came in, then a removal
+        assert_eq!(lines, 4);
+        // A fresh store reading the same log sees the same set
+        let reopened = AppendLogOrderTrackerStore::new(&dir, 4);
+        let reloaded: Vec<u64> = reopened.load("BTCUSDT").unwrap().iter().map(|e| e.timestamp).collect();
+        assert_eq!(reloaded, vec![3, 5]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn append_log_store_repairs_a_partial_last_line() {
+        let dir = temp_dir();
+        let store = AppendLogOrderTrackerStore::new(&dir, 100);
+        let mut tracked = TopN::new(10, None);
+        for timestamp in 1..=2 {
+            tracked.insert(entry(timestamp, OrderSide::Buy, dec!(1), dec!(100)));
+            store.insert("BTCUSDT", &entry(timestamp, OrderSide::Buy, dec!(1), dec!(100)), &tracked).unwrap();
+        }
+        // A crash in the middle of the third append
+        let path = store.file_path("BTCUSDT");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"op":"add","e":{"timest"#).unwrap();
+
+        // The next process appends on a line of its own and reloads every record
+        let reopened = AppendLogOrderTrackerStore::new(&dir, 100);
+        tracked.insert(entry(3, OrderSide::Buy, dec!(1), dec!(100)));
+        reopened.insert("BTCUSDT", &entry(3, OrderSide::Buy, dec!(1), dec!(100)), &tracked).unwrap();
+        let reloaded: Vec<u64> = AppendLogOrderTrackerStore::new(&dir, 100).load("BTCUSDT").unwrap().iter().map(|e| e.timestamp).collect();
+        assert_eq!(reloaded, vec![1, 2, 3]);
+
+        // A corrupt record before the last one is an error rather than a missing order
+        let contents = fs::read_to_string(&path).unwrap().replacen(r#""op":"add""#, r#""op":"ad"#, 1);
+        fs::write(&path, contents).unwrap();
+        assert!(AppendLogOrderTrackerStore::new(&dir, 100).load("BTCUSDT").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn in_memory_store() {
+        assert_eq!(round_trip(&InMemoryOrderTrackerStore::new()), vec![3, 5]);
+    }
+}
@@ -0,0 +1,571 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{AddAssign, SubAssign};
+use rust_decimal::Decimal;
+use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+use crate::futures::utils::order_tracker_retention::OrderSizeClasses;
+use crate::futures::utils::order_tracking_item::OrderTrackingItem;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+
+/// Tracked orders a rule counts within its period
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CountedOrders {
+    pub count: u64,
+    /// Sum of price x size of the orders
+    pub notional: Decimal,
+    /// Timestamp of the oldest counted order in nanoseconds
+    pub oldest: Option<u64>,
+}
+
+impl CountedOrders {
+    fn add(&mut self, other: CountedOrders) {
+        self.count += other.count;
+        self.notional += other.notional;
+        self.oldest = self.oldest.into_iter().chain(other.oldest).min();
+    }
+
+    pub(crate) fn add_entry(&mut self, entry: &TopNEntry<OrderTrackingItem>, notional: Decimal) {
+        self.add(CountedOrders {
+            count: 1,
+            notional,
+            oldest: Some(entry.timestamp),
+        });
+    }
+
+    /// Counts `entries` the slow way, for rules whose size bounds the index does not know
+    pub fn scan<'a>(entries: impl Iterator<Item = &'a TopNEntry<OrderTrackingItem>>, payload: &ExpectedOrderRequestsRulePayload, grid_id: Option<&String>, grid_scoped: bool) -> Self {
+        let mut counted = Self::default();
+        for entry in entries.filter(|entry| payload.counts_order(entry, grid_id, grid_scoped)) {
+            counted.add_entry(entry, payload.count_by.get_notional(&entry.item));
+        }
+        counted
+    }
+}
+
+/// Count and notional of the orders at a point of a series, or of a range of points
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SeriesWeight {
+    count: u64,
+    notional: Decimal,
+}
+
+impl AddAssign for SeriesWeight {
+    fn add_assign(&mut self, other: Self) {
+        self.count += other.count;
+        self.notional += other.notional;
+    }
+}
+
+impl SubAssign for SeriesWeight {
+    fn sub_assign(&mut self, other: Self) {
+        self.count -= other.count;
+        self.notional -= other.notional;
+    }
+}
+
+const BASES: usize = ExpectedOrderRequestsRuleCountBasis::ALL.len();
+
+/// Weight of an order on each count basis, zero on the bases that do not count it
+type BasisWeights = [SeriesWeight; BASES];
+
+fn get_weights(item: &OrderTrackingItem) -> BasisWeights {
+    ExpectedOrderRequestsRuleCountBasis::ALL.map(|basis| {
+        if !basis.counts(item) {
+            return SeriesWeight::default();
+        }
+        SeriesWeight {
+            count: 1,
+            notional: basis.get_notional(item),
+        }
+    })
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Points and tree nodes visited by queries and updates, so that tests can check they stay
+    /// logarithmic in the number of tracked orders
+    static VISITED: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+fn visit() {
+    #[cfg(test)]
+    VISITED.with(|visited| visited.set(visited.get() + 1));
+}
+
+fn lowest_bit(position: usize) -> usize {
+    position & position.wrapping_neg()
+}
+
+/// Fenwick tree over the weights of the points of a series, so that running totals and point
+/// updates both take O(log n)
+#[derive(Debug, Clone, Default)]
+struct FenwickTree {
+    /// Node `i` holds the sum of the points in `(i + 1 - lowest_bit(i + 1))..=i`
+    nodes: Vec<SeriesWeight>,
+}
+
+impl FenwickTree {
+    fn from_weights(weights: impl Iterator<Item = SeriesWeight>) -> Self {
+        let mut nodes: Vec<SeriesWeight> = weights.collect();
+        for position in 1..=nodes.len() {
+            let parent = position + lowest_bit(position);
+            if parent <= nodes.len() {
+                let node = nodes[position - 1];
+                nodes[parent - 1] += node;
+            }
+        }
+        Self { nodes }
+    }
+
+    /// Appends a point after the last one
+    fn push(&mut self, weight: SeriesWeight) {
+        let position = self.nodes.len() + 1;
+        let mut node = weight;
+        let mut child = position - 1;
+        while child > position - lowest_bit(position) {
+            visit();
+            node += self.nodes[child - 1];
+            child -= lowest_bit(child);
+        }
+        self.nodes.push(node);
+    }
+
+    fn add(&mut self, index: usize, weight: SeriesWeight) {
+        let mut position = index + 1;
+        while position <= self.nodes.len() {
+            visit();
+            self.nodes[position - 1] += weight;
+            position += lowest_bit(position);
+        }
+    }
+
+    fn sub(&mut self, index: usize, weight: SeriesWeight) {
+        let mut position = index + 1;
+        while position <= self.nodes.len() {
+            visit();
+            self.nodes[position - 1] -= weight;
+            position += lowest_bit(position);
+        }
+    }
+
+    /// Sum of the first `len` points
+    fn prefix(&self, len: usize) -> SeriesWeight {
+        let mut sum = SeriesWeight::default();
+        let mut position = len;
+        while position > 0 {
+            visit();
+            sum += self.nodes[position - 1];
+            position -= lowest_bit(position);
+        }
+        sum
+    }
+
+    /// Index of the point holding the order after the first `count` orders
+    fn find_after(&self, count: u64) -> usize {
+        let mut index = 0;
+        let mut remaining = count;
+        let mut step = match self.nodes.len() {
+            0 => 0,
+            len => 1 << len.ilog2(),
+        };
+        while step > 0 {
+            visit();
+            if index + step <= self.nodes.len() && self.nodes[index + step - 1].count <= remaining {
+                index += step;
+                remaining -= self.nodes[index - 1].count;
+            }
+            step >>= 1;
+        }
+        index
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SeriesPoint {
+    timestamp: u64,
+    id: String,
+    weights: BasisWeights,
+    /// Removed points keep their place with no weight until the series is compacted
+    removed: bool,
+}
+
+/// Orders of one side and size class sorted by time, with a Fenwick tree per count basis so that
+/// the orders after any timestamp are counted with a binary search
+#[derive(Debug, Clone, Default)]
+struct OrderSeries {
+    points: Vec<SeriesPoint>,
+    trees: [FenwickTree; BASES],
+    removed: usize,
+}
+
+impl OrderSeries {
+    fn insert(&mut self, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        let point = SeriesPoint {
+            timestamp: entry.timestamp,
+            id: entry.item.id.clone(),
+            weights: *weights,
+            removed: false,
+        };
+        // Orders are almost always tracked in time order, older ones come from the exchange history
+        if self.points.last().is_none_or(|last| last.timestamp <= entry.timestamp) {
+            for (tree, weight) in self.trees.iter_mut().zip(weights) {
+                tree.push(*weight);
+            }
+            self.points.push(point);
+            return;
+        }
+        let position = self.points.partition_point(|existing| existing.timestamp <= entry.timestamp);
+        self.points.insert(position, point);
+        self.compact();
+    }
+
+    fn find(&self, entry: &TopNEntry<OrderTrackingItem>) -> Option<usize> {
+        let start = self.points.partition_point(|existing| {
+            visit();
+            existing.timestamp < entry.timestamp
+        });
+        let offset = self.points[start..]
+            .iter()
+            .take_while(|existing| existing.timestamp == entry.timestamp)
+            .position(|existing| !existing.removed && existing.id == entry.item.id)?;
+        Some(start + offset)
+    }
+
+    fn remove(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        let Some(position) = self.find(entry) else {
+            return;
+        };
+        self.update_at(position, &BasisWeights::default());
+        self.points[position].removed = true;
+        self.removed += 1;
+        // Compacting once half of the points are removed keeps removals O(log n) amortized
+        if self.removed * 2 > self.points.len() {
+            self.compact();
+        }
+    }
+
+    fn update(&mut self, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        if let Some(position) = self.find(entry) {
+            self.update_at(position, weights);
+        }
+    }
+
+    fn update_at(&mut self, position: usize, weights: &BasisWeights) {
+        let point = &mut self.points[position];
+        for ((tree, previous), weight) in self.trees.iter_mut().zip(point.weights.iter()).zip(weights) {
+            if previous != weight {
+                tree.sub(position, *previous);
+                tree.add(position, *weight);
+            }
+        }
+        point.weights = *weights;
+    }
+
+    /// Drops the removed points and rebuilds the trees
+    fn compact(&mut self) {
+        self.points.retain(|point| !point.removed);
+        self.removed = 0;
+        self.trees = std::array::from_fn(|basis| FenwickTree::from_weights(self.points.iter().map(|point| point.weights[basis])));
+    }
+
+    fn count_gte_timestamp(&self, count_by: ExpectedOrderRequestsRuleCountBasis, timestamp: u64) -> CountedOrders {
+        let tree = &self.trees[count_by as usize];
+        let position = self.points.partition_point(|point| {
+            visit();
+            point.timestamp < timestamp
+        });
+        let before = tree.prefix(position);
+        let mut after = tree.prefix(self.points.len());
+        after -= before;
+        if after.count == 0 {
+            return CountedOrders::default();
+        }
+        CountedOrders {
+            count: after.count,
+            notional: after.notional,
+            oldest: Some(self.points[tree.find_after(before.count)].timestamp),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.points.len() == self.removed
+    }
+}
+
+type SeriesKey = (OrderSide, u32);
+
+#[derive(Debug, Clone, Default)]
+struct OrderSeriesSet {
+    series: BTreeMap<SeriesKey, OrderSeries>,
+}
+
+impl OrderSeriesSet {
+    fn insert(&mut self, key: SeriesKey, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        self.series.entry(key).or_default().insert(entry, weights);
+    }
+
+    fn remove(&mut self, key: SeriesKey, entry: &TopNEntry<OrderTrackingItem>) {
+        if let Some(series) = self.series.get_mut(&key) {
+            series.remove(entry);
+            if series.is_empty() {
+                self.series.remove(&key);
+            }
+        }
+    }
+
+    fn update(&mut self, key: &SeriesKey, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        if let Some(series) = self.series.get_mut(key) {
+            series.update(entry, weights);
+        }
+    }
+
+    fn count(&self, count_by: ExpectedOrderRequestsRuleCountBasis, side: &OrderSide, min_class: u32, max_class: u32, min_timestamp: u64) -> CountedOrders {
+        let mut counted = CountedOrders::default();
+        if min_class > max_class {
+            return counted;
+        }
+        for series in self.series.range((side.clone(), min_class)..=(side.clone(), max_class)).map(|(_, series)| series) {
+            counted.add(series.count_gte_timestamp(count_by, min_timestamp));
+        }
+        counted
+    }
+
+    fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+}
+
+/// Series of the orders overall and per grid
+#[derive(Debug, Clone, Default)]
+struct GridSeries {
+    all: OrderSeriesSet,
+    without_grid: OrderSeriesSet,
+    by_grid: HashMap<String, OrderSeriesSet>,
+}
+
+impl GridSeries {
+    fn insert(&mut self, key: SeriesKey, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        self.all.insert(key.clone(), entry, weights);
+        match &entry.item.grid_id {
+            Some(grid_id) => self.by_grid.entry(grid_id.clone()).or_default().insert(key, entry, weights),
+            None => self.without_grid.insert(key, entry, weights),
+        }
+    }
+
+    fn remove(&mut self, key: SeriesKey, entry: &TopNEntry<OrderTrackingItem>) {
+        self.all.remove(key.clone(), entry);
+        match &entry.item.grid_id {
+            Some(grid_id) => {
+                if let Some(grid) = self.by_grid.get_mut(grid_id) {
+                    grid.remove(key, entry);
+                    if grid.is_empty() {
+                        self.by_grid.remove(grid_id);
+                    }
+                }
+            }
+            None => self.without_grid.remove(key, entry),
+        }
+    }
+
+    fn update(&mut self, key: &SeriesKey, entry: &TopNEntry<OrderTrackingItem>, weights: &BasisWeights) {
+        self.all.update(key, entry, weights);
+        match &entry.item.grid_id {
+            Some(grid_id) => {
+                if let Some(grid) = self.by_grid.get_mut(grid_id) {
+                    grid.update(key, entry, weights);
+                }
+            }
+            None => self.without_grid.update(key, entry, weights),
+        }
+    }
+
+    fn get_series_set(&self, grid_id: Option<&String>, grid_scoped: bool) -> Option<&OrderSeriesSet> {
+        match (grid_scoped, grid_id) {
+            (false, _) => Some(&self.all),
+            (true, None) => Some(&self.without_grid),
+            (true, Some(grid_id)) => self.by_grid.get(grid_id),
+        }
+    }
+}
+
+/// Counters of the tracked orders of a symbol per side and size class, overall and per grid,
+/// with the weight of each order on every count basis
+///
+/// Rules whose size bounds are boundaries of `size_classes` count their orders in
+/// O(classes x log n) without allocating, see `count`.
+/// Entries whose outcome changes go through `update`, which takes O(log n).
+#[derive(Debug, Clone, Default)]
+pub struct OrderTrackingIndex {
+    size_classes: OrderSizeClasses,
+    series: GridSeries,
+}
+
+impl OrderTrackingIndex {
+    pub fn new(size_classes: OrderSizeClasses) -> Self {
+        Self {
+            size_classes,
+            ..Self::default()
+        }
+    }
+
+    pub fn get_size_classes(&self) -> &OrderSizeClasses {
+        &self.size_classes
+    }
+
+    fn get_key(&self, entry: &TopNEntry<OrderTrackingItem>) -> SeriesKey {
+        (entry.item.side.clone(), self.size_classes.get_size_class(entry.item.size))
+    }
+
+    pub fn insert(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        self.series.insert(self.get_key(entry), entry, &get_weights(&entry.item));
+    }
+
+    pub fn remove(&mut self, entry: &TopNEntry<OrderTrackingItem>) {
+        self.series.remove(self.get_key(entry), entry);
+    }
+
+    /// Replaces `previous` by `entry`, the same order with another outcome
+    pub fn update(&mut self, previous: &TopNEntry<OrderTrackingItem>, entry: &TopNEntry<OrderTrackingItem>) {
+        let key = self.get_key(entry);
+        if key != self.get_key(previous) || previous.timestamp != entry.timestamp || previous.item.grid_id != entry.item.grid_id {
+            self.remove(previous);
+            self.insert(entry);
+            return;
+        }
+        let weights = get_weights(&entry.item);
+        if weights != get_weights(&previous.item) {
+            self.series.update(&key, entry, &weights);
+        }
+    }
+
+    /// Orders counted by `payload` since `min_timestamp`, `None` if the size bounds of the rule
+    /// are not boundaries of the size classes. When `grid_scoped`, only the orders of `grid_id` are counted.
+    pub fn count(&self, payload: &ExpectedOrderRequestsRulePayload, min_timestamp: u64, grid_id: Option<&String>, grid_scoped: bool) -> Option<CountedOrders> {
+        let classes = self.size_classes.get_class_range(&payload.size_params)?;
+        Some(match self.series.get_series_set(grid_id, grid_scoped) {
+            Some(series_set) => series_set.count(payload.count_by, &payload.side, *classes.start(), *classes.end(), min_timestamp),
+            None => CountedOrders::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+    use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+    use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+    use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+    use crate::futures::utils::order_tracker::OrderTracker;
+    use crate::futures::utils::order_tracker_retention::RetentionPolicy;
+    use crate::futures::utils::order_tracker_store::{InMemoryOrderTrackerStore, OrderTrackerStore};
+    use crate::futures::utils::order_tracking_item::OrderTrackingOutcome;
+    use crate::futures::utils::test_support::{entry, rule};
+    use crate::futures::utils::top_n::TopN;
+
+    #[test]
+    fn counts_match_a_scan() {
+        let rules = [
+            rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(1), 10),
+            rule(ExpectedOrderRequestsRuleSizeParams::MinMax { min: dec!(0.5), max: dec!(2) }, RulePeriod::Hours(1), 10),
+            rule(ExpectedOrderRequestsRuleSizeParams::Min(dec!(2)), RulePeriod::Hours(1), 10),
+        ];
+        let mut index = OrderTrackingIndex::new(OrderSizeClasses::from_rules(rules.iter()));
+        let mut entries = vec![];
+        for (timestamp, size, grid_id) in [(5, dec!(0.5), None), (1, dec!(1), Some("a")), (3, dec!(2), Some("b")), (3, dec!(3), None), (7, dec!(0.1), Some("a")), (9, dec!(1.5), Some("a"))] {
+            let mut entry = entry(timestamp, OrderSide::Buy, size, dec!(10));
+            entry.item.grid_id = grid_id.map(str::to_string);
+            entries.push(entry);
+            index.insert(entries.last().unwrap());
+        }
+        index.remove(&entries.remove(2));
+        index.remove(&entries.remove(3));
+
+        let grid = "a".to_string();
+        for rule in rules.iter() {
+            let payload = rule.get_payload();
+            for min_timestamp in 0..10 {
+                for (grid_id, grid_scoped) in [(None, false), (None, true), (Some(&grid), true)] {
+                    let in_period = entries.iter().filter(|entry| entry.timestamp >= min_timestamp);
+                    assert_eq!(index.count(payload, min_timestamp, grid_id, grid_scoped), Some(CountedOrders::scan(in_period, payload, grid_id, grid_scoped)));
+                }
+            }
+        }
+        assert_eq!(index.count(rules[0].get_payload(), 0, None, false).map(|counted| (counted.count, counted.notional)), Some((2, dec!(15))));
+        assert_eq!(index.count(&ExpectedOrderRequestsRulePayload::default(), 0, None, false), None);
+    }
+
+    /// Points and tree nodes visited by `run`
+    fn visited(run: impl FnOnce()) -> u64 {
+        VISITED.with(|visited| visited.set(0));
+        run();
+        VISITED.with(|visited| visited.get())
+    }
+
+    #[test]
+    fn outcome_updates_match_a_scan() {
+        let payloads = ExpectedOrderRequestsRuleCountBasis::ALL.map(|count_by| ExpectedOrderRequestsRulePayload {
+            count_by,
+            ..rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(1), 10).get_payload().clone()
+        });
+        let mut index = OrderTrackingIndex::new(OrderSizeClasses::from_rules(payloads.iter().map(|payload| ExpectedOrderRequestsRule::Global(payload.clone())).collect::<Vec<_>>().iter()));
+        let mut entries: Vec<_> = (0..1_000).map(|timestamp| entry(timestamp, OrderSide::Buy, dec!(0.5), dec!(10))).collect();
+        for entry in entries.iter() {
+            index.insert(entry);
+        }
+
+        let mut rejected = entries[10].clone();
+        rejected.item.outcome = OrderTrackingOutcome::rejected();
+        let mut filled = entries[500].clone();
+        filled.item.outcome.filled_size = dec!(0.2);
+        filled.item.outcome.filled_notional = dec!(2);
+        let mut accepted = entries[900].clone();
+        accepted.item.outcome = OrderTrackingOutcome::accepted();
+        for updated in [rejected, filled, accepted] {
+            let position = entries.iter().position(|entry| entry.item.id == updated.item.id).unwrap();
+            // Only the trees of the bases whose weight changed are walked, never the whole series
+            assert!(visited(|| index.update(&entries[position], &updated)) < 100);
+            entries[position] = updated;
+        }
+
+        for payload in payloads.iter() {
+            for min_timestamp in [0, 10, 11, 500, 501, 999] {
+                let in_period = entries.iter().filter(|entry| entry.timestamp >= min_timestamp);
+                assert_eq!(index.count(payload, min_timestamp, None, false), Some(CountedOrders::scan(in_period, payload, None, false)));
+            }
+        }
+        assert_eq!(index.count(&payloads[2], 0, None, false), Some(CountedOrders { count: 1, notional: dec!(2), oldest: Some(500) }));
+    }
+
+    /// Points and tree nodes visited to validate an order against a rule with `tracked` orders in its period
+    fn validation_cost(tracked: usize) -> u64 {
+        let rule = rule(ExpectedOrderRequestsRuleSizeParams::Max(dec!(1)), RulePeriod::Hours(1), tracked as u64 + 1);
+        let store = Arc::new(InMemoryOrderTrackerStore::new());
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
+        let mut top_n = TopN::new(tracked, None);
+        for i in 0..tracked {
+            top_n.insert(entry(now - (tracked - i) as u64 * 1_000, OrderSide::Buy, dec!(0.5), dec!(10)));
+        }
+        let newest = top_n.get_all().pop().unwrap();
+        store.insert("BTCUSDT", &newest, &top_n).unwrap();
+        let tracker = OrderTracker::new(store).with_capacity(tracked);
+        tracker.set_retention_policy("BTCUSDT", RetentionPolicy::from_rules([&rule]));
+        tracker.load_symbol("BTCUSDT").unwrap();
+
+        let context = ExpectedOrderRequestsRuleContext::default();
+        visited(|| rule.validate(&newest, &tracker, &context).unwrap())
+    }
+
+    #[test]
+    fn validation_cost_stays_logarithmic() {
+        let small = validation_cost(1_000);
+        let large = validation_cost(100_000);
+        // The orders are counted from the index, a scan of the tracked orders would visit none of
+        // its points, and 100 times more orders
+        assert!(small > 0);
+        assert!(large < small * 2, "{large} points visited at 100k tracked orders against {small} at 1k");
+    }
+}
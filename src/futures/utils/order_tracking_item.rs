@@ -1,5 +1,5 @@
 use rust_decimal::Decimal;
-use crate::rest_model::OrderSide;
+use crate::rest_model::{MarginOCOOrderResult, MarginOrderResult, OrderCanceledReplaced, OrderSide, OrderStatus, Transaction};
 
 #[derive(Serialize, Clone, Debug, Deserialize)]
 pub struct OrderTrackingItem {
@@ -11,8 +11,203 @@ pub struct OrderTrackingItem {
     pub side: OrderSide,
     #[serde(rename = "i")]
     pub id: String,
+    /// Grid the order was submitted for, `None` for orders outside of any grid
+    #[serde(rename = "g", default, skip_serializing_if = "Option::is_none")]
+    pub grid_id: Option<String>,
+    /// Client order id the order was sent with, if any
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+    /// What the exchange made of the order so far, see `OrderTracker::record_outcome`
+    #[serde(rename = "x", default, skip_serializing_if = "OrderTrackingOutcome::is_pending")]
+    pub outcome: OrderTrackingOutcome,
 }
 
+impl OrderTrackingItem {
+    /// Size the order may still fill, zero once the order is final on the exchange
+    pub fn get_open_size(&self) -> Decimal {
+        if !self.outcome.status.is_open() {
+            return Decimal::ZERO;
+        }
+        (self.size - self.outcome.filled_size).max(Decimal::ZERO)
+    }
+}
+
+/// Lifecycle of a tracked order, from its submission to its final state on the exchange
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OrderTrackingStatus {
+    /// Sent, or about to be sent, without an answer from the exchange yet
+    #[default]
+    Submitted,
+    Accepted,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    Expired,
+    Rejected,
+}
+
+impl OrderTrackingStatus {
+    /// Whether the order may still fill
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderTrackingStatus::Submitted | OrderTrackingStatus::Accepted | OrderTrackingStatus::PartiallyFilled)
+    }
+
+    /// Statuses only move forward, final statuses share the last step
+    fn get_step(&self) -> u8 {
+        match self {
+            OrderTrackingStatus::Submitted => 0,
+            OrderTrackingStatus::Accepted => 1,
+            OrderTrackingStatus::PartiallyFilled => 2,
+            OrderTrackingStatus::Filled | OrderTrackingStatus::Canceled | OrderTrackingStatus::Expired | OrderTrackingStatus::Rejected => 3,
+        }
+    }
+}
+
+impl From<&OrderStatus> for OrderTrackingStatus {
+    fn from(status: &OrderStatus) -> Self {
+        match status {
+            OrderStatus::New | OrderStatus::PendingCancel => OrderTrackingStatus::Accepted,
+            OrderStatus::PartiallyFilled => OrderTrackingStatus::PartiallyFilled,
+            OrderStatus::Filled => OrderTrackingStatus::Filled,
+            OrderStatus::Canceled => OrderTrackingStatus::Canceled,
+            OrderStatus::Rejected => OrderTrackingStatus::Rejected,
+            OrderStatus::Expired | OrderStatus::ExpiredInMatch => OrderTrackingStatus::Expired,
+        }
+    }
+}
+
+/// Status and fills of a tracked order as reported by the exchange
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderTrackingOutcome {
+    #[serde(rename = "st", default)]
+    pub status: OrderTrackingStatus,
+    /// Id the exchange gave the order
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    /// Accumulated filled size
+    #[serde(rename = "fs", default, skip_serializing_if = "Decimal::is_zero")]
+    pub filled_size: Decimal,
+    /// Accumulated price x size of the fills
+    #[serde(rename = "fn", default, skip_serializing_if = "Decimal::is_zero")]
+    pub filled_notional: Decimal,
+}
+
+impl OrderTrackingOutcome {
+    pub fn accepted() -> Self {
+        Self {
+            status: OrderTrackingStatus::Accepted,
+            ..Self::default()
+        }
+    }
+
+    pub fn rejected() -> Self {
+        Self {
+            status: OrderTrackingStatus::Rejected,
+            ..Self::default()
+        }
+    }
+
+    /// Whether nothing was heard from the exchange about the order yet
+    pub fn is_pending(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        self.status == OrderTrackingStatus::Rejected
+    }
+
+    /// Applies a later report of the order. Reports can arrive out of order, e.g. the response to
+    /// the order after a fill event, so the status and the fills never move back.
+    /// Returns whether the outcome changed.
+    pub fn merge(&mut self, report: &OrderTrackingOutcome) -> bool {
+        let previous = self.clone();
+        if report.status.get_step() > self.status.get_step() {
+            self.status = report.status;
+        }
+        if report.order_id.is_some() {
+            self.order_id = report.order_id.clone();
+        }
+        if report.filled_size > self.filled_size {
+            self.filled_size = report.filled_size;
+            self.filled_notional = report.filled_notional;
+        }
+        *self != previous
+    }
+}
+
+impl From<&Transaction> for OrderTrackingOutcome {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            status: (&transaction.status).into(),
+            order_id: Some(transaction.order_id.to_string()),
+            filled_size: transaction.executed_qty,
+            filled_notional: transaction.cummulative_quote_qty,
+        }
+    }
+}
+
+/// The outcome of the replacing order
+impl From<&OrderCanceledReplaced> for OrderTrackingOutcome {
+    fn from(replaced: &OrderCanceledReplaced) -> Self {
+        (&replaced.new_order_response).into()
+    }
+}
+
+impl From<&MarginOrderResult> for OrderTrackingOutcome {
+    fn from(result: &MarginOrderResult) -> Self {
+        Self {
+            status: (&result.status).into(),
+            order_id: Some(result.order_id.to_string()),
+            filled_size: result.executed_qty,
+            filled_notional: result.cummulative_quote_qty,
+        }
+    }
+}
+
+/// Both legs of an accepted OCO order, their fills are reported separately
+impl From<&MarginOCOOrderResult> for OrderTrackingOutcome {
+    fn from(_result: &MarginOCOOrderResult) -> Self {
+        Self::accepted()
+    }
+}
+
+#[cfg(feature = "futures_api")]
+impl From<&crate::futures::rest_model::Transaction> for OrderTrackingOutcome {
+    fn from(transaction: &crate::futures::rest_model::Transaction) -> Self {
+        Self {
+            status: (&transaction.status).into(),
+            order_id: Some(transaction.order_id.clone()),
+            filled_size: transaction.executed_qty,
+            filled_notional: transaction.cum_quote,
+        }
+    }
+}
+
+#[cfg(feature = "futures_api")]
+impl From<&crate::futures::rest_model::Order> for OrderTrackingOutcome {
+    fn from(order: &crate::futures::rest_model::Order) -> Self {
+        Self {
+            status: (&order.status).into(),
+            order_id: Some(order.order_id.clone()),
+            filled_size: order.executed_qty,
+            filled_notional: order.cum_quote,
+        }
+    }
+}
+
+#[cfg(feature = "futures_api")]
+impl From<&crate::futures::ws_model::OrderTradeUpdate> for OrderTrackingOutcome {
+    fn from(update: &crate::futures::ws_model::OrderTradeUpdate) -> Self {
+        Self {
+            status: (&update.order.order_status).into(),
+            order_id: Some(update.order.order_id.clone()),
+            filled_size: update.order.order_filled_accumulated_quantity,
+            filled_notional: update.order.order_filled_accumulated_quantity * update.order.average_price,
+        }
+    }
+}
+
+// The outcome is left out so that an entry stays the same tracked order as the exchange reports on it
 impl PartialEq for OrderTrackingItem {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other) == std::cmp::Ordering::Equal
@@ -27,6 +222,8 @@ impl Ord for OrderTrackingItem {
             .then_with(|| self.price.cmp(&other.price))
             .then_with(|| self.size.cmp(&other.size))
             .then_with(|| self.side.cmp(&other.side))
+            .then_with(|| self.grid_id.cmp(&other.grid_id))
+            .then_with(|| self.client_order_id.cmp(&other.client_order_id))
     }
 }
 
@@ -36,3 +233,31 @@ impl PartialOrd for OrderTrackingItem {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use super::*;
+
+    #[test]
+    fn outcomes_never_move_back() {
+        let mut outcome = OrderTrackingOutcome::default();
+        let filled = OrderTrackingOutcome {
+            status: OrderTrackingStatus::Filled,
+            order_id: Some("1".to_string()),
+            filled_size: dec!(2),
+            filled_notional: dec!(200),
+        };
+        let partially_filled = OrderTrackingOutcome {
+            status: OrderTrackingStatus::PartiallyFilled,
+            filled_size: dec!(1),
+            filled_notional: dec!(100),
+            ..filled.clone()
+        };
+        assert!(outcome.merge(&partially_filled));
+        assert!(outcome.merge(&filled));
+        // The response to the order arrives after its fill events
+        assert!(!outcome.merge(&OrderTrackingOutcome { order_id: Some("1".to_string()), ..OrderTrackingOutcome::accepted() }));
+        assert!(!outcome.merge(&partially_filled));
+        assert_eq!(outcome, filled);
+    }
+}
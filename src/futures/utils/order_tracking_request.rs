@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+use rust_decimal::Decimal;
+use crate::account::{CancelReplaceRequest, OrderRequest};
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+use crate::rest_model::{MarginOCOOrder, MarginOrder, OrderSide};
+
+/// Market an order is sent to, rules and tracked orders of different markets never mix
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrderMarket {
+    #[default]
+    Futures,
+    Spot,
+    Margin,
+}
+
+impl OrderMarket {
+    pub fn is_futures(&self) -> bool {
+        *self == OrderMarket::Futures
+    }
+
+    /// Key the rules and tracked orders of `symbol` are stored under in this market.
+    /// Futures symbols are used as is so that existing rules and stores keep working,
+    /// e.g. `BTCUSDT`, `spot.BTCUSDT` and `margin.BTCUSDT`.
+    pub fn get_tracking_key<'a>(&self, symbol: &'a str) -> Cow<'a, str> {
+        match self {
+            OrderMarket::Futures => Cow::Borrowed(symbol),
+            OrderMarket::Spot => Cow::Owned(format!("spot.{symbol}")),
+            OrderMarket::Margin => Cow::Owned(format!("margin.{symbol}")),
+        }
+    }
+}
+
+/// What the order guard needs to know about an order of any market
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderTrackingRequest {
+    pub market: OrderMarket,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: Option<Decimal>,
+    pub price: Option<Decimal>,
+    /// Price stop orders trigger at
+    pub stop_price: Option<Decimal>,
+    /// Size in quote asset of orders sent without a quantity
+    pub quote_order_qty: Option<Decimal>,
+    pub grid_id: Option<String>,
+    pub client_order_id: Option<String>,
+}
+
+impl OrderTrackingRequest {
+    /// See `OrderMarket::get_tracking_key`
+    pub fn get_tracking_key(&self) -> Cow<'_, str> {
+        self.market.get_tracking_key(&self.symbol)
+    }
+
+    /// Whether the order has neither a price nor a stop price, such as market orders, and is
+    /// counted at a market price, see `with_context_price`
+    pub fn needs_market_price(&self) -> bool {
+        self.price.is_none() && self.stop_price.is_none()
+    }
+
+    /// Same order with the price and size the rules count it at. Orders without a price are
+    /// counted at their stop price, else at the last or mark price of `context`. Orders sized in
+    /// quote asset are counted at their quote quantity over that price.
+    pub fn with_context_price(&self, context: &ExpectedOrderRequestsRuleContext) -> Self {
+        let price = self.price.or(self.stop_price).or(context.last_price).or(context.mark_price);
+        let quantity = match (self.quantity, self.quote_order_qty, price) {
+            (Some(quantity), _, _) => Some(quantity),
+            (None, Some(quote_order_qty), Some(price)) if !price.is_zero() => Some(quote_order_qty / price),
+            _ => None,
+        };
+        Self {
+            quantity,
+            price,
+            ..self.clone()
+        }
+    }
+
+    /// The limit and the stop leg of an OCO order, both are counted by the rules
+    pub fn from_margin_oco_order(order: &MarginOCOOrder) -> [Self; 2] {
+        let leg = |price: Decimal, client_order_id: &Option<String>| Self {
+            market: OrderMarket::Margin,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: Some(order.quantity),
+            price: Some(price),
+            stop_price: None,
+            quote_order_qty: None,
+            grid_id: order.grid_id.clone(),
+            client_order_id: client_order_id.clone(),
+        };
+        [
+            leg(order.price, &order.limit_client_order_id),
+            // Stop loss legs without a limit price are filled around their stop price
+            leg(order.stop_limit_price.unwrap_or(order.stop_price), &order.stop_client_order_id),
+        ]
+    }
+}
+
+impl From<&OrderRequest> for OrderTrackingRequest {
+    fn from(order: &OrderRequest) -> Self {
+        Self {
+            market: OrderMarket::Spot,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            stop_price: order.stop_price,
+            quote_order_qty: order.quote_order_qty,
+            grid_id: order.grid_id.clone(),
+            client_order_id: order.new_client_order_id.clone(),
+        }
+    }
+}
+
+/// The replacing order, the cancellation is not counted
+impl From<&CancelReplaceRequest> for OrderTrackingRequest {
+    fn from(order: &CancelReplaceRequest) -> Self {
+        Self {
+            market: OrderMarket::Spot,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            stop_price: order.stop_price,
+            quote_order_qty: order.quote_order_qty,
+            grid_id: order.grid_id.clone(),
+            client_order_id: order.new_client_order_id.clone(),
+        }
+    }
+}
+
+impl From<&MarginOrder> for OrderTrackingRequest {
+    fn from(order: &MarginOrder) -> Self {
+        Self {
+            market: OrderMarket::Margin,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            stop_price: order.stop_price,
+            quote_order_qty: order.quote_order_qty,
+            grid_id: order.grid_id.clone(),
+            client_order_id: order.new_client_order_id.clone(),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use rust_decimal::Decimal;
+use crate::futures::utils::expected_order_requests::rule::ExpectedOrderRequestsRule;
+use crate::futures::utils::expected_order_requests::rule_count_basis::ExpectedOrderRequestsRuleCountBasis;
+use crate::futures::utils::expected_order_requests::rule_payload::ExpectedOrderRequestsRulePayload;
+use crate::futures::utils::expected_order_requests::rule_period::RulePeriod;
+use crate::futures::utils::expected_order_requests::rule_size_params::ExpectedOrderRequestsRuleSizeParams;
+use crate::futures::utils::order_tracking_item::{OrderTrackingItem, OrderTrackingOutcome};
+use crate::futures::utils::order_tracking_request::OrderMarket;
+use crate::futures::utils::top_n::TopNEntry;
+use crate::rest_model::OrderSide;
+
+/// Global rule on BTCUSDT futures buy orders, counting the submitted ones
+pub(crate) fn rule(size_params: ExpectedOrderRequestsRuleSizeParams, period: RulePeriod, max_count: u64) -> ExpectedOrderRequestsRule {
+    ExpectedOrderRequestsRule::Global(ExpectedOrderRequestsRulePayload {
+        market: OrderMarket::Futures,
+        count_by: ExpectedOrderRequestsRuleCountBasis::Submitted,
+        symbol: "BTCUSDT".to_string(),
+        size_params,
+        max_count,
+        period,
+        side: OrderSide::Buy,
+        limits: vec![],
+    })
+}
+
+/// Tracked order without grid, client order id nor exchange outcome
+pub(crate) fn entry(timestamp: u64, side: OrderSide, size: Decimal, price: Decimal) -> TopNEntry<OrderTrackingItem> {
+    TopNEntry {
+        timestamp,
+        item: OrderTrackingItem {
+            size,
+            price,
+            side,
+            id: format!("id-{timestamp}-{size}"),
+            grid_id: None,
+            client_order_id: None,
+            outcome: OrderTrackingOutcome::default(),
+        },
+    }
+}
@@ -72,15 +72,48 @@ where
     }
 
     pub fn insert(&mut self, item: TopNEntry<T>) {
+        self.insert_evicting(item);
+    }
+
+    /// Same as `insert`, returning the oldest entry if it was evicted to stay within the capacity
+    pub fn insert_evicting(&mut self, item: TopNEntry<T>) -> Option<TopNEntry<T>> {
         if self.set.contains(&item) {
-            return;
+            return None;
         }
         
         self.set.insert(item);
 
         if self.set.len() > self.capacity {
-            self.set.pop_first();
+            return self.set.pop_first();
         }
+        None
+    }
+
+    /// Removes and returns the entries older than `timestamp`
+    pub fn drain_lt_timestamp(&mut self, timestamp: u64) -> Vec<TopNEntry<T>> {
+        let mut drained = vec![];
+        while self.set.first().is_some_and(|entry| entry.timestamp < timestamp) {
+            drained.extend(self.set.pop_first());
+        }
+        drained
+    }
+
+    pub fn contains(&self, item: &TopNEntry<T>) -> bool {
+        self.set.contains(item)
+    }
+
+    /// The stored entry equal to `item`
+    pub fn get(&self, item: &TopNEntry<T>) -> Option<&TopNEntry<T>> {
+        self.set.get(item)
+    }
+
+    /// Entries at or after `timestamp`, newest first, without cloning them
+    pub fn iter_gte_timestamp(&self, timestamp: u64) -> impl Iterator<Item = &TopNEntry<T>> {
+        self.set.iter().rev().take_while(move |entry| entry.timestamp >= timestamp)
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -1,6 +1,8 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use anyhow::anyhow;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use serde_json::from_str;
 use tokio::net::TcpStream;
 use tokio_tungstenite::tungstenite::handshake::client::Response;
@@ -11,6 +13,8 @@ use url::Url;
 
 use crate::config::Config;
 use crate::errors::*;
+use crate::retry::RetryPolicy;
+use crate::traffic::{TrafficRecorder, TrafficReplay};
 
 pub static STREAM_ENDPOINT: &str = "stream";
 pub static WS_ENDPOINT: &str = "ws";
@@ -58,12 +62,73 @@ pub fn diff_book_depth_stream(symbol: &str, update_speed: u16) -> String { forma
 
 fn combined_stream(streams: Vec<String>) -> String { streams.join("/") }
 
+/// Why a supervised connection was dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The server sent a close frame
+    Closed(Option<String>),
+    /// Reading from the socket failed
+    Error(String),
+    /// The stream ended without a close frame
+    StreamEnded,
+    /// Nothing was received for longer than `ReconnectPolicy::max_idle`
+    Idle,
+    /// The connection reached `ReconnectPolicy::max_connection_age`, Binance drops connections after 24h
+    MaxAge,
+}
+
+/// Connection lifecycle events of a supervised connection
+///
+/// Market data sent while disconnected is lost, consumers that keep state built from the stream
+/// (e.g. order books) should resync when they get `Reconnected`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Disconnected(DisconnectReason),
+    Reconnecting { attempt: u32, delay: Duration },
+    Reconnected { attempt: u32, downtime: Duration },
+}
+
+/// How a dropped connection is re-established
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Delays between reconnection attempts, `max_attempts` is the number of consecutive failed
+    /// attempts after which the event loop gives up
+    pub backoff: RetryPolicy,
+    /// The connection is considered dead when nothing, not even a ping, was received for this long
+    pub max_idle: Duration,
+    /// The connection is renewed once it gets this old, ahead of the server dropping it
+    pub max_connection_age: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            backoff: RetryPolicy {
+                max_attempts: u32::MAX,
+                initial_backoff: Duration::from_millis(500),
+                max_backoff: Duration::from_secs(30),
+                backoff_multiplier: 2,
+                jitter: true,
+            },
+            // The server pings every 3 minutes
+            max_idle: Duration::from_secs(10 * 60),
+            max_connection_age: Duration::from_secs(23 * 60 * 60),
+        }
+    }
+}
+
 pub struct WebSockets<'a, WE> {
     pub socket: Option<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response)>,
     handler: Box<dyn FnMut(WE) -> Result<()> + 'a + Send>,
+    connection_handler: Option<Box<dyn FnMut(ConnectionEvent) -> Result<()> + 'a + Send>>,
     conf: Config,
+    reconnect: Option<ReconnectPolicy>,
+    /// Last connected url, reused when reconnecting so that the same streams are subscribed
+    url: Option<Url>,
+    connected_at: Option<Instant>,
+    recorder: Option<Arc<TrafficRecorder>>,
+    replay: Option<Arc<TrafficReplay>>,
 }
-
 impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
     /// New websocket holder with default configuration
     /// # Examples
@@ -85,10 +150,46 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
         WebSockets {
             socket: None,
             handler: Box::new(handler),
+            connection_handler: None,
             conf,
+            reconnect: None,
+            url: None,
+            connected_at: None,
+            recorder: None,
+            replay: None,
         }
     }
 
+    /// Records every message received to `recorder`
+    pub fn with_recorder(mut self, recorder: Arc<TrafficRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Reads the messages of the connected stream from a recording instead of connecting, the
+    /// event loop returns once the recording of the stream is over. Recorded close frames are
+    /// handled as live ones, reconnecting goes on with the messages recorded after them.
+    pub fn with_replay(mut self, replay: Arc<TrafficReplay>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Reconnects to the same endpoint whenever the connection drops, instead of returning an
+    /// error from the event loop
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Receives the connection lifecycle events of a supervised connection
+    pub fn with_connection_handler<Callback>(mut self, handler: Callback) -> Self
+    where
+        Callback: FnMut(ConnectionEvent) -> Result<()> + 'a + Send,
+    {
+        self.connection_handler = Some(Box::new(handler));
+        self
+    }
+
     /// Connect to multiple websocket endpoints
     /// N.B: WE has to be CombinedStreamEvent
     pub async fn connect_multiple(&mut self, endpoints: Vec<String>) -> Result<()> {
@@ -111,9 +212,16 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
     }
 
     async fn handle_connect(&mut self, url: Url) -> Result<()> {
-        match connect_async(url).await {
+        if self.replay.is_some() {
+            self.url = Some(url);
+            self.connected_at = Some(Instant::now());
+            return Ok(());
+        }
+        match connect_async(url.clone()).await {
             Ok(answer) => {
                 self.socket = Some(answer);
+                self.url = Some(url);
+                self.connected_at = Some(Instant::now());
                 Ok(())
             }
             Err(e) => Err(Error::Msg(format!("Error during handshake {e}"))),
@@ -122,7 +230,9 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
 
     /// Disconnect from the endpoint
     pub async fn disconnect(&mut self) -> Result<()> {
-        if let Some(ref mut socket) = self.socket {
+        if self.replay.is_some() {
+            Ok(())
+        } else if let Some(ref mut socket) = self.socket {
             socket.0.close(None).await?;
             Ok(())
         } else {
@@ -132,50 +242,217 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
 
     pub fn socket(&self) -> &Option<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response)> { &self.socket }
 
+    fn emit(&mut self, event: ConnectionEvent) -> Result<()> {
+        match self.connection_handler {
+            Some(ref mut handler) => handler(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the next message, bounded by the idle timeout and connection age of the reconnect policy.
+    /// Returns `None` once a replayed recording is over.
+    async fn next_message(&mut self) -> Result<Option<std::result::Result<Message, DisconnectReason>>> {
+        if let Some(ref replay) = self.replay {
+            let url = self.url.as_ref().ok_or_else(|| Error::Msg("Websocket is not connected".to_string()))?;
+            return Ok(replay.next_message(url).map(Ok));
+        }
+        let message = self.read_message().await?;
+        if let (Some(recorder), Some(url), Ok(message)) = (&self.recorder, &self.url, &message) {
+            recorder.record_message(url, message);
+        }
+        Ok(Some(message))
+    }
+
+    async fn read_message(&mut self) -> Result<std::result::Result<Message, DisconnectReason>> {
+        let Some((ref mut socket, _)) = self.socket else {
+            return Err(Error::Msg("Websocket is not connected".to_string()));
+        };
+        let Some(ref policy) = self.reconnect else {
+            return match socket.next().await {
+                Some(message) => Ok(Ok(message?)),
+                None => Err(Error::Msg("Websocket stream ended".to_string())),
+            };
+        };
+        let age = self.connected_at.map(|at| at.elapsed()).unwrap_or_default();
+        let remaining_age = policy.max_connection_age.saturating_sub(age);
+        if remaining_age.is_zero() {
+            return Ok(Err(DisconnectReason::MaxAge));
+        }
+        let timeout = policy.max_idle.min(remaining_age);
+        Ok(match tokio::time::timeout(timeout, socket.next()).await {
+            Ok(Some(Ok(message))) => Ok(message),
+            Ok(Some(Err(e))) => Err(DisconnectReason::Error(e.to_string())),
+            Ok(None) => Err(DisconnectReason::StreamEnded),
+            Err(_) if timeout < policy.max_idle => Err(DisconnectReason::MaxAge),
+            Err(_) => Err(DisconnectReason::Idle),
+        })
+    }
+
+    async fn reconnect(&mut self, running: &AtomicBool, reason: DisconnectReason) -> Result<()> {
+        let (Some(policy), Some(url)) = (self.reconnect.clone(), self.url.clone()) else {
+            return Err(Error::Msg(format!("Disconnected {reason:?}")));
+        };
+        if let Some((mut socket, _)) = self.socket.take() {
+            // The connection is dropped anyway, failing to close it cleanly does not matter
+            let _ = socket.close(None).await;
+        }
+        let disconnected_at = Instant::now();
+        // Renewing an old connection is planned, there is no reason to wait
+        let immediate = reason == DisconnectReason::MaxAge;
+        self.emit(ConnectionEvent::Disconnected(reason))?;
+        let mut attempt = 1;
+        while running.load(Ordering::Relaxed) {
+            let delay = if immediate && attempt == 1 {
+                Duration::ZERO
+            } else {
+                policy.backoff.backoff(attempt)
+            };
+            self.emit(ConnectionEvent::Reconnecting { attempt, delay })?;
+            tokio::time::sleep(delay).await;
+            match self.handle_connect(url.clone()).await {
+                Ok(()) => {
+                    return self.emit(ConnectionEvent::Reconnected {
+                        attempt,
+                        downtime: disconnected_at.elapsed(),
+                    });
+                }
+                Err(e) if attempt >= policy.backoff.max_attempts => return Err(e),
+                Err(_) => attempt += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispatches messages to the handler until `running` is false
+    ///
+    /// Without a reconnect policy, the loop returns an error as soon as the connection drops.
+    /// With one, the connection is re-established and `ConnectionEvent`s are reported to the
+    /// connection handler.
     pub async fn event_loop(&mut self, running: &AtomicBool) -> Result<()> {
         while running.load(Ordering::Relaxed) {
-            if let Some((ref mut socket, _)) = self.socket {
-                // TODO: return error instead of panic?
-                let message = socket.next().await.unwrap()?;
-                // let message = match socket.next().await {
-                //     Ok(msg) => msg,
-                //     Err(error) => {
-                //         return Err(Error::Msg(format!("Error during websocket event loop when calling next on socket {error:?}")));
-                //     }
-                // };
-
-                match message {
-                    Message::Text(msg) => {
-                        // println!("WE GOT TEXT: {msg}");
-                        if msg.is_empty() {
-                            return Ok(());
-                        }
-                        let event: WE = match from_str(msg.as_str()) {
-                            Ok(event) => event,
-                            Err(e) => return Err(anyhow!("Failed to parse this string: {msg}. Error: {e:?}").into())
-                        };
-                        (self.handler)(event)?;
-                    }
-                    Message::Ping(_) => {
-                        // println!("WE GOT PING");
-                        let event: WE = from_str(r#"{"e":"PING"}"#)?;
-                        (self.handler)(event)?;
-                    }
-                    Message::Pong(_)  => {
-                        // println!("WE GOT PONG");
-                    }
-                    Message::Binary(_) => {
-                        // println!("WE GOT BINARY");
-                    }
-                    Message::Frame(_) => {
-                        // println!("WE GOT FRAME");
+            let message = match self.next_message().await? {
+                Some(Ok(message)) => message,
+                Some(Err(reason)) => {
+                    self.reconnect(running, reason).await?;
+                    continue;
+                }
+                None => return Ok(()),
+            };
+            match message {
+                Message::Text(msg) => {
+                    if msg.is_empty() {
+                        return Ok(());
                     }
-                    Message::Close(e) => {
-                        return Err(Error::Msg(format!("Disconnected {e:?}")));
+                    let event: WE = match from_str(msg.as_str()) {
+                        Ok(event) => event,
+                        Err(e) => return Err(anyhow!("Failed to parse this string: {msg}. Error: {e:?}").into())
+                    };
+                    (self.handler)(event)?;
+                }
+                Message::Ping(payload) => {
+                    if let Some((ref mut socket, _)) = self.socket {
+                        socket.send(Message::Pong(payload)).await?;
                     }
+                    let event: WE = from_str(r#"{"e":"PING"}"#)?;
+                    (self.handler)(event)?;
+                }
+                Message::Pong(_) | Message::Binary(_) | Message::Frame(_) => {}
+                Message::Close(frame) if self.reconnect.is_some() => {
+                    let reason = frame.map(|frame| format!("{} {}", frame.code, frame.reason));
+                    self.reconnect(running, DisconnectReason::Closed(reason)).await?;
+                }
+                Message::Close(e) => {
+                    return Err(Error::Msg(format!("Disconnected {e:?}")));
                 }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reconnects_and_answers_pings() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(stream).await.unwrap();
+            ws.send(Message::Text(r#"{"n":1}"#.to_string())).await.unwrap();
+            ws.close(None).await.unwrap();
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(stream).await.unwrap();
+            ws.send(Message::Ping(b"hb".to_vec())).await.unwrap();
+            let pong = ws.next().await.unwrap().unwrap();
+            ws.send(Message::Text(r#"{"n":2}"#.to_string())).await.unwrap();
+            // Keep the connection open until the client is done
+            let _ = ws.next().await;
+            pong
+        });
+
+        let running = Arc::new(AtomicBool::new(true));
+        let events = Arc::new(Mutex::new(vec![]));
+        let connection_events = Arc::new(Mutex::new(vec![]));
+        let policy = ReconnectPolicy {
+            backoff: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+                backoff_multiplier: 1,
+                jitter: false,
+            },
+            ..ReconnectPolicy::default()
+        };
+        let conf = Config::default().set_futures_ws_endpoint(format!("ws://127.0.0.1:{port}"));
+        let mut web_socket: WebSockets<'_, serde_json::Value> = WebSockets::new_with_options(
+            {
+                let running = running.clone();
+                let events = events.clone();
+                move |event: serde_json::Value| {
+                    if event["n"] == 2 {
+                        running.store(false, Ordering::Relaxed);
+                    }
+                    events.lock().unwrap().push(event);
+                    Ok(())
+                }
+            },
+            conf,
+        )
+        .with_reconnect(policy)
+        .with_connection_handler({
+            let connection_events = connection_events.clone();
+            move |event| {
+                connection_events.lock().unwrap().push(event);
+                Ok(())
+            }
+        });
+        web_socket.connect("btcusdt@aggTrade").await.unwrap();
+        web_socket.event_loop(&running).await.unwrap();
+        web_socket.disconnect().await.unwrap();
+        assert_eq!(server.await.unwrap(), Message::Pong(b"hb".to_vec()));
+
+        let events = events.lock().unwrap();
+        assert_eq!(*events, vec![
+            serde_json::json!({"n": 1}),
+            serde_json::json!({"e": "PING"}),
+            serde_json::json!({"n": 2})
+        ]);
+        let connection_events = connection_events.lock().unwrap();
+        assert_eq!(connection_events.len(), 3);
+        assert_eq!(connection_events[0], ConnectionEvent::Disconnected(DisconnectReason::Closed(None)));
+        assert_eq!(connection_events[1], ConnectionEvent::Reconnecting {
+            attempt: 1,
+            delay: Duration::from_millis(10)
+        });
+        assert!(matches!(connection_events[2], ConnectionEvent::Reconnected { attempt: 1, .. }));
+    }
+}
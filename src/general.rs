@@ -47,6 +47,8 @@ impl General {
     /// assert!(exchange_info.is_ok(), "{:?}", exchange_info);
     /// ```
     pub async fn exchange_info(&self) -> Result<ExchangeInformation> {
-        self.client.get("/api/v3/exchangeInfo", None).await
+        let info: ExchangeInformation = self.client.get("/api/v3/exchangeInfo", None).await?;
+        self.client.set_rate_limits(&info.rate_limits);
+        Ok(info)
     }
 }
@@ -0,0 +1,111 @@
+//! Append-only files of one JSON value per line, shared by the order tracker log, the order
+//! journal and the traffic recordings
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+
+use crate::errors::Result;
+
+/// Reads every line of `path`, empty if the file does not exist
+///
+/// A crash while appending can only leave the last line truncated, so an invalid last line is
+/// skipped. An invalid line anywhere else means the file is corrupt and fails the read.
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(anyhow!("Failed to open {}: {error:?}", path.display()).into()),
+    };
+    let mut values = vec![];
+    let mut invalid_line = None;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => return Err(anyhow!("Failed to read {}: {error:?}", path.display()).into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some((line_number, error)) = invalid_line.take() {
+            return Err(anyhow!("Invalid record at line {line_number} of {}: {error:?}", path.display()).into());
+        }
+        match serde_json::from_str(&line) {
+            Ok(value) => values.push(value),
+            Err(error) => invalid_line = Some((index + 1, error)),
+        }
+    }
+    if let Some((line_number, error)) = invalid_line {
+        eprintln!("skipping truncated last record at line {line_number} of {}: {error:?}", path.display());
+    }
+    Ok(values)
+}
+
+/// Drops the partial line a crash while appending may have left at the end of `path`, so that the
+/// next append starts on a line of its own
+pub(crate) fn truncate_partial_last_line(path: &Path) -> Result<()> {
+    let repair = || -> io::Result<()> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let len = file.metadata()?.len();
+        let mut end = len;
+        let mut chunk = [0; 4096];
+        while end > 0 {
+            let start = end.saturating_sub(chunk.len() as u64);
+            let chunk = &mut chunk[..(end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(chunk)?;
+            if let Some(newline) = chunk.iter().rposition(|byte| *byte == b'\n') {
+                end = start + newline as u64 + 1;
+                break;
+            }
+            end = start;
+        }
+        if end < len {
+            eprintln!("dropping {} bytes of a partial last line of {}", len - end, path.display());
+            file.set_len(end)?;
+            file.sync_data()?;
+        }
+        Ok(())
+    };
+    match repair() {
+        Ok(()) => Ok(()),
+        Err(error) => Err(anyhow!("Failed to repair {}: {error:?}", path.display()).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn only_a_truncated_last_line_is_tolerated() {
+        let path = std::env::temp_dir().join(format!("json_lines_{}.jsonl", Uuid::new_v4()));
+        assert!(read_json_lines::<u64>(&path).unwrap().is_empty());
+        truncate_partial_last_line(&path).unwrap();
+        assert!(!path.exists());
+
+        fs::write(&path, "1\n\n2\n{\"trunc").unwrap();
+        assert_eq!(read_json_lines::<u64>(&path).unwrap(), vec![1, 2]);
+        truncate_partial_last_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n\n2\n");
+        truncate_partial_last_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n\n2\n");
+
+        fs::write(&path, "1\n{\"trunc3\n4\n").unwrap();
+        assert!(read_json_lines::<u64>(&path).is_err());
+
+        fs::write(&path, "{\"trunc").unwrap();
+        truncate_partial_last_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -25,7 +25,7 @@
 //! # use std::io;
 //! use binance::general::General;
 //! use binance::api::Binance;
-//! use binance::errors::Error as BinanceLibError;
+//! use binance::errors::{BinanceErrorCode, Error as BinanceLibError};
 //!
 //! #[tokio::main]
 //! async fn main() -> std::io::Result<()> {
@@ -35,8 +35,8 @@
 //!         Ok(answer) => println!("{:?}", answer),
 //!         Err(err) => {
 //!             match err {
-//!                 BinanceLibError::BinanceError { response } => match response.code {
-//!                     -1000_i32 => println!("An unknown error occured while processing the request"),
+//!                 BinanceLibError::BinanceError { response } => match response.error_code() {
+//!                     BinanceErrorCode::Unknown => println!("An unknown error occured while processing the request"),
 //!                     _ => println!("Unknown code {}: {}", response.code, response.msg),
 //!                 },
 //!                 _ => println!("Other errors: {}.", err),
@@ -70,21 +70,32 @@ pub use util::bool_to_string;
 pub use util::bool_to_string_some;
 
 mod client;
+pub mod clock;
+pub mod error_codes;
 pub mod errors;
 pub mod util;
 
 pub mod account;
 pub mod api;
 pub mod config;
-#[cfg(feature = "futures_api")]
 pub mod futures;
 pub mod general;
+mod json_lines;
 #[cfg(feature = "margin_api")]
 pub mod margin;
 pub mod market;
+pub mod order_book;
+pub mod rate_limit;
 pub mod rest_model;
+pub mod retry;
 #[cfg(feature = "savings_api")]
 pub mod savings;
+pub mod signer;
+#[cfg(feature = "test_server")]
+pub mod test_server;
+pub mod time_sync;
+pub mod traffic;
+pub mod transport;
 pub mod userstream;
 #[cfg(feature = "wallet_api")]
 pub mod wallet;
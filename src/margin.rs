@@ -1,7 +1,12 @@
+use std::sync::Arc;
 use rust_decimal::Decimal;
 use crate::client::*;
 use crate::errors::*;
+use crate::futures::utils::expected_order_requests::rule_limit::ExpectedOrderRequestsRuleContext;
+use crate::futures::utils::order_guard::OrderGuard;
+use crate::futures::utils::order_tracking_request::OrderTrackingRequest;
 use crate::rest_model::*;
+use crate::transport::HttpTransport;
 use crate::util::bool_to_string;
 
 static SAPI_V1_MARGIN_TRANSFER: &str = "/sapi/v1/margin/transfer";
@@ -41,9 +46,33 @@ static SAPI_V1_MARGIN_INTEREST_RATE_HISTORY: &str = "/sapi/v1/margin/interestRat
 pub struct Margin {
     pub client: Client,
     pub recv_window: u64,
+    /// Tracker and rules orders are checked against, orders are not checked by default
+    pub order_guard: Option<OrderGuard>,
 }
 
 impl Margin {
+    /// Checks orders against the margin rules of `order_guard`, e.g. `OrderGuard::global()`
+    pub fn with_order_guard(mut self, order_guard: OrderGuard) -> Self {
+        self.order_guard = Some(order_guard);
+        self
+    }
+
+    /// Sends requests through `transport`, see `Client::with_transport`
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.client = self.client.with_transport(transport);
+        self
+    }
+
+    /// Fetches the price index the margin rules count orders without a price at, see
+    /// `OrderTrackingRequest::with_context_price`
+    async fn get_rule_context(&self, order: &OrderTrackingRequest) -> Result<ExpectedOrderRequestsRuleContext> {
+        let mut context = ExpectedOrderRequestsRuleContext::default();
+        if order.needs_market_price() {
+            context.mark_price = Some(self.price_index(order.symbol.as_str()).await?.price);
+        }
+        Ok(context)
+    }
+
     /// Execute transfer between spot account and margin account.
     /// # Examples
     /// ```rust,no_run
@@ -198,6 +227,7 @@ impl Margin {
     }
 
     /// Post a new order for margin account.
+    /// Checked against the margin rules of `order_guard` first when set.
     /// # Examples
     /// ```rust,no_run
     /// use rust_decimal_macros::dec;
@@ -217,14 +247,28 @@ impl Margin {
     ///     time_in_force: Some(TimeInForce::FOK),
     ///     side_effect_type: SideEffectType::NoSideEffect,
     ///     is_isolated: None,
+    ///     grid_id: None,
     /// };
     /// let transaction_id = tokio_test::block_on(margin.trade(margin_order));
     /// assert!(transaction_id.is_ok(), "{:?}", transaction_id);
     /// ```
     pub async fn trade(&self, margin_order: MarginOrder) -> Result<MarginOrderResult> {
-        self.client
+        let tracking_request = OrderTrackingRequest::from(&margin_order);
+        let checked = match &self.order_guard {
+            Some(order_guard) => {
+                let context = self.get_rule_context(&tracking_request).await?;
+                Some(order_guard.check_order_with_context(&tracking_request, &context)?)
+            }
+            None => None,
+        };
+        let result = self
+            .client
             .post_signed_p(SAPI_V1_MARGIN_ORDER, margin_order, self.recv_window)
-            .await
+            .await;
+        if let (Some(order_guard), Some((tracking_entry, _))) = (&self.order_guard, &checked) {
+            order_guard.record_response(&tracking_request.get_tracking_key(), tracking_entry, &result);
+        }
+        result
     }
 
     /// Post a new order for margin account.
@@ -247,6 +291,7 @@ impl Margin {
     ///     time_in_force: Some(TimeInForce::FOK),
     ///     side_effect_type: SideEffectType::NoSideEffect,
     ///     is_isolated: None,
+    ///     grid_id: None,
     /// };
     /// let transaction_id = tokio_test::block_on(margin.new_order(margin_order));
     /// assert!(transaction_id.is_ok(), "{:?}", transaction_id);
@@ -256,6 +301,8 @@ impl Margin {
     }
 
     /// Post a new order for margin account.
+    /// Both legs are checked against the margin rules of `order_guard` first when set, neither is tracked
+    /// if one of them violates a rule.
     /// # Examples
     /// ```rust,no_run
     /// use rust_decimal_macros::dec;
@@ -273,9 +320,21 @@ impl Margin {
     /// assert!(transaction_id.is_ok(), "{:?}", transaction_id);
     /// ```
     pub async fn new_oco_order(&self, margin_order: MarginOCOOrder) -> Result<MarginOCOOrderResult> {
-        self.client
+        let tracking_requests = OrderTrackingRequest::from_margin_oco_order(&margin_order);
+        let checked = match &self.order_guard {
+            Some(order_guard) => Some(order_guard.check_orders(&tracking_requests)?),
+            None => None,
+        };
+        let result = self
+            .client
             .post_signed_p(SAPI_V1_MARGIN_OCO_ORDER, margin_order, self.recv_window)
-            .await
+            .await;
+        if let (Some(order_guard), Some(checked)) = (&self.order_guard, &checked) {
+            for (tracking_request, (tracking_entry, _)) in tracking_requests.iter().zip(checked.iter()) {
+                order_guard.record_response(&tracking_request.get_tracking_key(), tracking_entry, &result);
+            }
+        }
+        result
     }
 
     /// Cancel an existing order
@@ -1000,3 +1059,67 @@ impl Margin {
             .await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use reqwest::Method;
+    use rust_decimal_macros::dec;
+    use super::*;
+    use crate::api::Binance;
+    use crate::config::Config;
+    use crate::futures::utils::expected_order_requests::rules_config::ExpectedOrderRequestsRulesConfig;
+    use crate::futures::utils::order_tracker_store::InMemoryOrderTrackerStore;
+    use crate::rate_limit::RateLimitMode;
+    use crate::transport::{HttpResponse, MemoryTransport};
+
+    const MARGIN_ORDER_RESULT: &str = r#"{"symbol":"BTCUSDT","orderId":1,"clientOrderId":"a","transactTime":1,"price":"0",
+        "origQty":"0.5","executedQty":"0","cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC","type":"MARKET",
+        "side":"BUY","fills":[]}"#;
+
+    #[tokio::test]
+    async fn orders_without_a_price_are_counted_at_the_price_index() {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond(Method::POST, SAPI_V1_MARGIN_ORDER, HttpResponse::ok(MARGIN_ORDER_RESULT));
+        transport.respond(Method::GET, SAPI_V1_MARGIN_PRICE_INDEX, HttpResponse::ok(r#"{"calcTime":1,"price":"200","symbol":"BTCUSDT"}"#));
+        let guard = OrderGuard::new(Arc::new(InMemoryOrderTrackerStore::new()));
+        let config = ExpectedOrderRequestsRulesConfig::from_json(
+            r#"{"rules":[
+                {"Global":{"market":"MARGIN","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Hours":24},"side":"BUY"}},
+                {"Global":{"market":"MARGIN","symbol":"BTCUSDT","size_params":{"Max":"1"},"max_count":10,"period":{"Weeks":52},"side":"BUY"}}
+            ]}"#,
+        )
+        .unwrap();
+        guard.rules().load(config.into_rule_map()).unwrap();
+        let config = Config::default().set_rate_limit_mode(RateLimitMode::Disabled);
+        let margin = Margin::new_with_config(Some("api-key".to_string()), Some("secret-key".to_string()), &config)
+            .with_transport(transport.clone())
+            .with_order_guard(guard.clone());
+
+        let market = MarginOrder {
+            symbol: "BTCUSDT".to_string(),
+            side: OrderSide::Buy,
+            order_type: OrderType::Market,
+            quantity: Some(dec!(0.5)),
+            quote_order_qty: None,
+            price: None,
+            stop_price: None,
+            new_client_order_id: None,
+            iceberg_qty: None,
+            new_order_resp_type: OrderResponse::Full,
+            time_in_force: None,
+            side_effect_type: SideEffectType::NoSideEffect,
+            is_isolated: None,
+            grid_id: None,
+        };
+        let quote_sized = MarginOrder { quantity: None, quote_order_qty: Some(dec!(100)), ..market.clone() };
+        let stop = MarginOrder { order_type: OrderType::StopLoss, stop_price: Some(dec!(90)), ..market.clone() };
+        for order in [market, quote_sized, stop] {
+            assert!(margin.trade(order).await.is_ok());
+        }
+        // Stop orders are counted at their stop price without fetching the price index
+        assert_eq!(transport.requests_to(Method::GET, SAPI_V1_MARGIN_PRICE_INDEX).len(), 2);
+        let mut tracked: Vec<_> = guard.tracker().get_all_tracking_items("margin.BTCUSDT").unwrap().into_iter().map(|entry| (entry.item.size, entry.item.price)).collect();
+        tracked.sort();
+        assert_eq!(tracked, vec![(dec!(0.5), dec!(90)), (dec!(0.5), dec!(200)), (dec!(0.5), dec!(200))]);
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use rust_decimal::Decimal;
+
+use crate::errors::*;
+#[cfg(feature = "futures_api")]
+use crate::futures::market::FuturesMarket;
+use crate::market::Market;
+use crate::rest_model::{Asks, Bids, OrderBook};
+use crate::ws_model::DepthOrderBookEvent;
+
+/// Events received while waiting for a snapshot, older ones are dropped past this
+const MAX_BUFFERED_EVENTS: usize = 10_000;
+
+/// Snapshots that do not overlap with the buffered events are fetched again up to this many times
+const MAX_SNAPSHOT_ATTEMPTS: u32 = 5;
+
+/// The market a book is maintained for, spot and futures streams chain their events differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBookMarket {
+    /// Each event starts right after the previous one, `U` is the previous `u` + 1
+    Spot,
+    /// Each event references the previous one, `pu` is the previous `u`
+    Futures,
+}
+
+/// REST endpoint used to fetch depth snapshots
+#[derive(Clone)]
+pub enum DepthSnapshotSource {
+    Spot(Market),
+    #[cfg(feature = "futures_api")]
+    Futures(FuturesMarket),
+}
+
+impl DepthSnapshotSource {
+    pub fn market(&self) -> OrderBookMarket {
+        match self {
+            DepthSnapshotSource::Spot(_) => OrderBookMarket::Spot,
+            #[cfg(feature = "futures_api")]
+            DepthSnapshotSource::Futures(_) => OrderBookMarket::Futures,
+        }
+    }
+
+    pub async fn get_depth(&self, symbol: &str, limit: u16) -> Result<OrderBook> {
+        match self {
+            DepthSnapshotSource::Spot(market) => market.get_custom_depth(symbol, limit).await,
+            #[cfg(feature = "futures_api")]
+            DepthSnapshotSource::Futures(market) => Ok(market.get_custom_depth(symbol, limit).await?.into()),
+        }
+    }
+}
+
+impl From<Market> for DepthSnapshotSource {
+    fn from(market: Market) -> Self { DepthSnapshotSource::Spot(market) }
+}
+
+#[cfg(feature = "futures_api")]
+impl From<FuturesMarket> for DepthSnapshotSource {
+    fn from(market: FuturesMarket) -> Self { DepthSnapshotSource::Futures(market) }
+}
+
+/// Outcome of feeding an event or a snapshot to a `LocalOrderBook`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBookUpdate {
+    /// The book is up to date with the event
+    Applied,
+    /// The book is not synced yet, the event was kept until a snapshot arrives
+    Buffered,
+    /// The event is already included in the book and was ignored
+    Stale,
+    /// The snapshot is older than the buffered events, a newer one is needed
+    SnapshotTooOld,
+    /// Updates were missed, the book was cleared and needs a new snapshot
+    GapDetected,
+}
+
+/// Order book of a symbol maintained from a diff depth stream and REST snapshots
+///
+/// See <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly>
+/// Events are buffered until a snapshot is applied, then each event is checked to follow the
+/// previous one. When an update is missed the book is cleared and waits for a new snapshot.
+#[derive(Debug, Clone)]
+pub struct LocalOrderBook {
+    symbol: String,
+    market: OrderBookMarket,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    last_update_id: u64,
+    synced: bool,
+    /// No event was applied since the last snapshot
+    awaiting_first_event: bool,
+    buffer: VecDeque<DepthOrderBookEvent>,
+    snapshot_limit: u16,
+}
+
+impl LocalOrderBook {
+    pub fn new<S: Into<String>>(symbol: S, market: OrderBookMarket) -> Self {
+        LocalOrderBook {
+            symbol: symbol.into(),
+            market,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id: 0,
+            synced: false,
+            awaiting_first_event: false,
+            buffer: VecDeque::new(),
+            snapshot_limit: 1000,
+        }
+    }
+
+    /// Number of levels requested when fetching snapshots, defaults to 1000
+    pub fn with_snapshot_limit(mut self, snapshot_limit: u16) -> Self {
+        self.snapshot_limit = snapshot_limit;
+        self
+    }
+
+    pub fn symbol(&self) -> &str { &self.symbol }
+
+    pub fn market(&self) -> OrderBookMarket { self.market }
+
+    /// Whether the book reflects the stream, false until a matching snapshot is applied
+    pub fn is_synced(&self) -> bool { self.synced }
+
+    /// Update id of the last applied snapshot or event
+    pub fn last_update_id(&self) -> u64 { self.last_update_id }
+
+    /// Clears the book, e.g. after the websocket reconnected and events may have been missed
+    pub fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.last_update_id = 0;
+        self.synced = false;
+        self.awaiting_first_event = false;
+        self.buffer.clear();
+    }
+
+    /// Feeds an event of the diff depth stream
+    pub fn apply_event(&mut self, event: DepthOrderBookEvent) -> OrderBookUpdate {
+        if !self.synced {
+            if self.buffer.len() >= MAX_BUFFERED_EVENTS {
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back(event);
+            return OrderBookUpdate::Buffered;
+        }
+        self.apply_synced(event)
+    }
+
+    /// Replaces the book with a REST snapshot and replays the buffered events on top of it
+    pub fn apply_snapshot(&mut self, snapshot: OrderBook) -> OrderBookUpdate {
+        if let Some(first) = self.buffer.front() {
+            if snapshot.last_update_id < first.first_update_id {
+                return OrderBookUpdate::SnapshotTooOld;
+            }
+        }
+        self.bids = snapshot.bids.into_iter().filter(|b| !b.qty.is_zero()).map(|b| (b.price, b.qty)).collect();
+        self.asks = snapshot.asks.into_iter().filter(|a| !a.qty.is_zero()).map(|a| (a.price, a.qty)).collect();
+        self.last_update_id = snapshot.last_update_id;
+        self.synced = true;
+        self.awaiting_first_event = true;
+        let mut result = OrderBookUpdate::Applied;
+        while let Some(event) = self.buffer.pop_front() {
+            if self.apply_synced(event) == OrderBookUpdate::GapDetected {
+                result = OrderBookUpdate::GapDetected;
+                break;
+            }
+        }
+        result
+    }
+
+    fn is_stale(&self, event: &DepthOrderBookEvent) -> bool {
+        match self.market {
+            OrderBookMarket::Spot => event.final_update_id <= self.last_update_id,
+            // Futures events overlap the snapshot, the first one to apply has u >= lastUpdateId
+            OrderBookMarket::Futures if self.awaiting_first_event => event.final_update_id < self.last_update_id,
+            OrderBookMarket::Futures => event.final_update_id <= self.last_update_id,
+        }
+    }
+
+    fn follows(&self, event: &DepthOrderBookEvent) -> bool {
+        let last = self.last_update_id;
+        match (self.market, self.awaiting_first_event) {
+            (OrderBookMarket::Spot, true) => event.first_update_id <= last + 1 && last < event.final_update_id,
+            (OrderBookMarket::Futures, true) => event.first_update_id <= last && last <= event.final_update_id,
+            (OrderBookMarket::Futures, false) if event.previous_final_update_id.is_some() => {
+                event.previous_final_update_id == Some(last)
+            }
+            (_, false) => event.first_update_id == last + 1,
+        }
+    }
+
+    fn apply_synced(&mut self, event: DepthOrderBookEvent) -> OrderBookUpdate {
+        if self.is_stale(&event) {
+            return OrderBookUpdate::Stale;
+        }
+        if !self.follows(&event) {
+            self.reset();
+            // The event starts the sequence the next snapshot has to catch up with
+            self.buffer.push_back(event);
+            return OrderBookUpdate::GapDetected;
+        }
+        for bid in event.bids {
+            update_level(&mut self.bids, bid.price, bid.qty);
+        }
+        for ask in event.asks {
+            update_level(&mut self.asks, ask.price, ask.qty);
+        }
+        self.last_update_id = event.final_update_id;
+        self.awaiting_first_event = false;
+        OrderBookUpdate::Applied
+    }
+
+    /// Fetches a snapshot and applies it, fetching again while it is older than the buffered events
+    pub async fn sync(&mut self, source: &DepthSnapshotSource) -> Result<OrderBookUpdate> {
+        for _ in 0..MAX_SNAPSHOT_ATTEMPTS {
+            let snapshot = source.get_depth(&self.symbol, self.snapshot_limit).await?;
+            match self.apply_snapshot(snapshot) {
+                OrderBookUpdate::SnapshotTooOld => continue,
+                update => return Ok(update),
+            }
+        }
+        Err(Error::Msg(format!(
+            "Depth snapshots of {} stayed older than the buffered events after {MAX_SNAPSHOT_ATTEMPTS} attempts",
+            self.symbol
+        )))
+    }
+
+    /// Feeds an event and fetches a snapshot whenever the book is not synced
+    pub async fn update(&mut self, event: DepthOrderBookEvent, source: &DepthSnapshotSource) -> Result<OrderBookUpdate> {
+        let update = self.apply_event(event);
+        if self.synced {
+            return Ok(update);
+        }
+        match self.sync(source).await? {
+            // Gaps while replaying the buffer mean the snapshot was taken mid-stream, try once more
+            OrderBookUpdate::GapDetected => self.sync(source).await,
+            update => Ok(update),
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<Bids> { self.bids.iter().next_back().map(|(price, qty)| Bids::new(*price, *qty)) }
+
+    pub fn best_ask(&self) -> Option<Asks> { self.asks.iter().next().map(|(price, qty)| Asks::new(*price, *qty)) }
+
+    /// Difference between the best ask and the best bid
+    pub fn spread(&self) -> Option<Decimal> { Some(self.best_ask()?.price - self.best_bid()?.price) }
+
+    /// Best `depth` bids, highest price first
+    pub fn bids(&self, depth: usize) -> Vec<Bids> {
+        self.bids
+            .iter()
+            .rev()
+            .take(depth)
+            .map(|(price, qty)| Bids::new(*price, *qty))
+            .collect()
+    }
+
+    /// Best `depth` asks, lowest price first
+    pub fn asks(&self, depth: usize) -> Vec<Asks> {
+        self.asks
+            .iter()
+            .take(depth)
+            .map(|(price, qty)| Asks::new(*price, *qty))
+            .collect()
+    }
+
+    /// Copy of the book limited to `depth` levels per side, all levels if `None`
+    pub fn snapshot(&self, depth: Option<usize>) -> OrderBook {
+        let depth = depth.unwrap_or(usize::MAX);
+        OrderBook {
+            last_update_id: self.last_update_id,
+            bids: self.bids(depth),
+            asks: self.asks(depth),
+        }
+    }
+}
+
+fn update_level(levels: &mut BTreeMap<Decimal, Decimal>, price: Decimal, qty: Decimal) {
+    if qty.is_zero() {
+        levels.remove(&price);
+    } else {
+        levels.insert(price, qty);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn event(first: u64, last: u64, previous: Option<u64>, bids: &[(Decimal, Decimal)], asks: &[(Decimal, Decimal)]) -> DepthOrderBookEvent {
+        DepthOrderBookEvent {
+            event_time: 0,
+            symbol: "BTCUSDT".to_string(),
+            first_update_id: first,
+            final_update_id: last,
+            previous_final_update_id: previous,
+            bids: bids.iter().map(|(price, qty)| Bids::new(*price, *qty)).collect(),
+            asks: asks.iter().map(|(price, qty)| Asks::new(*price, *qty)).collect(),
+        }
+    }
+
+    fn snapshot(last_update_id: u64) -> OrderBook {
+        OrderBook {
+            last_update_id,
+            bids: vec![Bids::new(dec!(99), dec!(1)), Bids::new(dec!(98), dec!(2))],
+            asks: vec![Asks::new(dec!(101), dec!(1)), Asks::new(dec!(102), dec!(2))],
+        }
+    }
+
+    #[test]
+    fn spot_book_replays_buffered_events_and_detects_gaps() {
+        let mut book = LocalOrderBook::new("BTCUSDT", OrderBookMarket::Spot);
+        assert_eq!(book.apply_event(event(95, 100, None, &[(dec!(97), dec!(5))], &[])), OrderBookUpdate::Buffered);
+        assert_eq!(book.apply_snapshot(snapshot(94)), OrderBookUpdate::SnapshotTooOld);
+        assert_eq!(
+            book.apply_event(event(101, 105, None, &[(dec!(99), dec!(0))], &[(dec!(100), dec!(3))])),
+            OrderBookUpdate::Buffered
+        );
+        assert_eq!(book.apply_snapshot(snapshot(102)), OrderBookUpdate::Applied);
+        assert!(book.is_synced());
+        assert_eq!(book.last_update_id(), 105);
+        // The first event is already part of the snapshot
+        assert_eq!(book.bids(10).len(), 1);
+        assert_eq!(book.best_bid().unwrap().price, dec!(98));
+        assert_eq!(book.best_ask().unwrap().price, dec!(100));
+        assert_eq!(book.spread(), Some(dec!(2)));
+        assert_eq!(book.asks(2).iter().map(|a| a.price).collect::<Vec<_>>(), vec![dec!(100), dec!(101)]);
+
+        assert_eq!(book.apply_event(event(100, 105, None, &[], &[])), OrderBookUpdate::Stale);
+        assert_eq!(book.apply_event(event(106, 110, None, &[(dec!(98.5), dec!(1))], &[])), OrderBookUpdate::Applied);
+        assert_eq!(book.best_bid().unwrap().price, dec!(98.5));
+        assert_eq!(book.snapshot(Some(1)).bids.len(), 1);
+        assert_eq!(book.snapshot(None).asks.len(), 3);
+
+        assert_eq!(book.apply_event(event(112, 115, None, &[], &[])), OrderBookUpdate::GapDetected);
+        assert!(!book.is_synced());
+        assert!(book.best_bid().is_none());
+        assert_eq!(book.apply_snapshot(snapshot(111)), OrderBookUpdate::SnapshotTooOld);
+        assert_eq!(book.apply_snapshot(snapshot(113)), OrderBookUpdate::Applied);
+        assert_eq!(book.last_update_id(), 115);
+    }
+
+    #[test]
+    fn futures_book_chains_events_with_previous_update_id() {
+        let mut book = LocalOrderBook::new("BTCUSDT", OrderBookMarket::Futures);
+        book.apply_event(event(90, 99, Some(89), &[], &[]));
+        book.apply_event(event(100, 110, Some(99), &[(dec!(99), dec!(4))], &[]));
+        assert_eq!(book.apply_snapshot(snapshot(105)), OrderBookUpdate::Applied);
+        assert_eq!(book.best_bid().unwrap().qty, dec!(4));
+        // Futures update ids are not contiguous, only pu matters
+        assert_eq!(book.apply_event(event(120, 130, Some(110), &[], &[(dec!(101), dec!(0))])), OrderBookUpdate::Applied);
+        assert_eq!(book.best_ask().unwrap().price, dec!(102));
+        assert_eq!(book.apply_event(event(140, 150, Some(135), &[], &[])), OrderBookUpdate::GapDetected);
+        assert!(!book.is_synced());
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::Method;
+
+use crate::errors::*;
+use crate::rest_model::{RateLimit, RateLimitInterval, RateLimitType};
+
+/// What to do with a request that would exceed a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum RateLimitMode {
+    /// Requests are sent without any accounting
+    #[default]
+    Disabled,
+    /// Requests wait until the limit window resets
+    Delay,
+    /// Requests fail with [`Error::RateLimitExceeded`]
+    Reject,
+}
+
+type SharedRateLimiterState = Arc<Mutex<RateLimiterState>>;
+
+/// Request weight usage shared by every client talking to the same host
+static HOST_RATE_LIMITS: Lazy<DashMap<String, SharedRateLimiterState>> = Lazy::new(DashMap::new);
+
+/// Order count usage shared by every client of the same API key on a host
+static ACCOUNT_RATE_LIMITS: Lazy<DashMap<(String, String), SharedRateLimiterState>> = Lazy::new(DashMap::new);
+
+/// (type, interval, interval number) identifying a limit window
+type LimitKey = (RateLimitType, RateLimitInterval, i32);
+
+/// Who Binance counts a limit for: request weight is counted per IP, orders per account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum LimitScope {
+    #[default]
+    Ip,
+    Account,
+}
+
+impl LimitScope {
+    fn of(rate_limit_type: &RateLimitType) -> Option<LimitScope> {
+        match rate_limit_type {
+            RateLimitType::RequestWeight | RateLimitType::RawRequests => Some(LimitScope::Ip),
+            RateLimitType::Orders => Some(LimitScope::Account),
+            RateLimitType::Other => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WindowUsage {
+    window: i64,
+    used: i64,
+}
+
+#[derive(Debug, Default)]
+struct RateLimiterState {
+    /// Only limits of this scope are accounted for
+    scope: LimitScope,
+    /// Limits advertised by `exchangeInfo`, defaults for the API family are used until set
+    limits: Option<Vec<RateLimit>>,
+    usage: HashMap<LimitKey, WindowUsage>,
+    /// Epoch millis before which no request should be sent, after a 429 or 418
+    blocked_until_ms: i64,
+}
+
+/// Client side accounting of the request weight limits of a host and the order count limits of
+/// an account on that host
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    ip: SharedRateLimiterState,
+    account: SharedRateLimiterState,
+    mode: RateLimitMode,
+}
+
+impl RateLimiter {
+    pub fn new(mode: RateLimitMode) -> Self {
+        RateLimiter {
+            ip: RateLimiterState::shared(LimitScope::Ip),
+            account: RateLimiterState::shared(LimitScope::Account),
+            mode,
+        }
+    }
+
+    /// Returns a limiter sharing its request weight with all clients of `host` and its order
+    /// count with the clients of `api_key` on `host`, using `mode` for this handle
+    pub fn shared(host: &str, api_key: &str, mode: RateLimitMode) -> Self {
+        let ip = HOST_RATE_LIMITS
+            .entry(host.to_string())
+            .or_insert_with(|| RateLimiterState::shared(LimitScope::Ip))
+            .clone();
+        let account = ACCOUNT_RATE_LIMITS
+            .entry((host.to_string(), api_key.to_string()))
+            .or_insert_with(|| RateLimiterState::shared(LimitScope::Account))
+            .clone();
+        RateLimiter { ip, account, mode }
+    }
+
+    pub fn mode(&self) -> RateLimitMode { self.mode }
+
+    /// Replaces the limits, usually with `ExchangeInformation::rate_limits`
+    pub fn set_limits(&self, limits: &[RateLimit]) {
+        self.ip.lock().unwrap().limits = Some(limits.to_vec());
+        self.account.lock().unwrap().limits = Some(limits.to_vec());
+    }
+
+    /// Reserves the weight of a request, waiting or failing according to the mode if a limit
+    /// would be exceeded
+    pub async fn acquire(&self, method: &Method, endpoint: &str, query: &str) -> Result<()> {
+        if self.mode == RateLimitMode::Disabled || endpoint.starts_with("/sapi") {
+            return Ok(());
+        }
+        let cost = request_cost(method, endpoint, query);
+        loop {
+            let now_ms = Utc::now().timestamp_millis();
+            let wait = self.try_reserve(endpoint, cost, now_ms);
+            match wait {
+                None => return Ok(()),
+                Some((limit, retry_after)) => match self.mode {
+                    RateLimitMode::Reject => return Err(Error::RateLimitExceeded { limit, retry_after }),
+                    _ => tokio::time::sleep(retry_after).await,
+                },
+            }
+        }
+    }
+
+    /// Reserves the cost against the IP and account limits, or neither if one would be exceeded
+    fn try_reserve(&self, endpoint: &str, cost: RequestCost, now_ms: i64) -> Option<(String, Duration)> {
+        let mut ip = self.ip.lock().unwrap();
+        let mut account = self.account.lock().unwrap();
+        let ip_reservations = match ip.reservations(endpoint, cost, now_ms) {
+            Ok(reservations) => reservations,
+            Err(wait) => return Some(wait),
+        };
+        let account_reservations = match account.reservations(endpoint, cost, now_ms) {
+            Ok(reservations) => reservations,
+            Err(wait) => return Some(wait),
+        };
+        ip.commit(ip_reservations);
+        account.commit(account_reservations);
+        None
+    }
+
+    /// Replaces local estimates with the usage reported in the `X-MBX-USED-WEIGHT-*` and
+    /// `X-MBX-ORDER-COUNT-*` response headers
+    pub fn update_from_headers(&self, headers: &HeaderMap) {
+        let now_ms = Utc::now().timestamp_millis();
+        let mut ip = self.ip.lock().unwrap();
+        let mut account = self.account.lock().unwrap();
+        for (name, value) in headers.iter() {
+            let name = name.as_str();
+            let (rate_limit_type, interval) = if let Some(interval) = name.strip_prefix("x-mbx-used-weight-") {
+                (RateLimitType::RequestWeight, interval)
+            } else if let Some(interval) = name.strip_prefix("x-mbx-order-count-") {
+                (RateLimitType::Orders, interval)
+            } else {
+                continue;
+            };
+            let (Some((interval, interval_num)), Some(used)) = (
+                parse_interval(interval),
+                value.to_str().ok().and_then(|v| v.parse::<i64>().ok()),
+            ) else {
+                continue;
+            };
+            let window = now_ms / interval_millis(&interval, interval_num);
+            let state = if rate_limit_type == RateLimitType::Orders {
+                &mut account
+            } else {
+                &mut ip
+            };
+            state
+                .usage
+                .insert((rate_limit_type, interval, interval_num), WindowUsage { window, used });
+        }
+    }
+
+    /// Blocks all requests to the host for the `Retry-After` duration of a 429 or 418 response
+    pub fn block_for(&self, retry_after: Duration) {
+        let until_ms = Utc::now().timestamp_millis() + retry_after.as_millis() as i64;
+        let mut state = self.ip.lock().unwrap();
+        state.blocked_until_ms = state.blocked_until_ms.max(until_ms);
+    }
+}
+
+impl RateLimiterState {
+    fn shared(scope: LimitScope) -> SharedRateLimiterState {
+        Arc::new(Mutex::new(RateLimiterState {
+            scope,
+            ..Default::default()
+        }))
+    }
+
+    /// Usage of each limit of the scope once the cost is added, or the exceeded limit and the
+    /// time until its window resets
+    fn reservations(
+        &self,
+        endpoint: &str,
+        cost: RequestCost,
+        now_ms: i64,
+    ) -> std::result::Result<Vec<(LimitKey, WindowUsage)>, (String, Duration)> {
+        if self.blocked_until_ms > now_ms {
+            return Err((
+                "Retry-After".to_string(),
+                Duration::from_millis((self.blocked_until_ms - now_ms) as u64),
+            ));
+        }
+        let default_limits;
+        let limits = match self.limits {
+            Some(ref limits) => limits,
+            None => {
+                default_limits = self::default_limits(endpoint);
+                &default_limits
+            }
+        };
+        let mut reservations = vec![];
+        for limit in limits.iter() {
+            if LimitScope::of(&limit.rate_limit_type) != Some(self.scope) {
+                continue;
+            }
+            let amount = match limit.rate_limit_type {
+                RateLimitType::RequestWeight => cost.weight,
+                RateLimitType::Orders => cost.orders,
+                RateLimitType::RawRequests => 1,
+                RateLimitType::Other => continue,
+            };
+            if amount == 0 {
+                continue;
+            }
+            let window_ms = interval_millis(&limit.interval, limit.interval_num);
+            let window = now_ms / window_ms;
+            let key = (limit.rate_limit_type.clone(), limit.interval.clone(), limit.interval_num);
+            let used = match self.usage.get(&key) {
+                Some(usage) if usage.window == window => usage.used,
+                _ => 0,
+            };
+            // A request heavier than the limit itself can only go through on an empty window
+            if used > 0 && used + amount > limit.limit as i64 {
+                let retry_after = Duration::from_millis(((window + 1) * window_ms - now_ms) as u64);
+                return Err((
+                    format!("{:?} {}{:?}", limit.rate_limit_type, limit.interval_num, limit.interval),
+                    retry_after,
+                ));
+            }
+            reservations.push((key, WindowUsage {
+                window,
+                used: used + amount,
+            }));
+        }
+        Ok(reservations)
+    }
+
+    fn commit(&mut self, reservations: Vec<(LimitKey, WindowUsage)>) {
+        for (key, usage) in reservations {
+            self.usage.insert(key, usage);
+        }
+    }
+}
+
+/// Request weight and order count of a single call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestCost {
+    pub weight: i64,
+    pub orders: i64,
+}
+
+/// Returns the weight of an endpoint as documented by Binance, 1 for unknown endpoints
+///
+/// SAPI endpoints are limited separately from the API weight and are not accounted for.
+pub fn request_cost(method: &Method, endpoint: &str, query: &str) -> RequestCost {
+    let has_symbol = query.split('&').any(|param| param.starts_with("symbol="));
+    let limit = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("limit="))
+        .and_then(|limit| limit.parse::<u32>().ok());
+    let weight = |weight: i64| RequestCost { weight, orders: 0 };
+    let order = |weight: i64| RequestCost { weight, orders: 1 };
+    match (method.clone(), endpoint) {
+        (_, e) if e.starts_with("/sapi") => weight(0),
+        (_, "/api/v3/exchangeInfo") => weight(20),
+        (_, "/api/v3/depth") => weight(match limit.unwrap_or(100) {
+            0..=100 => 5,
+            101..=500 => 25,
+            501..=1000 => 50,
+            _ => 250,
+        }),
+        (_, "/api/v3/aggTrades") => weight(4),
+        (_, "/api/v3/klines" | "/api/v3/avgPrice") => weight(2),
+        (_, "/api/v3/ticker/24hr") => weight(if has_symbol { 2 } else { 80 }),
+        (_, "/api/v3/ticker/price" | "/api/v3/ticker/bookTicker") => weight(if has_symbol { 2 } else { 4 }),
+        (Method::POST, "/api/v3/order" | "/api/v3/order/cancelReplace") => order(1),
+        (Method::GET, "/api/v3/order") => weight(4),
+        (_, "/api/v3/openOrders") => weight(if has_symbol { 6 } else { 80 }),
+        (_, "/api/v3/allOrders" | "/api/v3/account" | "/api/v3/myTrades") => weight(20),
+        (_, "/api/v3/userDataStream") => weight(2),
+        (_, "/fapi/v1/depth") => weight(match limit.unwrap_or(500) {
+            0..=50 => 2,
+            51..=100 => 5,
+            101..=500 => 10,
+            _ => 20,
+        }),
+        (
+            _,
+            "/fapi/v1/klines" | "/fapi/v1/continuousKlines" | "/fapi/v1/indexPriceKlines" | "/fapi/v1/markPriceKlines",
+        ) => weight(match limit.unwrap_or(500) {
+            0..=99 => 1,
+            100..=499 => 2,
+            500..=1000 => 5,
+            _ => 10,
+        }),
+        (_, "/fapi/v1/trades") => weight(5),
+        (_, "/fapi/v1/historicalTrades" | "/fapi/v1/aggTrades") => weight(20),
+        (_, "/fapi/v1/ticker/24hr") => weight(if has_symbol { 1 } else { 40 }),
+        (_, "/fapi/v2/ticker/price") => weight(if has_symbol { 1 } else { 2 }),
+        (_, "/fapi/v1/ticker/bookTicker") => weight(if has_symbol { 2 } else { 5 }),
+        (_, "/fapi/v1/premiumIndex") => weight(if has_symbol { 1 } else { 10 }),
+        (_, "/fapi/v1/allForceOrders") => weight(if has_symbol { 20 } else { 50 }),
+        (Method::POST, "/fapi/v1/order") => order(0),
+        (_, "/fapi/v1/openOrders") => weight(if has_symbol { 1 } else { 40 }),
+        (
+            _,
+            "/fapi/v1/allOrders"
+            | "/fapi/v1/userTrades"
+            | "/fapi/v2/account"
+            | "/fapi/v3/account"
+            | "/fapi/v2/balance"
+            | "/fapi/v2/positionRisk"
+            | "/fapi/v3/positionRisk",
+        ) => weight(5),
+        _ => weight(1),
+    }
+}
+
+/// Documented limits, used until `exchangeInfo` has been loaded
+fn default_limits(endpoint: &str) -> Vec<RateLimit> {
+    let limit = |rate_limit_type, interval, interval_num, limit| RateLimit {
+        interval,
+        rate_limit_type,
+        interval_num,
+        limit,
+    };
+    if endpoint.starts_with("/fapi") {
+        vec![
+            limit(RateLimitType::RequestWeight, RateLimitInterval::Minute, 1, 2400),
+            limit(RateLimitType::Orders, RateLimitInterval::Minute, 1, 1200),
+            limit(RateLimitType::Orders, RateLimitInterval::Second, 10, 300),
+        ]
+    } else {
+        vec![
+            limit(RateLimitType::RequestWeight, RateLimitInterval::Minute, 1, 6000),
+            limit(RateLimitType::Orders, RateLimitInterval::Second, 10, 100),
+            limit(RateLimitType::Orders, RateLimitInterval::Day, 1, 200000),
+            limit(RateLimitType::RawRequests, RateLimitInterval::Minute, 5, 61000),
+        ]
+    }
+}
+
+fn interval_millis(interval: &RateLimitInterval, interval_num: i32) -> i64 {
+    let unit_ms = match interval {
+        RateLimitInterval::Second => 1_000,
+        RateLimitInterval::Minute => 60_000,
+        RateLimitInterval::Day => 86_400_000,
+    };
+    unit_ms * interval_num.max(1) as i64
+}
+
+/// Parses the interval suffix of a rate limit header, e.g. `1m` or `10s`
+fn parse_interval(suffix: &str) -> Option<(RateLimitInterval, i32)> {
+    let (num, unit) = suffix.split_at(suffix.len().checked_sub(1)?);
+    let interval = match unit {
+        "s" => RateLimitInterval::Second,
+        "m" => RateLimitInterval::Minute,
+        "d" => RateLimitInterval::Day,
+        _ => return None,
+    };
+    Some((interval, num.parse().ok()?))
+}
+
+/// Reads the `Retry-After` header, which Binance sends in seconds
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn depth_weight_depends_on_limit() {
+        assert_eq!(request_cost(&Method::GET, "/api/v3/depth", "symbol=BTCUSDT").weight, 5);
+        assert_eq!(request_cost(&Method::GET, "/api/v3/depth", "symbol=BTCUSDT&limit=5000").weight, 250);
+        assert_eq!(request_cost(&Method::GET, "/fapi/v1/depth", "symbol=BTCUSDT").weight, 10);
+        assert_eq!(request_cost(&Method::POST, "/fapi/v1/order", "symbol=BTCUSDT"), RequestCost {
+            weight: 0,
+            orders: 1
+        });
+    }
+
+    #[test]
+    fn requests_over_the_limit_wait_for_the_next_window() {
+        let limiter = RateLimiter::new(RateLimitMode::Delay);
+        limiter.set_limits(&[RateLimit {
+            interval: RateLimitInterval::Minute,
+            rate_limit_type: RateLimitType::RequestWeight,
+            interval_num: 1,
+            limit: 100,
+        }]);
+        let cost = request_cost(&Method::GET, "/api/v3/depth", "symbol=BTCUSDT&limit=1000");
+        let now_ms = 60_000 * 1000 + 15_000;
+        assert!(limiter.try_reserve("/api/v3/depth", cost, now_ms).is_none());
+        assert!(limiter.try_reserve("/api/v3/depth", cost, now_ms).is_none());
+        let (_, retry_after) = limiter.try_reserve("/api/v3/depth", cost, now_ms).unwrap();
+        assert_eq!(retry_after, Duration::from_secs(45));
+        // The next minute starts from scratch
+        assert!(limiter.try_reserve("/api/v3/depth", cost, now_ms + 45_000).is_none());
+    }
+
+    #[test]
+    fn headers_override_local_usage() {
+        let limiter = RateLimiter::new(RateLimitMode::Reject);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("2399"));
+        headers.insert("x-mbx-order-count-10s", HeaderValue::from_static("3"));
+        limiter.update_from_headers(&headers);
+        assert_eq!(
+            limiter
+                .ip
+                .lock()
+                .unwrap()
+                .usage
+                .get(&(RateLimitType::RequestWeight, RateLimitInterval::Minute, 1))
+                .unwrap()
+                .used,
+            2399
+        );
+        assert_eq!(
+            limiter
+                .account
+                .lock()
+                .unwrap()
+                .usage
+                .get(&(RateLimitType::Orders, RateLimitInterval::Second, 10))
+                .unwrap()
+                .used,
+            3
+        );
+    }
+
+    #[test]
+    fn orders_are_counted_per_api_key_and_weight_per_host() {
+        let host = "https://orders-per-api-key.test";
+        let first = RateLimiter::shared(host, "first-key", RateLimitMode::Reject);
+        let second = RateLimiter::shared(host, "second-key", RateLimitMode::Reject);
+        first.set_limits(&[
+            RateLimit {
+                interval: RateLimitInterval::Minute,
+                rate_limit_type: RateLimitType::RequestWeight,
+                interval_num: 1,
+                limit: 1000,
+            },
+            RateLimit {
+                interval: RateLimitInterval::Second,
+                rate_limit_type: RateLimitType::Orders,
+                interval_num: 10,
+                limit: 1,
+            },
+        ]);
+        let cost = request_cost(&Method::POST, "/api/v3/order", "symbol=BTCUSDT");
+        let now_ms = 60_000 * 1000;
+        assert!(first.try_reserve("/api/v3/order", cost, now_ms).is_none());
+        // The order limit of another key is untouched
+        assert!(second.try_reserve("/api/v3/order", cost, now_ms).is_none());
+        let (limit, _) = first.try_reserve("/api/v3/order", cost, now_ms).unwrap();
+        assert_eq!(limit, "Orders 10Second");
+        // Both keys spent the weight of the host, the rejected order did not
+        let weight_key = (RateLimitType::RequestWeight, RateLimitInterval::Minute, 1);
+        assert_eq!(second.ip.lock().unwrap().usage.get(&weight_key).unwrap().used, 2);
+    }
+}
@@ -266,12 +266,20 @@ pub struct Bids {
     pub qty: Decimal,
 }
 
+impl Bids {
+    pub fn new(price: Decimal, qty: Decimal) -> Bids { Bids { price, qty } }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Asks {
     pub price: Decimal,
     pub qty: Decimal,
 }
 
+impl Asks {
+    pub fn new(price: Decimal, qty: Decimal) -> Asks { Asks { price, qty } }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct UserDataStream {
@@ -550,6 +558,9 @@ pub struct MarginOrder {
     pub is_isolated: Option<String>,
     /// Default is `SideEffectType::NoSideEffect`
     pub side_effect_type: SideEffectType,
+    /// Grid or strategy the order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing, default)]
+    pub grid_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -623,6 +634,9 @@ pub struct MarginOCOOrder {
     pub new_order_resp_type: Option<OrderResponse>,
     /// Default is `SideEffectType::NoSideEffect`
     pub side_effect_type: Option<SideEffectType>,
+    /// Grid or strategy the order belongs to, used to scope `PerGrid` rules. Never sent to Binance
+    #[serde(skip_serializing, default)]
+    pub grid_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -1298,7 +1312,7 @@ pub enum ContingencyType {
 ///   "limit": 1200
 /// }
 ///
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RateLimitType {
     RequestWeight,
@@ -1309,7 +1323,7 @@ pub enum RateLimitType {
 }
 
 /// Rate Limit Interval, used by RateLimitType
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RateLimitInterval {
     Second,
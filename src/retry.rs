@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::errors::*;
+
+/// How transient failures of idempotent requests are retried
+///
+/// GET requests are retried automatically when the client has a policy. Orders are only retried
+/// when they carry a client order id, so a lost response can be reconciled with `get_order`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after each attempt
+    pub backoff_multiplier: u32,
+    /// Randomizes the second half of each delay so that clients do not retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            backoff_multiplier: 2,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Server errors, unavailability and timeouts, where the request may succeed if sent again
+    pub fn is_retryable(error: &Error) -> bool {
+        match error {
+            Error::InternalServerError | Error::ServiceUnavailable => true,
+            Error::BinanceError { response } => response.error_code().is_retryable(),
+            Error::ReqError(error) => error.is_timeout() || error.is_connect(),
+            // Replayed transport errors, see `TransportError`
+            Error::IoError(error) => matches!(error.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionRefused),
+            _ => false,
+        }
+    }
+
+    /// Whether another attempt should follow `attempt` (starting at 1) failing with `error`
+    pub fn should_retry(&self, error: &Error, attempt: u32) -> bool {
+        attempt < self.max_attempts && Self::is_retryable(error)
+    }
+
+    /// Delay to wait after `attempt` (starting at 1) failed
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.backoff_multiplier.max(1).saturating_pow(attempt.saturating_sub(1));
+        let backoff = self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+        if self.jitter {
+            let half = backoff / 2;
+            half + half.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            backoff
+        }
+    }
+
+    /// Runs `request` until it succeeds, fails with a non retryable error or runs out of attempts
+    /// The closure receives the attempt number, starting at 1
+    pub async fn run<T, F, Fut>(&self, mut request: F) -> Result<T>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request(attempt).await {
+                Err(error) if self.should_retry(&error, attempt) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            backoff_multiplier: 2,
+            jitter: false,
+        };
+        let backoffs: Vec<u128> = (1..=6).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+        assert_eq!(backoffs, vec![100, 200, 400, 800, 1000, 1000]);
+
+        let policy = RetryPolicy { jitter: true, ..policy };
+        for attempt in 1..=6 {
+            let backoff = policy.backoff(attempt);
+            let expected = Duration::from_millis(backoffs[attempt as usize - 1] as u64);
+            assert!(backoff >= expected / 2 && backoff <= expected, "{backoff:?}");
+        }
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(&Error::ServiceUnavailable, 1));
+        assert!(policy.should_retry(&Error::InternalServerError, 2));
+        assert!(!policy.should_retry(&Error::InternalServerError, 3));
+        assert!(!policy.should_retry(&Error::InvalidPrice, 1));
+        assert!(!policy.should_retry(&Error::TooManyRequests { retry_after: None }, 1));
+    }
+}
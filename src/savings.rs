@@ -1,6 +1,5 @@
 use crate::client::*;
 use crate::errors::*;
-use crate::util::*;
 use std::collections::BTreeMap;
 use rust_decimal::Decimal;
 
@@ -88,9 +87,8 @@ impl Savings {
     /// assert!(coins.is_ok(), "{:?}", coins)
     /// ```
     pub async fn get_all_coins(&self) -> Result<Vec<CoinInfo>> {
-        let request = build_signed_request([("", "")], self.recv_window)?;
         self.client
-            .get_signed_d("/sapi/v1/capital/config/getall", request.as_str())
+            .get_signed_p("/sapi/v1/capital/config/getall", None::<()>, self.recv_window)
             .await
     }
 
@@ -103,15 +101,9 @@ impl Savings {
     /// assert!(coins.is_ok(), "{:?}", coins)
     /// ```
     pub async fn asset_detail(&self, asset: Option<&str>) -> Result<BTreeMap<String, AssetDetail>> {
-        let parameters = if let Some(asset) = asset {
-            [("asset", asset)]
-        } else {
-            [("", "")]
-        };
-
-        let request = build_signed_request(parameters, self.recv_window)?;
+        let parameters = asset.map(|asset| BTreeMap::from([("asset", asset)]));
         self.client
-            .get_signed_d("/sapi/v1/asset/assetDetail", request.as_str())
+            .get_signed_p("/sapi/v1/asset/assetDetail", parameters, self.recv_window)
             .await
     }
 
@@ -130,16 +122,12 @@ impl Savings {
     where
         S: AsRef<str>,
     {
-        let request = if let Some(network) = network {
-            let parameters = [("network", network), ("coin", coin.as_ref())];
-            build_signed_request(parameters, self.recv_window)?
-        } else {
-            let parameters = [("coin", coin.as_ref())];
-            build_signed_request(parameters, self.recv_window)?
-        };
-
+        let mut parameters = BTreeMap::from([("coin", coin.as_ref())]);
+        if let Some(network) = network {
+            parameters.insert("network", network);
+        }
         self.client
-            .get_signed_d("/sapi/v1/capital/deposit/address", request.as_str())
+            .get_signed_p("/sapi/v1/capital/deposit/address", Some(parameters), self.recv_window)
             .await
     }
 }
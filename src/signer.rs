@@ -0,0 +1,202 @@
+use std::fmt;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hex::encode as hex_encode;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, RsaKeyPair, RSA_PKCS1_SHA256};
+use ring::hmac;
+use url::form_urlencoded::byte_serialize;
+
+use crate::errors::*;
+
+/// The kind of API key used to sign requests, see
+/// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/endpoint-security-type>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum SignatureType {
+    /// Shared secret, signature is the hex encoded HMAC-SHA256 of the payload
+    #[default]
+    Hmac,
+    /// Ed25519 private key in PKCS#8 PEM format
+    Ed25519,
+    /// RSA private key in PKCS#8 or PKCS#1 PEM format, signed with PKCS#1 v1.5 and SHA-256
+    Rsa,
+}
+
+/// Signs the query string of `SIGNED` endpoints
+#[derive(Clone)]
+pub enum RequestSigner {
+    Hmac(hmac::Key),
+    Ed25519(Arc<Ed25519KeyPair>),
+    Rsa(Arc<RsaKeyPair>),
+}
+
+impl RequestSigner {
+    /// Builds a signer of the given type from the secret key, which is the raw secret for HMAC
+    /// and a PEM encoded private key otherwise
+    pub fn new(signature_type: SignatureType, secret_key: &str) -> Result<Self> {
+        match signature_type {
+            SignatureType::Hmac => Ok(Self::hmac(secret_key)),
+            SignatureType::Ed25519 => Self::ed25519_from_pem(secret_key),
+            SignatureType::Rsa => Self::rsa_from_pem(secret_key),
+        }
+    }
+
+    pub fn hmac(secret_key: &str) -> Self { Self::Hmac(hmac::Key::new(hmac::HMAC_SHA256, secret_key.as_bytes())) }
+
+    pub fn ed25519_from_pem(pem: &str) -> Result<Self> {
+        let der = decode_pem(pem, &["PRIVATE KEY"])?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|e| Error::InvalidSigningKey(format!("failed to parse Ed25519 PKCS#8 key: {e}")))?;
+        Ok(Self::Ed25519(Arc::new(key_pair)))
+    }
+
+    pub fn rsa_from_pem(pem: &str) -> Result<Self> {
+        let key_pair = if pem.contains("BEGIN RSA PRIVATE KEY") {
+            let der = decode_pem(pem, &["RSA PRIVATE KEY"])?;
+            RsaKeyPair::from_der(&der)
+        } else {
+            let der = decode_pem(pem, &["PRIVATE KEY"])?;
+            RsaKeyPair::from_pkcs8(&der)
+        }
+        .map_err(|e| Error::InvalidSigningKey(format!("failed to parse RSA key: {e}")))?;
+        Ok(Self::Rsa(Arc::new(key_pair)))
+    }
+
+    pub fn signature_type(&self) -> SignatureType {
+        match self {
+            RequestSigner::Hmac(_) => SignatureType::Hmac,
+            RequestSigner::Ed25519(_) => SignatureType::Ed25519,
+            RequestSigner::Rsa(_) => SignatureType::Rsa,
+        }
+    }
+
+    /// Returns the raw signature of `payload`, hex encoded for HMAC and base64 encoded otherwise
+    pub fn sign(&self, payload: &str) -> Result<String> {
+        match self {
+            RequestSigner::Hmac(key) => Ok(hex_encode(hmac::sign(key, payload.as_bytes()).as_ref())),
+            RequestSigner::Ed25519(key_pair) => Ok(BASE64.encode(key_pair.sign(payload.as_bytes()).as_ref())),
+            RequestSigner::Rsa(key_pair) => {
+                let mut signature = vec![0; key_pair.public().modulus_len()];
+                key_pair
+                    .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), payload.as_bytes(), &mut signature)
+                    .map_err(|e| Error::InvalidSigningKey(format!("failed to sign with RSA key: {e}")))?;
+                Ok(BASE64.encode(signature))
+            }
+        }
+    }
+
+    /// Returns the signature of `payload` ready to be used as the `signature` query parameter
+    pub fn sign_url_encoded(&self, payload: &str) -> Result<String> {
+        let signature = self.sign(payload)?;
+        Ok(byte_serialize(signature.as_bytes()).collect())
+    }
+}
+
+impl fmt::Debug for RequestSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print key material
+        f.debug_tuple("RequestSigner").field(&self.signature_type()).finish()
+    }
+}
+
+fn decode_pem(pem: &str, labels: &[&str]) -> Result<Vec<u8>> {
+    let pem = pem.trim();
+    let label = labels
+        .iter()
+        .find(|label| pem.starts_with(&format!("-----BEGIN {label}-----")))
+        .ok_or_else(|| Error::InvalidSigningKey(format!("expected a PEM block labelled one of {labels:?}")))?;
+    let footer = format!("-----END {label}-----");
+    if !pem.ends_with(&footer) {
+        return Err(Error::InvalidSigningKey(format!("missing PEM footer {footer}")));
+    }
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .flat_map(|line| line.trim().chars())
+        .collect();
+    BASE64
+        .decode(body)
+        .map_err(|e| Error::InvalidSigningKey(format!("invalid base64 in PEM body: {e}")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use ring::signature::{KeyPair, UnparsedPublicKey, ED25519, RSA_PKCS1_2048_8192_SHA256};
+
+    use super::*;
+
+    const QUERY: &str = "symbol=BTCUSDT&side=SELL&type=LIMIT&timeInForce=GTC&quantity=1&price=0.2&timestamp=1668481559918";
+
+    fn read_test_key(name: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_data");
+        d.push(name);
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn hmac_signature() {
+        // Example from the Binance API documentation
+        let signer = RequestSigner::hmac("NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j");
+        let signature = signer
+            .sign("symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559")
+            .unwrap();
+        assert_eq!(signature, "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71");
+    }
+
+    #[test]
+    fn ed25519_signature() {
+        // RFC 8032 test vector 1
+        let signer = RequestSigner::ed25519_from_pem(&read_test_key("ed25519_test_key.pem")).unwrap();
+        let empty = BASE64.decode(signer.sign("").unwrap()).unwrap();
+        assert_eq!(
+            hex_encode(empty),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        assert_eq!(
+            signer.sign(QUERY).unwrap(),
+            "y9aW/+h7Ht5RYUgJlwCrziJWVCQEAVswbfIWMUW+f+Qh0+7YURsnrZrwHST7Y8ZGRlCC4fmkbiGNSmN27XYgBw=="
+        );
+        assert_eq!(
+            signer.sign_url_encoded(QUERY).unwrap(),
+            "y9aW%2F%2Bh7Ht5RYUgJlwCrziJWVCQEAVswbfIWMUW%2Bf%2BQh0%2B7YURsnrZrwHST7Y8ZGRlCC4fmkbiGNSmN27XYgBw%3D%3D"
+        );
+        let RequestSigner::Ed25519(key_pair) = &signer else {
+            panic!("expected an Ed25519 signer")
+        };
+        let signature = BASE64.decode(signer.sign(QUERY).unwrap()).unwrap();
+        UnparsedPublicKey::new(&ED25519, key_pair.public_key().as_ref())
+            .verify(QUERY.as_bytes(), &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn rsa_signature() {
+        // Expected value produced with `openssl dgst -sha256 -sign test_data/rsa_test_key.pem | base64`
+        let signer = RequestSigner::new(SignatureType::Rsa, &read_test_key("rsa_test_key.pem")).unwrap();
+        let signature = signer.sign(QUERY).unwrap();
+        assert_eq!(signature, "VRKya1ucYQKbQ3RD0SPLVf3UrdDclosY50QvynPyajNupQyeI8/BdrbjeweCcE2jFh8q9OtqzkSXCtb0r/A1GOFezqAAiqHmhCRDJ5JaiqewT9+a9tLtY1dAUJwiVM6mYY5dHXIH642hiFcySDCmWYWIDn4cBK7NaG3CfIybo1bbUg8h7LTYCcOyc37EowjQ2u7fLskqSiCeJI4Rgl4cmpRYTTVcKeAxVXT1UVJigvX79qhMLbzbaGbaMCjr0HGzdL0fDO3ESu/7qHolXlc6tB65gYcgj/zbPtYSmladQCbSOdfDKRT4p1BLtktokOJ9+GWLWQ+PPE3fzJkTbOq3Ug==");
+        let RequestSigner::Rsa(key_pair) = &signer else {
+            panic!("expected an RSA signer")
+        };
+        UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public().as_ref())
+            .verify(QUERY.as_bytes(), &BASE64.decode(signature).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(matches!(
+            RequestSigner::new(SignatureType::Ed25519, "not a pem"),
+            Err(Error::InvalidSigningKey(_))
+        ));
+        assert!(matches!(
+            RequestSigner::new(SignatureType::Rsa, &read_test_key("ed25519_test_key.pem")),
+            Err(Error::InvalidSigningKey(_))
+        ));
+    }
+}
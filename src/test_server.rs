@@ -0,0 +1,635 @@
+//! Local stand-in for the Binance REST and stream endpoints, to run integration tests offline
+//!
+//! The server speaks the subset of HTTP/1.1 and of the stream protocol this crate uses. It serves
+//! canned fixtures, checks the API key, the HMAC, Ed25519 or RSA signature and the timestamp of
+//! signed requests the way Binance does, and answers with scripted responses such as error payloads.
+//!
+//! ```no_run
+//! use binance_fork::api::Binance;
+//! use binance_fork::general::General;
+//! use binance_fork::test_server::TestServer;
+//!
+//! # async fn run() -> binance_fork::errors::Result<()> {
+//! let server = TestServer::start().await?;
+//! let general = General::new_with_config(None, None, &server.config());
+//! let exchange_info = general.exchange_info().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::future::{self, Either};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use ring::hmac;
+use ring::signature::{KeyPair, UnparsedPublicKey, ED25519, RSA_PKCS1_2048_8192_SHA256};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::clock::{global_clock, Clock};
+use crate::config::Config;
+use crate::errors::{BinanceErrorCode, Result};
+use crate::signer::{RequestSigner, SignatureType};
+
+/// API key the server accepts unless other credentials are set
+pub const TEST_API_KEY: &str = "test-api-key";
+/// Secret key the server checks signatures with unless other credentials are set
+pub const TEST_SECRET_KEY: &str = "test-secret-key";
+
+const API_KEY_HEADER: &str = "x-mbx-apikey";
+const DEFAULT_RECV_WINDOW: u64 = 5000;
+const MAX_RECV_WINDOW: u64 = 60000;
+/// Binance rejects requests stamped more than a second ahead of its clock
+const MAX_TIMESTAMP_AHEAD: u64 = 1000;
+const STREAM_CHANNEL_CAPACITY: usize = 1024;
+
+const EXCHANGE_INFO: &str = include_str!("../test_data/exchangeInfo.json");
+
+/// Response of the server to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResponse {
+    pub status: u16,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl TestResponse {
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+            headers: vec![],
+        }
+    }
+
+    /// 200 with a JSON body
+    pub fn ok(body: impl Into<String>) -> Self { Self::new(200, body) }
+
+    /// Error payload of Binance, e.g. 400 with `{"code":-2010,"msg":"..."}`
+    pub fn binance_error(status: u16, code: BinanceErrorCode, msg: &str) -> Self {
+        Self::new(status, json!({ "code": code.code(), "msg": msg }).to_string())
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds the `Retry-After` header sent along 429 and 418 responses
+    pub fn with_retry_after(self, seconds: u64) -> Self { self.with_header("Retry-After", seconds.to_string()) }
+}
+
+/// Request received by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Query string, without the leading `?`
+    pub query: String,
+    pub body: String,
+    /// Value of the `X-MBX-APIKEY` header, if sent
+    pub api_key: Option<String>,
+}
+
+impl RecordedRequest {
+    /// Value of the query or body parameter `name`, URL decoded
+    pub fn get_param(&self, name: &str) -> Option<String> {
+        parse_params(&self.query)
+            .chain(parse_params(&self.body))
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    fn is_signed(&self) -> bool { self.get_param("signature").is_some() || self.get_param("timestamp").is_some() }
+
+    /// Parameters the signature covers, the query followed by the body without the signature
+    fn get_signed_payload(&self) -> String {
+        let without_signature = |params: &str| {
+            params
+                .split('&')
+                .filter(|param| !param.is_empty() && !param.starts_with("signature="))
+                .collect::<Vec<_>>()
+                .join("&")
+        };
+        format!("{}{}", without_signature(&self.query), without_signature(&self.body))
+    }
+}
+
+fn parse_params(params: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    url::form_urlencoded::parse(params.as_bytes()).map(|(key, value)| (key.into_owned(), value.into_owned()))
+}
+
+type Route = (String, String);
+
+struct TestServerState {
+    api_key: String,
+    signer: RequestSigner,
+    clock: Option<Arc<dyn Clock>>,
+    /// Responses served in order before falling back to `responses`
+    scripted: HashMap<Route, VecDeque<TestResponse>>,
+    responses: HashMap<Route, TestResponse>,
+    requests: Vec<RecordedRequest>,
+    /// Paths of the stream connections, e.g. `/ws/btcusdt@aggTrade`
+    stream_paths: Vec<String>,
+    /// Messages sent to every stream connection once connected
+    stream_messages: Vec<String>,
+}
+
+impl TestServerState {
+    fn new() -> Self {
+        let mut responses = HashMap::new();
+        for path in ["/api/v3/ping", "/fapi/v1/ping"] {
+            responses.insert(route("GET", path), TestResponse::ok("{}"));
+        }
+        responses.insert(route("GET", "/api/v3/exchangeInfo"), TestResponse::ok(EXCHANGE_INFO));
+        Self {
+            api_key: TEST_API_KEY.to_string(),
+            signer: RequestSigner::hmac(TEST_SECRET_KEY),
+            clock: None,
+            scripted: HashMap::new(),
+            responses,
+            requests: vec![],
+            stream_paths: vec![],
+            stream_messages: vec![],
+        }
+    }
+
+    fn now_millis(&self) -> u64 {
+        match self.clock {
+            Some(ref clock) => clock.now_millis(),
+            None => global_clock().now_millis(),
+        }
+    }
+
+    fn respond(&mut self, request: RecordedRequest) -> TestResponse {
+        self.requests.push(request.clone());
+        if request.is_signed() {
+            if let Err(rejection) = self.check_signed(&request) {
+                return rejection;
+            }
+        }
+        let route = route(&request.method, &request.path);
+        if let Some(response) = self.scripted.get_mut(&route).and_then(|responses| responses.pop_front()) {
+            return response;
+        }
+        if let Some(response) = self.responses.get(&route) {
+            return response.clone();
+        }
+        match request.path.as_str() {
+            "/api/v3/time" | "/fapi/v1/time" => TestResponse::ok(json!({ "serverTime": self.now_millis() }).to_string()),
+            _ => TestResponse::new(404, ""),
+        }
+    }
+
+    /// Rejects the request the way Binance does if its API key, timestamp or signature is invalid
+    fn check_signed(&self, request: &RecordedRequest) -> std::result::Result<(), TestResponse> {
+        match request.api_key.as_deref() {
+            None | Some("") => return Err(TestResponse::binance_error(401, BinanceErrorCode::BadApiKeyFormat, "API-key format invalid.")),
+            Some(api_key) if api_key != self.api_key => {
+                return Err(TestResponse::binance_error(401, BinanceErrorCode::RejectedApiKey, "Invalid API-key, IP, or permissions for action."));
+            }
+            Some(_) => {}
+        }
+        let recv_window = match request.get_param("recvWindow") {
+            Some(recv_window) => recv_window.parse::<u64>().map_err(|_| mandatory_parameter_error("recvWindow"))?,
+            None => DEFAULT_RECV_WINDOW,
+        };
+        if recv_window > MAX_RECV_WINDOW {
+            return Err(TestResponse::binance_error(400, BinanceErrorCode::BadRecvWindow, "recvWindow must be less than 60000"));
+        }
+        let timestamp = request
+            .get_param("timestamp")
+            .and_then(|timestamp| timestamp.parse::<u64>().ok())
+            .ok_or_else(|| mandatory_parameter_error("timestamp"))?;
+        let now = self.now_millis();
+        if timestamp > now + MAX_TIMESTAMP_AHEAD {
+            return Err(TestResponse::binance_error(400, BinanceErrorCode::InvalidTimestamp, "Timestamp for this request was 1000ms ahead of the server's time."));
+        }
+        if now.saturating_sub(timestamp) > recv_window {
+            return Err(TestResponse::binance_error(400, BinanceErrorCode::InvalidTimestamp, "Timestamp for this request is outside of the recvWindow."));
+        }
+        let signature = request.get_param("signature").ok_or_else(|| mandatory_parameter_error("signature"))?;
+        if !verify_signature(&self.signer, request.get_signed_payload().as_bytes(), &signature) {
+            return Err(TestResponse::binance_error(400, BinanceErrorCode::InvalidSignature, "Signature for this request is not valid."));
+        }
+        Ok(())
+    }
+}
+
+/// Checks `signature` the way Binance does, against the public key of asymmetric keys
+fn verify_signature(signer: &RequestSigner, payload: &[u8], signature: &str) -> bool {
+    match signer {
+        RequestSigner::Hmac(key) => hex::decode(signature).is_ok_and(|signature| hmac::verify(key, payload, &signature).is_ok()),
+        RequestSigner::Ed25519(key_pair) => BASE64
+            .decode(signature)
+            .is_ok_and(|signature| UnparsedPublicKey::new(&ED25519, key_pair.public_key().as_ref()).verify(payload, &signature).is_ok()),
+        RequestSigner::Rsa(key_pair) => BASE64.decode(signature).is_ok_and(|signature| {
+            UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public().as_ref())
+                .verify(payload, &signature)
+                .is_ok()
+        }),
+    }
+}
+
+fn route(method: &str, path: &str) -> Route { (method.to_uppercase(), path.to_string()) }
+
+fn mandatory_parameter_error(name: &str) -> TestResponse {
+    TestResponse::binance_error(
+        400,
+        BinanceErrorCode::MandatoryParamEmptyOrMalformed,
+        &format!("Mandatory parameter '{name}' was not sent, was empty/null, or malformed."),
+    )
+}
+
+/// Local HTTP and WebSocket server standing in for Binance, stopped when dropped
+///
+/// Serves `GET /api/v3/ping`, `/fapi/v1/ping`, the server time and `test_data/exchangeInfo.json`
+/// as `GET /api/v3/exchangeInfo` out of the box, other routes answer 404 until mocked.
+/// Requests carrying a `timestamp` or a `signature` are signed requests and are checked against
+/// `TEST_API_KEY` and the HMAC key `TEST_SECRET_KEY` unless other credentials are set.
+pub struct TestServer {
+    http_address: SocketAddr,
+    ws_address: SocketAddr,
+    state: Arc<Mutex<TestServerState>>,
+    stream_sender: broadcast::Sender<String>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts the server on free ports of the loopback interface
+    pub async fn start() -> Result<Self> {
+        let http_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let ws_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let state = Arc::new(Mutex::new(TestServerState::new()));
+        let (stream_sender, _) = broadcast::channel(STREAM_CHANNEL_CAPACITY);
+        let server = Self {
+            http_address: http_listener.local_addr()?,
+            ws_address: ws_listener.local_addr()?,
+            tasks: vec![
+                tokio::spawn(serve_http(http_listener, state.clone())),
+                tokio::spawn(serve_streams(ws_listener, state.clone(), stream_sender.clone())),
+            ],
+            state,
+            stream_sender,
+        };
+        Ok(server)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TestServerState> { self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) }
+
+    /// Base URL of the REST endpoints, e.g. `http://127.0.0.1:41234`
+    pub fn rest_endpoint(&self) -> String { format!("http://{}", self.http_address) }
+
+    /// Base URL of the streams, e.g. `ws://127.0.0.1:41235`
+    pub fn ws_endpoint(&self) -> String { format!("ws://{}", self.ws_address) }
+
+    /// Default config with the spot and futures endpoints pointing at the server
+    pub fn config(&self) -> Config {
+        Config::default()
+            .set_rest_api_endpoint(self.rest_endpoint())
+            .set_ws_endpoint(self.ws_endpoint())
+            .set_futures_rest_api_endpoint(self.rest_endpoint())
+            .set_futures_ws_endpoint(self.ws_endpoint())
+    }
+
+    /// HMAC credentials signed requests are checked against
+    pub fn set_credentials(&self, api_key: &str, secret_key: &str) {
+        let mut state = self.lock();
+        state.api_key = api_key.to_string();
+        state.signer = RequestSigner::hmac(secret_key);
+    }
+
+    /// Credentials of the given type signed requests are checked against, `secret_key` being the
+    /// same key the client is configured with, a PEM encoded private key for Ed25519 and RSA
+    pub fn set_signing_credentials(&self, api_key: &str, signature_type: SignatureType, secret_key: &str) -> Result<()> {
+        let signer = RequestSigner::new(signature_type, secret_key)?;
+        let mut state = self.lock();
+        state.api_key = api_key.to_string();
+        state.signer = signer;
+        Ok(())
+    }
+
+    /// Checks timestamps against `clock` instead of the global clock
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) { self.lock().clock = Some(clock); }
+
+    /// Answers every `method` request to `path` with `response`, whatever its parameters
+    pub fn mock(&self, method: &str, path: &str, response: TestResponse) { self.lock().responses.insert(route(method, path), response); }
+
+    /// Answers the next `method` request to `path` with `response`, before the mocked response.
+    /// Responses scripted for the same route are served in order.
+    pub fn mock_once(&self, method: &str, path: &str, response: TestResponse) {
+        self.lock().scripted.entry(route(method, path)).or_default().push_back(response);
+    }
+
+    /// Answers every `method` request to `path` with the JSON fixture `path` of `test_data`
+    pub fn mock_fixture(&self, method: &str, path: &str, fixture: &str) -> Result<()> {
+        let fixture_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data").join(fixture);
+        self.mock(method, path, TestResponse::ok(std::fs::read_to_string(fixture_path)?));
+        Ok(())
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> { self.lock().requests.clone() }
+
+    /// Paths of the stream connections so far, e.g. `/ws/btcusdt@aggTrade` or `/stream?streams=...`
+    pub fn stream_paths(&self) -> Vec<String> { self.lock().stream_paths.clone() }
+
+    /// Sends `message` to every stream connection once connected, including later connections
+    pub fn queue_stream_message(&self, message: impl Into<String>) { self.lock().stream_messages.push(message.into()); }
+
+    /// Sends `message` to the open stream connections, returns their number
+    pub fn send_stream_message(&self, message: impl Into<String>) -> usize { self.stream_sender.send(message.into()).unwrap_or_default() }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+fn lock_state(state: &Mutex<TestServerState>) -> MutexGuard<'_, TestServerState> { state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) }
+
+async fn serve_http(listener: TcpListener, state: Arc<Mutex<TestServerState>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(error) = serve_http_connection(stream, state).await {
+                eprintln!("test server connection failed: {error:?}");
+            }
+        });
+    }
+}
+
+/// Serves the requests of a kept alive connection until the client closes it
+async fn serve_http_connection(stream: TcpStream, state: Arc<Mutex<TestServerState>>) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    while let Some(request) = read_request(&mut stream).await? {
+        let response = lock_state(&state).respond(request);
+        stream.get_mut().write_all(&encode_response(&response)).await?;
+    }
+    Ok(())
+}
+
+/// Reads the next request, `None` once the connection is closed
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Option<RecordedRequest>> {
+    let mut request_line = String::new();
+    if stream.read_line(&mut request_line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(anyhow::anyhow!("Malformed request line: {request_line:?}").into());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_length = 0;
+    let mut api_key = None;
+    loop {
+        let mut header = String::new();
+        if stream.read_line(&mut header).await? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => content_length = value.parse::<usize>().map_err(|e| anyhow::anyhow!("Malformed content length: {e}"))?,
+                API_KEY_HEADER => api_key = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+
+    Ok(Some(RecordedRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body: String::from_utf8_lossy(&body).into_owned(),
+        api_key,
+    }))
+}
+
+fn encode_response(response: &TestResponse) -> Vec<u8> {
+    let mut encoded = format!("HTTP/1.1 {} {}\r\n", response.status, reason_phrase(response.status));
+    encoded.push_str("Content-Type: application/json;charset=UTF-8\r\n");
+    encoded.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    for (name, value) in response.headers.iter() {
+        encoded.push_str(&format!("{name}: {value}\r\n"));
+    }
+    encoded.push_str("\r\n");
+    encoded.push_str(&response.body);
+    encoded.into_bytes()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        418 => "I'm a teapot",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+async fn serve_streams(listener: TcpListener, state: Arc<Mutex<TestServerState>>, stream_sender: broadcast::Sender<String>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let state = state.clone();
+        // Subscribed before the handshake so that no message sent after the connection is missed
+        let receiver = stream_sender.subscribe();
+        tokio::spawn(async move {
+            if let Err(error) = serve_stream_connection(stream, state, receiver).await {
+                eprintln!("test server stream failed: {error:?}");
+            }
+        });
+    }
+}
+
+/// Sends the queued then the broadcast messages until the client disconnects
+async fn serve_stream_connection(stream: TcpStream, state: Arc<Mutex<TestServerState>>, mut receiver: broadcast::Receiver<String>) -> Result<()> {
+    let mut path = String::new();
+    let ws = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+        path = request.uri().to_string();
+        Ok(response)
+    })
+    .await?;
+    let queued = {
+        let mut state = lock_state(&state);
+        state.stream_paths.push(path);
+        state.stream_messages.clone()
+    };
+    let (mut sink, mut incoming) = ws.split();
+    for message in queued.into_iter() {
+        sink.send(Message::Text(message)).await?;
+    }
+    loop {
+        match future::select(incoming.next(), Box::pin(receiver.recv())).await {
+            Either::Left((Some(Ok(Message::Close(_))) | Some(Err(_)) | None, _)) => return Ok(()),
+            // Pings are answered by the next write or read
+            Either::Left((Some(Ok(_)), _)) => {}
+            Either::Right((Ok(message), _)) => sink.send(Message::Text(message)).await?,
+            Either::Right((Err(broadcast::error::RecvError::Lagged(_)), _)) => {}
+            Either::Right((Err(broadcast::error::RecvError::Closed), _)) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::account::Account;
+    use crate::api::Binance;
+    use crate::clock::ManualClock;
+    use crate::errors::Error;
+    use crate::general::General;
+    use crate::rate_limit::RateLimitMode;
+
+    fn account(server: &TestServer, secret_key: &str) -> Account {
+        let config = server.config().set_rate_limit_mode(RateLimitMode::Disabled);
+        Account::new_with_config(Some(TEST_API_KEY.to_string()), Some(secret_key.to_string()), &config)
+    }
+
+    fn account_with_signature_type(server: &TestServer, signature_type: SignatureType, secret_key: &str) -> Account {
+        let config = server
+            .config()
+            .set_rate_limit_mode(RateLimitMode::Disabled)
+            .set_signature_type(signature_type);
+        Account::new_with_config(Some(TEST_API_KEY.to_string()), Some(secret_key.to_string()), &config)
+    }
+
+    fn read_test_key(name: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_data");
+        d.push(name);
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_fixtures_and_scripted_responses() {
+        let server = TestServer::start().await.unwrap();
+        let general = General::new_with_config(None, None, &server.config().set_rate_limit_mode(RateLimitMode::Disabled));
+
+        general.ping().await.unwrap();
+        let exchange_info = general.exchange_info().await.unwrap();
+        assert_eq!(exchange_info.symbols[0].symbol, "ETHBTC");
+
+        server.mock_once("GET", "/api/v3/exchangeInfo", TestResponse::new(429, "").with_retry_after(7));
+        server.mock_once("GET", "/api/v3/exchangeInfo", TestResponse::binance_error(400, BinanceErrorCode::Disconnected, "Internal error; unable to process your request. Please try again."));
+        assert!(matches!(general.exchange_info().await, Err(Error::TooManyRequests { retry_after: Some(retry_after) }) if retry_after == Duration::from_secs(7)));
+        let error = general.exchange_info().await.unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::Disconnected));
+        // Back to the fixture once the script is played
+        general.exchange_info().await.unwrap();
+
+        let paths = server.requests().into_iter().map(|request| request.path).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/api/v3/ping", "/api/v3/exchangeInfo", "/api/v3/exchangeInfo", "/api/v3/exchangeInfo", "/api/v3/exchangeInfo"]);
+    }
+
+    #[tokio::test]
+    async fn checks_signed_requests() {
+        let server = TestServer::start().await.unwrap();
+        server.mock("GET", "/api/v3/openOrders", TestResponse::ok("[]"));
+
+        assert!(account(&server, TEST_SECRET_KEY).get_open_orders("BTCUSDT").await.unwrap().is_empty());
+        let request = server.requests().pop().unwrap();
+        assert_eq!(request.api_key.as_deref(), Some(TEST_API_KEY));
+        assert_eq!(request.get_param("symbol").as_deref(), Some("BTCUSDT"));
+
+        let error = account(&server, "another-secret-key").get_open_orders("BTCUSDT").await.unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::InvalidSignature));
+
+        server.set_credentials("another-api-key", TEST_SECRET_KEY);
+        assert!(matches!(account(&server, TEST_SECRET_KEY).get_open_orders("BTCUSDT").await, Err(Error::Unauthorized)));
+        server.set_credentials(TEST_API_KEY, TEST_SECRET_KEY);
+
+        // The server clock runs a minute ahead of the requests, beyond the default recvWindow
+        server.set_clock(Arc::new(ManualClock::new((global_clock().now_millis() + 60_000) * 1_000_000)));
+        let error = account(&server, TEST_SECRET_KEY).get_open_orders("BTCUSDT").await.unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::InvalidTimestamp));
+
+        // The requests are stamped more than a second ahead of the server clock
+        server.set_clock(Arc::new(ManualClock::new((global_clock().now_millis() - 2_000) * 1_000_000)));
+        let error = account(&server, TEST_SECRET_KEY).get_open_orders("BTCUSDT").await.unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::InvalidTimestamp));
+    }
+
+    #[tokio::test]
+    async fn checks_asymmetric_signatures() {
+        let server = TestServer::start().await.unwrap();
+        server.mock("GET", "/api/v3/openOrders", TestResponse::ok("[]"));
+        let ed25519_key = read_test_key("ed25519_test_key.pem");
+        let rsa_key = read_test_key("rsa_test_key.pem");
+
+        for (signature_type, secret_key) in [(SignatureType::Ed25519, &ed25519_key), (SignatureType::Rsa, &rsa_key)] {
+            server.set_signing_credentials(TEST_API_KEY, signature_type, secret_key).unwrap();
+            let account = account_with_signature_type(&server, signature_type, secret_key);
+            assert!(account.get_open_orders("BTCUSDT").await.unwrap().is_empty());
+
+            let error = account_with_signature_type(&server, SignatureType::Hmac, TEST_SECRET_KEY)
+                .get_open_orders("BTCUSDT")
+                .await
+                .unwrap_err();
+            assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::InvalidSignature));
+        }
+
+        // An RSA signature does not verify against the Ed25519 key
+        server.set_signing_credentials(TEST_API_KEY, SignatureType::Ed25519, &ed25519_key).unwrap();
+        let error = account_with_signature_type(&server, SignatureType::Rsa, &rsa_key)
+            .get_open_orders("BTCUSDT")
+            .await
+            .unwrap_err();
+        assert_eq!(error.binance_error_code(), Some(BinanceErrorCode::InvalidSignature));
+    }
+
+    #[cfg(feature = "futures_api")]
+    #[tokio::test]
+    async fn sends_stream_messages() {
+        let server = TestServer::start().await.unwrap();
+        server.queue_stream_message(r#"{"n":1}"#);
+
+        let running = AtomicBool::new(true);
+        let mut events = vec![];
+        let mut web_socket: crate::futures::websockets::WebSockets<'_, serde_json::Value> = crate::futures::websockets::WebSockets::new_with_options(
+            |event: serde_json::Value| {
+                if event["n"] == 2 {
+                    running.store(false, Ordering::Relaxed);
+                }
+                events.push(event);
+                Ok(())
+            },
+            server.config(),
+        );
+        web_socket.connect("btcusdt@aggTrade").await.unwrap();
+        assert_eq!(server.send_stream_message(r#"{"n":2}"#), 1);
+        web_socket.event_loop(&running).await.unwrap();
+        web_socket.disconnect().await.unwrap();
+        drop(web_socket);
+
+        assert_eq!(events, vec![json!({"n": 1}), json!({"n": 2})]);
+        assert_eq!(server.stream_paths(), vec!["/ws/btcusdt@aggTrade"]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::Mutex;
+
+#[cfg(feature = "futures_api")]
+use crate::futures::general::FuturesGeneral;
+use crate::errors::*;
+use crate::general::General;
+use crate::rest_model::ServerTime;
+
+/// Server time endpoint used to measure the clock offset
+#[derive(Clone)]
+pub enum ServerTimeSource {
+    Spot(General),
+    #[cfg(feature = "futures_api")]
+    Futures(FuturesGeneral),
+}
+
+impl ServerTimeSource {
+    async fn get_server_time(&self) -> Result<ServerTime> {
+        match self {
+            ServerTimeSource::Spot(general) => general.get_server_time().await,
+            #[cfg(feature = "futures_api")]
+            ServerTimeSource::Futures(general) => general.get_server_time().await,
+        }
+    }
+}
+
+impl From<General> for ServerTimeSource {
+    fn from(general: General) -> Self { ServerTimeSource::Spot(general) }
+}
+
+#[cfg(feature = "futures_api")]
+impl From<FuturesGeneral> for ServerTimeSource {
+    fn from(general: FuturesGeneral) -> Self { ServerTimeSource::Futures(general) }
+}
+
+/// Result of a single server time measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSample {
+    /// Milliseconds to add to the local clock to get the server clock
+    pub offset_ms: i64,
+    /// Round trip of the server time request, in milliseconds
+    pub round_trip_ms: u64,
+}
+
+impl TimeSample {
+    /// Estimates the offset assuming the server read its clock halfway through the round trip
+    pub fn measure(sent_at_ms: i64, server_time_ms: i64, received_at_ms: i64) -> Self {
+        let round_trip_ms = (received_at_ms - sent_at_ms).max(0);
+        TimeSample {
+            offset_ms: server_time_ms - (sent_at_ms + round_trip_ms / 2),
+            round_trip_ms: round_trip_ms as u64,
+        }
+    }
+}
+
+struct TimeSyncState {
+    source: ServerTimeSource,
+    refresh_interval: Duration,
+    offset_ms: AtomicI64,
+    round_trip_ms: AtomicU64,
+    /// Local time of the last successful sync, 0 if never synced
+    synced_at_ms: AtomicI64,
+    sync_lock: Mutex<()>,
+}
+
+/// Keeps track of the offset between the local clock and the Binance server clock
+///
+/// Enable it with `Config::set_time_sync` to have every signed request use the server time.
+/// The offset is measured lazily and refreshed once older than the refresh interval.
+/// Clones share the same measurements.
+#[derive(Clone)]
+pub struct TimeSync {
+    state: Arc<TimeSyncState>,
+}
+
+impl TimeSync {
+    pub fn new<S: Into<ServerTimeSource>>(source: S, refresh_interval: Duration) -> Self {
+        TimeSync {
+            state: Arc::new(TimeSyncState {
+                source: source.into(),
+                refresh_interval,
+                offset_ms: AtomicI64::new(0),
+                round_trip_ms: AtomicU64::new(0),
+                synced_at_ms: AtomicI64::new(0),
+                sync_lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Measures the offset against the server now, regardless of the last sync
+    pub async fn sync(&self) -> Result<TimeSample> {
+        let _guard = self.state.sync_lock.lock().await;
+        self.sync_locked().await
+    }
+
+    async fn sync_locked(&self) -> Result<TimeSample> {
+        let sent_at_ms = Utc::now().timestamp_millis();
+        let server_time = self.state.source.get_server_time().await?;
+        let received_at_ms = Utc::now().timestamp_millis();
+        let sample = TimeSample::measure(sent_at_ms, server_time.server_time as i64, received_at_ms);
+        self.state.offset_ms.store(sample.offset_ms, Ordering::Relaxed);
+        self.state.round_trip_ms.store(sample.round_trip_ms, Ordering::Relaxed);
+        self.state.synced_at_ms.store(received_at_ms, Ordering::Relaxed);
+        Ok(sample)
+    }
+
+    fn is_stale(&self, now_ms: i64) -> bool {
+        let synced_at_ms = self.state.synced_at_ms.load(Ordering::Relaxed);
+        synced_at_ms == 0 || now_ms - synced_at_ms >= self.state.refresh_interval.as_millis() as i64
+    }
+
+    /// Returns the current server time in milliseconds, syncing first if the offset is stale
+    pub async fn timestamp(&self) -> Result<u64> {
+        if self.is_stale(Utc::now().timestamp_millis()) {
+            let _guard = self.state.sync_lock.lock().await;
+            // Another request may have synced while we waited for the lock
+            if self.is_stale(Utc::now().timestamp_millis()) {
+                self.sync_locked().await?;
+            }
+        }
+        Ok(self.corrected_timestamp())
+    }
+
+    /// Returns the local time corrected with the last measured offset, without syncing
+    pub fn corrected_timestamp(&self) -> u64 {
+        (Utc::now().timestamp_millis() + self.state.offset_ms.load(Ordering::Relaxed)) as u64
+    }
+
+    pub fn offset_ms(&self) -> i64 { self.state.offset_ms.load(Ordering::Relaxed) }
+
+    pub fn round_trip_ms(&self) -> u64 { self.state.round_trip_ms.load(Ordering::Relaxed) }
+}
+
+impl fmt::Debug for TimeSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeSync")
+            .field("refresh_interval", &self.state.refresh_interval)
+            .field("offset_ms", &self.offset_ms())
+            .field("round_trip_ms", &self.round_trip_ms())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offset_is_measured_from_the_middle_of_the_round_trip() {
+        // Local clock is 500ms behind, request took 100ms
+        let sample = TimeSample::measure(1_000, 1_550, 1_100);
+        assert_eq!(sample, TimeSample {
+            offset_ms: 500,
+            round_trip_ms: 100
+        });
+        // Local clock is ahead
+        let sample = TimeSample::measure(10_000, 9_000, 10_040);
+        assert_eq!(sample.offset_ms, -1_020);
+        assert_eq!(sample.round_trip_ms, 40);
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+
+use anyhow::anyhow;
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+use crate::clock::{global_clock, Clock, ManualClock};
+use crate::errors::{Error, Result};
+use crate::json_lines::{read_json_lines, truncate_partial_last_line};
+use crate::transport::{HttpRequest, HttpResponse, HttpTransport};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportErrorKind {
+    Timeout,
+    /// The connection could not be made or was lost
+    Connect,
+    Other,
+}
+
+/// Why a request got no response, replayed as an error retry policies treat the same way
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransportError {
+    pub kind: TransportErrorKind,
+    pub message: String,
+}
+
+impl TransportError {
+    fn from_error(error: &Error) -> Self {
+        let kind = match error {
+            Error::ReqError(error) if error.is_timeout() => TransportErrorKind::Timeout,
+            Error::ReqError(error) if error.is_connect() => TransportErrorKind::Connect,
+            Error::IoError(error) if error.kind() == io::ErrorKind::TimedOut => TransportErrorKind::Timeout,
+            Error::IoError(error) if matches!(error.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted) => TransportErrorKind::Connect,
+            _ => TransportErrorKind::Other,
+        };
+        Self {
+            kind,
+            message: error.to_string(),
+        }
+    }
+
+    fn to_error(&self) -> Error {
+        match self.kind {
+            TransportErrorKind::Timeout => io::Error::new(io::ErrorKind::TimedOut, self.message.clone()).into(),
+            TransportErrorKind::Connect => io::Error::new(io::ErrorKind::ConnectionRefused, self.message.clone()).into(),
+            TransportErrorKind::Other => Error::Msg(self.message.clone()),
+        }
+    }
+}
+
+/// A request and the response `Client` got for it, or the transport error it failed with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HttpExchange {
+    /// When the response or the error was received, in milliseconds
+    pub timestamp: u64,
+    pub method: String,
+    /// Full URL, query and signature included. Request headers, and so the API key, are left out.
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Zero when the request failed without a response
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<TransportError>,
+}
+
+impl HttpExchange {
+    fn new(timestamp: u64, request: &HttpRequest, response: &HttpResponse) -> Self {
+        Self {
+            timestamp,
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            body: request.body.clone(),
+            status: response.status.as_u16(),
+            headers: response
+                .headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            response: response.body.clone(),
+            error: None,
+        }
+    }
+
+    fn failed(timestamp: u64, request: &HttpRequest, error: &Error) -> Self {
+        Self {
+            timestamp,
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            body: request.body.clone(),
+            status: 0,
+            headers: BTreeMap::new(),
+            response: String::new(),
+            error: Some(TransportError::from_error(error)),
+        }
+    }
+
+    fn get_path(&self) -> String { Url::parse(&self.url).map(|url| url.path().to_string()).unwrap_or_default() }
+
+    fn to_response(&self) -> Result<HttpResponse> {
+        if let Some(ref error) = self.error {
+            return Err(error.to_error());
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers.iter() {
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| anyhow!("Invalid recorded header {name}: {e}"))?;
+            headers.insert(name, HeaderValue::from_str(value)?);
+        }
+        let status = StatusCode::from_u16(self.status).map_err(|e| anyhow!("Invalid recorded status {}: {e}", self.status))?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body: self.response.clone(),
+        })
+    }
+}
+
+/// Inbound stream message, pongs and binary messages are not recorded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "data")]
+pub enum FrameMessage {
+    Text(String),
+    Ping(Vec<u8>),
+    /// Close frame with its code and reason, if any
+    Close(Option<String>),
+}
+
+impl FrameMessage {
+    fn from_message(message: &Message) -> Option<Self> {
+        match message {
+            Message::Text(text) => Some(FrameMessage::Text(text.clone())),
+            Message::Ping(payload) => Some(FrameMessage::Ping(payload.clone())),
+            Message::Close(frame) => Some(FrameMessage::Close(frame.as_ref().map(|frame| format!("{} {}", frame.code, frame.reason)))),
+            Message::Pong(_) | Message::Binary(_) | Message::Frame(_) => None,
+        }
+    }
+
+    /// The close code and reason are lost on replay
+    fn to_message(&self) -> Message {
+        match self {
+            FrameMessage::Text(text) => Message::Text(text.clone()),
+            FrameMessage::Ping(payload) => Message::Ping(payload.clone()),
+            FrameMessage::Close(_) => Message::Close(None),
+        }
+    }
+}
+
+/// A message received on a stream
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamFrame {
+    /// When the message was received, in milliseconds
+    pub timestamp: u64,
+    /// Path and query of the stream URL, e.g. `/ws/btcusdt@aggTrade`, see `stream_key`
+    pub stream: String,
+    pub message: FrameMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TrafficRecord {
+    Http(HttpExchange),
+    Frame(StreamFrame),
+}
+
+impl TrafficRecord {
+    pub fn get_timestamp(&self) -> u64 {
+        match self {
+            TrafficRecord::Http(exchange) => exchange.timestamp,
+            TrafficRecord::Frame(frame) => frame.timestamp,
+        }
+    }
+}
+
+/// Identifies the stream of `url` whatever the host, so that recordings replay against any endpoint
+pub fn stream_key(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    }
+}
+
+enum RecorderCommand {
+    Append(String),
+    /// Answered once the lines sent before have been written
+    Flush(mpsc::Sender<()>),
+}
+
+/// Appends the REST exchanges and the stream messages seen by the clients and websockets it is
+/// given to, see `Client::with_recorder`, as JSON lines to a file
+///
+/// Lines are written by a background thread so that requests and streams never wait on the file
+/// system, see `flush`. Write failures are logged.
+#[derive(Debug)]
+pub struct TrafficRecorder {
+    path: PathBuf,
+    writer: mpsc::Sender<RecorderCommand>,
+    /// Clock the records are timestamped with, the global clock if `None`
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl TrafficRecorder {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let (writer, commands) = mpsc::channel();
+        let writer_path = path.clone();
+        thread::spawn(move || write_records(&writer_path, commands));
+        Self {
+            path,
+            writer,
+            clock: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn file_path(&self) -> &PathBuf { &self.path }
+
+    fn now_millis(&self) -> u64 {
+        match self.clock {
+            Some(ref clock) => clock.now_millis(),
+            None => global_clock().now_millis(),
+        }
+    }
+
+    /// Queues `record` to be appended to the file
+    pub fn record(&self, record: &TrafficRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        if self.writer.send(RecorderCommand::Append(line)).is_err() {
+            return Err(anyhow!("The writer of {} has stopped", self.path.display()).into());
+        }
+        Ok(())
+    }
+
+    /// Waits for the records sent before to be written, e.g. before loading the recording
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.writer.send(RecorderCommand::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Records the exchange, failing to record does not fail the request
+    pub fn record_exchange(&self, request: &HttpRequest, response: &HttpResponse) {
+        let record = TrafficRecord::Http(HttpExchange::new(self.now_millis(), request, response));
+        if let Err(error) = self.record(&record) {
+            eprintln!("failed to record http exchange {record:?}: {error:?}")
+        }
+    }
+
+    /// Records the transport error a request failed with, failing to record does not change the error
+    pub fn record_failure(&self, request: &HttpRequest, error: &Error) {
+        let record = TrafficRecord::Http(HttpExchange::failed(self.now_millis(), request, error));
+        if let Err(error) = self.record(&record) {
+            eprintln!("failed to record http failure {record:?}: {error:?}")
+        }
+    }
+
+    /// Records the message received on the stream of `url`, failing to record does not fail the stream
+    pub fn record_message(&self, url: &Url, message: &Message) {
+        let Some(message) = FrameMessage::from_message(message) else {
+            return;
+        };
+        let record = TrafficRecord::Frame(StreamFrame {
+            timestamp: self.now_millis(),
+            stream: stream_key(url),
+            message,
+        });
+        if let Err(error) = self.record(&record) {
+            eprintln!("failed to record stream frame {record:?}: {error:?}")
+        }
+    }
+}
+
+/// Runs until the recorder is dropped, reopening the file after a failed write
+fn write_records(path: &Path, commands: mpsc::Receiver<RecorderCommand>) {
+    let mut file: Option<File> = None;
+    for command in commands {
+        match command {
+            RecorderCommand::Append(line) => {
+                let written = match file {
+                    Some(ref mut file) => file.write_all(line.as_bytes()),
+                    None => {
+                        // A crash may have left a partial line the first record would extend
+                        let opened = truncate_partial_last_line(path)
+                            .map_err(|error| io::Error::other(error.to_string()))
+                            .and_then(|()| OpenOptions::new().create(true).append(true).open(path));
+                        opened.and_then(|opened| file.insert(opened).write_all(line.as_bytes()))
+                    }
+                };
+                if let Err(error) = written {
+                    eprintln!("failed to append to {}: {error:?}", path.display());
+                    file = None;
+                }
+            }
+            RecorderCommand::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Sends requests through another transport and records every exchange and transport error
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: Arc<dyn HttpTransport>,
+    recorder: Arc<TrafficRecorder>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Arc<dyn HttpTransport>, recorder: Arc<TrafficRecorder>) -> Self { Self { inner, recorder } }
+}
+
+impl HttpTransport for RecordingTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        async move {
+            let recorded = request.clone();
+            match self.inner.send(request).await {
+                Ok(response) => {
+                    self.recorder.record_exchange(&recorded, &response);
+                    Ok(response)
+                }
+                Err(error) => {
+                    self.recorder.record_failure(&recorded, &error);
+                    Err(error)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrafficReplayState {
+    exchanges: VecDeque<HttpExchange>,
+    frames: HashMap<String, VecDeque<StreamFrame>>,
+}
+
+/// Serves a recording back in order: the REST responses to `Client`, see `Client::with_replay`,
+/// and the messages of each stream to the websockets connected to it
+///
+/// REST requests must be sent in the recorded order, a request to another method or path than
+/// the next recorded one fails. Streams are replayed independently of each other and of the REST
+/// exchanges, as fast as they are read.
+#[derive(Debug)]
+pub struct TrafficReplay {
+    state: Mutex<TrafficReplayState>,
+    /// Set to the time of every replayed record, so that what depends on time replays the same
+    clock: Option<Arc<ManualClock>>,
+}
+
+impl TrafficReplay {
+    pub fn new(records: Vec<TrafficRecord>) -> Self {
+        let mut state = TrafficReplayState::default();
+        for record in records.into_iter() {
+            match record {
+                TrafficRecord::Http(exchange) => state.exchanges.push_back(exchange),
+                TrafficRecord::Frame(frame) => state.frames.entry(frame.stream.clone()).or_default().push_back(frame),
+            }
+        }
+        Self {
+            state: Mutex::new(state),
+            clock: None,
+        }
+    }
+
+    /// Loads a recording written by `TrafficRecorder`
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        if !path.exists() {
+            return Err(anyhow!("No recording at {}", path.display()).into());
+        }
+        let records = read_json_lines(&path)?;
+        Ok(Self::new(records))
+    }
+
+    /// Moves `clock` to the time of every replayed record, e.g. the clock of the client and of the
+    /// order tracker
+    pub fn with_clock(mut self, clock: Arc<ManualClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TrafficReplayState> { self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) }
+
+    fn advance_clock(&self, timestamp: u64) {
+        if let Some(ref clock) = self.clock {
+            if timestamp * 1_000_000 > clock.now_nanos() {
+                clock.set_nanos(timestamp * 1_000_000);
+            }
+        }
+    }
+
+    /// Number of REST exchanges and stream messages not replayed yet
+    pub fn remaining(&self) -> usize {
+        let state = self.lock();
+        state.exchanges.len() + state.frames.values().map(|frames| frames.len()).sum::<usize>()
+    }
+
+    /// Next message of the stream of `url`, `None` once its recording is over
+    pub fn next_message(&self, url: &Url) -> Option<Message> {
+        let frame = self.lock().frames.get_mut(&stream_key(url))?.pop_front()?;
+        self.advance_clock(frame.timestamp);
+        Some(frame.message.to_message())
+    }
+
+    fn next_response(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let Some(exchange) = self.lock().exchanges.pop_front() else {
+            return Err(anyhow!("No recorded response left for {} {}", request.method, request.url).into());
+        };
+        if exchange.method != request.method.as_str() || exchange.get_path() != request.path() {
+            return Err(anyhow!("Replay diverged, expected {} {} but got {} {}", exchange.method, exchange.url, request.method, request.url).into());
+        }
+        self.advance_clock(exchange.timestamp);
+        exchange.to_response()
+    }
+}
+
+impl HttpTransport for TrafficReplay {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> { future::ready(self.next_response(&request)).boxed() }
+}
+
+#[cfg(all(test, feature = "futures_api"))]
+mod test {
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use futures::SinkExt;
+    use reqwest::Method;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use uuid::Uuid;
+    use super::*;
+    use crate::client::Client;
+    use crate::config::Config;
+    use crate::errors::Error;
+    use crate::futures::websockets::WebSockets;
+    use crate::retry::RetryPolicy;
+    use crate::transport::MemoryTransport;
+
+    async fn read_stream(config: Config, recorder: Option<Arc<TrafficRecorder>>, replay: Option<Arc<TrafficReplay>>) -> Vec<Value> {
+        let running = AtomicBool::new(true);
+        let mut events = vec![];
+        let mut web_socket: WebSockets<'_, Value> = WebSockets::new_with_options(
+            |event: Value| {
+                if event["n"] == 2 {
+                    running.store(false, Ordering::Relaxed);
+                }
+                events.push(event);
+                Ok(())
+            },
+            config,
+        );
+        if let Some(recorder) = recorder {
+            web_socket = web_socket.with_recorder(recorder);
+        }
+        if let Some(replay) = replay {
+            web_socket = web_socket.with_replay(replay);
+        }
+        web_socket.connect("btcusdt@aggTrade").await.unwrap();
+        web_socket.event_loop(&running).await.unwrap();
+        web_socket.disconnect().await.unwrap();
+        drop(web_socket);
+        events
+    }
+
+    #[tokio::test]
+    async fn recordings_replay_in_order() {
+        let path = std::env::temp_dir().join(format!("traffic_{}.jsonl", Uuid::new_v4()));
+        let recorder = Arc::new(TrafficRecorder::new(&path));
+
+        let transport = Arc::new(MemoryTransport::new());
+        transport.respond_once(Method::GET, "/api/v3/ping", HttpResponse::new(StatusCode::TOO_MANY_REQUESTS, "").with_header("retry-after", "3").unwrap());
+        transport.respond(Method::GET, "/api/v3/ping", HttpResponse::ok("{}"));
+        let client = Client::new(None, None, "https://api.binance.com".to_string(), None)
+            .with_transport(transport)
+            .with_recorder(recorder.clone());
+        assert!(matches!(client.get::<Value>("/api/v3/ping", None).await, Err(Error::TooManyRequests { .. })));
+        assert_eq!(client.get::<Value>("/api/v3/ping", None).await.unwrap(), json!({}));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(stream).await.unwrap();
+            ws.send(Message::Ping(b"hb".to_vec())).await.unwrap();
+            ws.send(Message::Text(r#"{"n":1}"#.to_string())).await.unwrap();
+            ws.send(Message::Text(r#"{"n":2}"#.to_string())).await.unwrap();
+            // Keep the connection open until the client is done
+            let _ = futures::StreamExt::next(&mut ws).await;
+        });
+        let config = Config::default().set_futures_ws_endpoint(format!("ws://127.0.0.1:{port}"));
+        let recorded_events = read_stream(config, Some(recorder.clone()), None).await;
+        server.await.unwrap();
+        recorder.flush();
+
+        let clock = Arc::new(ManualClock::new(0));
+        let replay = Arc::new(TrafficReplay::load(&path).unwrap().with_clock(clock.clone()));
+        assert_eq!(replay.remaining(), 5);
+        let client = Client::new(None, None, "https://api.binance.com".to_string(), None).with_replay(replay.clone());
+        assert!(matches!(client.get::<Value>("/api/v3/ping", None).await, Err(Error::TooManyRequests { retry_after: Some(retry_after) }) if retry_after.as_secs() == 3));
+        assert!(client.get::<Value>("/api/v3/time", None).await.is_err());
+        // The stream replays against the default endpoint, without a network
+        let replayed_events = read_stream(Config::default(), None, Some(replay.clone())).await;
+        assert_eq!(replayed_events, recorded_events);
+        assert_eq!(replayed_events, vec![json!({"e": "PING"}), json!({"n": 1}), json!({"n": 2})]);
+        assert_eq!(replay.remaining(), 0);
+        assert!(clock.now_millis() > 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Fails every request as if it timed out
+    #[derive(Debug)]
+    struct TimingOutTransport;
+
+    impl HttpTransport for TimingOutTransport {
+        fn send(&self, _request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+            future::ready(Err(io::Error::new(io::ErrorKind::TimedOut, "operation timed out").into())).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn transport_errors_replay_as_the_same_errors() {
+        let path = std::env::temp_dir().join(format!("traffic_{}.jsonl", Uuid::new_v4()));
+        let recorder = Arc::new(TrafficRecorder::new(&path));
+        let client = Client::new(None, None, "https://api.binance.com".to_string(), None)
+            .with_transport(Arc::new(TimingOutTransport))
+            .with_recorder(recorder.clone());
+        let recorded = client.get::<Value>("/api/v3/ping", None).await.unwrap_err();
+        assert!(RetryPolicy::is_retryable(&recorded));
+        recorder.flush();
+
+        let replay = Arc::new(TrafficReplay::load(&path).unwrap());
+        assert_eq!(replay.remaining(), 1);
+        let client = Client::new(None, None, "https://api.binance.com".to_string(), None).with_replay(replay.clone());
+        let replayed = client.get::<Value>("/api/v3/ping", None).await.unwrap_err();
+        assert!(matches!(&replayed, Error::IoError(error) if error.kind() == io::ErrorKind::TimedOut), "{replayed:?}");
+        assert!(RetryPolicy::is_retryable(&replayed));
+        assert_eq!(replay.remaining(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
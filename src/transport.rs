@@ -0,0 +1,169 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+
+use crate::errors::Result;
+
+/// Request sent by `Client`, with the query string, signature included, in the URL
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<String>,
+}
+
+impl HttpRequest {
+    /// Path of the URL, e.g. `/fapi/v1/order`
+    pub fn path(&self) -> String {
+        url::Url::parse(&self.url).map(|url| url.path().to_string()).unwrap_or_default()
+    }
+
+    /// Value of the query parameter `name`, URL decoded
+    pub fn get_query_param(&self, name: &str) -> Option<String> {
+        let url = url::Url::parse(&self.url).ok()?;
+        let value = url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned());
+        value
+    }
+}
+
+/// Response of a transport, fully read
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    /// 200 with a JSON body
+    pub fn ok(body: impl Into<String>) -> Self { Self::new(StatusCode::OK, body) }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Result<Self> {
+        self.headers.insert(HeaderName::from_static(name), HeaderValue::from_str(value)?);
+        Ok(self)
+    }
+}
+
+/// Sends the requests of `Client`, `ReqwestTransport` by default
+///
+/// Implementations may add middleware around another transport or serve requests without a
+/// network, see `MemoryTransport`.
+pub trait HttpTransport: Send + Sync + fmt::Debug {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>>;
+}
+
+/// Sends requests over HTTP with reqwest
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    /// A new connection pool with requests timing out after `timeout` seconds, if any
+    pub fn new(timeout: Option<u64>) -> Result<Self> {
+        let mut builder = reqwest::ClientBuilder::new();
+        if let Some(timeout_secs) = timeout {
+            builder = builder.timeout(Duration::from_secs(timeout_secs))
+        }
+        Ok(Self { client: builder.build()? })
+    }
+}
+
+/// Shares the connection pool of `client`
+impl From<reqwest::Client> for ReqwestTransport {
+    fn from(client: reqwest::Client) -> Self { Self { client } }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        async move {
+            let mut builder = self.client.request(request.method, &request.url).headers(request.headers);
+            if let Some(body) = request.body {
+                builder = builder.body(body);
+            }
+            let response = builder.send().await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.text().await?;
+            Ok(HttpResponse { status, headers, body })
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryTransportState {
+    requests: Vec<HttpRequest>,
+    /// Responses served in order before falling back to `responses`
+    scripted: HashMap<(Method, String), VecDeque<HttpResponse>>,
+    responses: HashMap<(Method, String), HttpResponse>,
+}
+
+/// Records the requests and answers them with programmed responses, without a network.
+/// Requests to paths without a response are answered with 404.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    state: Mutex<MemoryTransportState>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self { Self::default() }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryTransportState> { self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) }
+
+    /// Answers every `method` request to `path` with `response`, whatever its query
+    pub fn respond(&self, method: Method, path: &str, response: HttpResponse) {
+        self.lock().responses.insert((method, path.to_string()), response);
+    }
+
+    /// Answers the next `method` request to `path` with `response`, before the response set by
+    /// `respond`. Responses programmed for the same route are served in order.
+    pub fn respond_once(&self, method: Method, path: &str, response: HttpResponse) {
+        self.lock().scripted.entry((method, path.to_string())).or_default().push_back(response);
+    }
+
+    /// Requests sent so far, in order
+    pub fn requests(&self) -> Vec<HttpRequest> { self.lock().requests.clone() }
+
+    /// Requests sent so far with `method` to `path`
+    pub fn requests_to(&self, method: Method, path: &str) -> Vec<HttpRequest> {
+        self.lock()
+            .requests
+            .iter()
+            .filter(|request| request.method == method && request.path() == path)
+            .cloned()
+            .collect()
+    }
+}
+
+impl HttpTransport for MemoryTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        let mut state = self.lock();
+        let route = (request.method.clone(), request.path());
+        state.requests.push(request);
+        let response = match state.scripted.get_mut(&route).and_then(|responses| responses.pop_front()) {
+            Some(response) => response,
+            None => state
+                .responses
+                .get(&route)
+                .cloned()
+                .unwrap_or_else(|| HttpResponse::new(StatusCode::NOT_FOUND, "")),
+        };
+        future::ready(Ok(response)).boxed()
+    }
+}
@@ -1,9 +1,10 @@
 use std::ops::Not;
 
-use chrono::{Duration, Utc};
+use chrono::Duration;
 use rust_decimal::Decimal;
 use serde_json::Value;
 
+use crate::clock::global_clock;
 use crate::errors::*;
 
 // pub fn build_request(parameters: &BTreeMap<String, String>) -> String {
@@ -26,36 +27,41 @@ pub fn build_signed_request(
     parameters: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
     recv_window: u64,
 ) -> Result<String> {
-    let s = IntoIterator::into_iter([
-        // Include recvWindow if window > 0
-        if recv_window > 0 {
-            Some(("recvWindow", recv_window))
-        } else {
-            None
-        },
-        // Always include timestamp
-        Some(("timestamp", get_timestamp()?)),
-    ])
-    .flatten()
-    .map(|(k, v)| format!("{k}={v}"))
-    .chain(
-        parameters
-            .into_iter()
-            .filter(|(k, _)| k.as_ref().is_empty().not())
-            .map(|(k, v)| format!("{}={}", k.as_ref(), v.as_ref())),
-    )
-    .collect::<Vec<String>>()
-    .join("&");
+    Ok(build_signed_request_at(parameters, recv_window, get_timestamp()?))
+}
 
-    Ok(s)
+/// Same as [`build_signed_request`], stamped with `timestamp` instead of the global clock
+pub fn build_signed_request_at(
+    parameters: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
+    recv_window: u64,
+    timestamp: u64,
+) -> String {
+    let query_string = parameters
+        .into_iter()
+        .filter(|(k, _)| k.as_ref().is_empty().not())
+        .map(|(k, v)| format!("{}={}", k.as_ref(), v.as_ref()))
+        .collect::<Vec<String>>()
+        .join("&");
+    with_signed_params(&query_string, recv_window, timestamp)
 }
 
 pub fn build_signed_request_p<S>(payload: S, recv_window: u64) -> Result<String>
+where
+    S: serde::Serialize,
+{
+    build_signed_request_p_at(payload, recv_window, get_timestamp()?)
+}
+
+/// Same as [`build_signed_request_p`], stamped with `timestamp` instead of the global clock
+pub fn build_signed_request_p_at<S>(payload: S, recv_window: u64, timestamp: u64) -> Result<String>
 where
     S: serde::Serialize,
 {
     let query_string = qs::to_string(&payload)?;
+    Ok(with_signed_params(&query_string, recv_window, timestamp))
+}
 
+fn with_signed_params(query_string: &str, recv_window: u64, timestamp: u64) -> String {
     let s = IntoIterator::into_iter([
         // Include recvWindow if window > 0
         if recv_window > 0 {
@@ -64,19 +70,18 @@ where
             None
         },
         // Always include timestamp
-        Some(("timestamp", get_timestamp()?)),
+        Some(("timestamp", timestamp)),
     ])
     .flatten()
     .map(|(k, v)| format!("{k}={v}"))
     .collect::<Vec<String>>()
     .join("&");
 
-    let request = if query_string.is_empty() {
+    if query_string.is_empty() {
         s
     } else {
         format!("{s}&{query_string}")
-    };
-    Ok(request)
+    }
 }
 
 pub fn to_i64(v: &Value) -> i64 {
@@ -94,7 +99,8 @@ pub fn to_decimal(v: &Value) -> Decimal {
     v.as_str().unwrap().parse().unwrap()
 }
 
-pub fn get_timestamp() -> Result<u64> { Ok(Utc::now().timestamp_millis() as u64) }
+/// Milliseconds since the epoch according to the global clock, see `clock::set_global_clock`
+pub fn get_timestamp() -> Result<u64> { Ok(global_clock().now_millis()) }
 
 /// Returns a duration in milliseconds for the `days`
 pub fn days_millis(days: i64) -> i64 { Duration::days(days).num_milliseconds() }
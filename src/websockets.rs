@@ -1,4 +1,5 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use futures::StreamExt;
 use serde_json::from_str;
@@ -11,6 +12,7 @@ use url::Url;
 
 use crate::config::Config;
 use crate::errors::*;
+use crate::traffic::{TrafficRecorder, TrafficReplay};
 
 pub static STREAM_ENDPOINT: &str = "stream";
 pub static WS_ENDPOINT: &str = "ws";
@@ -69,6 +71,10 @@ pub struct WebSockets<'a, WE> {
     pub socket: Option<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response)>,
     handler: Box<dyn FnMut(WE) -> Result<()> + 'a + Send>,
     conf: Config,
+    /// Last connected url
+    url: Option<Url>,
+    recorder: Option<Arc<TrafficRecorder>>,
+    replay: Option<Arc<TrafficReplay>>,
 }
 
 impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
@@ -93,9 +99,25 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
             socket: None,
             handler: Box::new(handler),
             conf,
+            url: None,
+            recorder: None,
+            replay: None,
         }
     }
 
+    /// Records every message received to `recorder`
+    pub fn with_recorder(mut self, recorder: Arc<TrafficRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Reads the messages of the connected stream from a recording instead of connecting, the
+    /// event loop returns once the recording of the stream is over
+    pub fn with_replay(mut self, replay: Arc<TrafficReplay>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
     /// Connect to multiple websocket endpoints
     /// N.B: WE has to be CombinedStreamEvent
     pub async fn connect_multiple(&mut self, endpoints: Vec<String>) -> Result<()> {
@@ -125,9 +147,14 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
     }
 
     async fn handle_connect(&mut self, url: Url) -> Result<()> {
-        match connect_async(url).await {
+        if self.replay.is_some() {
+            self.url = Some(url);
+            return Ok(());
+        }
+        match connect_async(url.clone()).await {
             Ok(answer) => {
                 self.socket = Some(answer);
+                self.url = Some(url);
                 Ok(())
             }
             Err(e) => Err(Error::Msg(format!("Error during handshake {e}"))),
@@ -136,7 +163,9 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
 
     /// Disconnect from the endpoint
     pub async fn disconnect(&mut self) -> Result<()> {
-        if let Some(ref mut socket) = self.socket {
+        if self.replay.is_some() {
+            Ok(())
+        } else if let Some(ref mut socket) = self.socket {
             socket.0.close(None).await?;
             Ok(())
         } else {
@@ -148,25 +177,42 @@ impl<'a, WE: serde::de::DeserializeOwned> WebSockets<'a, WE> {
 
     pub async fn event_loop(&mut self, running: &AtomicBool) -> Result<()> {
         while running.load(Ordering::Relaxed) {
-            if let Some((ref mut socket, _)) = self.socket {
+            if let (Some(replay), Some(url)) = (&self.replay, &self.url) {
+                let Some(message) = replay.next_message(url) else {
+                    return Ok(());
+                };
+                if !self.handle_message(message)? {
+                    return Ok(());
+                }
+            } else if let Some((ref mut socket, _)) = self.socket {
                 // TODO: return error instead of panic?
                 let message = socket.next().await.unwrap()?;
-
-                match message {
-                    Message::Text(msg) => {
-                        if msg.is_empty() {
-                            return Ok(());
-                        }
-                        let event: WE = from_str(msg.as_str())?;
-                        (self.handler)(event)?;
-                    }
-                    Message::Ping(_) | Message::Pong(_) | Message::Binary(_) | Message::Frame(_) => {}
-                    Message::Close(e) => {
-                        return Err(Error::Msg(format!("Disconnected {e:?}")));
-                    }
+                if let (Some(recorder), Some(url)) = (&self.recorder, &self.url) {
+                    recorder.record_message(url, &message);
+                }
+                if !self.handle_message(message)? {
+                    return Ok(());
                 }
             }
         }
         Ok(())
     }
+
+    /// Returns whether the event loop goes on
+    fn handle_message(&mut self, message: Message) -> Result<bool> {
+        match message {
+            Message::Text(msg) => {
+                if msg.is_empty() {
+                    return Ok(false);
+                }
+                let event: WE = from_str(msg.as_str())?;
+                (self.handler)(event)?;
+            }
+            Message::Ping(_) | Message::Pong(_) | Message::Binary(_) | Message::Frame(_) => {}
+            Message::Close(e) => {
+                return Err(Error::Msg(format!("Disconnected {e:?}")));
+            }
+        }
+        Ok(true)
+    }
 }
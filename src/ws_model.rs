@@ -244,6 +244,9 @@ pub struct DepthOrderBookEvent {
     pub first_update_id: u64,
     #[serde(rename = "u")]
     pub final_update_id: u64,
+    /// Final update id of the previous event, only sent on futures streams
+    #[serde(rename = "pu", default, skip_serializing_if = "Option::is_none")]
+    pub previous_final_update_id: Option<u64>,
     #[serde(rename = "b")]
     pub bids: Vec<Bids>,
     #[serde(rename = "a")]